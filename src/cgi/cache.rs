@@ -0,0 +1,234 @@
+/*!
+ * Cache of CGI responses
+ *
+ * Routes with `cgi_cache on` keep each script response for their TTL, keyed on
+ * method, host, URI and a hash of the request body. Once the cache is full the
+ * least recently used entry makes room for a new one.
+ */
+
+use crate::error::HttpStatus;
+use crate::http::{HttpRequest, HttpResponse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Most responses kept at once
+const MAX_ENTRIES: usize = 256;
+
+/// Largest body kept; bigger responses are always regenerated
+const MAX_ENTRY_SIZE: usize = 1024 * 1024;
+
+/// What makes two requests share a cached response
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CgiCacheKey {
+    method: String,
+    host: String,
+    uri: String,
+    body_hash: u64,
+}
+
+impl CgiCacheKey {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        Self {
+            method: request.method.as_str().to_string(),
+            host: request.get_header("host").cloned().unwrap_or_default().to_ascii_lowercase(),
+            uri: request.uri.clone(),
+            body_hash: hash_bytes(&request.body),
+        }
+    }
+}
+
+/// A stored response
+struct CacheEntry {
+    response: HttpResponse,
+    etag: String,
+    expires_at: Instant,
+    /// Value of the use counter when the entry was last read or written
+    last_used: u64,
+}
+
+/// Bounded, expiring store of CGI responses
+pub struct CgiCache {
+    entries: HashMap<CgiCacheKey, CacheEntry>,
+    max_entries: usize,
+    max_entry_size: usize,
+    uses: u64,
+}
+
+impl CgiCache {
+    pub fn new() -> Self {
+        Self::with_limits(MAX_ENTRIES, MAX_ENTRY_SIZE)
+    }
+
+    /// Create a cache holding at most `max_entries` bodies of up to `max_entry_size` bytes
+    pub fn with_limits(max_entries: usize, max_entry_size: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries,
+            max_entry_size,
+            uses: 0,
+        }
+    }
+
+    /// Fresh response for a key, with its ETag
+    pub fn get(&mut self, key: &CgiCacheKey) -> Option<(HttpResponse, String)> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&mut self, key: &CgiCacheKey, now: Instant) -> Option<(HttpResponse, String)> {
+        if self.entries.get(key)?.expires_at <= now {
+            self.entries.remove(key);
+            return None;
+        }
+
+        self.uses += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.uses;
+        Some((entry.response.clone(), entry.etag.clone()))
+    }
+
+    /// Keep a response for `ttl`, first giving it an ETag if the script set none.
+    /// Returns false when the response is not one that may be shared.
+    pub fn insert(&mut self, key: CgiCacheKey, response: &mut HttpResponse, ttl: Duration) -> bool {
+        self.insert_at(key, response, ttl, Instant::now())
+    }
+
+    fn insert_at(&mut self, key: CgiCacheKey, response: &mut HttpResponse, ttl: Duration, now: Instant) -> bool {
+        if !self.is_cacheable(response) {
+            return false;
+        }
+
+        let etag = match response.get_header("ETag") {
+            Some(etag) => etag.clone(),
+            None => {
+                let etag = format!("\"{:016x}\"", hash_bytes(&response.body));
+                response.add_header("ETag", &etag);
+                etag
+            }
+        };
+
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.evict(now);
+        }
+
+        self.uses += 1;
+        self.entries.insert(key, CacheEntry {
+            response: response.clone(),
+            etag,
+            expires_at: now + ttl,
+            last_used: self.uses,
+        });
+        true
+    }
+
+    /// Check if a response is the same for everyone asking, and small enough to keep
+    fn is_cacheable(&self, response: &HttpResponse) -> bool {
+        response.status == HttpStatus::Ok
+            && response.body_source.is_none()
+            && !response.head_only
+            && response.body.len() <= self.max_entry_size
+            && response.cookies.is_empty()
+            && response.get_header("Set-Cookie").is_none()
+    }
+
+    /// Make room by dropping expired entries, or else the least recently used one
+    fn evict(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| entry.expires_at > now);
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+
+        let oldest = self.entries.iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Number of responses held
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no response is held
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for CgiCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(uri: &str, body: &[u8]) -> CgiCacheKey {
+        let mut request = HttpRequest::new();
+        request.uri = uri.to_string();
+        request.body = body.to_vec();
+        CgiCacheKey::from_request(&request)
+    }
+
+    fn response(body: &str) -> HttpResponse {
+        HttpResponse::text(HttpStatus::Ok, body)
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let mut cache = CgiCache::new();
+        let start = Instant::now();
+        assert!(cache.insert_at(key("/a", b""), &mut response("a"), Duration::from_secs(10), start));
+
+        let (cached, etag) = cache.get_at(&key("/a", b""), start + Duration::from_secs(9)).unwrap();
+        assert_eq!(cached.body, b"a");
+        assert_eq!(cached.get_header("ETag"), Some(&etag));
+
+        assert!(cache.get_at(&key("/a", b"other body"), start).is_none());
+        assert!(cache.get_at(&key("/a", b""), start + Duration::from_secs(10)).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let mut cache = CgiCache::with_limits(2, 1024);
+        let start = Instant::now();
+        let ttl = Duration::from_secs(60);
+        cache.insert_at(key("/a", b""), &mut response("a"), ttl, start);
+        cache.insert_at(key("/b", b""), &mut response("b"), ttl, start);
+
+        // Reading /a leaves /b as the stalest entry
+        assert!(cache.get_at(&key("/a", b""), start).is_some());
+        cache.insert_at(key("/c", b""), &mut response("c"), ttl, start);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get_at(&key("/b", b""), start).is_none());
+        assert!(cache.get_at(&key("/a", b""), start).is_some());
+        assert!(cache.get_at(&key("/c", b""), start).is_some());
+    }
+
+    #[test]
+    fn test_personal_and_failed_responses_are_not_kept() {
+        let mut cache = CgiCache::new();
+        let ttl = Duration::from_secs(60);
+
+        let mut with_cookie = response("x");
+        with_cookie.add_header("Set-Cookie", "id=1");
+        assert!(!cache.insert(key("/cookie", b""), &mut with_cookie, ttl));
+
+        let mut failed = HttpResponse::text(HttpStatus::InternalServerError, "oops");
+        assert!(!cache.insert(key("/failed", b""), &mut failed, ttl));
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+/*!
+ * CGI worker pool
+ *
+ * Runs CGI scripts to completion on a fixed set of threads, so a script that
+ * keeps the CPU busy never stalls the event loop. Finished responses come
+ * back over a channel, and an eventfd registered with epoll says when.
+ */
+
+use crate::cgi::CgiProcess;
+use crate::error::ServerResult;
+use crate::http::HttpResponse;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A CGI script waiting for a worker
+struct CgiJob {
+    id: u64,
+    client_fd: RawFd,
+    process: CgiProcess,
+}
+
+/// A script run to completion by a worker
+pub struct CgiOutcome {
+    /// Identifies the job, so a reused client descriptor is not answered by mistake
+    pub id: u64,
+    pub client_fd: RawFd,
+    pub result: ServerResult<HttpResponse>,
+}
+
+/// Counter the workers bump and the event loop drains
+struct EventFd(RawFd);
+
+impl EventFd {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(fd))
+    }
+
+    /// Make the descriptor readable
+    fn notify(&self) {
+        let one: u64 = 1;
+        unsafe {
+            libc::write(self.0, &one as *const u64 as *const libc::c_void, std::mem::size_of::<u64>());
+        }
+    }
+
+    /// Reset the counter so the descriptor stops being readable
+    fn drain(&self) {
+        let mut count: u64 = 0;
+        unsafe {
+            libc::read(self.0, &mut count as *mut u64 as *mut libc::c_void, std::mem::size_of::<u64>());
+        }
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+/// Fixed pool of threads running CGI scripts to completion
+pub struct CgiWorkerPool {
+    jobs: Sender<CgiJob>,
+    outcomes: Receiver<CgiOutcome>,
+    // Shared with the workers, so it stays open until the last one exits
+    event_fd: Arc<EventFd>,
+    next_id: u64,
+}
+
+impl CgiWorkerPool {
+    /// Start `workers` threads
+    pub fn new(workers: usize) -> io::Result<Self> {
+        let (jobs, job_receiver) = mpsc::channel::<CgiJob>();
+        let (outcome_sender, outcomes) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let event_fd = Arc::new(EventFd::new()?);
+
+        for index in 0..workers {
+            let job_receiver = Arc::clone(&job_receiver);
+            let outcome_sender = outcome_sender.clone();
+            let event_fd = Arc::clone(&event_fd);
+            thread::Builder::new()
+                .name(format!("cgi-worker-{}", index))
+                .spawn(move || {
+                    // The loop ends once the pool, and with it the job sender, is dropped
+                    loop {
+                        let job = match job_receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => return,
+                        };
+                        let job = match job {
+                            Ok(job) => job,
+                            Err(_) => return,
+                        };
+
+                        let outcome = CgiOutcome {
+                            id: job.id,
+                            client_fd: job.client_fd,
+                            result: job.process.run_to_completion(),
+                        };
+                        if outcome_sender.send(outcome).is_err() {
+                            return;
+                        }
+                        event_fd.notify();
+                    }
+                })?;
+        }
+
+        Ok(Self {
+            jobs,
+            outcomes,
+            event_fd,
+            next_id: 0,
+        })
+    }
+
+    /// Descriptor that becomes readable when scripts have finished
+    pub fn event_fd(&self) -> RawFd {
+        self.event_fd.0
+    }
+
+    /// Queue a spawned script for the next free worker, returning its job id
+    pub fn submit(&mut self, client_fd: RawFd, process: CgiProcess) -> u64 {
+        self.next_id += 1;
+        let id = self.next_id;
+        // The workers only stop when the pool is dropped, so the send cannot fail
+        let _ = self.jobs.send(CgiJob { id, client_fd, process });
+        id
+    }
+
+    /// Take every outcome that has arrived since the last call
+    pub fn take_outcomes(&mut self) -> Vec<CgiOutcome> {
+        self.event_fd.drain();
+        self.outcomes.try_iter().collect()
+    }
+}
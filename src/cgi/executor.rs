@@ -4,17 +4,33 @@
 
 use crate::cgi::environment::CgiEnvironment;
 use crate::config::{RouteConfig, ServerConfig};
-use crate::error::{ServerError, ServerResult, HttpStatus};
+use crate::error::{CgiErrorKind, ServerError, ServerResult, HttpStatus};
+use crate::error::pages::ErrorPageManager;
 use crate::http::{HttpRequest, HttpResponse};
-use std::io::{Read, Write};
+use crate::cgi::process::CgiProcess;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::process::{Command, Stdio};
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+/// Seconds clients are asked to wait when scripts are overloaded: every
+/// process slot is taken, or a script ran out of time
+pub const CGI_RETRY_AFTER_SECS: u64 = 1;
+
+/// Interpreter name for scripts run directly, through their `#!` line
+pub const EXEC_INTERPRETER: &str = "exec";
+
+/// Check if a script may be run directly: it must have an execute bit set
+pub fn is_executable(script_path: &Path) -> bool {
+    std::fs::metadata(script_path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
 
 /// CGI script executor
+#[derive(Clone)]
 pub struct CgiExecutor {
     timeout: Duration,
     max_output_size: usize,
+    error_manager: ErrorPageManager,
 }
 
 impl CgiExecutor {
@@ -23,6 +39,7 @@ impl CgiExecutor {
         Self {
             timeout: Duration::from_secs(30), // 30 second timeout
             max_output_size: 1024 * 1024,    // 1MB max output
+            error_manager: ErrorPageManager::new(),
         }
     }
 
@@ -31,10 +48,11 @@ impl CgiExecutor {
         Self {
             timeout: Duration::from_secs(timeout_secs),
             max_output_size,
+            error_manager: ErrorPageManager::new(),
         }
     }
 
-    /// Execute a CGI script and return HTTP response
+    /// Execute a CGI script and return HTTP response, blocking until it exits
     pub fn execute(
         &self,
         request: &HttpRequest,
@@ -45,217 +63,74 @@ impl CgiExecutor {
         // Validate script exists and is executable
         let script_file = Path::new(script_path);
         if !script_file.exists() {
-            return Ok(HttpResponse::error(HttpStatus::NotFound, Some("CGI script not found")));
+            return Ok(self.error_manager.generate_error_response(HttpStatus::NotFound, Some("CGI script not found")));
         }
 
-        // Determine interpreter
-        let interpreter = route_config.cgi.as_ref()
-            .ok_or_else(|| ServerError::Cgi("No CGI interpreter configured".to_string()))?;
-
-        // Build environment variables
-        let path_info = self.extract_path_info(&request.path, &route_config.path);
-        let environment = CgiEnvironment::from_request(request, server_config, script_path, &path_info);
-
-        // Execute the script
-        self.execute_script(interpreter, script_path, &environment, &request.body)
+        self.spawn(request, server_config, route_config, script_path)?
+            .run_to_completion()
+            .or_else(|e| {
+                eprintln!("CGI execution error: {}", e);
+                Ok(self.error_response(&e))
+            })
     }
 
-    /// Execute the CGI script with the given interpreter
-    fn execute_script(
-        &self,
-        interpreter: &str,
-        script_path: &str,
-        environment: &CgiEnvironment,
-        input_data: &[u8],
-    ) -> ServerResult<HttpResponse> {
-        let start_time = Instant::now();
-
-        // Create command
-        let mut command = Command::new(interpreter);
-        command
-            .arg(script_path)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .envs(environment.to_env_vars());
-
-        // Spawn the process
-        let mut child = command.spawn()
-            .map_err(|e| ServerError::Cgi(format!("Failed to spawn CGI process: {}", e)))?;
-
-        // Write input data to stdin if present
-        if !input_data.is_empty() {
-            // Debug: Print first 100 bytes of input data
-            let debug_data = if input_data.len() > 100 {
-                &input_data[..100]
-            } else {
-                input_data
-            };
-            eprintln!("CGI Debug: Writing {} bytes to stdin. First 100 bytes: {:?}",
-                     input_data.len(),
-                     String::from_utf8_lossy(debug_data));
-
-            if let Some(stdin) = child.stdin.as_mut() {
-                stdin.write_all(input_data)
-                    .map_err(|e| ServerError::Cgi(format!("Failed to write to CGI stdin: {}", e)))?;
-            }
-        }
-
-        // Close stdin to signal end of input
-        drop(child.stdin.take());
-
-        // Read output with timeout
-        let (stdout, _stderr) = self.read_output_with_timeout(&mut child, start_time)?;
-
-        // Wait for process to complete
-        let exit_status = child.wait()
-            .map_err(|e| ServerError::Cgi(format!("Failed to wait for CGI process: {}", e)))?;
+    /// Error page for a script that gave no usable response, with the status
+    /// its kind of failure calls for. A timed out script is likely to succeed
+    /// later, so the client is told when to try again.
+    pub fn error_response(&self, error: &ServerError) -> HttpResponse {
+        let (status, message) = match error {
+            ServerError::Cgi(kind) => (kind.status(), kind.description()),
+            _ => (HttpStatus::InternalServerError, "CGI script execution failed"),
+        };
 
-        if !exit_status.success() {
-            return Ok(HttpResponse::error(
-                HttpStatus::InternalServerError,
-                Some("CGI script execution failed"),
-            ));
+        let mut response = self.error_manager.generate_error_response(status, Some(message));
+        if status == HttpStatus::ServiceUnavailable {
+            response.add_header("Retry-After", &CGI_RETRY_AFTER_SECS.to_string());
         }
-
-        // Parse CGI output
-        self.parse_cgi_output(&stdout)
+        response
     }
 
-    /// Read process output with timeout
-    fn read_output_with_timeout(
+    /// Spawn a CGI script with non-blocking pipes for the event loop to drive
+    pub fn spawn(
         &self,
-        child: &mut std::process::Child,
-        start_time: Instant,
-    ) -> ServerResult<(Vec<u8>, Vec<u8>)> {
-        // Simple timeout implementation - in production, you'd want non-blocking I/O
-        loop {
-            if start_time.elapsed() > self.timeout {
-                let _ = child.kill();
-                return Err(ServerError::Cgi("CGI script timeout".to_string()));
-            }
-
-            match child.try_wait() {
-                Ok(Some(_)) => {
-                    // Process has finished
-                    break;
-                }
-                Ok(None) => {
-                    // Process still running
-                    std::thread::sleep(Duration::from_millis(10));
-                    continue;
-                }
-                Err(e) => {
-                    return Err(ServerError::Cgi(format!("Error waiting for CGI process: {}", e)));
-                }
-            }
-        }
-
-        // Read the output
-        let mut stdout = Vec::new();
-        let mut stderr = Vec::new();
-
-        if let Some(mut stdout_handle) = child.stdout.take() {
-            stdout_handle.read_to_end(&mut stdout)
-                .map_err(|e| ServerError::Cgi(format!("Failed to read CGI stdout: {}", e)))?;
-        }
-
-        if let Some(mut stderr_handle) = child.stderr.take() {
-            stderr_handle.read_to_end(&mut stderr)
-                .map_err(|e| ServerError::Cgi(format!("Failed to read CGI stderr: {}", e)))?;
-        }
-
-        // Check output size
-        if stdout.len() > self.max_output_size {
-            return Err(ServerError::Cgi("CGI output too large".to_string()));
-        }
-
-        Ok((stdout, stderr))
-    }
+        request: &HttpRequest,
+        server_config: &ServerConfig,
+        route_config: &RouteConfig,
+        script_path: &str,
+    ) -> ServerResult<CgiProcess> {
+        // Determine interpreter
+        let interpreter = route_config.cgi_interpreter(script_path)
+            .ok_or_else(|| CgiErrorKind::Spawn("No CGI interpreter configured".to_string()))?;
 
-    /// Parse CGI output into HTTP response
-    fn parse_cgi_output(&self, output: &[u8]) -> ServerResult<HttpResponse> {
-        let output_str = String::from_utf8_lossy(output);
+        // Build environment variables
+        let path_info = self.extract_path_info(&request.path, &route_config.path);
+        let environment = CgiEnvironment::from_request(request, server_config, script_path, &path_info);
 
-        // Find the end of headers (double CRLF or double LF)
-        let header_end = if let Some(pos) = output_str.find("\r\n\r\n") {
-            pos + 4
-        } else if let Some(pos) = output_str.find("\n\n") {
-            pos + 2
+        // `exec` runs the script itself, leaving its `#!` line to pick the interpreter
+        let mut command = if interpreter == EXEC_INTERPRETER {
+            Command::new(script_path)
         } else {
-            // No headers found, treat entire output as body
-            return Ok(HttpResponse::html(HttpStatus::Ok, &output_str));
+            let mut command = Command::new(interpreter);
+            command.arg(script_path);
+            command
         };
 
-        let headers_str = &output_str[..header_end - 2]; // Remove the double newline
-        let body_str = &output_str[header_end..];
-
-        // Parse headers
-        let mut response = HttpResponse::new(HttpStatus::Ok);
-        let mut content_type_set = false;
-
-        for line in headers_str.lines() {
-            if line.trim().is_empty() {
-                continue;
-            }
-
-            if let Some(colon_pos) = line.find(':') {
-                let name = line[..colon_pos].trim();
-                let value = line[colon_pos + 1..].trim();
-
-                match name.to_lowercase().as_str() {
-                    "content-type" => {
-                        response.set_content_type(value);
-                        content_type_set = true;
-                    }
-                    "status" => {
-                        // Parse status line (e.g., "200 OK" or "404 Not Found")
-                        if let Some(space_pos) = value.find(' ') {
-                            if let Ok(status_code) = value[..space_pos].parse::<u16>() {
-                                // Map status code to HttpStatus (simplified)
-                                let status = match status_code {
-                                    200 => HttpStatus::Ok,
-                                    201 => HttpStatus::Created,
-                                    204 => HttpStatus::NoContent,
-                                    301 => HttpStatus::MovedPermanently,
-                                    302 => HttpStatus::Found,
-                                    400 => HttpStatus::BadRequest,
-                                    403 => HttpStatus::Forbidden,
-                                    404 => HttpStatus::NotFound,
-                                    405 => HttpStatus::MethodNotAllowed,
-                                    413 => HttpStatus::RequestEntityTooLarge,
-                                    500 => HttpStatus::InternalServerError,
-                                    _ => HttpStatus::Ok, // Default to OK for unknown codes
-                                };
-                                response = HttpResponse::new(status);
-                            }
-                        }
-                    }
-                    "location" => {
-                        response.add_header("Location", value);
-                    }
-                    _ => {
-                        response.add_header(name, value);
-                    }
-                }
-            }
-        }
-
-        // Set default content type if not specified
-        if !content_type_set {
-            response.set_content_type("text/html; charset=utf-8");
-        }
-
-        // Set body
-        response.set_body_string(body_str.to_string());
+        // Script errors go to the server's stderr rather than a third pipe
+        let child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .envs(environment.to_env_vars())
+            .spawn()
+            .map_err(|e| CgiErrorKind::Spawn(format!("Failed to spawn CGI process: {}", e)))?;
 
-        Ok(response)
+        CgiProcess::new(child, request.body.clone(), self.timeout, self.max_output_size)
     }
 
     /// Extract path info from request path and route path
     fn extract_path_info(&self, request_path: &str, route_path: &str) -> String {
-        if request_path.starts_with(route_path) {
-            let remaining = &request_path[route_path.len()..];
+        let route_path = crate::routing::router::literal_prefix(route_path);
+        if let Some(remaining) = request_path.strip_prefix(route_path) {
             remaining.strip_prefix('/').unwrap_or(remaining).to_string()
         } else {
             String::new()
@@ -267,6 +142,11 @@ impl CgiExecutor {
         self.timeout = timeout;
     }
 
+    /// Set the error page manager used for error responses
+    pub fn set_error_manager(&mut self, error_manager: ErrorPageManager) {
+        self.error_manager = error_manager;
+    }
+
     /// Set maximum output size
     pub fn set_max_output_size(&mut self, size: usize) {
         self.max_output_size = size;
@@ -288,3 +168,107 @@ impl Default for CgiExecutor {
         Self::new()
     }
 }
+
+/// Parse CGI output into HTTP response
+pub(crate) fn parse_cgi_output(output: &[u8]) -> ServerResult<HttpResponse> {
+    match parse_cgi_head(output)? {
+        Some((mut response, head_len)) => {
+            response.set_body(output[head_len..].to_vec());
+            Ok(response)
+        }
+        // No headers found, treat entire output as body
+        None => Ok(HttpResponse::html(HttpStatus::Ok, &String::from_utf8_lossy(output))),
+    }
+}
+
+/// Parse the header block at the start of CGI output, once all of it has arrived.
+/// Returns the response without a body and the length of the header block in bytes.
+/// Headers that could inject further header lines into the response are an error.
+pub(crate) fn parse_cgi_head(output: &[u8]) -> ServerResult<Option<(HttpResponse, usize)>> {
+    // Find the end of headers (double CRLF or double LF)
+    let header_end = if let Some(pos) = find_bytes(output, b"\r\n\r\n") {
+        pos + 4
+    } else if let Some(pos) = find_bytes(output, b"\n\n") {
+        pos + 2
+    } else {
+        return Ok(None);
+    };
+
+    let headers_str = String::from_utf8_lossy(&output[..header_end - 2]); // Remove the double newline
+
+    let mut response = HttpResponse::new(HttpStatus::Ok);
+    let mut content_type_set = false;
+
+    for line in headers_str.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(colon_pos) = line.find(':') {
+            let name = line[..colon_pos].trim();
+            let value = line[colon_pos + 1..].trim();
+
+            // A lone CR would end the header line for many clients
+            if !crate::http::request::is_token(name) {
+                return Err(CgiErrorKind::Failed(format!("Invalid CGI header name: {:?}", name)).into());
+            }
+            if value.contains(['\r', '\0']) {
+                return Err(CgiErrorKind::Failed(format!("Invalid value for CGI header {}", name)).into());
+            }
+
+            match name.to_lowercase().as_str() {
+                "content-type" => {
+                    response.set_content_type(value);
+                    content_type_set = true;
+                }
+                "status" => {
+                    // Parse status line (e.g., "200 OK" or "404 Not Found")
+                    if let Some(space_pos) = value.find(' ') {
+                        if let Ok(status_code) = value[..space_pos].parse::<u16>() {
+                            // Map status code to HttpStatus (simplified)
+                            let status = match status_code {
+                                200 => HttpStatus::Ok,
+                                201 => HttpStatus::Created,
+                                204 => HttpStatus::NoContent,
+                                301 => HttpStatus::MovedPermanently,
+                                302 => HttpStatus::Found,
+                                307 => HttpStatus::TemporaryRedirect,
+                                308 => HttpStatus::PermanentRedirect,
+                                400 => HttpStatus::BadRequest,
+                                401 => HttpStatus::Unauthorized,
+                                403 => HttpStatus::Forbidden,
+                                404 => HttpStatus::NotFound,
+                                405 => HttpStatus::MethodNotAllowed,
+                                409 => HttpStatus::Conflict,
+                                413 => HttpStatus::RequestEntityTooLarge,
+                                500 => HttpStatus::InternalServerError,
+                                502 => HttpStatus::BadGateway,
+                                503 => HttpStatus::ServiceUnavailable,
+                                _ => HttpStatus::Ok, // Default to OK for unknown codes
+                            };
+                            response = HttpResponse::new(status);
+                        }
+                    }
+                }
+                "location" => {
+                    response.add_header("Location", value);
+                }
+                _ => {
+                    response.add_header(name, value);
+                }
+            }
+        }
+    }
+
+    // Set default content type if not specified
+    if !content_type_set {
+        response.set_content_type("text/html; charset=utf-8");
+    }
+
+    Ok(Some((response, header_end)))
+}
+
+/// Position of the first occurrence of `needle` in `haystack`
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
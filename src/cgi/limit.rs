@@ -0,0 +1,93 @@
+/*!
+ * Bound on the number of CGI scripts running at once
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Counts running scripts against a limit; clones share the count
+#[derive(Debug, Clone)]
+pub struct CgiLimiter {
+    running: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl CgiLimiter {
+    pub fn new(max: usize) -> Self {
+        Self {
+            running: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// Limiter with a new maximum that shares this one's count, so scripts
+    /// started under an earlier configuration still hold their slots
+    pub fn with_max(&self, max: usize) -> Self {
+        Self {
+            running: Arc::clone(&self.running),
+            max,
+        }
+    }
+
+    /// Take a slot for a new script, or None when all are in use
+    pub fn try_acquire(&self) -> Option<CgiPermit> {
+        self.running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| (running < self.max).then_some(running + 1))
+            .ok()
+            .map(|_| CgiPermit { running: Arc::clone(&self.running) })
+    }
+
+    /// Number of scripts holding a slot
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Acquire)
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+/// A slot held by one script, given back when dropped however the script ended
+#[derive(Debug)]
+pub struct CgiPermit {
+    running: Arc<AtomicUsize>,
+}
+
+impl Drop for CgiPermit {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permits_are_returned_on_drop() {
+        let limiter = CgiLimiter::new(2);
+        let first = limiter.try_acquire().expect("first slot");
+        let second = limiter.clone().try_acquire().expect("second slot");
+        assert_eq!(limiter.running(), 2);
+        assert!(limiter.try_acquire().is_none());
+
+        drop(first);
+        assert_eq!(limiter.running(), 1);
+        let third = limiter.try_acquire().expect("slot freed by drop");
+
+        drop(second);
+        drop(third);
+        assert_eq!(limiter.running(), 0);
+    }
+
+    #[test]
+    fn test_new_maximum_keeps_running_count() {
+        let limiter = CgiLimiter::new(2);
+        let _first = limiter.try_acquire().expect("first slot");
+
+        let smaller = limiter.with_max(1);
+        assert_eq!(smaller.running(), 1);
+        assert!(smaller.try_acquire().is_none());
+        assert!(limiter.try_acquire().is_some());
+    }
+}
@@ -6,6 +6,14 @@
 
 pub mod executor;
 pub mod environment;
+pub mod process;
+pub mod dispatch;
+pub mod cache;
+pub mod limit;
 
 pub use executor::CgiExecutor;
 pub use environment::CgiEnvironment;
+pub use process::CgiProcess;
+pub use dispatch::{CgiOutcome, CgiWorkerPool};
+pub use cache::{CgiCache, CgiCacheKey};
+pub use limit::{CgiLimiter, CgiPermit};
@@ -0,0 +1,327 @@
+/*!
+ * Non-blocking CGI child process
+ *
+ * Owns the child's stdin/stdout pipes in non-blocking mode so the event
+ * loop can drive them alongside client sockets.
+ */
+
+use crate::cgi::executor::{parse_cgi_head, parse_cgi_output};
+use crate::cgi::limit::CgiPermit;
+use crate::error::{CgiErrorKind, ServerError, ServerResult};
+use crate::http::HttpResponse;
+use crate::server::socket::set_nonblocking;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::{Child, ChildStdin, ChildStdout, ExitStatus};
+use std::time::{Duration, Instant};
+
+/// A running CGI script
+pub struct CgiProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: Option<ChildStdout>,
+    input: Vec<u8>,
+    input_written: usize,
+    output: Vec<u8>,
+    started_at: Instant,
+    timeout: Duration,
+    max_output_size: usize,
+    exit_status: Option<ExitStatus>,
+    /// Slot under `max_cgi_processes`, released once the child has been reaped
+    permit: Option<CgiPermit>,
+}
+
+impl CgiProcess {
+    /// Wrap a spawned child, switching its pipes to non-blocking mode
+    pub fn new(mut child: Child, input: Vec<u8>, timeout: Duration, max_output_size: usize) -> ServerResult<Self> {
+        let mut stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+
+        if let Some(ref pipe) = stdin {
+            set_nonblocking(pipe.as_raw_fd())?;
+        }
+        if let Some(ref pipe) = stdout {
+            set_nonblocking(pipe.as_raw_fd())?;
+        }
+
+        // Nothing to send: close stdin right away so the script sees EOF
+        if input.is_empty() {
+            stdin = None;
+        }
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            input,
+            input_written: 0,
+            output: Vec::new(),
+            started_at: Instant::now(),
+            timeout,
+            max_output_size,
+            exit_status: None,
+            permit: None,
+        })
+    }
+
+    /// Count the process against a limit until it is dropped
+    pub fn hold_permit(&mut self, permit: CgiPermit) {
+        self.permit = Some(permit);
+    }
+
+    /// File descriptor of the child's stdin, while still open
+    pub fn stdin_fd(&self) -> Option<RawFd> {
+        self.stdin.as_ref().map(|pipe| pipe.as_raw_fd())
+    }
+
+    /// File descriptor of the child's stdout, while still open
+    pub fn stdout_fd(&self) -> Option<RawFd> {
+        self.stdout.as_ref().map(|pipe| pipe.as_raw_fd())
+    }
+
+    /// Write as much pending input as the pipe accepts.
+    /// Returns true once all input has been written.
+    pub fn write_input(&mut self) -> ServerResult<bool> {
+        let stdin = match self.stdin.as_mut() {
+            Some(stdin) => stdin,
+            None => return Ok(true),
+        };
+
+        while self.input_written < self.input.len() {
+            match stdin.write(&self.input[self.input_written..]) {
+                Ok(0) => break,
+                Ok(n) => self.input_written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(CgiErrorKind::Failed(format!("Failed to write to CGI stdin: {}", e)).into()),
+            }
+        }
+
+        Ok(self.input_written >= self.input.len())
+    }
+
+    /// Read everything currently available from stdout.
+    /// Returns true once the child has closed its stdout.
+    pub fn read_output(&mut self) -> ServerResult<bool> {
+        let stdout = match self.stdout.as_mut() {
+            Some(stdout) => stdout,
+            None => return Ok(true),
+        };
+
+        let mut buf = [0u8; 8192];
+        loop {
+            match stdout.read(&mut buf) {
+                Ok(0) => return Ok(true),
+                Ok(n) => {
+                    self.output.extend_from_slice(&buf[..n]);
+                    if self.output.len() > self.max_output_size {
+                        return Err(CgiErrorKind::Failed("CGI output too large".to_string()).into());
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(CgiErrorKind::Failed(format!("Failed to read CGI stdout: {}", e)).into()),
+            }
+        }
+    }
+
+    /// Parse the script's headers once all of them have been read,
+    /// with the number of output bytes they take up
+    pub fn parse_head(&self) -> ServerResult<Option<(HttpResponse, usize)>> {
+        parse_cgi_head(&self.output)
+    }
+
+    /// Take the output read so far, leaving the buffer empty
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Close the child's stdin
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+
+    /// Close the child's stdout
+    pub fn close_stdout(&mut self) {
+        self.stdout = None;
+    }
+
+    /// Check whether the child has exited successfully
+    pub fn succeeded(&self) -> bool {
+        self.exit_status.is_some_and(|status| status.success())
+    }
+
+    /// Check whether the child has exited, without blocking
+    pub fn try_wait(&mut self) -> ServerResult<bool> {
+        if self.exit_status.is_some() {
+            return Ok(true);
+        }
+
+        match self.child.try_wait() {
+            Ok(Some(status)) => {
+                self.exit_status = Some(status);
+                Ok(true)
+            }
+            Ok(None) => Ok(false),
+            Err(e) => Err(CgiErrorKind::Failed(format!("Error waiting for CGI process: {}", e)).into()),
+        }
+    }
+
+    /// Check if stdout is closed and the child has been reaped
+    pub fn is_finished(&self) -> bool {
+        self.stdout.is_none() && self.exit_status.is_some()
+    }
+
+    /// Check if stdout is closed but the child has not exited yet
+    pub fn is_awaiting_exit(&self) -> bool {
+        self.stdout.is_none() && self.exit_status.is_none()
+    }
+
+    /// Check if the script has exceeded its execution timeout
+    pub fn is_timed_out(&self) -> bool {
+        self.started_at.elapsed() > self.timeout
+    }
+
+    /// Check if the script is killed once a timeout passes; event streams are not
+    pub fn has_timeout(&self) -> bool {
+        self.timeout != Duration::MAX
+    }
+
+    /// Kill the child and reap it
+    pub fn kill(&mut self) {
+        if self.exit_status.is_none() {
+            let _ = self.child.kill();
+            self.exit_status = self.child.wait().ok();
+        }
+    }
+
+    /// Drive the process to completion, blocking the calling thread
+    pub fn run_to_completion(mut self) -> ServerResult<HttpResponse> {
+        while let Some(stdout_fd) = self.stdout_fd() {
+            let remaining = self.timeout.saturating_sub(self.started_at.elapsed());
+            if remaining.is_zero() {
+                self.kill();
+                return Err(CgiErrorKind::Timeout.into());
+            }
+
+            let mut fds = vec![libc::pollfd { fd: stdout_fd, events: libc::POLLIN, revents: 0 }];
+            if let Some(stdin_fd) = self.stdin_fd() {
+                fds.push(libc::pollfd { fd: stdin_fd, events: libc::POLLOUT, revents: 0 });
+            }
+
+            let timeout_ms = remaining.as_millis().min(i32::MAX as u128) as i32;
+            let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+            if result == -1 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(ServerError::Io(error));
+            }
+
+            if fds.len() > 1 && fds[1].revents != 0 {
+                // A failed write means the script stopped reading its input
+                if self.write_input().unwrap_or(true) {
+                    self.close_stdin();
+                }
+            }
+
+            if fds[0].revents != 0 && self.read_output()? {
+                self.close_stdout();
+            }
+        }
+
+        self.close_stdin();
+        let status = self.child.wait()
+            .map_err(|e| CgiErrorKind::Failed(format!("Failed to wait for CGI process: {}", e)))?;
+        self.exit_status = Some(status);
+
+        self.into_response()
+    }
+
+    /// Build the HTTP response from the collected output of a finished script
+    pub fn into_response(mut self) -> ServerResult<HttpResponse> {
+        match self.exit_status {
+            Some(status) if status.success() => {}
+            Some(status) => return Err(CgiErrorKind::Failed(format!("CGI script exited with {}", status)).into()),
+            None => return Err(CgiErrorKind::Failed("CGI script has not exited".to_string()).into()),
+        }
+
+        let output = std::mem::take(&mut self.output);
+        parse_cgi_output(&output)
+    }
+}
+
+impl Drop for CgiProcess {
+    fn drop(&mut self) {
+        // Never leave a running or zombie child behind
+        self.kill();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HttpStatus;
+    use std::process::{Command, Stdio};
+
+    fn spawn_sh(script: &str, input: Vec<u8>) -> CgiProcess {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        CgiProcess::new(child, input, Duration::from_secs(10), 4 * 1024 * 1024).unwrap()
+    }
+
+    #[test]
+    fn test_large_input_and_output_do_not_deadlock() {
+        // More than a pipe buffer in both directions at once
+        let input = vec![b'x'; 512 * 1024];
+        let process = spawn_sh("printf 'Content-Type: text/plain\\r\\n\\r\\n'; cat", input.clone());
+
+        let response = process.run_to_completion().unwrap();
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.body, input);
+    }
+
+    #[test]
+    fn test_timeout_kills_script() {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg("sleep 5")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let process = CgiProcess::new(child, Vec::new(), Duration::from_millis(100), 1024).unwrap();
+
+        let started = Instant::now();
+        assert!(matches!(process.run_to_completion(), Err(ServerError::Cgi(CgiErrorKind::Timeout))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_injected_headers_are_rejected() {
+        // A CR left in a reflected value would start a Set-Cookie line at the client
+        let process = spawn_sh("printf 'X-Echo: foo\\rSet-Cookie: evil=1\\r\\n\\r\\nbody'", Vec::new());
+        assert!(matches!(process.run_to_completion(), Err(ServerError::Cgi(_))));
+
+        let process = spawn_sh("printf 'Bad Name: x\\r\\n\\r\\nbody'", Vec::new());
+        assert!(matches!(process.run_to_completion(), Err(ServerError::Cgi(_))));
+
+        let process = spawn_sh("printf 'X-Echo: foo\\r\\n\\r\\nbody'", Vec::new());
+        let response = process.run_to_completion().unwrap();
+        assert_eq!(response.get_header("X-Echo"), Some(&"foo".to_string()));
+        assert!(response.get_header("Set-Cookie").is_none());
+    }
+
+    #[test]
+    fn test_failed_script_is_server_error() {
+        let process = spawn_sh("exit 3", Vec::new());
+        assert!(matches!(process.run_to_completion(), Err(ServerError::Cgi(CgiErrorKind::Failed(_)))));
+    }
+}
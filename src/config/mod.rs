@@ -16,34 +16,108 @@ use std::path::Path;
 impl Config {
     /// Load configuration from a file
     pub fn from_file<P: AsRef<Path>>(path: P) -> ServerResult<Self> {
-        let content = std::fs::read_to_string(path.as_ref())
-            .map_err(|e| ServerError::Config(format!("Failed to read config file: {}", e)))?;
-        
-        parser::parse_config(&content)
+        parser::parse_config_from_path(path.as_ref())
+    }
+
+    /// Canonicalize every route root once, failing on roots that are missing
+    /// or not directories, so requests need not resolve them again.
+    /// Redirect routes never serve files, so their roots are left alone.
+    pub fn resolve_roots(&mut self) -> ServerResult<()> {
+        for route in self.servers.iter_mut().flat_map(|server| server.routes.iter_mut()) {
+            let root = match &route.root {
+                Some(root) if route.redirect.is_none() => root,
+                _ => continue,
+            };
+            let canonical = std::fs::canonicalize(root).map_err(|e| ServerError::Config(format!(
+                "Root directory {} of route {}: {}", root, route.path, e
+            )))?;
+            if !canonical.is_dir() {
+                return Err(ServerError::Config(format!(
+                    "Root directory {} of route {} is not a directory", root, route.path
+                )));
+            }
+            route.canonical_root = Some(canonical);
+        }
+        Ok(())
     }
 
     /// Validate the configuration
     pub fn validate(&self) -> ServerResult<()> {
-        // Check for duplicate ports
-        let mut used_ports = std::collections::HashSet::new();
-        
+        // Route paths the router could never match
+        crate::routing::router::validate_servers(&self.servers)?;
+
+        // Servers may share a port when the ones after the first have names to be found by
+        let mut used_ports: std::collections::HashMap<(String, u16), &ServerConfig> = std::collections::HashMap::new();
+
         for server in &self.servers {
             for port in &server.ports {
                 let key = (server.host.clone(), *port);
-                if used_ports.contains(&key) {
-                    return Err(ServerError::Config(format!(
-                        "Duplicate port {} for host {}", port, server.host
-                    )));
+                if let Some(first) = used_ports.get(&key) {
+                    if server.server_names.is_empty() {
+                        return Err(ServerError::Config(format!(
+                            "Duplicate port {} for host {}", port, server.host
+                        )));
+                    }
+                    if server.ssl != first.ssl {
+                        return Err(ServerError::Config(format!(
+                            "Servers sharing port {} for host {} must all have ssl on or all off", port, server.host
+                        )));
+                    }
+                }
+                used_ports.entry(key).or_insert(server);
+            }
+        }
+
+        // Check for duplicate Unix socket paths
+        let mut used_paths = std::collections::HashSet::new();
+        for server in &self.servers {
+            for path in &server.unix_sockets {
+                if !used_paths.insert(path) {
+                    return Err(ServerError::Config(format!("Duplicate unix socket {}", path)));
                 }
-                used_ports.insert(key);
             }
         }
 
         // Validate routes
         for server in &self.servers {
+            if !(1..=65535).contains(&server.listen_backlog) {
+                return Err(ServerError::Config(format!(
+                    "listen_backlog must be between 1 and 65535, got {}", server.listen_backlog
+                )));
+            }
+            if server.max_connections == 0 {
+                return Err(ServerError::Config("max_connections must be positive".to_string()));
+            }
+            if server.max_requests_per_connection == 0 {
+                return Err(ServerError::Config("max_requests_per_connection must be positive".to_string()));
+            }
+            if server.header_timeout == 0 {
+                return Err(ServerError::Config("header_timeout must be positive".to_string()));
+            }
+            if server.ssl && (server.ssl_cert.is_none() || server.ssl_key.is_none()) {
+                return Err(ServerError::Config("ssl on requires ssl_cert and ssl_key".to_string()));
+            }
+            if server.cgi_workers > crate::defaults::MAX_CGI_WORKERS {
+                return Err(ServerError::Config(format!(
+                    "cgi_workers must be at most {}, got {}", crate::defaults::MAX_CGI_WORKERS, server.cgi_workers
+                )));
+            }
             for route in &server.routes {
-                if route.path.is_empty() {
-                    return Err(ServerError::Config("Route path cannot be empty".to_string()));
+                let segments: Vec<&str> = route.path.split('/').collect();
+                if segments.iter().rev().skip(1).any(|segment| *segment == "*") {
+                    return Err(ServerError::Config(format!(
+                        "Wildcard must be the last segment of route {}", route.path
+                    )));
+                }
+                if segments.contains(&":") {
+                    return Err(ServerError::Config(format!(
+                        "Route parameter without a name in {}", route.path
+                    )));
+                }
+                if route.request_timeout == 0 || route.cgi_timeout == 0 || route.cgi_cache_ttl == 0 {
+                    return Err(ServerError::Config(format!(
+                        "Timeouts for route {} must be positive", route.path
+                    )));
                 }
             }
         }
@@ -3,14 +3,150 @@
  */
 
 use crate::config::types::*;
-use crate::error::{ServerError, ServerResult};
+use crate::error::{HttpStatus, ServerError, ServerResult};
+use crate::utils::IpNetwork;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
-/// Parse configuration from string content
+/// How deeply `include` directives may nest
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Headers the server sets itself to frame and manage the connection
+const PROTECTED_HEADERS: &[&str] = &[
+    "Content-Length", "Transfer-Encoding", "Connection", "Keep-Alive", "Upgrade", "Trailer", "Date",
+];
+
+/// Files currently being parsed, used to resolve and bound `include` directives
+struct IncludeContext {
+    base_dir: PathBuf,
+    active: HashSet<PathBuf>,
+    depth: usize,
+}
+
+impl IncludeContext {
+    fn new(base_dir: PathBuf) -> Self {
+        Self {
+            base_dir,
+            active: HashSet::new(),
+            depth: 0,
+        }
+    }
+
+    /// Read every file matching an include pattern and parse each with `parse`,
+    /// relative to the including file's directory
+    fn include<F>(&mut self, pattern: &str, mut parse: F) -> ServerResult<()>
+    where
+        F: FnMut(&str, &mut IncludeContext) -> ServerResult<()>,
+    {
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(ServerError::Config(format!("include nested deeper than {} levels: {}", MAX_INCLUDE_DEPTH, pattern)));
+        }
+
+        for path in expand_include(&self.base_dir.join(pattern))? {
+            let path = path.canonicalize()
+                .map_err(|e| ServerError::Config(format!("Failed to include {}: {}", path.display(), e)))?;
+            if !self.active.insert(path.clone()) {
+                return Err(ServerError::Config(format!("include cycle at {}", path.display())));
+            }
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| ServerError::Config(format!("Failed to include {}: {}", path.display(), e)))?;
+
+            let parent_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            let base_dir = std::mem::replace(&mut self.base_dir, parent_dir);
+            self.depth += 1;
+            let result = parse(&content, self);
+            self.depth -= 1;
+            self.base_dir = base_dir;
+            self.active.remove(&path);
+            result?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Expand `*` and `?` in the file name of an include path; directories are taken literally
+fn expand_include(pattern: &Path) -> ServerResult<Vec<PathBuf>> {
+    let file_pattern = pattern.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    if !file_pattern.contains(['*', '?']) {
+        return Ok(vec![pattern.to_path_buf()]);
+    }
+
+    let dir = pattern.parent().unwrap_or_else(|| Path::new("."));
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| ServerError::Config(format!("Failed to include {}: {}", pattern.display(), e)))?;
+
+    // A pattern matching nothing is not an error, so an empty sites directory is fine
+    let mut matches: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|name| wildcard_match(file_pattern, name)))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    matches.sort();
+    Ok(matches)
+}
+
+/// Match a file name against a pattern of literal characters, `*` and `?`
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Parse configuration from string content; includes resolve against the working directory
 pub fn parse_config(content: &str) -> ServerResult<Config> {
+    parse_config_with(content, &mut IncludeContext::new(PathBuf::from(".")))
+}
+
+/// Parse a configuration file, resolving includes relative to its directory
+pub fn parse_config_from_path(path: &Path) -> ServerResult<Config> {
+    let path = path.canonicalize()
+        .map_err(|e| ServerError::Config(format!("Failed to read config file: {}", e)))?;
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| ServerError::Config(format!("Failed to read config file: {}", e)))?;
+
+    let mut context = IncludeContext::new(path.parent().map(Path::to_path_buf).unwrap_or_default());
+    context.active.insert(path);
+    parse_config_with(&content, &mut context)
+}
+
+fn parse_config_with(content: &str, context: &mut IncludeContext) -> ServerResult<Config> {
     let mut config = Config {
         servers: Vec::new(),
     };
+    parse_top_level(content, &mut config, context)?;
+
+    // If no servers defined, use default
+    if config.servers.is_empty() {
+        config.servers.push(ServerConfig::default());
+    }
+
+    Ok(config)
+}
 
+/// Parse server blocks and top-level includes into `config`
+fn parse_top_level(content: &str, config: &mut Config, context: &mut IncludeContext) -> ServerResult<()> {
     let lines: Vec<&str> = content.lines().collect();
     let mut i = 0;
 
@@ -24,31 +160,52 @@ pub fn parse_config(content: &str) -> ServerResult<Config> {
         }
 
         if line.starts_with("server") {
-            let (server, consumed) = parse_server_block(&lines[i..])?;
+            let (server, consumed) = parse_server_block(&lines[i..], context, config.servers.is_empty())?;
             config.servers.push(server);
             i += consumed;
+        } else if let Some(pattern) = line.strip_prefix("include ") {
+            context.include(pattern.trim(), |content, context| parse_top_level(content, config, context))?;
+            i += 1;
         } else {
             i += 1;
         }
     }
 
-    // If no servers defined, use default
-    if config.servers.is_empty() {
-        config.servers.push(ServerConfig::default());
-    }
-
-    Ok(config)
+    Ok(())
 }
 
-fn parse_server_block(lines: &[&str]) -> ServerResult<(ServerConfig, usize)> {
+/// Parse one server block; `first` marks the block whose process-wide
+/// settings apply to every server
+fn parse_server_block(lines: &[&str], context: &mut IncludeContext, first: bool) -> ServerResult<(ServerConfig, usize)> {
     let mut server = ServerConfig::default();
-    let mut i = 1; // Skip "server {" line
+    let mut routes = Vec::new();
 
     // Find opening brace
     if !lines[0].contains('{') {
         return Err(ServerError::Config("Expected '{' after server".to_string()));
     }
 
+    // Skip "server {" line
+    let consumed = parse_server_directives(&lines[1..], &mut server, &mut routes, context, first)?;
+
+    // Configured routes replace the default catch-all route
+    if !routes.is_empty() {
+        server.routes = routes;
+    }
+
+    Ok((server, consumed + 1))
+}
+
+/// Parse server directives and route blocks up to and including the closing brace
+fn parse_server_directives(
+    lines: &[&str],
+    server: &mut ServerConfig,
+    routes: &mut Vec<RouteConfig>,
+    context: &mut IncludeContext,
+    first: bool,
+) -> ServerResult<usize> {
+    let mut i = 0;
+
     while i < lines.len() {
         let line = lines[i].trim();
         
@@ -75,10 +232,18 @@ fn parse_server_block(lines: &[&str]) -> ServerResult<(ServerConfig, usize)> {
                 }
                 server.host = parts[1].to_string();
             }
-            "port" => {
+            "port" | "listen" => {
                 if parts.len() < 2 {
                     return Err(ServerError::Config("port requires a value".to_string()));
                 }
+                if let Some(path) = parts[1].strip_prefix("unix:") {
+                    if path.is_empty() {
+                        return Err(ServerError::Config("unix listener requires a socket path".to_string()));
+                    }
+                    server.unix_sockets.push(path.to_string());
+                    i += 1;
+                    continue;
+                }
                 let port: u16 = parts[1].parse()
                     .map_err(|_| ServerError::Config(format!("Invalid port: {}", parts[1])))?;
                 server.ports.push(port);
@@ -87,7 +252,49 @@ fn parse_server_block(lines: &[&str]) -> ServerResult<(ServerConfig, usize)> {
                 if parts.len() < 2 {
                     return Err(ServerError::Config("server_name requires a value".to_string()));
                 }
-                server.server_name = Some(parts[1].to_string());
+                server.server_names.extend(parts[1..].iter().map(|name| name.to_string()));
+            }
+            "trusted_proxy" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("trusted_proxy requires an address or CIDR range".to_string()));
+                }
+                for value in &parts[1..] {
+                    let network = IpNetwork::parse(value)
+                        .ok_or_else(|| ServerError::Config(format!("Invalid trusted_proxy: {}", value)))?;
+                    server.trusted_proxies.push(network);
+                }
+            }
+            "rate_limit" => {
+                if parts.len() != 4 || parts[2] != "per" {
+                    return Err(ServerError::Config("rate_limit requires '<requests> per <seconds>'".to_string()));
+                }
+                let requests: u32 = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid rate_limit requests: {}", parts[1])))?;
+                let period: u64 = parts[3].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid rate_limit period: {}", parts[3])))?;
+                if requests == 0 || period == 0 {
+                    return Err(ServerError::Config("rate_limit requests and period must be positive".to_string()));
+                }
+                server.rate_limit = Some(RateLimit { requests, period });
+            }
+            "mime_type" => {
+                if parts.len() < 3 {
+                    return Err(ServerError::Config("mime_type requires an extension and a type".to_string()));
+                }
+                let extension = parts[1].trim_start_matches('.').to_lowercase();
+                if extension.is_empty() || !parts[2].contains('/') {
+                    return Err(ServerError::Config(format!("Invalid mime_type: {}", parts[1..].join(" "))));
+                }
+                server.mime_types.insert(extension, parts[2..].join(" "));
+            }
+            "status_endpoint" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("status_endpoint requires a path".to_string()));
+                }
+                if !parts[1].starts_with('/') {
+                    return Err(ServerError::Config(format!("status_endpoint must start with '/': {}", parts[1])));
+                }
+                server.status_endpoint = Some(parts[1].to_string());
             }
             "error_page" => {
                 if parts.len() < 3 {
@@ -97,6 +304,18 @@ fn parse_server_block(lines: &[&str]) -> ServerResult<(ServerConfig, usize)> {
                     .map_err(|_| ServerError::Config(format!("Invalid status code: {}", parts[1])))?;
                 server.error_pages.insert(status, parts[2].to_string());
             }
+            "error_pages_root" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("error_pages_root requires a directory".to_string()));
+                }
+                server.error_pages_root = Some(parts[1].to_string());
+            }
+            "default_charset" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("default_charset requires a charset".to_string()));
+                }
+                server.default_charset = parse_charset("default_charset", parts[1])?;
+            }
             "max_body_size" => {
                 if parts.len() < 2 {
                     return Err(ServerError::Config("max_body_size requires a value".to_string()));
@@ -104,11 +323,149 @@ fn parse_server_block(lines: &[&str]) -> ServerResult<(ServerConfig, usize)> {
                 server.max_body_size = parts[1].parse()
                     .map_err(|_| ServerError::Config(format!("Invalid max_body_size: {}", parts[1])))?;
             }
+            "max_requests_per_connection" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("max_requests_per_connection requires a value".to_string()));
+                }
+                server.max_requests_per_connection = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid max_requests_per_connection: {}", parts[1])))?;
+            }
+            "max_connections" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("max_connections requires a value".to_string()));
+                }
+                // One limit covers every listener, so only the first server sets it
+                if !first {
+                    return Err(ServerError::Config(
+                        "max_connections applies to the whole server and may only be set in the first server block".to_string()
+                    ));
+                }
+                server.max_connections = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid max_connections: {}", parts[1])))?;
+            }
+            "cgi_workers" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cgi_workers requires a value".to_string()));
+                }
+                server.cgi_workers = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid cgi_workers: {}", parts[1])))?;
+            }
+            "max_cgi_processes" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("max_cgi_processes requires a value".to_string()));
+                }
+                server.max_cgi_processes = parts[1].parse().ok().filter(|&max| max > 0)
+                    .ok_or_else(|| ServerError::Config(format!("Invalid max_cgi_processes: {}", parts[1])))?;
+            }
+            "listen_backlog" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("listen_backlog requires a value".to_string()));
+                }
+                server.listen_backlog = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid listen_backlog: {}", parts[1])))?;
+            }
+            "keep_alive" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("keep_alive requires on/off".to_string()));
+                }
+                server.keep_alive_enabled = parts[1] == "on";
+            }
+            "method_override" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("method_override requires on/off".to_string()));
+                }
+                server.method_override = parts[1] == "on";
+            }
+            "strict_host" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("strict_host requires on/off".to_string()));
+                }
+                server.strict_host = parts[1] == "on";
+            }
+            "ssl" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("ssl requires on/off".to_string()));
+                }
+                server.ssl = parts[1] == "on";
+            }
+            "ssl_cert" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("ssl_cert requires a file".to_string()));
+                }
+                server.ssl_cert = Some(parts[1].to_string());
+            }
+            "ssl_key" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("ssl_key requires a file".to_string()));
+                }
+                server.ssl_key = Some(parts[1].to_string());
+            }
+            "reuse_port" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("reuse_port requires on/off".to_string()));
+                }
+                server.reuse_port = parts[1] == "on";
+            }
+            "tcp_nodelay" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("tcp_nodelay requires on/off".to_string()));
+                }
+                server.tcp_nodelay = parts[1] == "on";
+            }
+            "header_timeout" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("header_timeout requires a value".to_string()));
+                }
+                server.header_timeout = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid header_timeout: {}", parts[1])))?;
+            }
+            "client_max_header_line" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("client_max_header_line requires a value".to_string()));
+                }
+                server.client_max_header_line = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid client_max_header_line: {}", parts[1])))?;
+            }
+            "merge_folded_headers" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("merge_folded_headers requires on/off".to_string()));
+                }
+                server.merge_folded_headers = parts[1] == "on";
+            }
+            "access_log" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("access_log requires a path, stdout or off".to_string()));
+                }
+                server.access_log = match parts[1] {
+                    "off" => AccessLog::Off,
+                    "stdout" => AccessLog::Stdout,
+                    path => AccessLog::File(path.to_string()),
+                };
+            }
+            "epoll_mode" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("epoll_mode requires edge or level".to_string()));
+                }
+                server.epoll_mode = match parts[1] {
+                    "edge" => EpollMode::Edge,
+                    "level" => EpollMode::Level,
+                    other => return Err(ServerError::Config(format!("Invalid epoll_mode: {}", other))),
+                };
+            }
             "route" => {
                 let (route, consumed) = parse_route_block(&lines[i..])?;
-                server.routes.push(route);
+                routes.push(route);
                 i += consumed - 1; // -1 because we'll increment at the end of the loop
             }
+            "include" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("include requires a path".to_string()));
+                }
+                context.include(parts[1], |content, context| {
+                    let lines: Vec<&str> = content.lines().collect();
+                    parse_server_directives(&lines, server, routes, context, first).map(|_| ())
+                })?;
+            }
             _ => {
                 return Err(ServerError::Config(format!("Unknown directive: {}", parts[0])));
             }
@@ -117,7 +474,34 @@ fn parse_server_block(lines: &[&str]) -> ServerResult<(ServerConfig, usize)> {
         i += 1;
     }
 
-    Ok((server, i))
+    Ok(i)
+}
+
+/// Collapse runs of slashes in a route path
+fn normalize_route_path(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c == '/' && normalized.ends_with('/') {
+            continue;
+        }
+        normalized.push(c);
+    }
+    normalized
+}
+
+/// Check that a charset name is a bare token that can follow `charset=`
+fn parse_charset(directive: &str, value: &str) -> ServerResult<String> {
+    let valid = value.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:+".contains(&b));
+    if !valid {
+        return Err(ServerError::Config(format!("Invalid {}: {}", directive, value)));
+    }
+    Ok(value.to_ascii_lowercase())
+}
+
+/// Check if a `cgi` argument is a file extension such as `.py` rather than
+/// an interpreter, which may itself start with `.` as in `./bin/python3`
+fn is_extension(value: &str) -> bool {
+    value.len() > 1 && value.starts_with('.') && !value.contains('/')
 }
 
 fn parse_route_block(lines: &[&str]) -> ServerResult<(RouteConfig, usize)> {
@@ -130,7 +514,7 @@ fn parse_route_block(lines: &[&str]) -> ServerResult<(RouteConfig, usize)> {
     if parts.len() < 2 {
         return Err(ServerError::Config("route requires a path".to_string()));
     }
-    route.path = parts[1].to_string();
+    route.path = normalize_route_path(parts[1]);
 
     if !first_line.contains('{') {
         return Err(ServerError::Config("Expected '{' after route path".to_string()));
@@ -165,7 +549,21 @@ fn parse_route_block(lines: &[&str]) -> ServerResult<(RouteConfig, usize)> {
                 if parts.len() < 2 {
                     return Err(ServerError::Config("redirect requires a URL".to_string()));
                 }
-                route.redirect = Some(parts[1].to_string());
+                // An optional status code comes before the target
+                route.redirect = Some(if parts.len() >= 3 {
+                    let code = parts[1].parse().ok()
+                        .filter(|&code| HttpStatus::from_redirect_code(code).is_some())
+                        .ok_or_else(|| ServerError::Config(format!("Invalid redirect status: {} (expected 301, 302, 307 or 308)", parts[1])))?;
+                    (code, parts[2].to_string())
+                } else {
+                    (HttpStatus::Found.as_u16(), parts[1].to_string())
+                });
+            }
+            "directory_redirect" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("directory_redirect requires a URL".to_string()));
+                }
+                route.directory_redirect = Some(parts[1].to_string());
             }
             "root" => {
                 if parts.len() < 2 {
@@ -177,13 +575,27 @@ fn parse_route_block(lines: &[&str]) -> ServerResult<(RouteConfig, usize)> {
                 if parts.len() < 2 {
                     return Err(ServerError::Config("index requires a filename".to_string()));
                 }
-                route.index = Some(parts[1].to_string());
+                route.index = parts[1..].iter().map(|name| name.to_string()).collect();
+            }
+            "try_files" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("try_files requires at least one path".to_string()));
+                }
+                route.try_files = parts[1..].iter().map(|path| path.to_string()).collect();
             }
             "cgi" => {
                 if parts.len() < 2 {
                     return Err(ServerError::Config("cgi requires an interpreter".to_string()));
                 }
-                route.cgi = Some(parts[1].to_string());
+                // `cgi .php /usr/bin/php-cgi` maps one extension; `cgi python3` covers the rest
+                if is_extension(parts[1]) {
+                    if parts.len() < 3 {
+                        return Err(ServerError::Config(format!("cgi {} requires an interpreter", parts[1])));
+                    }
+                    route.cgi_interpreters.insert(parts[1].to_string(), parts[2].to_string());
+                } else {
+                    route.cgi = Some(parts[1].to_string());
+                }
             }
             "directory_listing" => {
                 if parts.len() < 2 {
@@ -191,12 +603,141 @@ fn parse_route_block(lines: &[&str]) -> ServerResult<(RouteConfig, usize)> {
                 }
                 route.directory_listing = parts[1] == "on";
             }
+            "markdown_index" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("markdown_index requires on/off".to_string()));
+                }
+                route.markdown_index = parts[1] == "on";
+            }
             "upload_enabled" => {
                 if parts.len() < 2 {
                     return Err(ServerError::Config("upload_enabled requires on/off".to_string()));
                 }
                 route.upload_enabled = parts[1] == "on";
             }
+            "auth_basic" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("auth_basic requires a realm or off".to_string()));
+                }
+                let realm = parts[1..].join(" ");
+                let realm = realm.strip_prefix('"').and_then(|r| r.strip_suffix('"')).unwrap_or(&realm);
+                // The realm is sent back as a quoted string
+                if realm.contains(['"', '\\']) {
+                    return Err(ServerError::Config(format!("Invalid auth_basic realm: {}", realm)));
+                }
+                route.auth_basic = (parts[1..] != ["off"]).then(|| realm.to_string());
+            }
+            "auth_user_file" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("auth_user_file requires a path".to_string()));
+                }
+                route.auth_user_file = Some(parts[1].to_string());
+            }
+            "allow" | "deny" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config(format!("{} requires an address, CIDR range or 'all'", parts[0])));
+                }
+                for value in &parts[1..] {
+                    let network = match *value {
+                        "all" => None,
+                        value => Some(IpNetwork::parse(value)
+                            .ok_or_else(|| ServerError::Config(format!("Invalid {}: {}", parts[0], value)))?),
+                    };
+                    route.access_rules.push(AccessRule { allow: parts[0] == "allow", network });
+                }
+            }
+            "websocket" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("websocket requires on/off or a handler name".to_string()));
+                }
+                route.websocket = match parts[1] {
+                    "on" => Some(crate::ws::ECHO_HANDLER.to_string()),
+                    "off" => None,
+                    handler => Some(handler.to_string()),
+                };
+            }
+            "upload_to_tempfile" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("upload_to_tempfile requires on/off".to_string()));
+                }
+                route.upload_to_tempfile = parts[1] == "on";
+            }
+            "request_timeout" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("request_timeout requires a value".to_string()));
+                }
+                route.request_timeout = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid request_timeout: {}", parts[1])))?;
+            }
+            "cgi_timeout" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cgi_timeout requires a value".to_string()));
+                }
+                route.cgi_timeout = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid cgi_timeout: {}", parts[1])))?;
+            }
+            "charset" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("charset requires a charset".to_string()));
+                }
+                route.charset = Some(parse_charset("charset", parts[1])?);
+            }
+            "event_stream" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("event_stream requires on/off".to_string()));
+                }
+                route.event_stream = parts[1] == "on";
+            }
+            "cgi_cache" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cgi_cache requires on/off".to_string()));
+                }
+                route.cgi_cache = parts[1] == "on";
+            }
+            "cgi_cache_ttl" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cgi_cache_ttl requires a value".to_string()));
+                }
+                route.cgi_cache_ttl = parts[1].parse()
+                    .map_err(|_| ServerError::Config(format!("Invalid cgi_cache_ttl: {}", parts[1])))?;
+            }
+            "cors_allow_origin" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cors_allow_origin requires an origin or *".to_string()));
+                }
+                route.cors.allow_origins.extend(parts[1..].iter().map(|origin| origin.to_string()));
+            }
+            "cors_allow_methods" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cors_allow_methods requires a method".to_string()));
+                }
+                route.cors.allow_methods.extend(parts[1..].iter().map(|method| method.to_uppercase()));
+            }
+            "cors_allow_headers" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cors_allow_headers requires a header name".to_string()));
+                }
+                route.cors.allow_headers.extend(parts[1..].iter().map(|name| name.to_string()));
+            }
+            "cors_allow_credentials" => {
+                if parts.len() < 2 {
+                    return Err(ServerError::Config("cors_allow_credentials requires on/off".to_string()));
+                }
+                route.cors.allow_credentials = parts[1] == "on";
+            }
+            "header" => {
+                if parts.len() < 3 {
+                    return Err(ServerError::Config("header requires a name and a value".to_string()));
+                }
+                let name = parts[1];
+                if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)) {
+                    return Err(ServerError::Config(format!("Invalid header name: {}", name)));
+                }
+                if PROTECTED_HEADERS.iter().any(|protected| protected.eq_ignore_ascii_case(name)) {
+                    return Err(ServerError::Config(format!("header cannot set {}, which the server manages", name)));
+                }
+                route.headers.push((name.to_string(), parts[2..].join(" ")));
+            }
             _ => {
                 return Err(ServerError::Config(format!("Unknown route directive: {}", parts[0])));
             }
@@ -205,5 +746,9 @@ fn parse_route_block(lines: &[&str]) -> ServerResult<(RouteConfig, usize)> {
         i += 1;
     }
 
+    if route.auth_basic.is_some() && route.auth_user_file.is_none() {
+        return Err(ServerError::Config(format!("auth_basic on route {} requires auth_user_file", route.path)));
+    }
+
     Ok((route, i))
 }
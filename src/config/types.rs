@@ -2,7 +2,10 @@
  * Configuration data structures
  */
 
+use crate::utils::IpNetwork;
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 /// Main configuration structure
 #[derive(Debug, Clone)]
@@ -15,23 +18,224 @@ pub struct Config {
 pub struct ServerConfig {
     pub host: String,
     pub ports: Vec<u16>,
-    pub server_name: Option<String>,
+    pub unix_sockets: Vec<String>,
+    /// Host names this server answers for; `*.` prefixes match any subdomain
+    pub server_names: Vec<String>,
     pub error_pages: HashMap<u16, String>,
+    /// Directory of `<code>.html` pages used for statuses without an `error_page`
+    pub error_pages_root: Option<String>,
+    /// Charset appended to text content types
+    pub default_charset: String,
     pub max_body_size: usize,
+    pub max_requests_per_connection: usize,
+    /// Connections open at once across every server; clients beyond it are
+    /// answered with 503. Only the first server block may set it
+    pub max_connections: usize,
+    /// Whether connections may carry more than one request
+    pub keep_alive_enabled: bool,
+    /// Whether POST requests may name the method to handle them as
+    pub method_override: bool,
+    /// Whether HTTP/1.1 requests without exactly one Host header are rejected
+    pub strict_host: bool,
+    pub epoll_mode: EpollMode,
+    pub access_log: AccessLog,
+    pub listen_backlog: u32,
+    pub reuse_port: bool,
+    /// Whether TCP_NODELAY is set, so small responses are not held back by Nagle's algorithm
+    pub tcp_nodelay: bool,
+    /// Whether connections are encrypted with TLS
+    pub ssl: bool,
+    /// PEM certificate chain presented to TLS clients
+    pub ssl_cert: Option<String>,
+    /// PEM private key of `ssl_cert`
+    pub ssl_key: Option<String>,
+    pub header_timeout: u64,
+    /// Longest header line accepted, in bytes; longer lines get 431
+    pub client_max_header_line: usize,
+    /// Whether folded header lines are joined to the previous header instead of rejected with 400
+    pub merge_folded_headers: bool,
+    /// Path answered with live server statistics instead of being routed
+    pub status_endpoint: Option<String>,
+    /// Proxies whose `X-Forwarded-For` header names the real client
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// Requests each client address may make, refilled over a period
+    pub rate_limit: Option<RateLimit>,
+    /// Content types by lowercase file extension, added to or replacing the built-in ones
+    pub mime_types: HashMap<String, String>,
+    /// Threads that run CGI scripts off the event loop; 0 lets the event loop drive them
+    pub cgi_workers: usize,
+    /// CGI scripts running at once; requests beyond it get 503
+    pub max_cgi_processes: usize,
     pub routes: Vec<RouteConfig>,
 }
 
+/// Identifies a server block across configuration reloads: where it listens
+/// and the names it answers to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ServerKey {
+    host: String,
+    ports: Vec<u16>,
+    unix_sockets: Vec<String>,
+    server_names: Vec<String>,
+}
+
+impl ServerConfig {
+    /// Key of this server block, the same for its counterpart in a reloaded configuration
+    pub fn key(&self) -> ServerKey {
+        ServerKey {
+            host: self.host.clone(),
+            ports: self.ports.clone(),
+            unix_sockets: self.unix_sockets.clone(),
+            server_names: self.server_names.clone(),
+        }
+    }
+}
+
+/// Request budget of `requests` per client address every `period` seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period: u64,
+}
+
+/// Where completed requests are logged
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum AccessLog {
+    /// No access logging
+    Off,
+    /// Log lines go to standard output
+    #[default]
+    Stdout,
+    /// Log lines are appended to a file
+    File(String),
+}
+
+/// How the event loop registers file descriptors with epoll
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EpollMode {
+    /// Re-notified while an fd stays ready
+    #[default]
+    Level,
+    /// Notified once per readiness change; reads and writes drain until EAGAIN
+    Edge,
+}
+
 /// Route configuration
 #[derive(Debug, Clone)]
 pub struct RouteConfig {
     pub path: String,
     pub methods: Vec<String>,
-    pub redirect: Option<String>,
+    /// Redirect status code and target
+    pub redirect: Option<(u16, String)>,
     pub root: Option<String>,
-    pub index: Option<String>,
+    /// Canonical path of `root`, filled in by `Config::resolve_roots`
+    pub canonical_root: Option<PathBuf>,
+    /// Index file names tried in order when a directory is requested
+    pub index: Vec<String>,
+    /// Paths tried in order for GET requests, with `$uri` standing for the request path
+    pub try_files: Vec<String>,
+    /// Interpreter for every script on the route without an extension-specific one
     pub cgi: Option<String>,
+    /// Interpreters by script extension, such as `.php`, taking precedence over `cgi`
+    pub cgi_interpreters: HashMap<String, String>,
     pub directory_listing: bool,
+    /// Whether a directory's README.md is rendered as its index when it has no index file
+    pub markdown_index: bool,
+    /// Where clients asking for a directory with no index file are sent when listing is off
+    pub directory_redirect: Option<String>,
     pub upload_enabled: bool,
+    /// Whether large upload bodies are written to a temporary file as they arrive
+    pub upload_to_tempfile: bool,
+    /// Seconds a connection on this route may stay idle before it is dropped
+    pub request_timeout: u64,
+    /// Seconds a CGI script on this route may run
+    pub cgi_timeout: u64,
+    /// Whether script responses are cached and reused for identical requests
+    pub cgi_cache: bool,
+    /// Seconds a cached script response stays fresh
+    pub cgi_cache_ttl: u64,
+    /// Whether script responses are `text/event-stream` pushes, streamed for as long as the script runs
+    pub event_stream: bool,
+    /// Charset for text files on this route, replacing the server's `default_charset`
+    pub charset: Option<String>,
+    /// Extra headers added to every response for this route
+    pub headers: Vec<(String, String)>,
+    /// Cross-origin access to this route
+    pub cors: CorsConfig,
+    /// Name of the handler for connections upgraded to WebSocket, if the route accepts them
+    pub websocket: Option<String>,
+    /// Realm of the route's Basic authentication, if it requires credentials
+    pub auth_basic: Option<String>,
+    /// File of `name:{SSHA}hash` lines that credentials are checked against
+    pub auth_user_file: Option<String>,
+    /// `allow` and `deny` rules in the order written; the first that matches the client decides
+    pub access_rules: Vec<AccessRule>,
+}
+
+impl RouteConfig {
+    /// Interpreter for a script path: the one mapped to its extension, or
+    /// else the route's `cgi`. None means the file is not a script.
+    pub fn cgi_interpreter(&self, path: &str) -> Option<&str> {
+        Path::new(path).extension()
+            .and_then(|extension| self.cgi_interpreters.get(&format!(".{}", extension.to_string_lossy())))
+            .or(self.cgi.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// One `allow` or `deny` line of a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessRule {
+    pub allow: bool,
+    /// Addresses the rule covers, or None for `all`
+    pub network: Option<IpNetwork>,
+}
+
+impl AccessRule {
+    /// Check if the rule covers a client; an unknown address is only covered by `all`
+    pub fn matches(&self, ip: Option<IpAddr>) -> bool {
+        match (self.network, ip) {
+            (None, _) => true,
+            (Some(network), Some(ip)) => network.contains(ip),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Check if a client may use a route: the first matching rule decides, and
+/// a client no rule matches is allowed
+pub fn is_access_allowed(rules: &[AccessRule], ip: Option<IpAddr>) -> bool {
+    rules.iter().find(|rule| rule.matches(ip)).is_none_or(|rule| rule.allow)
+}
+
+/// Cross-origin resource sharing settings of a route
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Origins allowed to make requests, or `*` for any; empty disables CORS
+    pub allow_origins: Vec<String>,
+    /// Methods allowed in preflights; empty means the route's methods
+    pub allow_methods: Vec<String>,
+    /// Request headers allowed in preflights, or `*` for any
+    pub allow_headers: Vec<String>,
+    /// Whether cookies and credentials may be sent cross-origin
+    pub allow_credentials: bool,
+}
+
+impl CorsConfig {
+    /// Check if CORS is configured for the route
+    pub fn is_enabled(&self) -> bool {
+        !self.allow_origins.is_empty()
+    }
+
+    /// Check if an origin may access the route
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allow_origins.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Check if a preflight may ask to send a request header
+    pub fn allows_header(&self, name: &str) -> bool {
+        self.allow_headers.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(name))
+    }
 }
 
 impl Default for Config {
@@ -47,9 +251,34 @@ impl Default for ServerConfig {
         Self {
             host: crate::defaults::DEFAULT_HOST.to_string(),
             ports: Vec::new(), // Start with empty ports, they'll be added by config parser
-            server_name: None,
+            unix_sockets: Vec::new(),
+            server_names: Vec::new(),
             error_pages: HashMap::new(),
+            error_pages_root: None,
+            default_charset: crate::utils::mime::DEFAULT_CHARSET.to_string(),
             max_body_size: crate::defaults::DEFAULT_MAX_BODY_SIZE,
+            max_requests_per_connection: crate::defaults::DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            max_connections: crate::defaults::MAX_CONNECTIONS,
+            keep_alive_enabled: true,
+            method_override: false,
+            strict_host: false,
+            epoll_mode: EpollMode::default(),
+            access_log: AccessLog::default(),
+            listen_backlog: crate::defaults::DEFAULT_LISTEN_BACKLOG,
+            reuse_port: false,
+            tcp_nodelay: true,
+            ssl: false,
+            ssl_cert: None,
+            ssl_key: None,
+            header_timeout: crate::defaults::DEFAULT_HEADER_TIMEOUT,
+            client_max_header_line: crate::http::request::DEFAULT_MAX_HEADER_LINE_BYTES,
+            merge_folded_headers: false,
+            status_endpoint: None,
+            trusted_proxies: Vec::new(),
+            rate_limit: None,
+            mime_types: HashMap::new(),
+            cgi_workers: 0,
+            max_cgi_processes: crate::defaults::DEFAULT_MAX_CGI_PROCESSES,
             routes: vec![RouteConfig::default()],
         }
     }
@@ -62,10 +291,28 @@ impl Default for RouteConfig {
             methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
             redirect: None,
             root: Some("www".to_string()),
-            index: Some("index.html".to_string()),
+            canonical_root: None,
+            index: vec!["index.html".to_string()],
+            try_files: Vec::new(),
             cgi: None,
+            cgi_interpreters: HashMap::new(),
             directory_listing: false,
+            markdown_index: false,
+            directory_redirect: None,
             upload_enabled: false,
+            upload_to_tempfile: false,
+            request_timeout: crate::defaults::DEFAULT_TIMEOUT,
+            cgi_timeout: crate::defaults::DEFAULT_TIMEOUT,
+            cgi_cache: false,
+            cgi_cache_ttl: crate::defaults::DEFAULT_CGI_CACHE_TTL,
+            event_stream: false,
+            charset: None,
+            headers: Vec::new(),
+            cors: CorsConfig::default(),
+            websocket: None,
+            auth_basic: None,
+            auth_user_file: None,
+            access_rules: Vec::new(),
         }
     }
 }
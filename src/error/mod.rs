@@ -6,6 +6,7 @@ pub mod pages;
 pub mod types;
 
 pub use pages::*;
+pub use types::{CgiErrorKind, HttpErrorKind};
 
 use std::fmt;
 
@@ -16,10 +17,10 @@ pub enum ServerError {
     Config(String),
     /// I/O errors
     Io(std::io::Error),
-    /// HTTP parsing errors
-    Http(String),
-    /// CGI execution errors
-    Cgi(String),
+    /// HTTP request errors, answered with the kind's status
+    Http(HttpErrorKind),
+    /// CGI execution errors, answered with the kind's status
+    Cgi(CgiErrorKind),
     /// Internal server errors
     Internal(String),
 }
@@ -38,6 +39,28 @@ impl fmt::Display for ServerError {
 
 impl std::error::Error for ServerError {}
 
+impl ServerError {
+    /// Status to answer the client with, for errors caused by the request
+    pub fn http_status(&self) -> Option<HttpStatus> {
+        match self {
+            ServerError::Http(kind) => Some(kind.status()),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpErrorKind> for ServerError {
+    fn from(kind: HttpErrorKind) -> Self {
+        ServerError::Http(kind)
+    }
+}
+
+impl From<CgiErrorKind> for ServerError {
+    fn from(kind: CgiErrorKind) -> Self {
+        ServerError::Cgi(kind)
+    }
+}
+
 impl From<std::io::Error> for ServerError {
     fn from(err: std::io::Error) -> Self {
         ServerError::Io(err)
@@ -50,17 +73,37 @@ pub type ServerResult<T> = Result<T, ServerError>;
 /// HTTP status codes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpStatus {
+    Continue = 100,
+    SwitchingProtocols = 101,
     Ok = 200,
     Created = 201,
     NoContent = 204,
+    PartialContent = 206,
     MovedPermanently = 301,
     Found = 302,
+    NotModified = 304,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
     BadRequest = 400,
+    Unauthorized = 401,
     Forbidden = 403,
     NotFound = 404,
     MethodNotAllowed = 405,
+    RequestTimeout = 408,
+    Conflict = 409,
     RequestEntityTooLarge = 413,
+    UriTooLong = 414,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    ExpectationFailed = 417,
+    UpgradeRequired = 426,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
     InternalServerError = 500,
+    NotImplemented = 501,
+    BadGateway = 502,
+    ServiceUnavailable = 503,
+    HttpVersionNotSupported = 505,
 }
 
 impl HttpStatus {
@@ -68,19 +111,50 @@ impl HttpStatus {
         self as u16
     }
 
+    /// Status for a redirect code accepted by the `redirect` directive
+    pub fn from_redirect_code(code: u16) -> Option<Self> {
+        match code {
+            301 => Some(HttpStatus::MovedPermanently),
+            302 => Some(HttpStatus::Found),
+            307 => Some(HttpStatus::TemporaryRedirect),
+            308 => Some(HttpStatus::PermanentRedirect),
+            _ => None,
+        }
+    }
+
     pub fn reason_phrase(self) -> &'static str {
         match self {
+            HttpStatus::Continue => "Continue",
+            HttpStatus::SwitchingProtocols => "Switching Protocols",
             HttpStatus::Ok => "OK",
             HttpStatus::Created => "Created",
             HttpStatus::NoContent => "No Content",
+            HttpStatus::PartialContent => "Partial Content",
             HttpStatus::MovedPermanently => "Moved Permanently",
             HttpStatus::Found => "Found",
+            HttpStatus::NotModified => "Not Modified",
+            HttpStatus::TemporaryRedirect => "Temporary Redirect",
+            HttpStatus::PermanentRedirect => "Permanent Redirect",
             HttpStatus::BadRequest => "Bad Request",
+            HttpStatus::Unauthorized => "Unauthorized",
             HttpStatus::Forbidden => "Forbidden",
             HttpStatus::NotFound => "Not Found",
             HttpStatus::MethodNotAllowed => "Method Not Allowed",
+            HttpStatus::RequestTimeout => "Request Timeout",
+            HttpStatus::Conflict => "Conflict",
             HttpStatus::RequestEntityTooLarge => "Request Entity Too Large",
+            HttpStatus::UriTooLong => "URI Too Long",
+            HttpStatus::UnsupportedMediaType => "Unsupported Media Type",
+            HttpStatus::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatus::ExpectationFailed => "Expectation Failed",
+            HttpStatus::UpgradeRequired => "Upgrade Required",
+            HttpStatus::TooManyRequests => "Too Many Requests",
+            HttpStatus::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             HttpStatus::InternalServerError => "Internal Server Error",
+            HttpStatus::NotImplemented => "Not Implemented",
+            HttpStatus::BadGateway => "Bad Gateway",
+            HttpStatus::ServiceUnavailable => "Service Unavailable",
+            HttpStatus::HttpVersionNotSupported => "HTTP Version Not Supported",
         }
     }
 }
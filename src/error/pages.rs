@@ -8,8 +8,11 @@ use crate::http::HttpResponse;
 use std::collections::HashMap;
 
 /// Error page manager
+#[derive(Debug, Clone)]
 pub struct ErrorPageManager {
     custom_pages: HashMap<u16, String>,
+    /// Directory searched for `<code>.html` when a status has no custom page
+    pages_root: Option<String>,
 }
 
 impl ErrorPageManager {
@@ -17,6 +20,7 @@ impl ErrorPageManager {
     pub fn new() -> Self {
         Self {
             custom_pages: HashMap::new(),
+            pages_root: None,
         }
     }
 
@@ -24,6 +28,7 @@ impl ErrorPageManager {
     pub fn from_config(server_config: &ServerConfig) -> Self {
         Self {
             custom_pages: server_config.error_pages.clone(),
+            pages_root: server_config.error_pages_root.clone(),
         }
     }
 
@@ -53,6 +58,14 @@ impl ErrorPageManager {
             }
         }
 
+        // Then a page named after the status in the error pages directory
+        if let Some(root) = &self.pages_root {
+            let path = std::path::Path::new(root).join(format!("{}.html", status_code));
+            if let Ok(content) = std::fs::read_to_string(path) {
+                return content;
+            }
+        }
+
         // Generate default error page
         self.generate_default_error_page(status, custom_message)
     }
@@ -136,6 +149,11 @@ impl ErrorPageManager {
         self.custom_pages.insert(status_code, file_path);
     }
 
+    /// Set the directory searched for `<code>.html` pages
+    pub fn set_pages_root(&mut self, root: Option<String>) {
+        self.pages_root = root;
+    }
+
     /// Remove a custom error page
     pub fn remove_custom_page(&mut self, status_code: u16) {
         self.custom_pages.remove(&status_code);
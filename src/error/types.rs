@@ -1,5 +1,140 @@
 /*!
- * Error types - stub implementation
+ * Error kinds for HTTP-level and CGI failures
  */
 
-// This file is intentionally empty as error types are defined in mod.rs
+use crate::error::HttpStatus;
+use std::fmt;
+
+/// Why a request could not be served, each kind answered with its own status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpErrorKind {
+    /// Request line missing parts or not valid UTF-8
+    MalformedRequestLine(String),
+    /// Method token the server does not recognise
+    UnknownMethod(String),
+    /// Protocol version other than HTTP/1.0 and HTTP/1.1
+    UnsupportedVersion(String),
+    /// Request line over the length limit
+    UriTooLong(String),
+    /// Header line that cannot be parsed
+    MalformedHeader(String),
+    /// Header section over the size or line count limit
+    HeaderTooLarge(String),
+    /// Broken body framing, such as a bad chunk size or multipart boundary
+    MalformedBody(String),
+    /// Body larger than the configured limit
+    BodyTooLarge(String),
+    /// Invalid percent-encoding or UTF-8 in the URL, or control characters in its path
+    InvalidEncoding(String),
+    /// Body in a format the handler does not accept
+    UnsupportedMediaType(String),
+    /// `Expect` header other than `100-continue`
+    ExpectationFailed(String),
+    /// Path resolving outside the route root
+    PathTraversal,
+    /// No route matches the request path
+    NoMatchingRoute,
+}
+
+impl HttpErrorKind {
+    /// Status the client is answered with
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            HttpErrorKind::MalformedRequestLine(_)
+            | HttpErrorKind::MalformedHeader(_)
+            | HttpErrorKind::MalformedBody(_)
+            | HttpErrorKind::InvalidEncoding(_) => HttpStatus::BadRequest,
+            HttpErrorKind::UnknownMethod(_) => HttpStatus::NotImplemented,
+            HttpErrorKind::UnsupportedVersion(_) => HttpStatus::HttpVersionNotSupported,
+            HttpErrorKind::UriTooLong(_) => HttpStatus::UriTooLong,
+            HttpErrorKind::HeaderTooLarge(_) => HttpStatus::RequestHeaderFieldsTooLarge,
+            HttpErrorKind::BodyTooLarge(_) => HttpStatus::RequestEntityTooLarge,
+            HttpErrorKind::UnsupportedMediaType(_) => HttpStatus::UnsupportedMediaType,
+            HttpErrorKind::ExpectationFailed(_) => HttpStatus::ExpectationFailed,
+            HttpErrorKind::PathTraversal => HttpStatus::Forbidden,
+            HttpErrorKind::NoMatchingRoute => HttpStatus::NotFound,
+        }
+    }
+
+    /// Message shown to the client on the error page, without request details
+    pub fn description(&self) -> &'static str {
+        match self {
+            HttpErrorKind::MalformedRequestLine(_)
+            | HttpErrorKind::MalformedHeader(_)
+            | HttpErrorKind::MalformedBody(_)
+            | HttpErrorKind::InvalidEncoding(_) => "Invalid HTTP request",
+            HttpErrorKind::UnknownMethod(_) => "Method not implemented",
+            HttpErrorKind::UnsupportedVersion(_) => "HTTP version not supported",
+            HttpErrorKind::UriTooLong(_) => "Request line too long",
+            HttpErrorKind::HeaderTooLarge(_) => "Request header fields too large",
+            HttpErrorKind::BodyTooLarge(_) => "Request body too large",
+            HttpErrorKind::UnsupportedMediaType(_) => "Unsupported media type",
+            HttpErrorKind::ExpectationFailed(_) => "Unsupported expectation",
+            HttpErrorKind::PathTraversal => "Access denied",
+            HttpErrorKind::NoMatchingRoute => "No matching route found",
+        }
+    }
+}
+
+impl fmt::Display for HttpErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpErrorKind::MalformedRequestLine(detail) => write!(f, "Malformed request line: {}", detail),
+            HttpErrorKind::UnknownMethod(method) => write!(f, "Unknown HTTP method: {}", method),
+            HttpErrorKind::UriTooLong(detail) => write!(f, "Request line too long: {}", detail),
+            HttpErrorKind::UnsupportedVersion(version) => write!(f, "Unsupported HTTP version: {}", version),
+            HttpErrorKind::MalformedHeader(detail) => write!(f, "Malformed header: {}", detail),
+            HttpErrorKind::HeaderTooLarge(detail) => write!(f, "Request header fields too large: {}", detail),
+            HttpErrorKind::MalformedBody(detail) => write!(f, "Malformed body: {}", detail),
+            HttpErrorKind::BodyTooLarge(detail) => write!(f, "Request body too large: {}", detail),
+            HttpErrorKind::InvalidEncoding(detail) => write!(f, "Invalid encoding: {}", detail),
+            HttpErrorKind::UnsupportedMediaType(media_type) => write!(f, "Unsupported media type: {}", media_type),
+            HttpErrorKind::ExpectationFailed(expect) => write!(f, "Expectation failed: {}", expect),
+            HttpErrorKind::PathTraversal => write!(f, "Path traversal attempt detected"),
+            HttpErrorKind::NoMatchingRoute => write!(f, "No matching route found"),
+        }
+    }
+}
+
+/// How a CGI script failed, each kind answered with its own status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CgiErrorKind {
+    /// The script could not be started
+    Spawn(String),
+    /// The script ran past its timeout and was killed
+    Timeout,
+    /// The script exited unsuccessfully or gave output that could not be used
+    Failed(String),
+}
+
+impl CgiErrorKind {
+    /// Status the client is answered with: a timeout is taken as passing
+    /// overload, a failed script as a bad upstream, and a script that would
+    /// not start as the server's own fault
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            CgiErrorKind::Spawn(_) => HttpStatus::InternalServerError,
+            CgiErrorKind::Timeout => HttpStatus::ServiceUnavailable,
+            CgiErrorKind::Failed(_) => HttpStatus::BadGateway,
+        }
+    }
+
+    /// Message shown to the client on the error page
+    pub fn description(&self) -> &'static str {
+        match self {
+            CgiErrorKind::Spawn(_) => "CGI script could not be started",
+            CgiErrorKind::Timeout => "CGI script timed out",
+            CgiErrorKind::Failed(_) => "CGI script execution failed",
+        }
+    }
+}
+
+impl fmt::Display for CgiErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CgiErrorKind::Spawn(detail) => write!(f, "{}", detail),
+            CgiErrorKind::Timeout => write!(f, "CGI script timeout"),
+            CgiErrorKind::Failed(detail) => write!(f, "{}", detail),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+/*!
+ * HTTP Basic authentication (RFC 7617)
+ *
+ * Credentials are checked against a user file of `name:hash` lines. Hashes
+ * are salted SHA-1 in the LDAP `{SSHA}` form, as written by
+ * `slappasswd -h {SSHA}`: base64 of the digest of password and salt,
+ * followed by the salt.
+ */
+
+use crate::error::{ServerError, ServerResult};
+use crate::utils::{base64_decode, base64_encode, sha1};
+use std::collections::HashMap;
+use std::fs;
+
+/// Prefix of salted SHA-1 hashes
+const SSHA_PREFIX: &str = "{SSHA}";
+
+/// Length of a SHA-1 digest in bytes
+const DIGEST_LEN: usize = 20;
+
+/// User name and password from an `Authorization: Basic` header value
+pub fn parse_basic_credentials(header: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = String::from_utf8(base64_decode(encoded.trim())?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// `{SSHA}` hash of a password with the given salt
+pub fn ssha_hash(password: &str, salt: &[u8]) -> String {
+    let mut salted = password.as_bytes().to_vec();
+    salted.extend_from_slice(salt);
+
+    let mut hash = sha1(&salted).to_vec();
+    hash.extend_from_slice(salt);
+    format!("{}{}", SSHA_PREFIX, base64_encode(&hash))
+}
+
+/// Check a password against an `{SSHA}` hash; other hash forms never match
+fn verify_ssha(password: &str, hash: &str) -> bool {
+    let decoded = match hash.strip_prefix(SSHA_PREFIX).and_then(base64_decode) {
+        Some(decoded) if decoded.len() > DIGEST_LEN => decoded,
+        _ => return false,
+    };
+    let (digest, salt) = decoded.split_at(DIGEST_LEN);
+
+    let mut salted = password.as_bytes().to_vec();
+    salted.extend_from_slice(salt);
+
+    // Compare every byte so the time taken does not reveal how much matched
+    sha1(&salted).iter().zip(digest).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Users and password hashes read from an `auth_user_file`
+#[derive(Debug, Clone, Default)]
+pub struct UserFile {
+    users: HashMap<String, String>,
+}
+
+impl UserFile {
+    /// Parse `name:hash` lines, skipping blank lines and `#` comments
+    pub fn parse(contents: &str) -> ServerResult<Self> {
+        let mut users = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once(':') {
+                Some((user, hash)) if !user.is_empty() && hash.starts_with(SSHA_PREFIX) => {
+                    users.insert(user.to_string(), hash.to_string());
+                }
+                _ => {
+                    return Err(ServerError::Config(format!(
+                        "user file line {}: expected name:{{SSHA}}hash", number + 1
+                    )));
+                }
+            }
+        }
+        Ok(Self { users })
+    }
+
+    /// Read and parse a user file
+    pub fn load(path: &str) -> ServerResult<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ServerError::Config(format!("Cannot read user file {}: {}", path, e)))?;
+        Self::parse(&contents)
+    }
+
+    /// Check if a user exists and the password matches their hash
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        self.users.get(user).is_some_and(|hash| verify_ssha(password, hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_basic_credentials() {
+        // Example from RFC 7617 section 2
+        assert_eq!(
+            parse_basic_credentials("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+            Some(("Aladdin".to_string(), "open sesame".to_string()))
+        );
+        assert_eq!(parse_basic_credentials("basic  dXNlcjo= "), Some(("user".to_string(), String::new())));
+        assert_eq!(parse_basic_credentials("Bearer QWxhZGRpbjpvcGVuIHNlc2FtZQ=="), None);
+        assert_eq!(parse_basic_credentials("Basic bm9jb2xvbg=="), None);
+        assert_eq!(parse_basic_credentials("Basic !!!"), None);
+    }
+
+    #[test]
+    fn test_user_file_verifies_salted_hashes() {
+        let file = UserFile::parse(&format!("# admins\n\nalice:{}\n", ssha_hash("secret", b"salt1234"))).unwrap();
+        assert!(file.verify("alice", "secret"));
+        assert!(!file.verify("alice", "Secret"));
+        assert!(!file.verify("bob", "secret"));
+
+        // A 4-byte salt, as `slappasswd -h {SSHA}` uses
+        let file = UserFile::parse("carol:{SSHA}+q4ex+F45/B4THkDxZDC493L912PIVwH").unwrap();
+        assert!(file.verify("carol", "secret"));
+    }
+
+    #[test]
+    fn test_user_file_rejects_unsupported_lines() {
+        assert!(UserFile::parse("alice:plaintext\n").is_err());
+        assert!(UserFile::parse("no-colon\n").is_err());
+        assert!(UserFile::parse(":{SSHA}AAAA\n").is_err());
+    }
+}
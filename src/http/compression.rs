@@ -0,0 +1,410 @@
+/*!
+ * Content-coding negotiation (RFC 9110 section 12.5.3) and gzip compression
+ *
+ * Decides from a request's `Accept-Encoding` whether its response may be
+ * gzip-compressed, honouring q-values so clients can refuse gzip or prefer
+ * the uncompressed body, and compresses in-memory bodies with a small
+ * deflate encoder (RFC 1951 fixed Huffman blocks in an RFC 1952 wrapper).
+ */
+
+use crate::error::HttpStatus;
+use crate::http::HttpResponse;
+
+/// Bodies shorter than this are sent as they are; gzip's framing would outweigh the savings
+pub const MIN_COMPRESS_SIZE: usize = 256;
+
+/// Coding a response body is sent with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    /// Name of the coding as used in `Content-Encoding`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Identity => "identity",
+        }
+    }
+}
+
+/// Coding to answer a request with, given its `Accept-Encoding` value.
+/// Gzip is chosen when the client accepts it at least as much as the
+/// uncompressed body; an empty value asks for no coding at all.
+pub fn negotiate_encoding(accept_encoding: &str) -> Encoding {
+    let mut gzip = None;
+    let mut identity = None;
+    let mut any = None;
+
+    for (coding, quality) in accept_encoding.split(',').filter_map(parse_coding) {
+        let slot = match coding.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => &mut gzip,
+            "identity" => &mut identity,
+            "*" => &mut any,
+            _ => continue,
+        };
+        slot.get_or_insert(quality);
+    }
+
+    // Codings not listed take the `*` weight. Identity stays the fallback
+    // even when unlisted, so it only outranks gzip when weighted explicitly.
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let identity = identity.or(any).unwrap_or(0.0);
+    if gzip > 0.0 && gzip >= identity {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+/// Coding name and q-value of one `Accept-Encoding` element, or None if it is malformed
+fn parse_coding(element: &str) -> Option<(&str, f32)> {
+    let mut parts = element.split(';');
+    let coding = parts.next()?.trim();
+    if coding.is_empty() {
+        return None;
+    }
+
+    let mut quality = 1.0;
+    for parameter in parts {
+        let (name, value) = parameter.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("q") {
+            quality = value.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))?;
+        }
+    }
+    Some((coding, quality))
+}
+
+/// Compress a response body with gzip when the client accepts it.
+/// Only complete in-memory bodies of compressible types are touched;
+/// files streamed from disk, partial content and small bodies are left alone.
+pub fn compress_response(response: &mut HttpResponse, accept_encoding: Option<&str>) {
+    if response.status != HttpStatus::Ok
+        || response.body_source.is_some()
+        || response.body.len() < MIN_COMPRESS_SIZE
+        || response.get_header("Content-Encoding").is_some()
+        || response.get_header("Content-Range").is_some()
+        || response.get_header("Cache-Control").is_some_and(|value| value.to_ascii_lowercase().contains("no-transform"))
+        || !response.get_header("Content-Type").is_some_and(|value| is_compressible(value))
+    {
+        return;
+    }
+
+    // Caches must keep the two codings apart even when this client got the plain body
+    match response.get_header("Vary").cloned() {
+        Some(vary) if vary.split(',').any(|name| {
+            let name = name.trim();
+            name == "*" || name.eq_ignore_ascii_case("Accept-Encoding")
+        }) => {}
+        Some(vary) => response.add_header("Vary", &format!("{}, Accept-Encoding", vary)),
+        None => response.add_header("Vary", "Accept-Encoding"),
+    }
+
+    if accept_encoding.map_or(Encoding::Identity, negotiate_encoding) != Encoding::Gzip {
+        return;
+    }
+    let compressed = gzip(&response.body);
+    if compressed.len() >= response.body.len() {
+        return;
+    }
+
+    response.set_body(compressed);
+    response.add_header("Content-Encoding", Encoding::Gzip.as_str());
+    // The compressed bytes are a different representation of the same resource
+    if let Some(etag) = response.get_header("ETag").cloned() {
+        if !etag.starts_with("W/") {
+            response.add_header("ETag", &format!("W/{}", etag));
+        }
+    }
+}
+
+/// Check if a media type is worth compressing; images, archives and
+/// other already-compressed formats are not
+pub fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(media_type.as_str(),
+            "application/json" | "application/javascript" | "application/xml"
+            | "application/wasm" | "image/svg+xml" | "image/x-icon")
+}
+
+/// Compress `data` into a gzip member
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, deflate method, no flags, no modification time, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Base match length of each length code (257 to 285) and its extra bits
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base of each distance code and its extra bits
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW_SIZE: usize = 32 * 1024;
+const HASH_BITS: u32 = 15;
+/// Earlier positions with the same hash to try before settling for the best match so far
+const MAX_CHAIN: usize = 32;
+
+/// Encode `data` as a single fixed-Huffman deflate block, finding repeats with hash chains
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // Final block, fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    // Most recent position + 1 of each 3-byte hash, and the one before each position
+    let mut head = vec![0usize; 1 << HASH_BITS];
+    let mut prev = vec![0usize; data.len()];
+    let insert = |at: usize, head: &mut [usize], prev: &mut [usize]| {
+        if at + MIN_MATCH <= data.len() {
+            let slot = hash(data, at);
+            prev[at] = head[slot];
+            head[slot] = at + 1;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(data, pos)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == 0 || pos - (candidate - 1) > WINDOW_SIZE {
+                    break;
+                }
+                let start = candidate - 1;
+                let len = data[start..].iter().zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_distance = pos - start;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[start];
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            writer.write_length(best_len);
+            writer.write_distance(best_distance);
+            for at in pos..pos + best_len {
+                insert(at, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            writer.write_literal(data[pos] as u16);
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    // End of block
+    writer.write_literal(256);
+    writer.finish()
+}
+
+/// Hash of the three bytes at `at`, for finding earlier occurrences of them
+fn hash(data: &[u8], at: usize) -> usize {
+    let value = (data[at] as u32) << 16 | (data[at + 1] as u32) << 8 | data[at + 2] as u32;
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Packs bit fields least significant bit first, as deflate stores them
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { out: Vec::new(), bits: 0, count: 0 }
+    }
+
+    /// Write the low `count` bits of `value`
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which deflate stores most significant bit first
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Write a literal/length symbol with its fixed code (RFC 1951 section 3.2.6)
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        let index = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+        self.write_literal(257 + index as u16);
+        self.write_bits((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+    }
+
+    fn write_distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+        self.write_code(index as u32, 5);
+        self.write_bits((distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index] as u32);
+    }
+
+    /// Pad the last byte with zero bits and return the output
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// CRC-32 lookup table for the reflected polynomial 0xEDB88320
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 checksum gzip stores of the uncompressed data
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_encoding() {
+        assert_eq!(negotiate_encoding("gzip, deflate"), Encoding::Gzip);
+        assert_eq!(negotiate_encoding("GZIP;Q=0.8"), Encoding::Gzip);
+        assert_eq!(negotiate_encoding("*"), Encoding::Gzip);
+
+        // Refused outright, or not listed
+        assert_eq!(negotiate_encoding("gzip;q=0"), Encoding::Identity);
+        assert_eq!(negotiate_encoding("gzip;q=0, identity"), Encoding::Identity);
+        assert_eq!(negotiate_encoding("*;q=0, identity"), Encoding::Identity);
+        assert_eq!(negotiate_encoding("br, deflate"), Encoding::Identity);
+        assert_eq!(negotiate_encoding(""), Encoding::Identity);
+
+        // Preferences between the two
+        assert_eq!(negotiate_encoding("identity;q=1, gzip;q=0.5"), Encoding::Identity);
+        assert_eq!(negotiate_encoding("identity;q=0.5, gzip"), Encoding::Gzip);
+        assert_eq!(negotiate_encoding("gzip;q=0.5, *;q=0.1"), Encoding::Gzip);
+    }
+
+    #[test]
+    fn test_malformed_elements_ignored() {
+        assert_eq!(negotiate_encoding("gzip;q=2"), Encoding::Identity);
+        assert_eq!(negotiate_encoding("gzip;q=high, identity;q=0"), Encoding::Identity);
+        assert_eq!(negotiate_encoding(" , gzip ; q=0.3"), Encoding::Gzip);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_gzip_framing() {
+        let data = b"hello hello hello hello hello hello";
+        let compressed = gzip(data);
+        assert_eq!(&compressed[..3], &[0x1f, 0x8b, 8]);
+        let trailer = &compressed[compressed.len() - 8..];
+        assert_eq!(trailer[..4], crc32(data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+
+        // Repeats become back-references, so the deflate stream is shorter than the input
+        assert!(compressed.len() - 18 < data.len());
+
+        // An empty input is just the end-of-block code: final fixed block, symbol 256
+        assert_eq!(deflate(b""), vec![0x03, 0x00]);
+    }
+
+    #[test]
+    fn test_compressible_types() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+        assert!(!is_compressible("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_compress_response() {
+        let text = "all work and no play makes jack a dull boy. ".repeat(20);
+
+        let mut response = HttpResponse::text(HttpStatus::Ok, &text);
+        compress_response(&mut response, Some("gzip"));
+        assert_eq!(response.get_header("Content-Encoding"), Some(&"gzip".to_string()));
+        assert_eq!(response.get_header("Vary"), Some(&"Accept-Encoding".to_string()));
+        assert!(response.body.len() < text.len());
+
+        // Refused, the body stays as it is but caches still learn it varies
+        let mut response = HttpResponse::text(HttpStatus::Ok, &text);
+        compress_response(&mut response, Some("gzip;q=0, identity"));
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Vary"), Some(&"Accept-Encoding".to_string()));
+        assert_eq!(response.body, text.as_bytes());
+
+        // Too small, or already compressed
+        let mut response = HttpResponse::text(HttpStatus::Ok, "short");
+        compress_response(&mut response, Some("gzip"));
+        assert_eq!(response.get_header("Vary"), None);
+        let mut response = HttpResponse::file(HttpStatus::Ok, text.clone().into_bytes(), "image/png");
+        compress_response(&mut response, Some("gzip"));
+        assert_eq!(response.get_header("Content-Encoding"), None);
+    }
+}
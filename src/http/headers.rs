@@ -118,11 +118,7 @@ impl Headers {
                 .skip(1)
                 .find_map(|part| {
                     let part = part.trim();
-                    if part.starts_with("charset=") {
-                        Some(part[8..].trim().to_string())
-                    } else {
-                        None
-                    }
+                    part.strip_prefix("charset=").map(|charset| charset.trim().to_string())
                 });
 
             (media_type, charset)
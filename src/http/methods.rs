@@ -2,21 +2,53 @@
  * HTTP methods implementation
  */
 
-use crate::cgi::CgiExecutor;
-use crate::config::{Config, RouteConfig};
+use crate::cgi::{CgiCache, CgiCacheKey, CgiExecutor, CgiLimiter, CgiProcess};
+use crate::cgi::executor::{self, CGI_RETRY_AFTER_SECS, EXEC_INTERPRETER};
+use crate::config::{Config, CorsConfig, RouteConfig, ServerConfig, ServerKey};
 use crate::error::{ServerError, ServerResult, HttpStatus};
 use crate::error::pages::ErrorPageManager;
-use crate::http::{HttpRequest, HttpResponse};
-use crate::routing::{Router, StaticFileServer};
+use crate::http::{auth, multipart, HttpRequest, HttpResponse};
+use crate::routing::{ListingSort, Router, StaticFileServer};
+use crate::ws::handshake;
+use std::collections::HashMap;
 use std::fs;
+use std::io::Write;
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Every method the server can handle, listed in answer to `OPTIONS *`
+const SERVER_METHODS: &str = "GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS";
+
+/// Result of dispatching a request
+pub enum Dispatch {
+    /// The response is ready to send
+    Response(HttpResponse),
+    /// A CGI script is running; its response arrives once the process finishes
+    Cgi(CgiProcess),
+    /// The client is switching to WebSocket: send the `101` response, then
+    /// hand the connection to the named handler
+    WebSocket { response: HttpResponse, handler: String },
+}
 
 /// HTTP method handler
 pub struct MethodHandler {
     router: Router,
+    /// Resolves paths, and serves files for servers without their own below
     static_server: StaticFileServer,
+    /// File servers labelling types with each server block's `mime_type` mappings
+    static_servers: HashMap<ServerKey, StaticFileServer>,
+    /// Error pages of the first server, for requests addressed to none
     error_manager: ErrorPageManager,
+    /// Error pages of each server block
+    error_managers: HashMap<ServerKey, ErrorPageManager>,
     cgi_executor: CgiExecutor,
+    /// Executors rendering script failures with each server block's error pages
+    cgi_executors: HashMap<ServerKey, CgiExecutor>,
+    /// Scripts running at once, counted for each server block
+    cgi_limiters: HashMap<ServerKey, CgiLimiter>,
+    /// Responses of routes with `cgi_cache on`
+    cgi_cache: Mutex<CgiCache>,
 }
 
 impl MethodHandler {
@@ -28,98 +60,407 @@ impl MethodHandler {
             ErrorPageManager::new()
         };
 
+        let error_managers: HashMap<ServerKey, ErrorPageManager> = config.servers.iter()
+            .map(|server| (server.key(), ErrorPageManager::from_config(server)))
+            .collect();
+
+        // Static and CGI errors render through the custom pages of their server
+        let new_static_server = |server: Option<&ServerConfig>, error_manager: &ErrorPageManager| {
+            let mut static_server = StaticFileServer::new();
+            static_server.set_error_manager(error_manager.clone());
+            if let Some(server) = server {
+                static_server.set_charset(&server.default_charset);
+                for (extension, mime_type) in &server.mime_types {
+                    static_server.add_mime_type(extension, mime_type);
+                }
+            }
+            static_server
+        };
+        let new_cgi_executor = |error_manager: &ErrorPageManager| {
+            let mut cgi_executor = CgiExecutor::new();
+            cgi_executor.set_error_manager(error_manager.clone());
+            cgi_executor
+        };
+        let static_server = new_static_server(None, &error_manager);
+        let cgi_executor = new_cgi_executor(&error_manager);
+        let mut static_servers = HashMap::new();
+        let mut cgi_executors = HashMap::new();
+        for server in &config.servers {
+            let server_errors = &error_managers[&server.key()];
+            static_servers.insert(server.key(), new_static_server(Some(server), server_errors));
+            cgi_executors.insert(server.key(), new_cgi_executor(server_errors));
+        }
+        let cgi_limiters = config.servers.iter()
+            .map(|server| (server.key(), CgiLimiter::new(server.max_cgi_processes)))
+            .collect();
+
         Self {
             router: Router::new(&config),
-            static_server: StaticFileServer::new(),
+            static_server,
+            static_servers,
             error_manager,
-            cgi_executor: CgiExecutor::new(),
+            error_managers,
+            cgi_executor,
+            cgi_executors,
+            cgi_limiters,
+            cgi_cache: Mutex::new(CgiCache::new()),
+        }
+    }
+
+    /// Count scripts still running under `previous` against the same server
+    /// blocks here, so a reload neither frees their slots nor forgets them
+    pub fn keep_cgi_limiters(&mut self, previous: &MethodHandler) {
+        for (key, limiter) in self.cgi_limiters.iter_mut() {
+            if let Some(old) = previous.cgi_limiters.get(key) {
+                *limiter = old.with_max(limiter.max());
+            }
+        }
+    }
+
+    /// File server with the `mime_type` mappings of a server block
+    fn static_server_for(&self, server: &ServerConfig) -> &StaticFileServer {
+        self.static_servers.get(&server.key()).unwrap_or(&self.static_server)
+    }
+
+    /// CGI executor with the error pages of a server block
+    fn cgi_executor_for(&self, server: &ServerConfig) -> &CgiExecutor {
+        self.cgi_executors.get(&server.key()).unwrap_or(&self.cgi_executor)
+    }
+
+    /// Error pages of a server block
+    fn error_manager_for(&self, server: &ServerConfig) -> &ErrorPageManager {
+        self.error_managers.get(&server.key()).unwrap_or(&self.error_manager)
+    }
+
+    /// Error pages of the server block a request is addressed to
+    pub fn error_manager_for_request(&self, request: &HttpRequest) -> &ErrorPageManager {
+        self.server_for(request).map_or(&self.error_manager, |server| self.error_manager_for(server))
+    }
+
+    /// Handle an HTTP request and generate a response, running CGI scripts to completion.
+    /// WebSocket upgrades give just their handshake response. This is the entry
+    /// point for driving a parsed request to its response in memory, with no sockets.
+    pub fn handle_request(&self, request: &mut HttpRequest) -> ServerResult<HttpResponse> {
+        match self.dispatch(request)? {
+            Dispatch::Response(response) => Ok(response),
+            Dispatch::Cgi(process) => Ok(self.finish_cgi(request, process.run_to_completion())),
+            Dispatch::WebSocket { response, .. } => Ok(response),
+        }
+    }
+
+    /// Check if a request without exactly one Host header must be refused.
+    /// With no single Host to pick a server by, the request is refused if any
+    /// server listening where it arrived has `strict_host on`; when that place
+    /// is unknown, as for Unix socket clients, every server counts.
+    fn requires_one_host(&self, request: &HttpRequest) -> bool {
+        if request.get_header_all("host").len() == 1 {
+            return false;
+        }
+        self.router.servers().iter()
+            .filter(|server| request.local_addr.is_none_or(|addr| listens_on(server, addr)))
+            .any(|server| server.strict_host)
+    }
+
+    /// Server block a request is addressed to
+    pub fn server_for(&self, request: &HttpRequest) -> Option<&crate::config::ServerConfig> {
+        let host = request.get_header("host").map(|s| s.as_str());
+        self.router.find_server(host).ok()
+    }
+
+    /// Check if a request's CGI response may be cached, in which case it must be
+    /// collected whole rather than streamed
+    pub fn caches_cgi(&self, request: &HttpRequest) -> bool {
+        let host = request.get_header("host").map(|s| s.as_str());
+        self.router.find_route(host, &request.path)
+            .is_ok_and(|(_, route, _)| route.cgi_cache && !route.event_stream)
+    }
+
+    /// Check if a request maps to a route with `event_stream on`, whose scripts
+    /// push events for as long as they run
+    pub fn streams_events(&self, request: &HttpRequest) -> bool {
+        let host = request.get_header("host").map(|s| s.as_str());
+        self.router.find_route(host, &request.path)
+            .is_ok_and(|(_, route, _)| route.event_stream)
+    }
+
+    /// Check if the body of a request whose headers have arrived should be
+    /// written to disk: a POST or PUT upload on a route with `upload_to_tempfile on`.
+    /// Multipart and CGI bodies are parsed from memory, so they are never spooled.
+    pub fn spools_upload(&self, request: &HttpRequest) -> bool {
+        let host = request.get_header("host").map(|s| s.as_str());
+        let route = match self.router.find_route(host, &request.path) {
+            Ok((_, route, _)) => route,
+            Err(_) => return false,
+        };
+        let is_multipart = request.get_header("content-type")
+            .and_then(|content_type| multipart::boundary_from_content_type(content_type))
+            .is_some();
+
+        route.upload_to_tempfile && route.upload_enabled && !is_multipart && match request.method {
+            crate::http::HttpMethod::POST => route.cgi_interpreter(&request.path).is_none(),
+            crate::http::HttpMethod::PUT => true,
+            _ => false,
         }
     }
 
-    /// Handle an HTTP request and generate a response
-    pub fn handle_request(&self, request: &HttpRequest) -> ServerResult<HttpResponse> {
+    /// Idle timeout configured for the route a request maps to
+    pub fn request_timeout(&self, request: &HttpRequest) -> Option<Duration> {
+        let host = request.get_header("host").map(|s| s.as_str());
+        self.router.find_route(host, &request.path).ok()
+            .map(|(_, route, _)| Duration::from_secs(route.request_timeout))
+    }
+
+    /// Handle an HTTP request, leaving CGI scripts running for the caller to drive.
+    /// Parameters captured by the matched route are stored on the request.
+    pub fn dispatch(&self, request: &mut HttpRequest) -> ServerResult<Dispatch> {
+        // HTTP/1.1 requests must name exactly one host (RFC 7230 section 5.4)
+        if request.version == crate::http::HttpVersion::Http11 && self.requires_one_host(request) {
+            return Ok(Dispatch::Response(self.error_manager_for_request(request).generate_error_response(
+                HttpStatus::BadRequest,
+                Some("Missing or repeated Host header")
+            )));
+        }
+
+        // `OPTIONS *` asks about the server as a whole, so no route applies
+        if request.uri == "*" {
+            let mut response = HttpResponse::no_content();
+            response.add_header("Allow", SERVER_METHODS);
+            return Ok(Dispatch::Response(response));
+        }
+
         // Find matching route using the router
         let host = request.get_header("host").map(|s| s.as_str());
-        let (server, route) = self.router.find_route(host, &request.path)?;
+        let (server, route, params) = self.router.find_route(host, &request.path)?;
+        request.path_params = params;
+
+        // The parser joins folded lines if any server allows it; the matched server decides
+        if request.folded_headers && !server.merge_folded_headers {
+            return Ok(Dispatch::Response(self.error_manager_for(server).generate_error_response(
+                HttpStatus::BadRequest,
+                Some("Obsolete line folding in headers")
+            )));
+        }
+
+        // The overriding method is checked against the route like any other
+        if server.method_override && request.method == crate::http::HttpMethod::POST {
+            if let Some(name) = method_override(request) {
+                match crate::http::HttpMethod::from_str(&name) {
+                    Some(method) => request.method = method,
+                    None => {
+                        return Ok(Dispatch::Response(self.error_manager_for(server).generate_error_response(
+                            HttpStatus::BadRequest,
+                            Some(&format!("Unknown override method {}", name))
+                        )));
+                    }
+                }
+            }
+        }
+
+        let mut dispatch = self.dispatch_route(request, server, route)?;
+        if let Dispatch::Response(ref mut response) = dispatch {
+            apply_route_headers(route, response);
+            apply_cors_headers(route, request, response);
+        }
+        Ok(dispatch)
+    }
+
+    /// User named by a request's valid Basic credentials for a route, or None when
+    /// they are missing or wrong. The user file is read for each request, so
+    /// changes to it apply without a restart.
+    fn authenticate(&self, request: &HttpRequest, route: &RouteConfig) -> ServerResult<Option<String>> {
+        let (user, password) = match request.get_header("authorization").and_then(|value| auth::parse_basic_credentials(value)) {
+            Some(credentials) => credentials,
+            None => return Ok(None),
+        };
+        let path = route.auth_user_file.as_deref().unwrap_or_default();
+        let users = auth::UserFile::load(path)?;
+        Ok(users.verify(&user, &password).then_some(user))
+    }
+
+    /// Handle a request within the route it matched
+    fn dispatch_route(&self, request: &mut HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<Dispatch> {
+        // Clients refused by the route's allow/deny rules learn nothing more about it
+        let client_ip = request.client_ip.or(request.remote_addr.map(|addr| addr.ip()));
+        if !crate::config::is_access_allowed(&route.access_rules, client_ip) {
+            return Ok(Dispatch::Response(self.error_manager_for(server).generate_error_response(
+                HttpStatus::Forbidden,
+                Some("Access denied for this client")
+            )));
+        }
 
         // Check request body size limits
-        if request.body.len() > server.max_body_size {
-            return Ok(self.error_manager.generate_error_response(
+        if request.body_len() > server.max_body_size {
+            return Ok(Dispatch::Response(self.error_manager_for(server).generate_error_response(
                 HttpStatus::RequestEntityTooLarge,
                 Some(&format!("Request body size ({} bytes) exceeds limit ({} bytes)",
-                    request.body.len(), server.max_body_size))
-            ));
+                    request.body_len(), server.max_body_size))
+            )));
+        }
+
+        // CORS preflights are answered before the route's own method rules apply
+        if let Some(response) = self.handle_cors_preflight(request, route) {
+            return Ok(Dispatch::Response(response));
+        }
+
+        // OPTIONS is answered automatically, whether or not the route lists it
+        if request.method == crate::http::HttpMethod::OPTIONS {
+            return Ok(Dispatch::Response(self.handle_options(route)));
+        }
+
+        if let Some(realm) = &route.auth_basic {
+            match self.authenticate(request, route) {
+                Ok(Some(user)) => request.remote_user = Some(user),
+                Ok(None) => {
+                    let mut response = self.error_manager_for(server).generate_error_response(HttpStatus::Unauthorized, None);
+                    response.add_header("WWW-Authenticate", &format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm));
+                    return Ok(Dispatch::Response(response));
+                }
+                Err(e) => {
+                    eprintln!("Authentication error on route {}: {}", route.path, e);
+                    return Ok(Dispatch::Response(self.error_manager_for(server).generate_error_response(HttpStatus::InternalServerError, None)));
+                }
+            }
         }
 
         // Check if method is allowed
         if !route.methods.contains(&request.method.as_str().to_string()) {
-            return Ok(self.error_manager.generate_error_response(
+            let mut response = self.error_manager_for(server).generate_error_response(
                 HttpStatus::MethodNotAllowed,
                 Some(&format!("Method {} not allowed for this route", request.method.as_str()))
-            ));
+            );
+            response.add_header("Allow", &route.methods.join(", "));
+            return Ok(Dispatch::Response(response));
+        }
+
+        // Redirects apply whatever the method, ahead of CGI and static handling
+        if let Some((code, target)) = &route.redirect {
+            return Ok(Dispatch::Response(redirect_response(request, *code, target)));
+        }
+
+        // Upgrades on a WebSocket route go to its handler instead of the files
+        if let Some(handler) = &route.websocket {
+            if handshake::is_upgrade_request(request) {
+                return Ok(match handshake::handshake_response(request) {
+                    Ok(response) => Dispatch::WebSocket { response, handler: handler.clone() },
+                    Err(status) => {
+                        let mut response = self.error_manager_for(server).generate_error_response(status, Some("Invalid WebSocket handshake"));
+                        if status == HttpStatus::UpgradeRequired {
+                            response.add_header("Sec-WebSocket-Version", handshake::WEBSOCKET_VERSION);
+                        }
+                        Dispatch::Response(response)
+                    }
+                });
+            }
+        }
+
+        // CGI takes precedence over static handling; files the route has no
+        // interpreter for are served like any other
+        let is_cgi = route.cgi_interpreter(&request.path).is_some() && matches!(request.method,
+            crate::http::HttpMethod::GET | crate::http::HttpMethod::HEAD | crate::http::HttpMethod::POST);
+        let result = if is_cgi {
+            self.spawn_cgi(request, server, route)
+        } else {
+            self.handle_method(request, server, route).map(Dispatch::Response)
+        };
+
+        // Request errors, such as paths escaping the route root, are answered
+        // with their own status rather than treated as server errors
+        match result {
+            Err(ServerError::Http(kind)) => {
+                Ok(Dispatch::Response(self.error_manager_for(server).generate_error_response(
+                    kind.status(),
+                    Some(kind.description())
+                )))
+            }
+            result => result,
         }
+    }
 
-        // Handle based on method
+    /// Route a non-CGI request to the handler for its method
+    fn handle_method(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
         match request.method {
             crate::http::HttpMethod::GET => self.handle_get(request, server, route),
             crate::http::HttpMethod::POST => self.handle_post(request, server, route),
             crate::http::HttpMethod::DELETE => self.handle_delete(request, server, route),
             crate::http::HttpMethod::HEAD => self.handle_head(request, server, route),
-            _ => Ok(self.error_manager.generate_error_response(
-                HttpStatus::MethodNotAllowed,
-                Some(&format!("Method {} not implemented", request.method.as_str()))
-            )),
+            crate::http::HttpMethod::PUT => self.handle_put(request, server, route),
+            crate::http::HttpMethod::PATCH => self.handle_patch(request, server, route),
+            crate::http::HttpMethod::OPTIONS => Ok(self.handle_options(route)),
+            // Recognised and allowed by the route, but not something this server does
+            crate::http::HttpMethod::TRACE | crate::http::HttpMethod::CONNECT => {
+                Ok(self.error_manager_for(server).generate_error_response(
+                    HttpStatus::NotImplemented,
+                    Some(&format!("Method {} is not implemented", request.method.as_str()))
+                ))
+            }
         }
     }
 
     /// Handle GET requests
     fn handle_get(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
-        // Handle redirects
-        if let Some(redirect_url) = &route.redirect {
-            return Ok(HttpResponse::redirect(redirect_url, false));
-        }
-
-        // Handle CGI first (takes precedence)
-        if route.cgi.is_some() {
-            return self.handle_cgi(request, server, route);
-        }
-
         // Get root directory
         let root = route.root.as_ref()
             .ok_or_else(|| ServerError::Config("Route has no root directory".to_string()))?;
 
         // Resolve file path using static file server
-        let file_path = self.static_server.resolve_path(root, &request.path, &route.path)?;
+        let file_path = match self.find_static_target(root, request, route)? {
+            Some(file_path) => file_path,
+            None => {
+                return Ok(self.error_manager_for(server).generate_error_response(HttpStatus::NotFound, Some("File not found")));
+            }
+        };
 
-        // Check if path exists
-        if !file_path.exists() {
-            return Ok(self.error_manager.generate_error_response(HttpStatus::NotFound, Some("File not found")));
-        }
+        // Text files are labelled with the route's charset, else the server's
+        let charset = route.charset.as_deref().unwrap_or(&server.default_charset);
 
         // Handle directories
         if file_path.is_dir() {
-            return self.static_server.serve_directory(
+            let response = self.static_server_for(server).serve_directory(
                 &file_path,
-                route.index.as_deref(),
-                route.directory_listing,
+                &route.index,
+                route.markdown_index,
+                route.directory_listing
+                    .then(|| ListingSort::from_query(request.query_params.get("sort").map(String::as_str))),
                 &request.path,
-            );
+                Some(charset),
+            )?;
+
+            // A directory that may not be listed is refused with the 403
+            // error page, unless the route sends such clients elsewhere
+            if let (HttpStatus::Forbidden, Some(target)) = (response.status, &route.directory_redirect) {
+                return Ok(redirect_response(request, HttpStatus::Found.as_u16(), target));
+            }
+            return Ok(response);
         }
 
         // Serve file using static file server
-        self.static_server.serve_file(&file_path)
+        self.static_server_for(server).serve_request_file(&file_path, request, Some(charset))
     }
 
-    /// Handle POST requests
-    fn handle_post(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
-        // Handle CGI first (takes precedence)
-        if route.cgi.is_some() {
-            return self.handle_cgi(request, server, route);
+    /// Path a GET request is served from: the request path if it exists, or
+    /// with `try_files` the first of the route's candidates that does
+    fn find_static_target(&self, root: &str, request: &HttpRequest, route: &RouteConfig) -> ServerResult<Option<std::path::PathBuf>> {
+        if route.try_files.is_empty() {
+            let file_path = self.static_server.resolve_path(root, route.canonical_root.as_deref(), &request.path, &route.path)?;
+            return Ok(file_path.exists().then_some(file_path));
+        }
+
+        for candidate in &route.try_files {
+            let candidate = candidate.replace("$uri", &request.path);
+            let file_path = self.static_server.resolve_path(root, route.canonical_root.as_deref(), &candidate, &route.path)?;
+            let found = if candidate.ends_with('/') { file_path.is_dir() } else { file_path.is_file() };
+            if found {
+                return Ok(Some(file_path));
+            }
         }
+        Ok(None)
+    }
 
+    /// Handle POST requests
+    fn handle_post(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
         // Handle file uploads
         if route.upload_enabled {
-            return self.handle_file_upload(request, route);
+            return self.handle_file_upload(request, server, route);
         }
 
         // Default POST handling
@@ -127,50 +468,185 @@ impl MethodHandler {
     }
 
     /// Handle DELETE requests
-    fn handle_delete(&self, request: &HttpRequest, _server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
+    fn handle_delete(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
         let root = route.root.as_ref()
             .ok_or_else(|| ServerError::Config("Route has no root directory".to_string()))?;
-        let file_path = self.static_server.resolve_path(root, &request.path, &route.path)?;
+        let file_path = self.static_server.resolve_path(root, route.canonical_root.as_deref(), &request.path, &route.path)?;
 
         if !file_path.exists() {
-            return Ok(self.error_manager.generate_error_response(HttpStatus::NotFound, Some("File not found")));
+            return Ok(self.error_manager_for(server).generate_error_response(HttpStatus::NotFound, Some("File not found")));
         }
 
         // Only allow deletion in upload directories for security
         if !route.upload_enabled {
-            return Ok(self.error_manager.generate_error_response(
+            return Ok(self.error_manager_for(server).generate_error_response(
                 HttpStatus::Forbidden,
                 Some("Deletion not allowed in this directory")
             ));
         }
 
         match fs::remove_file(&file_path) {
-            Ok(_) => Ok(HttpResponse::text(HttpStatus::NoContent, "")),
-            Err(_) => Ok(self.error_manager.generate_error_response(
+            Ok(_) => Ok(HttpResponse::no_content()),
+            Err(_) => Ok(self.error_manager_for(server).generate_error_response(
                 HttpStatus::InternalServerError,
                 Some("Failed to delete file")
             )),
         }
     }
 
-    /// Handle HEAD requests (like GET but without body)
+    /// Handle HEAD requests (like GET but without body).
+    /// The body is kept until the server has encoded the response, so the
+    /// headers match what a GET would get, and dropped just before sending.
     fn handle_head(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
-        let mut response = self.handle_get(request, server, route)?;
-        response.body.clear(); // Remove body for HEAD requests
-        response.add_header("Content-Length", "0");
-        Ok(response)
+        self.handle_get(request, server, route)
     }
 
 
 
+    /// Handle PUT requests: create or replace the file at the request path
+    fn handle_put(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
+        if !route.upload_enabled {
+            return Ok(self.error_manager_for(server).generate_error_response(
+                HttpStatus::Forbidden,
+                Some("Uploads not allowed in this directory")
+            ));
+        }
+
+        let root = route.root.as_ref()
+            .ok_or_else(|| ServerError::Config("Route has no root directory".to_string()))?;
+        let file_path = self.static_server.resolve_path(root, route.canonical_root.as_deref(), &request.path, &route.path)?;
+
+        if file_path.is_dir() || !file_path.parent().map(|parent| parent.is_dir()).unwrap_or(false) {
+            return Ok(self.error_manager_for(server).generate_error_response(
+                HttpStatus::Conflict,
+                Some("Target is a directory or its parent does not exist")
+            ));
+        }
+
+        let existed = file_path.exists();
+        save_body(request, &file_path)
+            .map_err(|_| ServerError::Internal("Failed to write file".to_string()))?;
+
+        if existed {
+            Ok(HttpResponse::no_content())
+        } else {
+            let mut response = HttpResponse::text(HttpStatus::Created, "Created");
+            response.add_header("Location", &request.path);
+            Ok(response)
+        }
+    }
+
+    /// Handle PATCH requests: append the body to an existing file
+    fn handle_patch(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
+        if !route.upload_enabled {
+            return Ok(self.error_manager_for(server).generate_error_response(
+                HttpStatus::Forbidden,
+                Some("Modification not allowed in this directory")
+            ));
+        }
+
+        let root = route.root.as_ref()
+            .ok_or_else(|| ServerError::Config("Route has no root directory".to_string()))?;
+        let file_path = self.static_server.resolve_path(root, route.canonical_root.as_deref(), &request.path, &route.path)?;
+
+        if !file_path.is_file() {
+            return Ok(self.error_manager_for(server).generate_error_response(HttpStatus::NotFound, Some("File not found")));
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&file_path)
+            .map_err(|_| ServerError::Internal("Failed to open file".to_string()))?;
+        append_body(request, &mut file)
+            .map_err(|_| ServerError::Internal("Failed to write file".to_string()))?;
+
+        Ok(HttpResponse::no_content())
+    }
+
+    /// Handle OPTIONS requests: advertise the route's methods
+    fn handle_options(&self, route: &RouteConfig) -> HttpResponse {
+        let mut response = HttpResponse::no_content();
+        response.add_header("Allow", &route.methods.join(", "));
+        response
+    }
+
+    /// Answer a CORS preflight for a route with CORS configured.
+    /// A preflight asking for a method or header the route does not allow
+    /// gets a plain OPTIONS response, which browsers treat as a refusal.
+    fn handle_cors_preflight(&self, request: &HttpRequest, route: &RouteConfig) -> Option<HttpResponse> {
+        if !is_cors_preflight(request) || !route.cors.is_enabled() {
+            return None;
+        }
+        let origin = request.get_header("origin")?;
+        let requested_method = request.get_header("access-control-request-method")?;
+
+        let allowed_methods = if route.cors.allow_methods.is_empty() {
+            &route.methods
+        } else {
+            &route.cors.allow_methods
+        };
+        let requested_headers = request.get_header_joined("access-control-request-headers");
+        let headers_allowed = requested_headers.as_deref().is_none_or(|value| {
+            value.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .all(|name| route.cors.allows_header(name))
+        });
+
+        let allowed = route.cors.allows_origin(origin)
+            && allowed_methods.iter().any(|method| method == requested_method)
+            && headers_allowed;
+        if !allowed {
+            return Some(self.handle_options(route));
+        }
+
+        let mut response = HttpResponse::no_content();
+        add_allow_origin(&route.cors, origin, &mut response);
+        response.add_header("Access-Control-Allow-Methods", &allowed_methods.join(", "));
+        if let Some(headers) = requested_headers {
+            response.add_header("Access-Control-Allow-Headers", &headers);
+        }
+        Some(response)
+    }
+
     /// Handle file upload
-    fn handle_file_upload(&self, request: &HttpRequest, route: &RouteConfig) -> ServerResult<HttpResponse> {
-        // Simple file upload implementation
-        // In a real implementation, you'd parse multipart/form-data
+    fn handle_file_upload(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
         let upload_path = route.root.as_ref()
             .ok_or_else(|| ServerError::Config("Upload route has no root directory".to_string()))?;
 
-        // For now, just save the raw body as a file
+        // multipart/form-data: save each file part under its original name
+        if let Some(boundary) = request.get_header("content-type")
+            .and_then(|content_type| multipart::boundary_from_content_type(content_type))
+        {
+            let parts = match multipart::parse_multipart(&request.body, &boundary) {
+                Ok(parts) => parts,
+                Err(e) => {
+                    return Ok(self.error_manager_for(server).generate_error_response(HttpStatus::BadRequest, Some(&e.to_string())));
+                }
+            };
+
+            let mut saved = Vec::new();
+            for part in parts.iter().filter(|part| part.is_file()) {
+                let filename = match part.filename.as_deref().and_then(multipart::sanitize_filename) {
+                    Some(filename) => filename,
+                    None => continue, // Empty file input or unusable name
+                };
+
+                let file_path = Path::new(upload_path).join(&filename);
+                fs::write(&file_path, &part.data)
+                    .map_err(|_| ServerError::Internal("Failed to save uploaded file".to_string()))?;
+                saved.push(filename);
+            }
+
+            if saved.is_empty() {
+                return Ok(self.error_manager_for(server).generate_error_response(HttpStatus::BadRequest, Some("No files in upload")));
+            }
+
+            return Ok(HttpResponse::text(HttpStatus::Created,
+                &format!("Files uploaded successfully: {}", saved.join(", "))));
+        }
+
+        // Any other body is saved raw
         let filename = format!("upload_{}.bin", std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
@@ -178,40 +654,228 @@ impl MethodHandler {
 
         let file_path = Path::new(upload_path).join(filename);
 
-        fs::write(&file_path, &request.body)
-            .map_err(|_| ServerError::Http("Failed to save uploaded file".to_string()))?;
+        save_body(request, &file_path)
+            .map_err(|_| ServerError::Internal("Failed to save uploaded file".to_string()))?;
 
         Ok(HttpResponse::text(HttpStatus::Created,
             &format!("File uploaded successfully: {}", file_path.display())))
     }
 
-    /// Handle CGI requests
-    fn handle_cgi(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<HttpResponse> {
+    /// Spawn the CGI script for a request
+    fn spawn_cgi(&self, request: &HttpRequest, server: &crate::config::ServerConfig, route: &RouteConfig) -> ServerResult<Dispatch> {
         // Get root directory
         let root = route.root.as_ref()
             .ok_or_else(|| ServerError::Config("CGI route has no root directory".to_string()))?;
 
         // Resolve script path
-        let script_path = self.static_server.resolve_path(root, &request.path, &route.path)?;
+        let script_path = self.static_server.resolve_path(root, route.canonical_root.as_deref(), &request.path, &route.path)?;
 
         // Check if script exists
         if !script_path.exists() {
-            return Ok(self.error_manager.generate_error_response(HttpStatus::NotFound, Some("CGI script not found")));
+            return Ok(Dispatch::Response(
+                self.error_manager_for(server).generate_error_response(HttpStatus::NotFound, Some("CGI script not found"))
+            ));
         }
 
         if !script_path.is_file() {
-            return Ok(self.error_manager.generate_error_response(HttpStatus::Forbidden, Some("Not a valid CGI script")));
+            return Ok(Dispatch::Response(
+                self.error_manager_for(server).generate_error_response(HttpStatus::Forbidden, Some("Not a valid CGI script"))
+            ));
+        }
+
+        // Scripts run directly need the execute bit their `#!` line relies on
+        if route.cgi_interpreter(&request.path) == Some(EXEC_INTERPRETER) && !executor::is_executable(&script_path) {
+            return Ok(Dispatch::Response(
+                self.error_manager_for(server).generate_error_response(HttpStatus::Forbidden, Some("CGI script is not executable"))
+            ));
         }
 
-        // Execute CGI script
+        if route.cgi_cache && !route.event_stream {
+            if let Some(response) = self.cached_cgi_response(request) {
+                return Ok(Dispatch::Response(response));
+            }
+        }
+
+        // A burst of script requests must not fork without bound
+        let limiter = self.cgi_limiters.get(&server.key())
+            .ok_or_else(|| ServerError::Internal("No CGI limiter for server".to_string()))?;
+        let permit = match limiter.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                let mut response = self.error_manager_for(server).generate_error_response(
+                    HttpStatus::ServiceUnavailable,
+                    Some(&format!("All {} CGI process slots are in use", limiter.max()))
+                );
+                response.add_header("Retry-After", &CGI_RETRY_AFTER_SECS.to_string());
+                return Ok(Dispatch::Response(response));
+            }
+        };
+
+        // Each route may allow its scripts a different running time; event
+        // streams run until the script ends or the client leaves
+        let mut executor = self.cgi_executor_for(server).clone();
+        executor.set_timeout(if route.event_stream { Duration::MAX } else { Duration::from_secs(route.cgi_timeout) });
+
         let script_path_str = script_path.to_string_lossy();
-        Ok(self.cgi_executor.execute(request, server, route, &script_path_str)
-            .unwrap_or_else(|e| {
-                eprintln!("CGI execution error: {}", e);
-                self.error_manager.generate_error_response(
-                    HttpStatus::InternalServerError,
-                    Some("CGI script execution failed")
-                )
-            }))
+        match executor.spawn(request, server, route, &script_path_str) {
+            Ok(mut process) => {
+                process.hold_permit(permit);
+                Ok(Dispatch::Cgi(process))
+            }
+            Err(e) => Ok(Dispatch::Response(self.finish_cgi(request, Err(e)))),
+        }
+    }
+
+    /// Answer from the cache, with `304 Not Modified` when the client already has the response
+    fn cached_cgi_response(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        let (mut response, etag) = self.cgi_cache.lock().ok()?.get(&CgiCacheKey::from_request(request))?;
+
+        if let Some(if_none_match) = request.get_header("if-none-match") {
+            if crate::routing::static_files::if_none_match_matches(if_none_match, &etag) {
+                let mut response = HttpResponse::new(HttpStatus::NotModified);
+                response.add_header("ETag", &etag);
+                return Some(response);
+            }
+        }
+
+        response.add_header("Date", &crate::utils::http_date::format(std::time::SystemTime::now()));
+        Some(response)
+    }
+
+    /// Turn the outcome of a CGI script into the response for its request
+    pub fn finish_cgi(&self, request: &HttpRequest, result: ServerResult<HttpResponse>) -> HttpResponse {
+        let mut response = result.unwrap_or_else(|e| {
+            eprintln!("CGI execution error: {}", e);
+            self.server_for(request)
+                .map_or(&self.cgi_executor, |server| self.cgi_executor_for(server))
+                .error_response(&e)
+        });
+
+        let host = request.get_header("host").map(|s| s.as_str());
+        let route = self.router.find_route(host, &request.path).ok().map(|(_, route, _)| route);
+
+        // Stored before anything specific to this request is added
+        if let Some(route) = route.filter(|route| route.cgi_cache && !route.event_stream) {
+            if let Ok(mut cache) = self.cgi_cache.lock() {
+                let ttl = Duration::from_secs(route.cgi_cache_ttl);
+                cache.insert(CgiCacheKey::from_request(request), &mut response, ttl);
+            }
+        }
+
+        if request.method == crate::http::HttpMethod::HEAD {
+            response.strip_body();
+        }
+
+        // Events must reach the client as they are sent, not from a cache
+        if route.is_some_and(|route| route.event_stream) && response.status == HttpStatus::Ok {
+            response.set_content_type("text/event-stream");
+            response.add_header("Cache-Control", "no-cache");
+        }
+
+        if let Some(route) = route {
+            apply_route_headers(route, &mut response);
+            apply_cors_headers(route, request, &mut response);
+        }
+
+        response
+    }
+}
+
+/// Redirect for a route's configured status and target. Clients may turn
+/// other methods into GET when following 301 and 302, so those requests get
+/// the method-preserving 308 and 307 instead.
+fn redirect_response(request: &HttpRequest, code: u16, target: &str) -> HttpResponse {
+    let status = HttpStatus::from_redirect_code(code).unwrap_or(HttpStatus::Found);
+    let status = match request.method {
+        crate::http::HttpMethod::GET | crate::http::HttpMethod::HEAD => status,
+        _ => match status {
+            HttpStatus::MovedPermanently => HttpStatus::PermanentRedirect,
+            HttpStatus::Found => HttpStatus::TemporaryRedirect,
+            status => status,
+        },
+    };
+
+    let mut response = HttpResponse::redirect_with_status(status, target);
+    if request.method == crate::http::HttpMethod::HEAD {
+        response.strip_body();
+    }
+    response
+}
+
+/// Check if a server block has a listener bound to `addr`
+fn listens_on(server: &ServerConfig, addr: std::net::SocketAddr) -> bool {
+    server.ports.contains(&addr.port())
+        && server.host.parse::<std::net::IpAddr>()
+            .map_or(true, |ip| ip.is_unspecified() || ip == addr.ip())
+}
+
+/// Method a POST asks to be handled as, from the `X-HTTP-Method-Override`
+/// header or else a `_method` field of a form-urlencoded body
+fn method_override(request: &HttpRequest) -> Option<String> {
+    if let Some(name) = request.get_header("x-http-method-override") {
+        return Some(name.trim().to_string());
+    }
+
+    request.form_params().get("_method").cloned()
+}
+
+/// Write a request body to `path`, moving it there when it was spooled to disk
+fn save_body(request: &HttpRequest, path: &Path) -> std::io::Result<()> {
+    match &request.body_file {
+        Some(spooled) => spooled.persist(path),
+        None => fs::write(path, &request.body),
+    }
+}
+
+/// Append a request body to `file`, reading it back when it was spooled to disk
+fn append_body(request: &HttpRequest, file: &mut fs::File) -> std::io::Result<()> {
+    match &request.body_file {
+        Some(spooled) => std::io::copy(&mut fs::File::open(spooled.path())?, file).map(|_| ()),
+        None => file.write_all(&request.body),
+    }
+}
+
+/// Check if a request is a CORS preflight rather than an ordinary OPTIONS request
+fn is_cors_preflight(request: &HttpRequest) -> bool {
+    request.method == crate::http::HttpMethod::OPTIONS
+        && request.get_header("origin").is_some()
+        && request.get_header("access-control-request-method").is_some()
+}
+
+/// Tell the browser an allowed origin may read the response.
+/// Preflights are left alone; their handler has already decided.
+fn apply_cors_headers(route: &RouteConfig, request: &HttpRequest, response: &mut HttpResponse) {
+    if !route.cors.is_enabled() || is_cors_preflight(request)
+        || response.get_header("Access-Control-Allow-Origin").is_some()
+    {
+        return;
+    }
+    if let Some(origin) = request.get_header("origin") {
+        if route.cors.allows_origin(origin) {
+            add_allow_origin(&route.cors, origin, response);
+        }
+    }
+}
+
+/// Add Access-Control-Allow-Origin for an allowed origin. `*` cannot be
+/// used with credentials, so credentialed routes echo the origin instead.
+fn add_allow_origin(cors: &CorsConfig, origin: &str, response: &mut HttpResponse) {
+    let wildcard = cors.allow_origins.iter().any(|allowed| allowed == "*");
+    if wildcard && !cors.allow_credentials {
+        response.add_header("Access-Control-Allow-Origin", "*");
+        return;
+    }
+
+    response.add_header("Access-Control-Allow-Origin", origin);
+    response.add_header("Vary", "Origin");
+    if cors.allow_credentials {
+        response.add_header("Access-Control-Allow-Credentials", "true");
+    }
+}
+
+/// Add a route's configured headers, replacing any the handler set
+fn apply_route_headers(route: &RouteConfig, response: &mut HttpResponse) {
+    for (name, value) in &route.headers {
+        response.add_header(name, value);
     }
 }
@@ -4,12 +4,17 @@
  * HTTP/1.1 protocol implementation
  */
 
+pub mod auth;
+pub mod compression;
 pub mod request;
 pub mod response;
 pub mod headers;
+pub mod json;
 pub mod methods;
+pub mod multipart;
 pub mod status;
 
-pub use request::{HttpRequest, HttpRequestParser, HttpMethod, HttpVersion};
-pub use response::HttpResponse;
+pub use request::{HttpRequest, HttpRequestParser, HttpMethod, HttpVersion, SpooledBody};
+pub use response::{BodySource, HttpResponse};
 pub use headers::{Headers, HeaderNames};
+pub use json::JsonValue;
@@ -0,0 +1,297 @@
+/*!
+ * multipart/form-data parsing (RFC 7578)
+ */
+
+use crate::error::{HttpErrorKind, ServerError, ServerResult};
+
+/// A single part of a multipart/form-data body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    /// Check if this part is a file upload rather than a plain form field
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+}
+
+/// Extract the boundary from a `multipart/form-data` Content-Type value
+pub fn boundary_from_content_type(content_type: &str) -> Option<String> {
+    let mut params = split_params(content_type).into_iter();
+    let media_type = params.next()?;
+    if !media_type.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| unquote(value.trim()))
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Split a multipart/form-data body into its parts
+pub fn parse_multipart(body: &[u8], boundary: &str) -> ServerResult<Vec<Part>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    // Skip the preamble up to the first delimiter
+    let mut pos = if body.starts_with(&delimiter) {
+        0
+    } else {
+        find_delimiter(body, &delimiter, 0)
+            .ok_or_else(|| ServerError::Http(HttpErrorKind::MalformedBody("multipart body has no boundary".to_string())))?
+            + 2
+    };
+
+    let mut parts = Vec::new();
+    loop {
+        pos += delimiter.len();
+
+        // The final delimiter is followed by "--"
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        pos = skip_line_end(body, pos)
+            .ok_or_else(|| ServerError::Http(HttpErrorKind::MalformedBody("malformed multipart boundary line".to_string())))?;
+
+        let (headers_end, body_start) = find_headers_end(body, pos)
+            .ok_or_else(|| ServerError::Http(HttpErrorKind::MalformedBody("unterminated multipart part headers".to_string())))?;
+        let headers = std::str::from_utf8(&body[pos..headers_end])
+            .map_err(|_| ServerError::Http(HttpErrorKind::MalformedBody("invalid UTF-8 in multipart headers".to_string())))?;
+
+        let data_end = find_delimiter(body, &delimiter, body_start)
+            .ok_or_else(|| ServerError::Http(HttpErrorKind::MalformedBody("multipart body is missing its closing boundary".to_string())))?;
+
+        parts.push(parse_part(headers, &body[body_start..data_end])?);
+
+        // Step over the CRLF that belongs to the delimiter
+        pos = data_end + 2;
+    }
+}
+
+/// Reduce a client-supplied filename to a safe single path component
+pub fn sanitize_filename(filename: &str) -> Option<String> {
+    // Browsers may send full client paths, e.g. "C:\Users\me\photo.png"
+    let base = filename.rsplit(['/', '\\']).next().unwrap_or("");
+
+    let sanitized: String = base.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    let sanitized = sanitized.trim_start_matches('.');
+
+    if sanitized.is_empty() {
+        None
+    } else {
+        Some(sanitized.to_string())
+    }
+}
+
+/// Build a part from its header block and data
+fn parse_part(headers: &str, data: &[u8]) -> ServerResult<Part> {
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+
+    for line in headers.lines() {
+        let (header, value) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => continue,
+        };
+
+        if header.trim().eq_ignore_ascii_case("content-disposition") {
+            for param in split_params(value).into_iter().skip(1) {
+                if let Some((key, value)) = param.split_once('=') {
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "name" => name = Some(unquote(value.trim())),
+                        "filename" => filename = Some(unquote(value.trim())),
+                        _ => {}
+                    }
+                }
+            }
+        } else if header.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+
+    let name = name
+        .ok_or_else(|| ServerError::Http(HttpErrorKind::MalformedBody("multipart part has no name".to_string())))?;
+
+    Ok(Part {
+        name,
+        filename,
+        content_type,
+        data: data.to_vec(),
+    })
+}
+
+/// Find the next "\r\n--boundary" that is a real delimiter line.
+/// Returns the position of the leading CRLF.
+fn find_delimiter(body: &[u8], delimiter: &[u8], from: usize) -> Option<usize> {
+    let mut search = from;
+    while let Some(offset) = find_sequence(&body[search..], b"\r\n") {
+        let crlf = search + offset;
+        let after = crlf + 2;
+        if body[after..].starts_with(delimiter) {
+            // The boundary must be followed by "--", whitespace or a line end,
+            // otherwise it is just data that happens to share a prefix
+            match body.get(after + delimiter.len()) {
+                None | Some(b'-') | Some(b'\r') | Some(b'\n') | Some(b' ') | Some(b'\t') => return Some(crlf),
+                _ => {}
+            }
+        }
+        search = crlf + 2;
+    }
+    None
+}
+
+/// Skip transport padding and the line end after a delimiter
+fn skip_line_end(body: &[u8], mut pos: usize) -> Option<usize> {
+    while matches!(body.get(pos), Some(b' ') | Some(b'\t')) {
+        pos += 1;
+    }
+    if body[pos..].starts_with(b"\r\n") {
+        Some(pos + 2)
+    } else if body[pos..].starts_with(b"\n") {
+        Some(pos + 1)
+    } else {
+        None
+    }
+}
+
+/// Find the blank line ending a part's headers: (headers end, data start)
+fn find_headers_end(body: &[u8], from: usize) -> Option<(usize, usize)> {
+    // A part may have no headers at all
+    if body[from..].starts_with(b"\r\n") {
+        return Some((from, from + 2));
+    }
+    find_sequence(&body[from..], b"\r\n\r\n").map(|offset| (from + offset, from + offset + 4))
+}
+
+/// Split a header value on ';' outside of quoted strings
+fn split_params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&value[start..]);
+    params
+}
+
+/// Remove surrounding quotes and backslash escapes from a parameter value
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => {
+            let mut result = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' {
+                    if let Some(next) = chars.next() {
+                        result.push(next);
+                    }
+                } else {
+                    result.push(c);
+                }
+            }
+            result
+        }
+        None => value.to_string(),
+    }
+}
+
+fn find_sequence(buffer: &[u8], pattern: &[u8]) -> Option<usize> {
+    buffer.windows(pattern.len()).position(|window| window == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----WebKitFormBoundary7MA4YWxkTrZu0gW";
+
+    fn two_part_body(file_data: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"title\"\r\n\r\n");
+        body.extend_from_slice(b"Holiday");
+        body.extend_from_slice(format!("\r\n--{}\r\n", BOUNDARY).as_bytes());
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"photo\"; filename=\"photo.png\"\r\n");
+        body.extend_from_slice(b"Content-Type: image/png\r\n\r\n");
+        body.extend_from_slice(file_data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    #[test]
+    fn test_text_field_and_file_part() {
+        let file_data = b"\x89PNG\r\n\x1a\n\x00\x01binary";
+        let parts = parse_multipart(&two_part_body(file_data), BOUNDARY).unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "title");
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].data, b"Holiday");
+        assert!(!parts[0].is_file());
+
+        assert_eq!(parts[1].name, "photo");
+        assert_eq!(parts[1].filename.as_deref(), Some("photo.png"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("image/png"));
+        assert_eq!(parts[1].data, file_data);
+    }
+
+    #[test]
+    fn test_boundary_prefix_inside_binary_data() {
+        // Looks like a delimiter but continues with more boundary characters
+        let file_data = format!("before\r\n--{}X after", BOUNDARY).into_bytes();
+        let parts = parse_multipart(&two_part_body(&file_data), BOUNDARY).unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[1].data, file_data);
+    }
+
+    #[test]
+    fn test_missing_closing_boundary() {
+        let body = format!("--{}\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue", BOUNDARY);
+        assert!(parse_multipart(body.as_bytes(), BOUNDARY).is_err());
+    }
+
+    #[test]
+    fn test_boundary_from_content_type() {
+        assert_eq!(
+            boundary_from_content_type("multipart/form-data; boundary=abc123"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            boundary_from_content_type("Multipart/Form-Data; charset=utf-8; boundary=\"a;b\""),
+            Some("a;b".to_string())
+        );
+        assert_eq!(boundary_from_content_type("application/json"), None);
+        assert_eq!(boundary_from_content_type("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("photo.png"), Some("photo.png".to_string()));
+        assert_eq!(sanitize_filename("../../etc/passwd"), Some("passwd".to_string()));
+        assert_eq!(sanitize_filename("C:\\Users\\me\\my photo.png"), Some("my_photo.png".to_string()));
+        assert_eq!(sanitize_filename(".."), None);
+        assert_eq!(sanitize_filename(""), None);
+    }
+}
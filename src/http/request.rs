@@ -2,10 +2,18 @@
  * HTTP request parsing
  */
 
-use crate::error::{ServerError, ServerResult};
+use crate::error::{HttpErrorKind, ServerResult};
+use crate::http::json::{self, JsonValue};
 use crate::session::CookieJar;
 use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 
 /// HTTP request method
 #[derive(Debug, Clone, PartialEq)]
@@ -17,9 +25,12 @@ pub enum HttpMethod {
     PUT,
     OPTIONS,
     PATCH,
+    TRACE,
+    CONNECT,
 }
 
 impl HttpMethod {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_uppercase().as_str() {
             "GET" => Some(HttpMethod::GET),
@@ -29,6 +40,8 @@ impl HttpMethod {
             "PUT" => Some(HttpMethod::PUT),
             "OPTIONS" => Some(HttpMethod::OPTIONS),
             "PATCH" => Some(HttpMethod::PATCH),
+            "TRACE" => Some(HttpMethod::TRACE),
+            "CONNECT" => Some(HttpMethod::CONNECT),
             _ => None,
         }
     }
@@ -42,6 +55,8 @@ impl HttpMethod {
             HttpMethod::PUT => "PUT",
             HttpMethod::OPTIONS => "OPTIONS",
             HttpMethod::PATCH => "PATCH",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::CONNECT => "CONNECT",
         }
     }
 }
@@ -54,6 +69,7 @@ pub enum HttpVersion {
 }
 
 impl HttpVersion {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "HTTP/1.0" => Some(HttpVersion::Http10),
@@ -76,11 +92,31 @@ pub struct HttpRequest {
     pub method: HttpMethod,
     pub uri: String,
     pub version: HttpVersion,
-    pub headers: HashMap<String, String>,
+    /// Header values by lowercase name, in the order they were received
+    pub headers: HashMap<String, Vec<String>>,
     pub body: Vec<u8>,
+    /// Body written to a temporary file instead of `body`, for routes with `upload_to_tempfile on`
+    pub body_file: Option<SpooledBody>,
     pub query_params: HashMap<String, String>,
     pub path: String,
     pub cookies: CookieJar,
+    pub session_id: Option<String>,
+    /// Parameters captured by the matched route's `:name` segments
+    pub path_params: HashMap<String, String>,
+    /// Address of the connected client, set by the server
+    pub remote_addr: Option<SocketAddr>,
+    /// Address the client connected to, set by the server
+    pub local_addr: Option<SocketAddr>,
+    /// Client address after trusted proxies are looked through, set by the server
+    pub client_ip: Option<IpAddr>,
+    /// Host and port named by an absolute-form request target
+    pub authority: Option<String>,
+    /// User who passed the route's Basic authentication, set by the server
+    pub remote_user: Option<String>,
+    /// Whether a header arrived folded over several lines and was joined by the parser
+    pub folded_headers: bool,
+    /// Form fields decoded from the body on first use
+    form_params: OnceLock<HashMap<String, String>>,
 }
 
 impl HttpRequest {
@@ -91,35 +127,70 @@ impl HttpRequest {
             version: HttpVersion::Http11,
             headers: HashMap::new(),
             body: Vec::new(),
+            body_file: None,
             query_params: HashMap::new(),
             path: "/".to_string(),
             cookies: CookieJar::new(),
+            session_id: None,
+            path_params: HashMap::new(),
+            remote_addr: None,
+            local_addr: None,
+            client_ip: None,
+            authority: None,
+            remote_user: None,
+            folded_headers: false,
+            form_params: OnceLock::new(),
         }
     }
 
-    /// Get header value (case-insensitive)
+    /// Get the first value of a header (case-insensitive)
     pub fn get_header(&self, name: &str) -> Option<&String> {
+        self.get_header_all(name).into_iter().next()
+    }
+
+    /// Get every value of a repeated header (case-insensitive)
+    pub fn get_header_all(&self, name: &str) -> Vec<&String> {
         let name_lower = name.to_lowercase();
         self.headers.iter()
             .find(|(k, _)| k.to_lowercase() == name_lower)
-            .map(|(_, v)| v)
+            .map(|(_, values)| values.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Get a header's values combined into one field value, per RFC 7230 section 3.2.2
+    pub fn get_header_joined(&self, name: &str) -> Option<String> {
+        let values = self.get_header_all(name);
+        if values.is_empty() {
+            return None;
+        }
+
+        // Cookie pairs are separated by semicolons rather than commas
+        let separator = if name.eq_ignore_ascii_case("cookie") { "; " } else { ", " };
+        Some(values.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(separator))
+    }
+
+    /// Tokens listed in the `Connection` header, lowercased, across every
+    /// occurrence of the header (RFC 9110 section 7.6.1)
+    pub fn connection_tokens(&self) -> Vec<String> {
+        self.get_header_all("connection").iter()
+            .flat_map(|value| value.split(','))
+            .map(|token| token.trim().to_ascii_lowercase())
+            .filter(|token| !token.is_empty())
+            .collect()
     }
 
     /// Check if connection should be kept alive
     pub fn keep_alive(&self) -> bool {
+        let tokens = self.connection_tokens();
+        if tokens.iter().any(|token| token == "close") {
+            return false;
+        }
+
         match self.version {
-            HttpVersion::Http11 => {
-                // HTTP/1.1 defaults to keep-alive unless explicitly closed
-                self.get_header("connection")
-                    .map(|v| v.to_lowercase() != "close")
-                    .unwrap_or(true)
-            }
-            HttpVersion::Http10 => {
-                // HTTP/1.0 defaults to close unless explicitly keep-alive
-                self.get_header("connection")
-                    .map(|v| v.to_lowercase() == "keep-alive")
-                    .unwrap_or(false)
-            }
+            // HTTP/1.1 defaults to keep-alive unless explicitly closed
+            HttpVersion::Http11 => true,
+            // HTTP/1.0 defaults to close unless explicitly keep-alive
+            HttpVersion::Http10 => tokens.iter().any(|token| token == "keep-alive"),
         }
     }
 
@@ -129,20 +200,170 @@ impl HttpRequest {
             .and_then(|v| v.parse().ok())
     }
 
+    /// Size of the body, whether held in memory or spooled to disk
+    pub fn body_len(&self) -> usize {
+        self.body_file.as_ref().map_or(self.body.len(), |file| file.len() as usize)
+    }
+
+    /// Fields of an `application/x-www-form-urlencoded` body, decoded once and kept.
+    /// Empty for other content types; a repeated key keeps its last value and
+    /// pairs that fail to decode are skipped.
+    pub fn form_params(&self) -> &HashMap<String, String> {
+        self.form_params.get_or_init(|| {
+            let is_form = self.get_header("content-type")
+                .and_then(|content_type| content_type.split(';').next())
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"));
+            if !is_form {
+                return HashMap::new();
+            }
+
+            let body = match self.body_file {
+                Some(ref file) => fs::read(file.path()).unwrap_or_default(),
+                None => self.body.clone(),
+            };
+            String::from_utf8_lossy(&body)
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .filter_map(|pair| {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    Some((url_decode(key).ok()?, url_decode(value).ok()?))
+                })
+                .collect()
+        })
+    }
+
+    /// Parse a JSON body, which must be sent with a JSON Content-Type
+    pub fn json_value(&self) -> ServerResult<JsonValue> {
+        let content_type = self.get_header("content-type").map(String::as_str).unwrap_or("");
+        if !json::is_json_content_type(content_type) {
+            return Err(HttpErrorKind::UnsupportedMediaType(format!("expected application/json, got {:?}", content_type)).into());
+        }
+
+        match self.body_file {
+            Some(ref file) => json::parse_json(&fs::read(file.path())?),
+            None => json::parse_json(&self.body),
+        }
+    }
+
     /// Check if request has chunked transfer encoding
     pub fn is_chunked(&self) -> bool {
         self.get_header("transfer-encoding")
             .map(|v| v.to_lowercase().contains("chunked"))
             .unwrap_or(false)
     }
+
+    /// Add a header value, keeping earlier values of the same header
+    /// (name is stored lowercase, matching the parser)
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.entry(name.to_lowercase()).or_default().push(value.to_string());
+    }
+}
+
+impl Default for HttpRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Temporary file removed when the last handle to it is dropped
+#[derive(Debug)]
+struct TempPath(PathBuf);
+
+impl TempPath {
+    /// Create an empty file only this user can read, under the system temp directory
+    fn create() -> io::Result<(fs::File, Self)> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let name = format!("localhost_upload_{}_{}", std::process::id(), NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok((file, Self(path)))
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // Already gone if the body was moved into place
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Request body the parser wrote to a temporary file
+#[derive(Debug, Clone)]
+pub struct SpooledBody {
+    temp: Arc<TempPath>,
+    len: u64,
+}
+
+impl SpooledBody {
+    /// Location of the temporary file
+    pub fn path(&self) -> &Path {
+        &self.temp.0
+    }
+
+    /// Size of the body in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Check if the body is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Move the body to `destination`, copying it when a rename is not
+    /// possible, as across filesystems
+    pub fn persist(&self, destination: &Path) -> io::Result<()> {
+        if fs::rename(self.path(), destination).is_err() {
+            fs::copy(self.path(), destination)?;
+        }
+        Ok(())
+    }
 }
 
+/// Temporary file the body of the request being parsed is written to
+struct BodySpool {
+    file: fs::File,
+    temp: Arc<TempPath>,
+    written: u64,
+}
+
+impl BodySpool {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Bodies larger than this are spooled to disk on routes with `upload_to_tempfile on`
+pub const UPLOAD_SPOOL_THRESHOLD: usize = 64 * 1024;
+
+/// Default maximum number of header lines per request
+pub const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+
+/// Default maximum length of the request line in bytes
+pub const DEFAULT_MAX_REQUEST_LINE_BYTES: usize = 8 * 1024;
+
+/// Default maximum size of the header section in bytes
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// Default maximum length of one header line in bytes
+pub const DEFAULT_MAX_HEADER_LINE_BYTES: usize = 8 * 1024;
+
+/// Maximum length of a request path in bytes once percent-decoded
+pub const MAX_PATH_BYTES: usize = 4096;
+
 /// HTTP request parser state
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseState {
     RequestLine,
     Headers,
     Body,
+    ChunkedBody,
     Complete,
 }
 
@@ -153,6 +374,18 @@ pub struct HttpRequestParser {
     body_bytes_remaining: Option<usize>,
     buffer: Vec<u8>,
     headers_end_pos: Option<usize>,
+    chunk_bytes_remaining: Option<usize>,
+    in_chunk_trailer: bool,
+    max_body_size: usize,
+    max_request_line_bytes: usize,
+    max_header_count: usize,
+    max_header_bytes: usize,
+    max_header_line_bytes: usize,
+    /// Whether obsolete line folding is joined into the previous header instead of rejected
+    merge_folded_headers: bool,
+    continue_pending: bool,
+    body_start_pending: bool,
+    spool: Option<BodySpool>,
 }
 
 impl HttpRequestParser {
@@ -163,9 +396,51 @@ impl HttpRequestParser {
             body_bytes_remaining: None,
             buffer: Vec::new(),
             headers_end_pos: None,
+            chunk_bytes_remaining: None,
+            in_chunk_trailer: false,
+            max_body_size: crate::defaults::DEFAULT_MAX_BODY_SIZE,
+            max_request_line_bytes: DEFAULT_MAX_REQUEST_LINE_BYTES,
+            max_header_count: DEFAULT_MAX_HEADER_COUNT,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_header_line_bytes: DEFAULT_MAX_HEADER_LINE_BYTES,
+            merge_folded_headers: false,
+            continue_pending: false,
+            body_start_pending: false,
+            spool: None,
         }
     }
 
+    /// Set the maximum decoded body size accepted for chunked requests
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    /// Set the maximum length of the request line in bytes
+    pub fn set_max_request_line_bytes(&mut self, max_request_line_bytes: usize) {
+        self.max_request_line_bytes = max_request_line_bytes;
+    }
+
+    /// Set the maximum number of header lines accepted
+    pub fn set_max_header_count(&mut self, max_header_count: usize) {
+        self.max_header_count = max_header_count;
+    }
+
+    /// Set the maximum size of the header section in bytes
+    pub fn set_max_header_bytes(&mut self, max_header_bytes: usize) {
+        self.max_header_bytes = max_header_bytes;
+    }
+
+    /// Set the maximum length of one header line in bytes
+    pub fn set_max_header_line_bytes(&mut self, max_header_line_bytes: usize) {
+        self.max_header_line_bytes = max_header_line_bytes;
+    }
+
+    /// Set whether folded header lines are joined to the header they continue
+    /// (RFC 7230 section 3.2.4) rather than rejected with 400
+    pub fn set_merge_folded_headers(&mut self, merge_folded_headers: bool) {
+        self.merge_folded_headers = merge_folded_headers;
+    }
+
     /// Parse HTTP request from buffer data
     /// Returns (Option<HttpRequest>, bytes_consumed)
     pub fn parse(&mut self, data: &[u8]) -> ServerResult<(Option<HttpRequest>, usize)> {
@@ -176,12 +451,15 @@ impl HttpRequestParser {
         loop {
             match self.state {
                 ParseState::RequestLine => {
-                    if let Some(line_end) = self.find_sequence(&self.buffer, b"\r\n") {
+                    let line_end = find_line_end(&self.buffer);
+                    self.check_request_line_limit(line_end.map(|(end, _)| end))?;
+
+                    if let Some((line_end, next_line)) = line_end {
                         let line_bytes = &self.buffer[..line_end];
                         let line = str::from_utf8(line_bytes)
-                            .map_err(|_| ServerError::Http("Invalid UTF-8 in request line".to_string()))?
+                            .map_err(|_| HttpErrorKind::MalformedRequestLine("invalid UTF-8".to_string()))?
                             .to_string();
-                        self.buffer.drain(..line_end + 2);
+                        self.buffer.drain(..next_line);
                         self.parse_request_line(&line)?;
                         self.state = ParseState::Headers;
                     } else {
@@ -189,16 +467,31 @@ impl HttpRequestParser {
                     }
                 }
                 ParseState::Headers => {
-                    if let Some(headers_end) = self.find_sequence(&self.buffer, b"\r\n\r\n") {
+                    let headers_end = find_headers_end(&self.buffer);
+                    self.check_header_limits(headers_end.map(|(end, _)| end))?;
+
+                    if let Some((headers_end, body_start)) = headers_end {
                         let headers_bytes = &self.buffer[..headers_end];
                         let headers_str = str::from_utf8(headers_bytes)
-                            .map_err(|_| ServerError::Http("Invalid UTF-8 in headers".to_string()))?
+                            .map_err(|_| HttpErrorKind::MalformedHeader("invalid UTF-8".to_string()))?
                             .to_string();
-                        self.buffer.drain(..headers_end + 4);
+                        self.buffer.drain(..body_start);
                         self.parse_headers(&headers_str)?;
 
+                        // The authority of an absolute-form target overrides any Host header
+                        if let Some(authority) = self.request.authority.clone() {
+                            self.request.headers.insert("host".to_string(), vec![authority]);
+                        }
+
                         // Determine if we need to read body
-                        if let Some(content_length) = self.request.content_length() {
+                        if let Some(content_length) = self.body_length()? {
+                            // Refuse an oversized body before any of it is buffered
+                            if content_length > self.max_body_size {
+                                return Err(HttpErrorKind::BodyTooLarge(format!(
+                                    "{} bytes exceeds limit ({} bytes)", content_length, self.max_body_size
+                                )).into());
+                            }
+
                             if content_length > 0 {
                                 self.body_bytes_remaining = Some(content_length);
                                 self.state = ParseState::Body;
@@ -213,17 +506,31 @@ impl HttpRequestParser {
                                 self.state = ParseState::Complete;
                             }
                         } else if self.request.is_chunked() {
-                            // TODO: Implement chunked encoding
-                            return Err(ServerError::Http("Chunked encoding not yet implemented".to_string()));
+                            self.state = ParseState::ChunkedBody;
                         } else {
                             self.state = ParseState::Complete;
                         }
+
+                        if self.state != ParseState::Complete {
+                            self.check_expectation()?;
+                            self.body_start_pending = true;
+                        }
                     } else {
                         break; // Need more data
                     }
                 }
                 ParseState::Body => {
-                    if let Some(remaining) = self.body_bytes_remaining {
+                    if let (Some(remaining), Some(spool)) = (self.body_bytes_remaining, self.spool.as_mut()) {
+                        // Spooled bodies go to disk as they arrive rather than all at once
+                        let available = std::cmp::min(remaining, self.buffer.len());
+                        spool.write(&self.buffer[..available])?;
+                        self.buffer.drain(..available);
+                        self.body_bytes_remaining = Some(remaining - available);
+                        if remaining > available {
+                            break; // Need more data
+                        }
+                        self.state = ParseState::Complete;
+                    } else if let Some(remaining) = self.body_bytes_remaining {
                         let available = self.buffer.len();
                         if available >= remaining {
                             // We have all the body data - keep it as binary
@@ -245,7 +552,17 @@ impl HttpRequestParser {
                         self.state = ParseState::Complete;
                     }
                 }
+                ParseState::ChunkedBody => {
+                    if self.parse_chunked_body()? {
+                        self.state = ParseState::Complete;
+                    } else {
+                        break; // Need more data
+                    }
+                }
                 ParseState::Complete => {
+                    if let Some(spool) = self.spool.take() {
+                        self.request.body_file = Some(SpooledBody { temp: spool.temp, len: spool.written });
+                    }
                     let consumed = initial_buffer_len + data.len() - self.buffer.len();
                     return Ok((Some(self.request.clone()), consumed));
                 }
@@ -256,6 +573,121 @@ impl HttpRequestParser {
         Ok((None, consumed)) // Need more data
     }
 
+    /// Reject a request line over the configured length, without waiting
+    /// for its line ending when the limit is already exceeded
+    fn check_request_line_limit(&self, line_end: Option<usize>) -> ServerResult<()> {
+        let length = line_end.unwrap_or(self.buffer.len());
+        if length > self.max_request_line_bytes {
+            return Err(HttpErrorKind::UriTooLong(format!(
+                "request line exceeds {} bytes", self.max_request_line_bytes
+            )).into());
+        }
+        Ok(())
+    }
+
+    /// Reject header sections over the configured limits, without waiting
+    /// for the terminating empty line when the limit is already exceeded
+    fn check_header_limits(&self, headers_end: Option<usize>) -> ServerResult<()> {
+        let headers = &self.buffer[..headers_end.unwrap_or(self.buffer.len())];
+
+        if headers.len() > self.max_header_bytes {
+            return Err(HttpErrorKind::HeaderTooLarge(format!(
+                "header section exceeds {} bytes", self.max_header_bytes
+            )).into());
+        }
+
+        let longest_line = headers.split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line).len())
+            .max()
+            .unwrap_or(0);
+        if longest_line > self.max_header_line_bytes {
+            return Err(HttpErrorKind::HeaderTooLarge(format!(
+                "header line exceeds {} bytes", self.max_header_line_bytes
+            )).into());
+        }
+
+        // Only complete lines count; each ends in LF whether or not CR precedes it
+        let line_count = headers.iter().filter(|&&b| b == b'\n').count();
+        if line_count > self.max_header_count {
+            return Err(HttpErrorKind::HeaderTooLarge(format!(
+                "more than {} header lines", self.max_header_count
+            )).into());
+        }
+
+        Ok(())
+    }
+
+    /// Decode as much of a chunked body as the buffer allows.
+    /// Returns true once the final chunk and any trailers have been consumed.
+    fn parse_chunked_body(&mut self) -> ServerResult<bool> {
+        loop {
+            if self.in_chunk_trailer {
+                // Trailer headers are read and discarded until the empty line
+                match self.find_sequence(&self.buffer, b"\r\n") {
+                    Some(0) => {
+                        self.buffer.drain(..2);
+                        return Ok(true);
+                    }
+                    Some(line_end) => {
+                        self.buffer.drain(..line_end + 2);
+                    }
+                    None => return Ok(false),
+                }
+            } else if let Some(remaining) = self.chunk_bytes_remaining {
+                if remaining > 0 {
+                    let available = std::cmp::min(remaining, self.buffer.len());
+                    if available == 0 {
+                        return Ok(false);
+                    }
+                    match self.spool.as_mut() {
+                        Some(spool) => {
+                            spool.write(&self.buffer[..available])?;
+                            self.buffer.drain(..available);
+                        }
+                        None => self.request.body.extend(self.buffer.drain(..available)),
+                    }
+                    self.chunk_bytes_remaining = Some(remaining - available);
+                    continue;
+                }
+
+                // Chunk data must be followed by CRLF
+                if self.buffer.len() < 2 {
+                    return Ok(false);
+                }
+                if &self.buffer[..2] != b"\r\n" {
+                    return Err(HttpErrorKind::MalformedBody("missing CRLF after chunk data".to_string()).into());
+                }
+                self.buffer.drain(..2);
+                self.chunk_bytes_remaining = None;
+            } else {
+                let line_end = match self.find_sequence(&self.buffer, b"\r\n") {
+                    Some(pos) => pos,
+                    None => return Ok(false),
+                };
+                let line = str::from_utf8(&self.buffer[..line_end])
+                    .map_err(|_| HttpErrorKind::MalformedBody("invalid UTF-8 in chunk size line".to_string()))?;
+
+                // Ignore chunk extensions after ';'
+                let size_str = line.split(';').next().unwrap_or("").trim();
+                let chunk_size = usize::from_str_radix(size_str, 16)
+                    .map_err(|_| HttpErrorKind::MalformedBody(format!("invalid chunk size: {}", size_str)))?;
+                self.buffer.drain(..line_end + 2);
+
+                if chunk_size == 0 {
+                    self.in_chunk_trailer = true;
+                } else {
+                    let decoded = self.spool.as_ref().map_or(self.request.body.len(), |spool| spool.written as usize);
+                    if decoded.saturating_add(chunk_size) > self.max_body_size {
+                        return Err(HttpErrorKind::BodyTooLarge(format!(
+                            "chunked body exceeds limit ({} bytes)", self.max_body_size
+                        )).into());
+                    }
+                    self.chunk_bytes_remaining = Some(chunk_size);
+                }
+            }
+        }
+    }
+
     /// Find a byte sequence in a buffer
     fn find_sequence(&self, buffer: &[u8], pattern: &[u8]) -> Option<usize> {
         buffer.windows(pattern.len()).position(|window| window == pattern)
@@ -265,26 +697,48 @@ impl HttpRequestParser {
     fn parse_request_line(&mut self, line: &str) -> ServerResult<()> {
         let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.len() != 3 {
-            return Err(ServerError::Http("Invalid request line".to_string()));
+            return Err(HttpErrorKind::MalformedRequestLine(line.to_string()).into());
         }
 
         // Parse method
         self.request.method = HttpMethod::from_str(parts[0])
-            .ok_or_else(|| ServerError::Http(format!("Unknown HTTP method: {}", parts[0])))?;
+            .ok_or_else(|| HttpErrorKind::UnknownMethod(parts[0].to_string()))?;
 
         // Parse URI and extract path and query parameters
-        self.request.uri = parts[1].to_string();
         self.parse_uri(parts[1])?;
 
         // Parse version
         self.request.version = HttpVersion::from_str(parts[2])
-            .ok_or_else(|| ServerError::Http(format!("Unsupported HTTP version: {}", parts[2])))?;
+            .ok_or_else(|| HttpErrorKind::UnsupportedVersion(parts[2].to_string()))?;
 
         Ok(())
     }
 
-    /// Parse URI to extract path and query parameters
+    /// Parse URI to extract path and query parameters.
+    /// An absolute-form target (`http://host/path`) keeps its authority and is
+    /// routed on the path alone; the asterisk-form `*` is allowed for OPTIONS.
     fn parse_uri(&mut self, uri: &str) -> ServerResult<()> {
+        if uri == "*" {
+            if self.request.method != HttpMethod::OPTIONS {
+                return Err(HttpErrorKind::MalformedRequestLine(format!("* target with {}", self.request.method.as_str())).into());
+            }
+            self.request.uri = uri.to_string();
+            self.request.path = uri.to_string();
+            return Ok(());
+        }
+
+        let uri = match split_absolute_uri(uri) {
+            Some((authority, target)) => {
+                if authority.is_empty() || authority.contains('@') {
+                    return Err(HttpErrorKind::MalformedRequestLine(format!("invalid authority in {}", uri)).into());
+                }
+                self.request.authority = Some(authority.to_string());
+                target
+            }
+            None => uri.to_string(),
+        };
+        self.request.uri = uri.clone();
+
         if let Some(query_start) = uri.find('?') {
             self.request.path = uri[..query_start].to_string();
             let query_string = &uri[query_start + 1..];
@@ -295,8 +749,7 @@ impl HttpRequestParser {
 
         // URL decode the path
         self.request.path = url_decode(&self.request.path)?;
-
-        Ok(())
+        check_path(&self.request.path)
     }
 
     /// Parse query parameters
@@ -314,27 +767,152 @@ impl HttpRequestParser {
         Ok(())
     }
 
+    /// Work out the declared body length, rejecting framing that two parsers
+    /// could read differently: Content-Length alongside Transfer-Encoding,
+    /// differing Content-Length values, or a non-numeric Content-Length
+    fn body_length(&self) -> ServerResult<Option<usize>> {
+        let values = self.request.get_header_all("content-length");
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        if self.request.get_header("transfer-encoding").is_some() {
+            return Err(HttpErrorKind::MalformedHeader(
+                "both Content-Length and Transfer-Encoding present".to_string()
+            ).into());
+        }
+
+        let mut length = None;
+        for value in values.iter().flat_map(|value| value.split(',')) {
+            let value = value.trim();
+            let parsed = if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+                value.parse::<usize>().ok()
+            } else {
+                None
+            };
+            let parsed = parsed.ok_or_else(|| HttpErrorKind::MalformedHeader(
+                format!("invalid Content-Length: {}", value)
+            ))?;
+
+            if length.is_some_and(|length| length != parsed) {
+                return Err(HttpErrorKind::MalformedHeader("conflicting Content-Length values".to_string()).into());
+            }
+            length = Some(parsed);
+        }
+
+        Ok(length)
+    }
+
+    /// Handle an Expect header on a request that has a body still to come
+    fn check_expectation(&mut self) -> ServerResult<()> {
+        let expect = match self.request.get_header("expect") {
+            Some(expect) => expect.to_lowercase(),
+            None => return Ok(()),
+        };
+
+        // HTTP/1.0 clients do not understand interim responses
+        if self.request.version != HttpVersion::Http11 {
+            return Ok(());
+        }
+
+        if expect != "100-continue" {
+            return Err(HttpErrorKind::ExpectationFailed(expect.to_string()).into());
+        }
+
+        // Clients that did not wait for the interim response need none
+        self.continue_pending = self.buffer.is_empty();
+        Ok(())
+    }
+
+    /// Check whether a `100 Continue` should be sent now.
+    /// Returns true at most once per request.
+    pub fn take_continue(&mut self) -> bool {
+        std::mem::take(&mut self.continue_pending)
+    }
+
+    /// Check whether the headers of a request with a body have just been parsed.
+    /// Returns true at most once per request.
+    pub fn take_body_start(&mut self) -> bool {
+        std::mem::take(&mut self.body_start_pending)
+    }
+
+    /// Request whose headers have been parsed and whose body is still arriving
+    pub fn pending_request(&self) -> &HttpRequest {
+        &self.request
+    }
+
+    /// Write the rest of the body being read to a temporary file instead of
+    /// memory, moving any of it already decoded there too
+    pub fn spool_body(&mut self) -> io::Result<()> {
+        if self.spool.is_some() {
+            return Ok(());
+        }
+        let (file, temp) = TempPath::create()?;
+        let mut spool = BodySpool { file, temp: Arc::new(temp), written: 0 };
+        spool.write(&std::mem::take(&mut self.request.body))?;
+        self.spool = Some(spool);
+        Ok(())
+    }
+
     /// Parse HTTP headers
     fn parse_headers(&mut self, headers_str: &str) -> ServerResult<()> {
+        let mut fields: Vec<(String, String)> = Vec::new();
+
         for line in headers_str.lines() {
             if line.is_empty() {
                 continue;
             }
 
-            if let Some(colon_pos) = line.find(':') {
-                let name = line[..colon_pos].trim().to_lowercase();
-                let value = line[colon_pos + 1..].trim().to_string();
+            // A line starting with whitespace continues the previous header's value
+            if line.starts_with([' ', '\t']) {
+                let (name, value) = match fields.last_mut() {
+                    Some(field) => field,
+                    None => return Err(HttpErrorKind::MalformedHeader("folded line before any header".to_string()).into()),
+                };
+                if !self.merge_folded_headers {
+                    return Err(HttpErrorKind::MalformedHeader(format!("obsolete line folding in header {}", name)).into());
+                }
+                if line.contains(['\r', '\0']) {
+                    return Err(HttpErrorKind::MalformedHeader(format!("invalid value for header {}", name)).into());
+                }
+                self.request.folded_headers = true;
+                let continuation = line.trim();
+                if !continuation.is_empty() {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(continuation);
+                }
+                continue;
+            }
 
-                // Parse cookies if this is a Cookie header
-                if name == "cookie" {
-                    self.request.cookies.parse_cookie_header(&value);
+            if let Some(colon_pos) = line.find(':') {
+                // A header echoed back must not be able to split the response:
+                // names are tokens, with no whitespace before the colon, and
+                // values hold no CR, LF or NUL
+                let name = &line[..colon_pos];
+                if !is_token(name) {
+                    return Err(HttpErrorKind::MalformedHeader(format!("invalid header name: {:?}", name)).into());
+                }
+                let value = &line[colon_pos + 1..];
+                if value.contains(['\r', '\n', '\0']) {
+                    return Err(HttpErrorKind::MalformedHeader(format!("invalid value for header {}", name)).into());
                 }
 
-                self.request.headers.insert(name, value);
+                fields.push((name.to_lowercase(), value.trim().to_string()));
             } else {
-                return Err(ServerError::Http(format!("Invalid header line: {}", line)));
+                return Err(HttpErrorKind::MalformedHeader(line.to_string()).into());
             }
         }
+
+        for (name, value) in fields {
+            // Parse cookies if this is a Cookie header
+            if name == "cookie" {
+                self.request.cookies.parse_cookie_header(&value);
+            }
+
+            self.request.headers.entry(name).or_default().push(value);
+        }
         Ok(())
     }
 
@@ -345,37 +923,138 @@ impl HttpRequestParser {
         self.body_bytes_remaining = None;
         self.buffer.clear();
         self.headers_end_pos = None;
+        self.chunk_bytes_remaining = None;
+        self.in_chunk_trailer = false;
+        self.continue_pending = false;
+        self.body_start_pending = false;
+        self.spool = None;
+    }
+
+    /// Reset for the next request on a connection, keeping any bytes the
+    /// client pipelined after the request just parsed
+    pub fn reset_for_next_request(&mut self) {
+        let pipelined = std::mem::take(&mut self.buffer);
+        self.reset();
+        self.buffer = pipelined;
+    }
+
+    /// Give back buffer space beyond `capacity` bytes
+    pub fn shrink_buffer(&mut self, capacity: usize) {
+        self.buffer.shrink_to(capacity);
+    }
+
+    /// Check if bytes of a further request are already buffered
+    pub fn has_buffered_data(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Take the bytes buffered after the request just parsed, for a
+    /// connection that switches to another protocol
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
     }
 
     /// Check if parsing is complete
     pub fn is_complete(&self) -> bool {
         self.state == ParseState::Complete
     }
+
+    /// Check if the request line and headers have been fully parsed
+    pub fn headers_complete(&self) -> bool {
+        !matches!(self.state, ParseState::RequestLine | ParseState::Headers)
+    }
 }
 
-/// URL decode a string
-fn url_decode(s: &str) -> ServerResult<String> {
-    let mut result = String::new();
-    let mut chars = s.chars();
+impl Default for HttpRequestParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check if a string is an RFC 7230 token, as header names must be
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Find the end of the first line, accepting a bare LF as well as CRLF.
+/// Returns where the line's content ends and where the next line starts;
+/// a CR anywhere else stays part of the line.
+fn find_line_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    let lf = buffer.iter().position(|&b| b == b'\n')?;
+    let end = if lf > 0 && buffer[lf - 1] == b'\r' { lf - 1 } else { lf };
+    Some((end, lf + 1))
+}
+
+/// Find the empty line ending a header section.
+/// Returns the length of the header lines, terminators included, and where the body starts.
+fn find_headers_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+    while let Some((end, next)) = find_line_end(&buffer[start..]) {
+        if end == 0 {
+            return Some((start, start + next));
+        }
+        start += next;
+    }
+    None
+}
+
+/// Split an absolute-form target such as `http://host:8080/path?q` into its
+/// authority and the origin-form target that follows, `/` when the path is empty
+fn split_absolute_uri(uri: &str) -> Option<(&str, String)> {
+    let (scheme, rest) = uri.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, target) = rest.split_at(end);
+    let target = if target.starts_with('/') {
+        target.to_string()
+    } else {
+        format!("/{}", target)
+    };
+    Some((authority, target))
+}
+
+/// Check a decoded path before it can reach the filesystem. Control
+/// characters are refused, NUL among them, since some system calls take it
+/// as the end of the name: `/safe.txt%00.cgi` must not open `/safe.txt`.
+pub fn check_path(path: &str) -> ServerResult<()> {
+    if path.len() > MAX_PATH_BYTES {
+        return Err(HttpErrorKind::UriTooLong(format!(
+            "decoded path of {} bytes exceeds limit ({} bytes)", path.len(), MAX_PATH_BYTES
+        )).into());
+    }
+    if path.chars().any(char::is_control) {
+        return Err(HttpErrorKind::InvalidEncoding("control character in path".to_string()).into());
+    }
+    Ok(())
+}
 
-    while let Some(ch) = chars.next() {
-        match ch {
-            '%' => {
-                let hex1 = chars.next()
-                    .ok_or_else(|| ServerError::Http("Invalid URL encoding".to_string()))?;
-                let hex2 = chars.next()
-                    .ok_or_else(|| ServerError::Http("Invalid URL encoding".to_string()))?;
+/// URL decode a string, assembling percent-encoded bytes as UTF-8
+pub(crate) fn url_decode(s: &str) -> ServerResult<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut input = s.bytes();
 
-                let hex_str = format!("{}{}", hex1, hex2);
-                let byte = u8::from_str_radix(&hex_str, 16)
-                    .map_err(|_| ServerError::Http("Invalid URL encoding".to_string()))?;
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => {
+                let hex = [
+                    input.next().ok_or_else(|| HttpErrorKind::InvalidEncoding("invalid percent-encoding".to_string()))?,
+                    input.next().ok_or_else(|| HttpErrorKind::InvalidEncoding("invalid percent-encoding".to_string()))?,
+                ];
+                let hex_str = str::from_utf8(&hex)
+                    .map_err(|_| HttpErrorKind::InvalidEncoding("invalid percent-encoding".to_string()))?;
+                let decoded = u8::from_str_radix(hex_str, 16)
+                    .map_err(|_| HttpErrorKind::InvalidEncoding("invalid percent-encoding".to_string()))?;
 
-                result.push(byte as char);
+                bytes.push(decoded);
             }
-            '+' => result.push(' '),
-            _ => result.push(ch),
+            b'+' => bytes.push(b' '),
+            _ => bytes.push(byte),
         }
     }
 
-    Ok(result)
+    String::from_utf8(bytes)
+        .map_err(|_| HttpErrorKind::InvalidEncoding("invalid UTF-8".to_string()).into())
 }
@@ -4,17 +4,51 @@
 
 use crate::error::HttpStatus;
 use crate::http::request::HttpVersion;
-use crate::session::Cookie;
-use std::collections::HashMap;
+use crate::session::{Cookie, CookieJar};
+use crate::utils::http_date;
 use std::fmt::Write;
+use std::fs::File;
+use std::sync::Arc;
+
+/// Where the bytes following the response head come from
+#[derive(Debug, Clone)]
+pub enum BodySource {
+    /// Bytes already in memory, framed and ready to send
+    Bytes(Vec<u8>),
+    /// A byte range of an open file, read as the socket accepts data
+    File { file: Arc<File>, offset: u64, len: u64 },
+    /// Chunks handed over later by a running producer such as a CGI script
+    Stream,
+}
+
+impl BodySource {
+    /// Number of bytes still to send, zero when not known in advance
+    pub fn len(&self) -> u64 {
+        match self {
+            BodySource::Bytes(bytes) => bytes.len() as u64,
+            BodySource::File { len, .. } => *len,
+            BodySource::Stream => 0,
+        }
+    }
+
+    /// Check if nothing is left to send
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
 /// HTTP response structure
 #[derive(Debug, Clone)]
 pub struct HttpResponse {
     pub version: HttpVersion,
     pub status: HttpStatus,
-    pub headers: HashMap<String, String>,
+    /// Headers in the order they are sent
+    pub headers: Vec<(String, String)>,
     pub body: Vec<u8>,
+    /// Body streamed from disk or a running producer in place of `body`
+    pub body_source: Option<BodySource>,
+    /// Headers describe a body that is not sent, as for HEAD
+    pub head_only: bool,
     pub cookies: Vec<Cookie>,
 }
 
@@ -24,21 +58,48 @@ impl HttpResponse {
         let mut response = Self {
             version: HttpVersion::Http11,
             status,
-            headers: HashMap::new(),
+            headers: Vec::new(),
             body: Vec::new(),
+            body_source: None,
+            head_only: false,
             cookies: Vec::new(),
         };
 
         // Add default headers
         response.add_header("Server", "localhost-http-server/0.1.0");
-        response.add_header("Date", &httpdate::fmt_http_date(std::time::SystemTime::now()));
+        response.add_header("Date", &http_date::format(std::time::SystemTime::now()));
 
         response
     }
 
-    /// Add a header
+    /// Add a header, replacing any header of the same name regardless of case.
+    /// `Set-Cookie` may repeat, so it is always appended.
     pub fn add_header(&mut self, name: &str, value: &str) {
-        self.headers.insert(name.to_string(), value.to_string());
+        let existing = if name.eq_ignore_ascii_case("Set-Cookie") {
+            None
+        } else {
+            self.headers.iter_mut().find(|(key, _)| key.eq_ignore_ascii_case(name))
+        };
+
+        match existing {
+            Some(header) => *header = (name.to_string(), value.to_string()),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Get a header value, ignoring case
+    pub fn get_header(&self, name: &str) -> Option<&String> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Remove every header of a name, ignoring case, returning the first value
+    pub fn remove_header(&mut self, name: &str) -> Option<String> {
+        let position = self.headers.iter().position(|(key, _)| key.eq_ignore_ascii_case(name))?;
+        let (_, value) = self.headers.remove(position);
+        self.headers.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        Some(value)
     }
 
     /// Set the response body
@@ -47,6 +108,48 @@ impl HttpResponse {
         self.add_header("Content-Length", &self.body.len().to_string());
     }
 
+    /// Stream the response body from a range of an open file
+    pub fn set_body_file(&mut self, file: File, offset: u64, len: u64) {
+        self.body.clear();
+        self.body_source = Some(BodySource::File { file: Arc::new(file), offset, len });
+        self.add_header("Content-Length", &len.to_string());
+    }
+
+    /// Send the body as chunks handed to the connection as they are produced
+    pub fn set_body_stream(&mut self) {
+        self.body.clear();
+        self.body_source = Some(BodySource::Stream);
+        self.remove_header("Content-Length");
+        self.add_header("Transfer-Encoding", "chunked");
+    }
+
+    /// Drop the body, whether in memory or streamed
+    pub fn clear_body(&mut self) {
+        self.body.clear();
+        self.body_source = None;
+    }
+
+    /// Drop the body but keep the Content-Length it would have had, as a HEAD response does
+    pub fn strip_body(&mut self) {
+        if self.head_only {
+            return;
+        }
+        let len = self.body_len();
+        self.clear_body();
+        self.head_only = true;
+        if !self.is_chunked() {
+            self.add_header("Content-Length", &len.to_string());
+        }
+    }
+
+    /// Length of the body, whether in memory or streamed
+    pub fn body_len(&self) -> u64 {
+        match &self.body_source {
+            Some(source) => source.len(),
+            None => self.body.len() as u64,
+        }
+    }
+
     /// Set the response body from string
     pub fn set_body_string(&mut self, body: String) {
         self.set_body(body.into_bytes());
@@ -57,8 +160,10 @@ impl HttpResponse {
         self.add_header("Content-Type", content_type);
     }
 
-    /// Enable/disable keep-alive
+    /// Enable/disable keep-alive, replacing any Connection and Keep-Alive headers already set
     pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.remove_header("Connection");
+        self.remove_header("Keep-Alive");
         if keep_alive {
             self.add_header("Connection", "keep-alive");
         } else {
@@ -76,6 +181,11 @@ impl HttpResponse {
         self.cookies.extend(cookies);
     }
 
+    /// Add every cookie in a jar, each sent on its own Set-Cookie line
+    pub fn add_cookie_jar(&mut self, jar: &CookieJar) {
+        self.cookies.extend(jar.cookies().values().cloned());
+    }
+
     /// Clear all cookies
     pub fn clear_cookies(&mut self) {
         self.cookies.clear();
@@ -86,8 +196,89 @@ impl HttpResponse {
         &self.cookies
     }
 
-    /// Convert response to bytes for transmission
+    /// Check if the response uses chunked transfer coding
+    pub fn is_chunked(&self) -> bool {
+        self.get_header("Transfer-Encoding")
+            .map(|value| value.to_ascii_lowercase().contains("chunked"))
+            .unwrap_or(false)
+    }
+
+    /// Check if the status forbids a message body: 1xx, 204 and 304
+    pub fn is_bodyless(&self) -> bool {
+        self.status.as_u16() < 200 || matches!(self.status, HttpStatus::NoContent | HttpStatus::NotModified)
+    }
+
+    /// Check if the receiver can tell where the body ends without closing the connection
+    pub fn is_framed(&self) -> bool {
+        if self.is_bodyless() || self.is_chunked() || self.head_only {
+            return true;
+        }
+        self.get_header("Content-Length")
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            == Some(self.body_len())
+    }
+
+    /// Make the message framing consistent with the body.
+    /// Must be called right before serialization.
+    pub fn finalize(&mut self) {
+        if self.is_bodyless() {
+            self.clear_body();
+            self.remove_header("Transfer-Encoding");
+            self.remove_header("Content-Length");
+            return;
+        }
+
+        // Content-Length describes the body a GET would have carried
+        if self.head_only {
+            return;
+        }
+
+        // HTTP/1.0 clients do not understand chunked framing, and streamed
+        // files are always sent with their length
+        let is_file = matches!(self.body_source, Some(BodySource::File { .. }));
+        if self.is_chunked() && (self.version == HttpVersion::Http10 || is_file) {
+            self.remove_header("Transfer-Encoding");
+        }
+
+        // Content-Length must match the body exactly, and never accompany chunked framing
+        self.remove_header("Content-Length");
+        if !self.is_chunked() {
+            self.add_header("Content-Length", &self.body_len().to_string());
+        }
+    }
+
+    /// Split the response into its serialized head and the body to follow it
+    pub fn into_parts(mut self) -> (Vec<u8>, BodySource) {
+        let head = self.head_bytes();
+        let body = match self.body_source.take() {
+            Some(source) => source,
+            None => BodySource::Bytes(self.framed_body()),
+        };
+        (head, body)
+    }
+
+    /// Convert response to bytes for transmission.
+    /// A body streamed from a file or producer is not included.
     pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.head_bytes();
+        if self.body_source.is_none() {
+            bytes.extend_from_slice(&self.framed_body());
+        }
+        bytes
+    }
+
+    /// Bytes the response takes on the wire, body included, counted without
+    /// serializing the body
+    pub fn wire_len(&self) -> usize {
+        let body_len = match &self.body_source {
+            Some(source) => source.len() as usize,
+            None => self.framed_body_len(),
+        };
+        self.head_bytes().len() + body_len
+    }
+
+    /// Serialize the status line, headers and the blank line after them
+    fn head_bytes(&self) -> Vec<u8> {
         let mut response = String::new();
 
         // Status line
@@ -109,13 +300,50 @@ impl HttpResponse {
         // Empty line to separate headers from body
         response.push_str("\r\n");
 
-        // Convert to bytes and append body
-        let mut bytes = response.into_bytes();
-        bytes.extend_from_slice(&self.body);
+        response.into_bytes()
+    }
+
+    /// The in-memory body with chunked framing applied if the headers ask for it
+    fn framed_body(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if self.head_only || self.is_bodyless() {
+            return bytes;
+        }
+        if self.is_chunked() {
+            if !self.body.is_empty() {
+                bytes.extend_from_slice(format!("{:x}\r\n", self.body.len()).as_bytes());
+                bytes.extend_from_slice(&self.body);
+                bytes.extend_from_slice(b"\r\n");
+            }
+            bytes.extend_from_slice(b"0\r\n\r\n");
+        } else {
+            bytes.extend_from_slice(&self.body);
+        }
 
         bytes
     }
 
+    /// Length `framed_body` would return
+    fn framed_body_len(&self) -> usize {
+        if self.head_only || self.is_bodyless() {
+            return 0;
+        }
+        if !self.is_chunked() {
+            return self.body.len();
+        }
+        let terminator = b"0\r\n\r\n".len();
+        if self.body.is_empty() {
+            terminator
+        } else {
+            format!("{:x}\r\n", self.body.len()).len() + self.body.len() + 2 + terminator
+        }
+    }
+
+    /// Create a `204 No Content` response, which has neither a body nor a content type
+    pub fn no_content() -> Self {
+        Self::new(HttpStatus::NoContent)
+    }
+
     /// Create a simple text response
     pub fn text(status: HttpStatus, text: &str) -> Self {
         let mut response = Self::new(status);
@@ -155,7 +383,11 @@ impl HttpResponse {
         } else {
             HttpStatus::Found
         };
+        Self::redirect_with_status(status, location)
+    }
 
+    /// Create a redirect response with a given 3xx status
+    pub fn redirect_with_status(status: HttpStatus, location: &str) -> Self {
         let mut response = Self::new(status);
         response.add_header("Location", location);
         response.set_body_string(format!(
@@ -181,17 +413,3 @@ impl HttpResponse {
         Self::html(status, &html)
     }
 }
-
-/// Simple HTTP date formatting (basic implementation)
-mod httpdate {
-    use std::time::{SystemTime, UNIX_EPOCH};
-
-    pub fn fmt_http_date(time: SystemTime) -> String {
-        let duration = time.duration_since(UNIX_EPOCH).unwrap();
-        let timestamp = duration.as_secs();
-
-        // This is a simplified implementation
-        // In a production server, you'd want proper RFC 2822 formatting
-        format!("Thu, 01 Jan 1970 00:00:{:02} GMT", timestamp % 60)
-    }
-}
@@ -12,6 +12,7 @@ pub mod cgi;
 pub mod session;
 pub mod error;
 pub mod utils;
+pub mod ws;
 
 // Re-export commonly used types
 pub use config::Config;
@@ -29,6 +30,12 @@ pub mod defaults {
     pub const DEFAULT_TIMEOUT: u64 = 30; // seconds
     pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024; // 1MB
     pub const DEFAULT_BUFFER_SIZE: usize = 8192; // 8KB
+    pub const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+    pub const DEFAULT_LISTEN_BACKLOG: u32 = 128;
+    pub const DEFAULT_HEADER_TIMEOUT: u64 = 10; // seconds
+    pub const DEFAULT_CGI_CACHE_TTL: u64 = 60; // seconds
     pub const MAX_CONNECTIONS: usize = 1024;
+    pub const MAX_CGI_WORKERS: usize = 256;
+    pub const DEFAULT_MAX_CGI_PROCESSES: usize = 16;
     pub const MAX_EVENTS: usize = 1024;
 }
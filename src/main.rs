@@ -54,6 +54,9 @@ fn main() {
         }
     };
 
+    // SIGHUP reloads the configuration from the same file
+    server.set_config_path(config_path);
+
     // Run the server (this will block until shutdown)
     if let Err(e) = server.run() {
         eprintln!("Server error: {}", e);
@@ -9,4 +9,4 @@ pub mod static_files;
 pub mod directory;
 
 pub use router::Router;
-pub use static_files::StaticFileServer;
+pub use static_files::{ListingSort, StaticFileServer};
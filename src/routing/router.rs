@@ -3,7 +3,22 @@
  */
 
 use crate::config::{Config, RouteConfig, ServerConfig};
-use crate::error::{ServerError, ServerResult};
+use crate::error::{HttpErrorKind, ServerError, ServerResult};
+use std::collections::HashMap;
+
+/// Parameters captured from `:name` segments of a route path
+pub type RouteParams = HashMap<String, String>;
+
+/// How a route path matched a request path, from least to most specific
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchKind {
+    /// Literal prefix or trailing `*` wildcard
+    Prefix,
+    /// Every segment matched, some through `:name` parameters
+    Param,
+    /// Literal path equal to the request path
+    Exact,
+}
 
 /// Router for matching URLs to route configurations
 pub struct Router {
@@ -18,29 +33,27 @@ impl Router {
         }
     }
 
-    /// Find the best matching route for a request
-    pub fn find_route(&self, host: Option<&str>, path: &str) -> ServerResult<(&ServerConfig, &RouteConfig)> {
+    /// Find the best matching route for a request, with any captured path parameters
+    pub fn find_route(&self, host: Option<&str>, path: &str) -> ServerResult<(&ServerConfig, &RouteConfig, RouteParams)> {
         // Find the appropriate server based on host header
         let server = self.find_server(host)?;
 
         // Find the best matching route within that server
-        let route = self.find_best_route(server, path)?;
+        let (route, params) = self.find_best_route(server, path)?;
 
-        Ok((server, route))
+        Ok((server, route, params))
     }
 
     /// Find the appropriate server based on host header
-    fn find_server(&self, host: Option<&str>) -> ServerResult<&ServerConfig> {
+    pub fn find_server(&self, host: Option<&str>) -> ServerResult<&ServerConfig> {
         if let Some(host_header) = host {
             // Extract hostname from host header (remove port if present)
             let hostname = host_header.split(':').next().unwrap_or(host_header);
 
-            // Look for a server with matching server_name
+            // Look for a server with a matching server_name
             for server in &self.servers {
-                if let Some(server_name) = &server.server_name {
-                    if server_name == hostname {
-                        return Ok(server);
-                    }
+                if server.server_names.iter().any(|name| host_matches(name, hostname)) {
+                    return Ok(server);
                 }
             }
         }
@@ -50,26 +63,42 @@ impl Router {
             .ok_or_else(|| ServerError::Config("No servers configured".to_string()))
     }
 
-    /// Find the best matching route within a server
-    fn find_best_route<'a>(&self, server: &'a ServerConfig, path: &str) -> ServerResult<&'a RouteConfig> {
-        let mut best_match: Option<&RouteConfig> = None;
-        let mut best_match_len = 0;
+    /// Find the best matching route within a server.
+    /// Exact matches beat parameter matches, which beat prefix and wildcard
+    /// matches; the longest route path breaks ties.
+    fn find_best_route<'a>(&self, server: &'a ServerConfig, path: &str) -> ServerResult<(&'a RouteConfig, RouteParams)> {
+        let mut best_match: Option<(&RouteConfig, RouteParams)> = None;
+        let mut best_rank = None;
 
-        // Find the longest matching route prefix
         for route in &server.routes {
-            if self.path_matches_route(path, &route.path) {
-                let match_len = route.path.len();
-                if match_len > best_match_len {
-                    best_match = Some(route);
-                    best_match_len = match_len;
+            if let Some((kind, params)) = self.match_route(path, &route.path) {
+                let rank = Some((kind, route.path.len()));
+                if rank > best_rank {
+                    best_match = Some((route, params));
+                    best_rank = rank;
                 }
             }
         }
 
-        best_match.ok_or_else(|| ServerError::Http("No matching route found".to_string()))
+        best_match.ok_or(ServerError::Http(HttpErrorKind::NoMatchingRoute))
+    }
+
+    /// Match a path against a route, capturing parameters
+    fn match_route(&self, path: &str, route_path: &str) -> Option<(MatchKind, RouteParams)> {
+        if is_pattern(route_path) {
+            return match_pattern(path, route_path);
+        }
+
+        if path == route_path {
+            Some((MatchKind::Exact, RouteParams::new()))
+        } else if self.path_matches_route(path, route_path) {
+            Some((MatchKind::Prefix, RouteParams::new()))
+        } else {
+            None
+        }
     }
 
-    /// Check if a path matches a route pattern
+    /// Check if a path matches a literal route prefix
     fn path_matches_route(&self, path: &str, route_path: &str) -> bool {
         // Exact match
         if path == route_path {
@@ -82,8 +111,7 @@ impl Router {
         }
 
         // Prefix match with implicit trailing slash
-        if path.starts_with(route_path) {
-            let remaining = &path[route_path.len()..];
+        if let Some(remaining) = path.strip_prefix(route_path) {
             return remaining.is_empty() || remaining.starts_with('/');
         }
 
@@ -97,29 +125,113 @@ impl Router {
 
     /// Validate router configuration
     pub fn validate(&self) -> ServerResult<()> {
-        if self.servers.is_empty() {
-            return Err(ServerError::Config("No servers configured".to_string()));
+        validate_servers(&self.servers)
+    }
+}
+
+/// Check that every server has routes whose paths start with `/` and are not repeated
+pub fn validate_servers(servers: &[ServerConfig]) -> ServerResult<()> {
+    if servers.is_empty() {
+        return Err(ServerError::Config("No servers configured".to_string()));
+    }
+
+    for server in servers {
+        if server.routes.is_empty() {
+            return Err(ServerError::Config(format!(
+                "Server {} has no routes configured", describe_server(server)
+            )));
         }
 
-        for server in &self.servers {
-            if server.routes.is_empty() {
-                return Err(ServerError::Config("Server has no routes configured".to_string()));
+        let mut seen = std::collections::HashSet::new();
+        for route in &server.routes {
+            if route.path.is_empty() {
+                return Err(ServerError::Config(format!(
+                    "Route path cannot be empty in server {}", describe_server(server)
+                )));
             }
 
-            for route in &server.routes {
-                if route.path.is_empty() {
-                    return Err(ServerError::Config("Route path cannot be empty".to_string()));
-                }
+            if !route.path.starts_with('/') {
+                return Err(ServerError::Config(format!(
+                    "Route path must start with '/': {} in server {}", route.path, describe_server(server)
+                )));
+            }
 
-                if !route.path.starts_with('/') {
-                    return Err(ServerError::Config(format!(
-                        "Route path must start with '/': {}", route.path
-                    )));
-                }
+            // A repeated path could never be reached
+            if !seen.insert(route.path.as_str()) {
+                return Err(ServerError::Config(format!(
+                    "Duplicate route {} in server {}", route.path, describe_server(server)
+                )));
             }
         }
+    }
+
+    Ok(())
+}
 
-        Ok(())
+/// Name a server in error messages by its first name, or else its address
+fn describe_server(server: &ServerConfig) -> String {
+    match (server.server_names.first(), server.ports.first()) {
+        (Some(name), _) => name.clone(),
+        (None, Some(port)) => format!("{}:{}", server.host, port),
+        (None, None) => server.host.clone(),
+    }
+}
+
+/// Check if a route path uses `:name` segments or a `*` wildcard
+fn is_pattern(route_path: &str) -> bool {
+    route_path.split('/').any(|segment| segment == "*" || segment.starts_with(':'))
+}
+
+/// Match a path segment by segment against a route pattern
+fn match_pattern(path: &str, pattern: &str) -> Option<(MatchKind, RouteParams)> {
+    let mut params = RouteParams::new();
+    let mut path_segments = path.split('/');
+
+    for segment in pattern.split('/') {
+        // A trailing wildcard accepts whatever remains
+        if segment == "*" {
+            return Some((MatchKind::Prefix, params));
+        }
+
+        let value = path_segments.next()?;
+        if let Some(name) = segment.strip_prefix(':') {
+            if value.is_empty() {
+                return None;
+            }
+            params.insert(name.to_string(), value.to_string());
+        } else if segment != value {
+            return None;
+        }
+    }
+
+    if path_segments.next().is_some() {
+        return None;
+    }
+    Some((MatchKind::Param, params))
+}
+
+/// Literal part of a route path before its first parameter or wildcard,
+/// used to strip the route from request paths
+pub fn literal_prefix(route_path: &str) -> &str {
+    let mut end = 0;
+    for segment in route_path.split_inclusive('/') {
+        let name = segment.trim_end_matches('/');
+        if name == "*" || name.starts_with(':') {
+            return &route_path[..end];
+        }
+        end += segment.len();
+    }
+    route_path
+}
+
+/// Check a hostname against a server name, where `*.example.com` matches
+/// any subdomain of `example.com` but not `example.com` itself
+pub(crate) fn host_matches(server_name: &str, hostname: &str) -> bool {
+    match server_name.strip_prefix("*.") {
+        Some(suffix) => hostname.len() > suffix.len() + 1
+            && hostname.as_bytes()[hostname.len() - suffix.len() - 1] == b'.'
+            && hostname[hostname.len() - suffix.len()..].eq_ignore_ascii_case(suffix),
+        None => server_name.eq_ignore_ascii_case(hostname),
     }
 }
 
@@ -134,29 +246,90 @@ mod tests {
                 ServerConfig {
                     host: "127.0.0.1".to_string(),
                     ports: vec![8080],
-                    server_name: Some("localhost".to_string()),
+                    unix_sockets: Vec::new(),
+                    server_names: vec!["localhost".to_string()],
                     error_pages: std::collections::HashMap::new(),
+                    error_pages_root: None,
+                    default_charset: "utf-8".to_string(),
                     max_body_size: 1024 * 1024,
+                    max_requests_per_connection: crate::defaults::DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+                    max_connections: crate::defaults::MAX_CONNECTIONS,
+                    keep_alive_enabled: true,
+                    method_override: false,
+                    strict_host: false,
+                    epoll_mode: crate::config::EpollMode::Level,
+                    access_log: crate::config::AccessLog::Off,
+                    listen_backlog: crate::defaults::DEFAULT_LISTEN_BACKLOG,
+                    reuse_port: false,
+                    tcp_nodelay: true,
+                    ssl: false,
+                    ssl_cert: None,
+                    ssl_key: None,
+                    header_timeout: crate::defaults::DEFAULT_HEADER_TIMEOUT,
+                    client_max_header_line: crate::http::request::DEFAULT_MAX_HEADER_LINE_BYTES,
+                    merge_folded_headers: false,
+                    status_endpoint: None,
+                    trusted_proxies: Vec::new(),
+                    rate_limit: None,
+                    mime_types: HashMap::new(),
+                    cgi_workers: 0,
+                    max_cgi_processes: crate::defaults::DEFAULT_MAX_CGI_PROCESSES,
                     routes: vec![
                         RouteConfig {
                             path: "/".to_string(),
                             methods: vec!["GET".to_string()],
                             redirect: None,
                             root: Some("www".to_string()),
-                            index: Some("index.html".to_string()),
+                            canonical_root: None,
+                            index: vec!["index.html".to_string()],
+                            try_files: Vec::new(),
                             cgi: None,
+                            cgi_interpreters: HashMap::new(),
                             directory_listing: false,
+                            markdown_index: false,
+                            directory_redirect: None,
                             upload_enabled: false,
+                            upload_to_tempfile: false,
+                            request_timeout: crate::defaults::DEFAULT_TIMEOUT,
+                            cgi_timeout: crate::defaults::DEFAULT_TIMEOUT,
+                            cgi_cache: false,
+                            cgi_cache_ttl: crate::defaults::DEFAULT_CGI_CACHE_TTL,
+                            event_stream: false,
+                            charset: None,
+                            headers: Vec::new(),
+                            cors: crate::config::CorsConfig::default(),
+                            websocket: None,
+                            auth_basic: None,
+                            auth_user_file: None,
+                            access_rules: Vec::new(),
                         },
                         RouteConfig {
                             path: "/api/".to_string(),
                             methods: vec!["GET".to_string(), "POST".to_string()],
                             redirect: None,
                             root: None,
-                            index: None,
+                            canonical_root: None,
+                            index: Vec::new(),
+                            try_files: Vec::new(),
                             cgi: Some("python3".to_string()),
+                            cgi_interpreters: HashMap::new(),
                             directory_listing: false,
+                            markdown_index: false,
+                            directory_redirect: None,
                             upload_enabled: false,
+                            upload_to_tempfile: false,
+                            request_timeout: crate::defaults::DEFAULT_TIMEOUT,
+                            cgi_timeout: crate::defaults::DEFAULT_TIMEOUT,
+                            cgi_cache: false,
+                            cgi_cache_ttl: crate::defaults::DEFAULT_CGI_CACHE_TTL,
+                            event_stream: false,
+                            charset: None,
+                            headers: Vec::new(),
+                            cors: crate::config::CorsConfig::default(),
+                            websocket: None,
+                            auth_basic: None,
+                            auth_user_file: None,
+                            access_rules: Vec::new(),
                         },
                     ],
                 },
@@ -170,15 +343,93 @@ mod tests {
         let router = Router::new(&config);
 
         // Test exact match
-        let (_, route) = router.find_route(Some("localhost"), "/").unwrap();
+        let (_, route, _) = router.find_route(Some("localhost"), "/").unwrap();
         assert_eq!(route.path, "/");
 
         // Test prefix match
-        let (_, route) = router.find_route(Some("localhost"), "/api/test").unwrap();
+        let (_, route, _) = router.find_route(Some("localhost"), "/api/test").unwrap();
         assert_eq!(route.path, "/api/");
 
         // Test longest match
-        let (_, route) = router.find_route(Some("localhost"), "/index.html").unwrap();
+        let (_, route, _) = router.find_route(Some("localhost"), "/index.html").unwrap();
         assert_eq!(route.path, "/");
     }
+
+    fn config_with_routes(paths: &[&str]) -> Config {
+        let mut config = create_test_config();
+        config.servers[0].routes = paths.iter()
+            .map(|path| RouteConfig { path: path.to_string(), ..RouteConfig::default() })
+            .collect();
+        config
+    }
+
+    #[test]
+    fn test_param_and_wildcard_routes() {
+        let config = config_with_routes(&["/", "/users/:id", "/users/me", "/static/*", "/files/:owner/*"]);
+        let router = Router::new(&config);
+
+        let (_, route, params) = router.find_route(Some("localhost"), "/users/42").unwrap();
+        assert_eq!(route.path, "/users/:id");
+        assert_eq!(params.get("id"), Some(&"42".to_string()));
+
+        // Exact beats parameter
+        let (_, route, params) = router.find_route(Some("localhost"), "/users/me").unwrap();
+        assert_eq!(route.path, "/users/me");
+        assert!(params.is_empty());
+
+        // Parameters match a single segment only
+        let (_, route, _) = router.find_route(Some("localhost"), "/users/42/posts").unwrap();
+        assert_eq!(route.path, "/");
+
+        let (_, route, _) = router.find_route(Some("localhost"), "/static/a/b.png").unwrap();
+        assert_eq!(route.path, "/static/*");
+
+        let (_, route, params) = router.find_route(Some("localhost"), "/files/ann/docs/cv.pdf").unwrap();
+        assert_eq!(route.path, "/files/:owner/*");
+        assert_eq!(params.get("owner"), Some(&"ann".to_string()));
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(literal_prefix("/static/*"), "/static/");
+        assert_eq!(literal_prefix("/users/:id/posts"), "/users/");
+        assert_eq!(literal_prefix("/api/"), "/api/");
+        assert_eq!(literal_prefix("/"), "/");
+    }
+
+    fn config_with_virtual_host() -> Config {
+        let mut config = create_test_config();
+        let mut vhost = config.servers[0].clone();
+        vhost.ports = vec![9090];
+        vhost.server_names = vec!["example.com".to_string(), "www.example.com".to_string(), "*.example.org".to_string()];
+        config.servers.push(vhost);
+        config
+    }
+
+    #[test]
+    fn test_find_server_multiple_names() {
+        let config = config_with_virtual_host();
+        let router = Router::new(&config);
+
+        assert_eq!(router.find_server(Some("example.com")).unwrap().ports, vec![9090]);
+        assert_eq!(router.find_server(Some("www.example.com:8080")).unwrap().ports, vec![9090]);
+        assert_eq!(router.find_server(Some("WWW.Example.com")).unwrap().ports, vec![9090]);
+
+        // Unknown hosts fall back to the default server
+        assert_eq!(router.find_server(Some("other.example.com")).unwrap().ports, vec![8080]);
+        assert_eq!(router.find_server(None).unwrap().ports, vec![8080]);
+    }
+
+    #[test]
+    fn test_find_server_wildcard_subdomain() {
+        let config = config_with_virtual_host();
+        let router = Router::new(&config);
+
+        assert_eq!(router.find_server(Some("api.example.org")).unwrap().ports, vec![9090]);
+        assert_eq!(router.find_server(Some("a.b.example.org")).unwrap().ports, vec![9090]);
+
+        // The wildcard needs a subdomain and a dot boundary
+        assert_eq!(router.find_server(Some("example.org")).unwrap().ports, vec![8080]);
+        assert_eq!(router.find_server(Some("badexample.org")).unwrap().ports, vec![8080]);
+    }
 }
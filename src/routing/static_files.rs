@@ -2,16 +2,61 @@
  * Static file serving implementation
  */
 
-use crate::error::{ServerError, ServerResult, HttpStatus};
-use crate::http::HttpResponse;
+use crate::error::{HttpErrorKind, ServerError, ServerResult, HttpStatus};
+use crate::error::pages::ErrorPageManager;
+use crate::http::{HttpRequest, HttpResponse};
+use crate::utils::{html_escape, http_date, markdown_to_html, url_encode};
 use crate::utils::mime::MimeDetector;
 use std::fs;
+use std::io::Read;
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
+/// Bodies larger than this are streamed from disk instead of read into memory
+pub const STREAM_THRESHOLD: u64 = 64 * 1024;
+
+/// How much of a file is read to sniff its type
+const SNIFF_BYTES: u64 = 512;
+
+/// File rendered as the index of a directory with `markdown_index on`
+const MARKDOWN_INDEX: &str = "README.md";
+
 /// Static file server
 pub struct StaticFileServer {
     mime_detector: MimeDetector,
+    error_manager: ErrorPageManager,
+}
+
+/// Order of the entries in a directory listing, directories always first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListingSort {
+    /// Alphabetically by name
+    #[default]
+    Name,
+    /// Oldest modification time first
+    Date,
+    /// Smallest file first
+    Size,
+}
+
+impl ListingSort {
+    /// Read the `sort` query parameter, falling back to name order
+    pub fn from_query(value: Option<&str>) -> Self {
+        match value {
+            Some("date") => ListingSort::Date,
+            Some("size") => ListingSort::Size,
+            _ => ListingSort::Name,
+        }
+    }
+}
+
+/// One row of a directory listing
+struct ListingEntry {
+    name: String,
+    is_dir: bool,
+    size: Option<u64>,
+    modified: Option<SystemTime>,
 }
 
 impl StaticFileServer {
@@ -19,29 +64,118 @@ impl StaticFileServer {
     pub fn new() -> Self {
         Self {
             mime_detector: MimeDetector::new(),
+            error_manager: ErrorPageManager::new(),
         }
     }
 
+    /// Set the error page manager used for error responses
+    pub fn set_error_manager(&mut self, error_manager: ErrorPageManager) {
+        self.error_manager = error_manager;
+    }
+
+    /// Set the charset given to text types when no route overrides it
+    pub fn set_charset(&mut self, charset: &str) {
+        self.mime_detector.set_charset(charset);
+    }
+
+    /// Register a content type for a file extension, replacing any built-in one
+    pub fn add_mime_type(&mut self, extension: &str, mime_type: &str) {
+        self.mime_detector.add_mapping(extension, mime_type);
+    }
+
     /// Serve a file from the filesystem
     pub fn serve_file(&self, file_path: &Path) -> ServerResult<HttpResponse> {
+        self.serve_file_ranged(file_path, None)
+    }
+
+    /// Serve a file for a request, honoring conditional and range headers.
+    /// A text file is labelled with `charset`, or the configured one when none is given.
+    pub fn serve_request_file(&self, file_path: &Path, request: &HttpRequest, charset: Option<&str>) -> ServerResult<HttpResponse> {
+        if let Ok(metadata) = fs::metadata(file_path) {
+            if metadata.is_file() && self.is_not_modified(request, &metadata) {
+                let mut response = HttpResponse::new(HttpStatus::NotModified);
+                self.add_caching_headers(&mut response, file_path)?;
+                return Ok(response);
+            }
+        }
+
+        let range = request.get_header("range").map(|s| s.as_str());
+        self.serve_file_as(file_path, range, charset)
+    }
+
+    /// Check If-None-Match / If-Modified-Since against the file's validators
+    fn is_not_modified(&self, request: &HttpRequest, metadata: &fs::Metadata) -> bool {
+        // If-None-Match takes precedence over If-Modified-Since (RFC 7232 section 6)
+        if let Some(if_none_match) = request.get_header("if-none-match") {
+            let etag = match compute_etag(metadata) {
+                Some(etag) => etag,
+                None => return false,
+            };
+            return if_none_match_matches(if_none_match, &etag);
+        }
+
+        if let Some(if_modified_since) = request.get_header("if-modified-since") {
+            if let (Some(since), Ok(modified)) = (http_date::parse(if_modified_since), metadata.modified()) {
+                // HTTP dates have one-second resolution
+                let modified_secs = modified.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                let since_secs = since.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                return modified_secs <= since_secs;
+            }
+        }
+
+        false
+    }
+
+    /// Serve a file, honoring a single-range `Range` header if present
+    pub fn serve_file_ranged(&self, file_path: &Path, range_header: Option<&str>) -> ServerResult<HttpResponse> {
+        self.serve_file_as(file_path, range_header, None)
+    }
+
+    /// Serve a file, labelling a text type with `charset` or else the configured one
+    fn serve_file_as(&self, file_path: &Path, range_header: Option<&str>, charset: Option<&str>) -> ServerResult<HttpResponse> {
         // Check if file exists and is readable
         if !file_path.exists() {
-            return Ok(HttpResponse::error(HttpStatus::NotFound, Some("File not found")));
+            return Ok(self.error_manager.generate_error_response(HttpStatus::NotFound, Some("File not found")));
         }
 
         if !file_path.is_file() {
-            return Ok(HttpResponse::error(HttpStatus::Forbidden, Some("Not a file")));
+            return Ok(self.error_manager.generate_error_response(HttpStatus::Forbidden, Some("Not a file")));
         }
 
-        // Read file content
-        let content = fs::read(file_path)
-            .map_err(|e| ServerError::Http(format!("Failed to read file: {}", e)))?;
-
-        // Detect content type
-        let content_type = self.mime_detector.detect_from_path(file_path);
+        let read_error = |e: std::io::Error| ServerError::Internal(format!("Failed to read file: {}", e));
+        let file = fs::File::open(file_path).map_err(read_error)?;
+        let total = file.metadata().map_err(read_error)?.len();
+
+        // Detect content type from the extension or the first bytes
+        let mut head = Vec::new();
+        (&file).take(SNIFF_BYTES).read_to_end(&mut head).map_err(read_error)?;
+        let charset = charset.unwrap_or_else(|| self.mime_detector.charset());
+        let content_type = self.mime_detector.detect_with_charset(file_path, &head, charset);
+
+        let (status, start, len) = match range_header.and_then(|header| parse_range(header, total)) {
+            Some(Ok((start, end))) => (HttpStatus::PartialContent, start, end - start + 1),
+            Some(Err(())) => {
+                let mut response = self.error_manager.generate_error_response(HttpStatus::RangeNotSatisfiable, None);
+                response.add_header("Content-Range", &format!("bytes */{}", total));
+                return Ok(response);
+            }
+            None => (HttpStatus::Ok, 0, total),
+        };
 
-        // Create response
-        let mut response = HttpResponse::file(HttpStatus::Ok, content, &content_type);
+        // Small bodies are read now; large ones are streamed as the client accepts them
+        let mut response = HttpResponse::new(status);
+        response.set_content_type(&content_type);
+        if len > STREAM_THRESHOLD {
+            response.set_body_file(file, start, len);
+        } else {
+            let mut body = vec![0; len as usize];
+            file.read_exact_at(&mut body, start).map_err(read_error)?;
+            response.set_body(body);
+        }
+        if status == HttpStatus::PartialContent {
+            response.add_header("Content-Range", &format!("bytes {}-{}/{}", start, start + len - 1, total));
+        }
+        response.add_header("Accept-Ranges", "bytes");
 
         // Add caching headers
         self.add_caching_headers(&mut response, file_path)?;
@@ -49,40 +183,65 @@ impl StaticFileServer {
         Ok(response)
     }
 
-    /// Serve a directory (either index file or directory listing)
+    /// Serve a directory (either index file or directory listing).
+    /// `listing` gives the order of a listing, or None if the directory may not be listed.
     pub fn serve_directory(
         &self,
         dir_path: &Path,
-        index_file: Option<&str>,
-        allow_listing: bool,
+        index_files: &[String],
+        markdown_index: bool,
+        listing: Option<ListingSort>,
         url_path: &str,
+        charset: Option<&str>,
     ) -> ServerResult<HttpResponse> {
         // Check if directory exists
         if !dir_path.exists() || !dir_path.is_dir() {
-            return Ok(HttpResponse::error(HttpStatus::NotFound, Some("Directory not found")));
+            return Ok(self.error_manager.generate_error_response(HttpStatus::NotFound, Some("Directory not found")));
         }
 
-        // Try to serve index file if specified
-        if let Some(index) = index_file {
+        // Serve the first index file that exists
+        for index in index_files {
             let index_path = dir_path.join(index);
-            if index_path.exists() && index_path.is_file() {
-                return self.serve_file(&index_path);
+            if index_path.is_file() {
+                return self.serve_file_as(&index_path, None, charset);
             }
         }
 
+        // Failing an index file, a README is rendered in its place
+        let readme_path = dir_path.join(MARKDOWN_INDEX);
+        if markdown_index && readme_path.is_file() {
+            return self.render_markdown_index(&readme_path, url_path);
+        }
+
         // If directory listing is allowed, generate listing
-        if allow_listing {
-            return self.generate_directory_listing(dir_path, url_path);
+        if let Some(sort) = listing {
+            return self.generate_directory_listing(dir_path, url_path, sort);
         }
 
         // Otherwise, return forbidden
-        Ok(HttpResponse::error(HttpStatus::Forbidden, Some("Directory listing disabled")))
+        Ok(self.error_manager.generate_error_response(HttpStatus::Forbidden, Some("Directory listing disabled")))
+    }
+
+    /// Page made from a directory's README.md
+    fn render_markdown_index(&self, readme_path: &Path, url_path: &str) -> ServerResult<HttpResponse> {
+        let source = fs::read(readme_path)
+            .map_err(|e| ServerError::Internal(format!("Failed to read {}: {}", readme_path.display(), e)))?;
+
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n");
+        html.push_str("<html><head>\n");
+        html.push_str(&format!("<title>{}</title>\n", html_escape(url_path)));
+        html.push_str("</head><body>\n");
+        html.push_str(&markdown_to_html(&String::from_utf8_lossy(&source)));
+        html.push_str("</body></html>");
+
+        Ok(HttpResponse::html(HttpStatus::Ok, &html))
     }
 
     /// Generate HTML directory listing
-    fn generate_directory_listing(&self, dir_path: &Path, url_path: &str) -> ServerResult<HttpResponse> {
+    fn generate_directory_listing(&self, dir_path: &Path, url_path: &str, sort: ListingSort) -> ServerResult<HttpResponse> {
         let entries = fs::read_dir(dir_path)
-            .map_err(|e| ServerError::Http(format!("Failed to read directory: {}", e)))?;
+            .map_err(|e| ServerError::Internal(format!("Failed to read directory: {}", e)))?;
 
         let mut html = String::new();
         html.push_str("<!DOCTYPE html>\n");
@@ -100,74 +259,62 @@ impl StaticFileServer {
         html.push_str(".date { white-space: nowrap; }\n");
         html.push_str("</style>\n");
         html.push_str("</head><body>\n");
-        html.push_str(&format!("<h1>Directory listing for {}</h1>\n", url_path));
+        html.push_str(&format!("<h1>Directory listing for {}</h1>\n", html_escape(url_path)));
 
         // Add parent directory link if not root
         if url_path != "/" {
             let parent_path = if url_path.ends_with('/') {
                 format!("{}../", url_path)
             } else {
-                format!("{}/", url_path.rsplitn(2, '/').nth(1).unwrap_or(""))
+                format!("{}/", url_path.rsplit_once('/').map(|(parent, _)| parent).unwrap_or(""))
             };
-            html.push_str(&format!("<p><a href=\"{}\">📁 Parent Directory</a></p>\n", parent_path));
+            html.push_str(&format!("<p><a href=\"{}\">📁 Parent Directory</a></p>\n", html_escape(&url_encode(&parent_path))));
         }
 
         html.push_str("<table>\n");
-        html.push_str("<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n");
-
-        // Collect and sort entries
-        let mut entries_vec = Vec::new();
-        for entry in entries {
-            if let Ok(entry) = entry {
-                entries_vec.push(entry);
-            }
-        }
-
-        // Sort: directories first, then files, both alphabetically
-        entries_vec.sort_by(|a, b| {
-            let a_is_dir = a.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-            let b_is_dir = b.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
+        html.push_str("<tr><th><a href=\"?sort=name\">Name</a></th><th><a href=\"?sort=size\">Size</a></th><th><a href=\"?sort=date\">Modified</a></th></tr>\n");
 
-            match (a_is_dir, b_is_dir) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.file_name().cmp(&b.file_name()),
+        // Collect entries with the metadata they are sorted and shown by
+        let mut listing: Vec<ListingEntry> = entries.flatten().map(|entry| {
+            let metadata = entry.metadata().ok();
+            let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
+            ListingEntry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                is_dir,
+                size: metadata.as_ref().filter(|_| !is_dir).map(|metadata| metadata.len()),
+                modified: metadata.and_then(|metadata| metadata.modified().ok()),
             }
+        }).collect();
+
+        // Directories first, then the requested order, then by name
+        listing.sort_by(|a, b| {
+            b.is_dir.cmp(&a.is_dir)
+                .then_with(|| match sort {
+                    ListingSort::Name => std::cmp::Ordering::Equal,
+                    ListingSort::Date => a.modified.cmp(&b.modified),
+                    ListingSort::Size => a.size.cmp(&b.size),
+                })
+                .then_with(|| a.name.cmp(&b.name))
         });
 
         // Generate table rows
-        for entry in entries_vec {
-            let name = entry.file_name().to_string_lossy().to_string();
-            let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-
-            let display_name = if is_dir {
-                format!("📁 {}/", name)
+        for entry in listing {
+            // File names are untrusted: escape them for the page and encode them for the link
+            let display_name = if entry.is_dir {
+                format!("📁 {}/", html_escape(&entry.name))
             } else {
-                format!("📄 {}", name)
+                format!("📄 {}", html_escape(&entry.name))
             };
 
             let href = if url_path.ends_with('/') {
-                format!("{}{}", url_path, name)
+                format!("{}{}", url_path, entry.name)
             } else {
-                format!("{}/{}", url_path, name)
+                format!("{}/{}", url_path, entry.name)
             };
+            let href = html_escape(&url_encode(&href));
 
-            // Get file size and modification time
-            let (size_str, modified_str) = if let Ok(metadata) = entry.metadata() {
-                let size = if is_dir {
-                    "-".to_string()
-                } else {
-                    format_file_size(metadata.len())
-                };
-
-                let modified = metadata.modified()
-                    .map(|time| format_time(time))
-                    .unwrap_or_else(|_| "-".to_string());
-
-                (size, modified)
-            } else {
-                ("-".to_string(), "-".to_string())
-            };
+            let size_str = entry.size.map(format_file_size).unwrap_or_else(|| "-".to_string());
+            let modified_str = entry.modified.map(format_time).unwrap_or_else(|| "-".to_string());
 
             html.push_str(&format!(
                 "<tr><td><a href=\"{}\">{}</a></td><td class=\"size\">{}</td><td class=\"date\">{}</td></tr>\n",
@@ -183,11 +330,13 @@ impl StaticFileServer {
 
     /// Add caching headers to response
     fn add_caching_headers(&self, response: &mut HttpResponse, file_path: &Path) -> ServerResult<()> {
-        // Add Last-Modified header
+        // Add Last-Modified and ETag headers
         if let Ok(metadata) = fs::metadata(file_path) {
             if let Ok(modified) = metadata.modified() {
-                let http_date = format_http_date(modified);
-                response.add_header("Last-Modified", &http_date);
+                response.add_header("Last-Modified", &http_date::format(modified));
+            }
+            if let Some(etag) = compute_etag(&metadata) {
+                response.add_header("ETag", &etag);
             }
         }
 
@@ -197,14 +346,15 @@ impl StaticFileServer {
         Ok(())
     }
 
-    /// Resolve file path with security checks
-    pub fn resolve_path(&self, root: &str, request_path: &str, route_path: &str) -> ServerResult<PathBuf> {
+    /// Resolve file path with security checks. `canonical_root` is the root
+    /// already canonicalized at startup; without it the root is resolved here.
+    pub fn resolve_path(&self, root: &str, canonical_root: Option<&Path>, request_path: &str, route_path: &str) -> ServerResult<PathBuf> {
+        // Paths built from `try_files` candidates never went through the parser's check
+        crate::http::request::check_path(request_path)?;
+
         // Remove route prefix from request path
-        let relative_path = if request_path.starts_with(route_path) {
-            &request_path[route_path.len()..]
-        } else {
-            request_path
-        };
+        let route_path = crate::routing::router::literal_prefix(route_path);
+        let relative_path = request_path.strip_prefix(route_path).unwrap_or(request_path);
 
         // Remove leading slash
         let relative_path = relative_path.strip_prefix('/').unwrap_or(relative_path);
@@ -216,15 +366,38 @@ impl StaticFileServer {
         }
 
         // Security check: ensure path doesn't escape root directory
-        let canonical_root = fs::canonicalize(root)
-            .map_err(|_| ServerError::Config(format!("Invalid root directory: {}", root)))?;
+        let resolved_root;
+        let canonical_root = match canonical_root {
+            Some(canonical_root) => canonical_root,
+            None => {
+                resolved_root = fs::canonicalize(root)
+                    .map_err(|_| ServerError::Config(format!("Invalid root directory: {}", root)))?;
+                resolved_root.as_path()
+            }
+        };
 
-        if let Ok(canonical_path) = fs::canonicalize(&full_path) {
-            if !canonical_path.starts_with(&canonical_root) {
-                return Err(ServerError::Http("Path traversal attempt detected".to_string()));
+        // Targets of PUT, DELETE or uploads may not exist yet: canonicalize the
+        // deepest existing ancestor and re-attach the missing components
+        let traversal = || ServerError::Http(HttpErrorKind::PathTraversal);
+        let mut existing = full_path.clone();
+        let mut missing = Vec::new();
+        while !existing.exists() {
+            // A ".." below a missing directory can never resolve
+            missing.push(existing.file_name().ok_or_else(traversal)?.to_os_string());
+            if !existing.pop() {
+                return Err(traversal());
             }
         }
 
+        let mut canonical_path = fs::canonicalize(&existing).map_err(|_| traversal())?;
+        for component in missing.iter().rev() {
+            canonical_path.push(component);
+        }
+
+        if !canonical_path.starts_with(canonical_root) {
+            return Err(traversal());
+        }
+
         Ok(full_path)
     }
 }
@@ -235,6 +408,61 @@ impl Default for StaticFileServer {
     }
 }
 
+/// Compute a weak ETag from file size and modification time
+fn compute_etag(metadata: &fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?
+        .duration_since(SystemTime::UNIX_EPOCH).ok()?;
+    Some(format!("W/\"{:x}-{:x}\"", metadata.len(), modified.as_secs()))
+}
+
+/// Check an `If-None-Match` header against a resource's entity tag
+pub(crate) fn if_none_match_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || weak_etag_eq(tag, etag))
+}
+
+/// Weak comparison of two entity tags (ignores the W/ prefix)
+fn weak_etag_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+/// Parse a `Range` header against a resource of `total` bytes.
+/// Returns None when the header should be ignored (unsupported unit or
+/// multiple ranges), Some(Err) when the range cannot be satisfied, and
+/// Some(Ok((start, end))) with an inclusive byte range otherwise.
+fn parse_range(header: &str, total: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start_str, end_str) = spec.split_once('-')?;
+    let (start_str, end_str) = (start_str.trim(), end_str.trim());
+
+    let range = if start_str.is_empty() {
+        // Suffix range: the last N bytes
+        let suffix: u64 = end_str.parse().ok()?;
+        if suffix == 0 || total == 0 {
+            return Some(Err(()));
+        }
+        (total.saturating_sub(suffix), total - 1)
+    } else {
+        let start: u64 = start_str.parse().ok()?;
+        let end: u64 = if end_str.is_empty() {
+            total.saturating_sub(1)
+        } else {
+            end_str.parse().ok()?
+        };
+        if start > end || start >= total {
+            return Some(Err(()));
+        }
+        (start, end.min(total - 1))
+    };
+
+    Some(Ok(range))
+}
+
 /// Format file size in human-readable format
 fn format_file_size(size: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
@@ -253,27 +481,245 @@ fn format_file_size(size: u64) -> String {
     }
 }
 
-/// Format system time as HTTP date
-fn format_http_date(time: SystemTime) -> String {
-    // This is a simplified implementation
-    // In production, you'd want proper RFC 2822 formatting
-    match time.duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(duration) => {
-            let timestamp = duration.as_secs();
-            format!("Thu, 01 Jan 1970 00:00:{:02} GMT", timestamp % 60)
-        }
-        Err(_) => "Thu, 01 Jan 1970 00:00:00 GMT".to_string(),
-    }
+/// Format system time for directory listing, in UTC
+fn format_time(time: SystemTime) -> String {
+    let dt = http_date::DateTime::from_system_time(time);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second)
 }
 
-/// Format system time for directory listing
-fn format_time(time: SystemTime) -> String {
-    match time.duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(duration) => {
-            let timestamp = duration.as_secs();
-            // Simplified time formatting
-            format!("1970-01-01 00:00:{:02}", timestamp % 60)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::BodySource;
+
+    fn write_temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("localhost_static_{}_{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a String> {
+        response.get_header(name)
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some(Ok((0, 9))));
+        assert_eq!(parse_range("bytes=90-", 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range("bytes=-10", 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range("bytes=50-500", 100), Some(Ok((50, 99))));
+        assert_eq!(parse_range("bytes=10-5", 100), Some(Err(())));
+        assert_eq!(parse_range("bytes=100-", 100), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+    }
+
+    #[test]
+    fn test_serve_file_range() {
+        let path = write_temp_file("range.txt", b"0123456789");
+        let server = StaticFileServer::new();
+
+        let response = server.serve_file_ranged(&path, Some("bytes=2-5")).unwrap();
+        assert_eq!(response.status, HttpStatus::PartialContent);
+        assert_eq!(response.body, b"2345");
+        assert_eq!(header(&response, "Content-Range"), Some(&"bytes 2-5/10".to_string()));
+        assert_eq!(header(&response, "Content-Length"), Some(&"4".to_string()));
+
+        let response = server.serve_file_ranged(&path, Some("bytes=20-30")).unwrap();
+        assert_eq!(response.status, HttpStatus::RangeNotSatisfiable);
+        assert_eq!(header(&response, "Content-Range"), Some(&"bytes */10".to_string()));
+
+        let response = server.serve_file(&path).unwrap();
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.body, b"0123456789");
+        assert_eq!(header(&response, "Accept-Ranges"), Some(&"bytes".to_string()));
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_large_file_is_streamed() {
+        let content: Vec<u8> = (0..STREAM_THRESHOLD as usize * 2).map(|i| (i % 251) as u8).collect();
+        let path = write_temp_file("stream.bin", &content);
+        let server = StaticFileServer::new();
+
+        let response = server.serve_file(&path).unwrap();
+        assert!(response.body.is_empty());
+        assert_eq!(response.body_len(), content.len() as u64);
+        assert_eq!(header(&response, "Content-Length"), Some(&content.len().to_string()));
+        assert!(matches!(response.body_source, Some(BodySource::File { offset: 0, .. })));
+
+        let response = server.serve_file_ranged(&path, Some("bytes=10-")).unwrap();
+        assert!(matches!(response.body_source, Some(BodySource::File { offset: 10, .. })));
+        assert_eq!(response.body_len(), content.len() as u64 - 10);
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_conditional_get_with_etag() {
+        let path = write_temp_file("etag.txt", b"cached content");
+        let server = StaticFileServer::new();
+
+        let first = server.serve_request_file(&path, &HttpRequest::new(), None).unwrap();
+        assert_eq!(first.status, HttpStatus::Ok);
+        let etag = header(&first, "ETag").expect("ETag header missing").clone();
+        assert!(etag.starts_with("W/\""));
+
+        let mut request = HttpRequest::new();
+        request.add_header("If-None-Match", &etag);
+        let second = server.serve_request_file(&path, &request, None).unwrap();
+        assert_eq!(second.status, HttpStatus::NotModified);
+        assert!(second.body.is_empty());
+        assert_eq!(header(&second, "ETag"), Some(&etag));
+
+        let mut request = HttpRequest::new();
+        request.add_header("If-None-Match", "W/\"0-0\"");
+        let third = server.serve_request_file(&path, &request, None).unwrap();
+        assert_eq!(third.status, HttpStatus::Ok);
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_conditional_get_with_if_modified_since() {
+        let path = write_temp_file("ims.txt", b"cached content");
+        let server = StaticFileServer::new();
+
+        let first = server.serve_request_file(&path, &HttpRequest::new(), None).unwrap();
+        let last_modified = header(&first, "Last-Modified").unwrap().clone();
+
+        let mut request = HttpRequest::new();
+        request.add_header("If-Modified-Since", &last_modified);
+        let second = server.serve_request_file(&path, &request, None).unwrap();
+        assert_eq!(second.status, HttpStatus::NotModified);
+
+        let mut request = HttpRequest::new();
+        request.add_header("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT");
+        let third = server.serve_request_file(&path, &request, None).unwrap();
+        assert_eq!(third.status, HttpStatus::Ok);
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_serve_directory_tries_index_files_in_order() {
+        let dir = std::env::temp_dir().join(format!("localhost_static_{}_index", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.htm"), "second candidate").unwrap();
+        let server = StaticFileServer::new();
+        let index = vec!["index.html".to_string(), "index.htm".to_string()];
+
+        let response = server.serve_directory(&dir, &index, false, None, "/", None).unwrap();
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.body, b"second candidate");
+
+        // With no candidate present the directory is refused
+        let response = server.serve_directory(&dir, &index[..1], false, None, "/", None).unwrap();
+        assert_eq!(response.status, HttpStatus::Forbidden);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_markdown_index_renders_readme() {
+        let dir = std::env::temp_dir().join(format!("localhost_static_{}_markdown", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("README.md"), "# Title\n\nSee *this*.\n").unwrap();
+        let server = StaticFileServer::new();
+        let index = vec!["index.html".to_string()];
+
+        let response = server.serve_directory(&dir, &index, true, None, "/docs/", None).unwrap();
+        assert_eq!(response.status, HttpStatus::Ok);
+        let html = String::from_utf8(response.body).unwrap();
+        assert!(html.contains("<h1>Title</h1>\n<p>See <em>this</em>.</p>"), "unexpected page: {}", html);
+
+        // Off, the README is not used
+        let response = server.serve_directory(&dir, &index, false, None, "/docs/", None).unwrap();
+        assert_eq!(response.status, HttpStatus::Forbidden);
+
+        // An index file still comes first
+        fs::write(dir.join("index.html"), "index page").unwrap();
+        let response = server.serve_directory(&dir, &index, true, None, "/docs/", None).unwrap();
+        assert_eq!(response.body, b"index page");
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_directory_listing_escapes_file_names() {
+        let dir = std::env::temp_dir().join(format!("localhost_static_{}_listing", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("<img src=x onerror='alert(1)'>&.txt"), "x").unwrap();
+        let server = StaticFileServer::new();
+
+        let response = server.serve_directory(&dir, &[], false, Some(ListingSort::Name), "/files/", None).unwrap();
+        let html = String::from_utf8(response.body).unwrap();
+        assert!(!html.contains("<img"), "markup leaked into listing: {}", html);
+        assert!(html.contains("📄 &lt;img src=x onerror=&#39;alert(1)&#39;&gt;&amp;.txt"));
+        assert!(html.contains("href=\"/files/%3Cimg%20src%3Dx%20onerror%3D%27alert%281%29%27%3E%26.txt\""));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_directory_listing_times_and_sort() {
+        let dir = std::env::temp_dir().join(format!("localhost_static_{}_sorted", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a_large.txt"), vec![b'x'; 3000]).unwrap();
+        fs::write(dir.join("b_small.txt"), "x").unwrap();
+        fs::write(dir.join("c_medium.txt"), vec![b'x'; 200]).unwrap();
+        let server = StaticFileServer::new();
+
+        let listing = |sort| {
+            let response = server.serve_directory(&dir, &[], false, Some(sort), "/", None).unwrap();
+            String::from_utf8(response.body).unwrap()
+        };
+        let order = |html: &str, names: &[&str]| {
+            let positions: Vec<usize> = names.iter().map(|name| html.find(name).unwrap()).collect();
+            positions.windows(2).all(|pair| pair[0] < pair[1])
+        };
+
+        let html = listing(ListingSort::Name);
+        assert!(order(&html, &["sub/", "a_large", "b_small", "c_medium"]));
+        assert!(!html.contains("1970-01-01"), "listing shows epoch times: {}", html);
+
+        let html = listing(ListingSort::from_query(Some("size")));
+        assert!(order(&html, &["sub/", "b_small", "c_medium", "a_large"]));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_710_513_127);
+        assert_eq!(format_time(time), "2024-03-15 14:32:07");
+    }
+
+    #[test]
+    fn test_resolve_path_rejects_traversal_to_missing_files() {
+        let root = std::env::temp_dir().join(format!("localhost_static_{}_root", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let root_str = root.to_string_lossy();
+        let server = StaticFileServer::new();
+
+        // New files inside the root resolve normally
+        assert_eq!(server.resolve_path(&root_str, None, "/new.txt", "/").unwrap(), root.join("new.txt"));
+        assert!(server.resolve_path(&root_str, None, "/sub/../new.txt", "/").is_ok());
+
+        for path in ["/../missing.txt", "/sub/../../missing.txt", "/nodir/../../missing.txt"] {
+            assert!(
+                matches!(server.resolve_path(&root_str, None, path, "/"), Err(ServerError::Http(HttpErrorKind::PathTraversal))),
+                "{} should be rejected", path
+            );
         }
-        Err(_) => "1970-01-01 00:00:00".to_string(),
+
+        // Control characters never reach the filesystem
+        assert!(matches!(
+            server.resolve_path(&root_str, None, "/safe.txt\0.cgi", "/"),
+            Err(ServerError::Http(HttpErrorKind::InvalidEncoding(_)))
+        ));
+
+        fs::remove_dir_all(&root).ok();
     }
 }
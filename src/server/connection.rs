@@ -2,10 +2,16 @@
  * Connection management
  */
 
-use crate::http::HttpRequestParser;
+use crate::cgi::CgiProcess;
+use crate::http::{BodySource, HttpRequest, HttpRequestParser};
+use crate::server::tls::TlsSession;
 use crate::utils::buffer::Buffer;
 use crate::utils::{TimeoutManager, ConnectionState as TimeoutConnectionState, ResourceMonitor};
+use crate::ws::WebSocket;
 use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileExt;
 use std::os::unix::io::RawFd;
 use std::time::{Duration, Instant};
 
@@ -13,37 +19,249 @@ use std::time::{Duration, Instant};
 #[derive(Debug, Clone)]
 pub enum ConnectionState {
     Reading,
+    WaitingForCgi,
     Writing,
     KeepAlive,
+    /// Upgraded to WebSocket; frames are exchanged instead of requests
+    WebSocket,
     Closed,
 }
 
+/// CGI script running on behalf of a connection
+pub struct CgiState {
+    pub process: CgiProcess,
+    pub request: HttpRequest,
+    pub output_mode: CgiOutputMode,
+}
+
+/// CGI script handed to the worker pool on behalf of a connection
+pub struct CgiJobState {
+    pub id: u64,
+    pub request: HttpRequest,
+}
+
+/// How a CGI script's output reaches the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgiOutputMode {
+    /// Waiting for the script's headers to decide
+    Pending,
+    /// Collected and sent as one response once the script exits
+    Buffered,
+    /// Relayed with chunked framing as it is read
+    Streaming,
+}
+
+/// How much of a streamed body is read into the write buffer at a time
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Initial size of a connection's read and write buffers
+const INITIAL_BUFFER_SIZE: usize = 8192;
+
+/// Pooled buffers that grew past this are shrunk back to it
+const POOLED_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// Parser and buffers of a closed connection, kept for the next one
+struct ConnectionParts {
+    http_parser: HttpRequestParser,
+    read_buffer: Buffer,
+    write_buffer: Buffer,
+}
+
+impl ConnectionParts {
+    fn new() -> Self {
+        Self {
+            http_parser: HttpRequestParser::new(),
+            read_buffer: Buffer::new(INITIAL_BUFFER_SIZE),
+            write_buffer: Buffer::new(INITIAL_BUFFER_SIZE),
+        }
+    }
+
+    /// Forget everything about the previous connection
+    fn recycle(&mut self) {
+        self.http_parser.reset();
+        self.http_parser.shrink_buffer(POOLED_BUFFER_LIMIT);
+        self.read_buffer.recycle(POOLED_BUFFER_LIMIT);
+        self.write_buffer.recycle(POOLED_BUFFER_LIMIT);
+    }
+}
+
 /// Individual client connection
 pub struct Connection {
     pub fd: RawFd,
+    /// Client address, unknown for Unix socket clients
+    pub peer_addr: Option<SocketAddr>,
+    /// Address the client connected to, unknown for Unix socket clients
+    pub local_addr: Option<SocketAddr>,
     pub state: ConnectionState,
     pub read_buffer: Buffer,
     pub write_buffer: Buffer,
+    /// Body still to be read from disk or produced once the write buffer drains
+    pub pending_body: Option<BodySource>,
     pub last_activity: Instant,
+    /// When the first bytes of the current request arrived
+    pub request_started_at: Option<Instant>,
+    /// How long a request's headers may take, set by the server owning the listener
+    pub header_timeout: Duration,
     pub keep_alive: bool,
     pub request_count: usize,
     pub http_parser: HttpRequestParser,
+    pub cgi: Option<CgiState>,
+    pub cgi_job: Option<CgiJobState>,
+    /// Session encrypting the connection, for clients of an `ssl on` listener
+    pub tls: Option<TlsSession>,
+    /// Frame state once the connection has been upgraded to WebSocket
+    pub websocket: Option<WebSocket>,
 }
 
 impl Connection {
     pub fn new(fd: RawFd) -> Self {
+        Self::with_parts(fd, ConnectionParts::new())
+    }
+
+    fn with_parts(fd: RawFd, parts: ConnectionParts) -> Self {
         Self {
             fd,
+            peer_addr: None,
+            local_addr: None,
             state: ConnectionState::Reading,
-            read_buffer: Buffer::new(8192),
-            write_buffer: Buffer::new(8192),
+            read_buffer: parts.read_buffer,
+            write_buffer: parts.write_buffer,
+            pending_body: None,
             last_activity: Instant::now(),
+            request_started_at: None,
+            header_timeout: Duration::from_secs(crate::defaults::DEFAULT_HEADER_TIMEOUT),
             keep_alive: false,
             request_count: 0,
-            http_parser: HttpRequestParser::new(),
+            http_parser: parts.http_parser,
+            cgi: None,
+            cgi_job: None,
+            tls: None,
+            websocket: None,
+        }
+    }
+
+    /// Take the parser and buffers apart for reuse
+    fn into_parts(self) -> ConnectionParts {
+        let mut parts = ConnectionParts {
+            http_parser: self.http_parser,
+            read_buffer: self.read_buffer,
+            write_buffer: self.write_buffer,
+        };
+        parts.recycle();
+        parts
+    }
+
+    /// Read from the socket into the read buffer, decrypting if the connection uses TLS.
+    /// Returns the bytes added and whether the client has closed.
+    pub fn read_from_socket(&mut self, drain: bool) -> io::Result<(usize, bool)> {
+        match &mut self.tls {
+            // TLS records are always read until the socket would block
+            Some(tls) => tls.read_from_fd(self.fd, &mut self.read_buffer),
+            None if drain => self.read_buffer.drain_from_fd(self.fd),
+            // Only a zero-byte read is end of file; EAGAIN just means nothing yet
+            None => self.read_buffer.read_from_fd(self.fd).map(|read| match read {
+                Some(n) => (n, n == 0),
+                None => (0, false),
+            }),
         }
     }
 
+    /// Write the write buffer to the socket, encrypting if the connection uses TLS.
+    /// Returns the bytes taken from the buffer; a socket that would block takes none.
+    pub fn write_to_socket(&mut self) -> io::Result<usize> {
+        match &mut self.tls {
+            Some(tls) => tls.write_to_fd(self.fd, &mut self.write_buffer),
+            None => self.write_buffer.write_to_fd(self.fd),
+        }
+    }
+
+    /// Check if encrypted bytes, such as handshake messages, are waiting for the socket
+    pub fn has_pending_tls(&self) -> bool {
+        self.tls.as_ref().is_some_and(|tls| tls.wants_write())
+    }
+
+    /// Send whatever encrypted bytes the socket will take
+    pub fn flush_tls(&mut self) -> io::Result<()> {
+        match &mut self.tls {
+            Some(tls) => tls.flush(self.fd),
+            None => Ok(()),
+        }
+    }
+
+    /// Tell a TLS client the connection is about to close
+    pub fn close_tls(&mut self) {
+        if let Some(tls) = &mut self.tls {
+            tls.close(self.fd);
+        }
+    }
+
+    /// Queue a response body behind whatever is already in the write buffer
+    pub fn queue_body(&mut self, body: BodySource) -> io::Result<()> {
+        match body {
+            BodySource::Bytes(bytes) => self.write_buffer.append(&bytes),
+            BodySource::Stream => {
+                self.pending_body = Some(BodySource::Stream);
+                Ok(())
+            }
+            file => {
+                if !file.is_empty() {
+                    self.pending_body = Some(file);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Check if a streamed body is still waiting on its producer
+    pub fn is_streaming(&self) -> bool {
+        matches!(self.pending_body, Some(BodySource::Stream))
+    }
+
+    /// Frame and queue one chunk of a streamed body
+    pub fn queue_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            // An empty chunk would end the body early
+            return Ok(());
+        }
+        self.write_buffer.append(format!("{:x}\r\n", data.len()).as_bytes())?;
+        self.write_buffer.append(data)?;
+        self.write_buffer.append(b"\r\n")
+    }
+
+    /// Queue the last chunk of a streamed body
+    pub fn finish_stream(&mut self) -> io::Result<()> {
+        self.pending_body = None;
+        self.write_buffer.append(b"0\r\n\r\n")
+    }
+
+    /// Top up the write buffer from a streamed body once it runs low
+    pub fn fill_write_buffer(&mut self) -> io::Result<()> {
+        if self.write_buffer.readable_bytes() >= STREAM_CHUNK_SIZE {
+            return Ok(());
+        }
+
+        if let Some(BodySource::File { file, offset, len }) = &mut self.pending_body {
+            let mut chunk = vec![0; STREAM_CHUNK_SIZE.min(*len as usize)];
+            let read = file.read_at(&mut chunk, *offset)?;
+            if read == 0 {
+                // The file shrank after its length was promised in Content-Length
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file truncated while streaming"));
+            }
+            self.write_buffer.append(&chunk[..read])?;
+            *offset += read as u64;
+            *len -= read as u64;
+            if *len == 0 {
+                self.pending_body = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the whole response has been handed to the socket
+    pub fn is_response_sent(&self) -> bool {
+        self.write_buffer.is_empty() && self.pending_body.is_none() && !self.has_pending_tls()
+    }
+
     /// Update last activity timestamp
     pub fn touch(&mut self) {
         self.last_activity = Instant::now();
@@ -54,20 +272,48 @@ impl Connection {
         self.last_activity.elapsed() > timeout
     }
 
+    /// Start the header deadline when the first bytes of a request arrive
+    pub fn start_request(&mut self) {
+        self.request_started_at.get_or_insert_with(Instant::now);
+    }
+
+    /// Check if part of a request has arrived but not all of it
+    pub fn is_mid_request(&self) -> bool {
+        self.request_started_at.is_some() && !self.http_parser.is_complete()
+    }
+
+    /// Check if a request's headers are still incomplete past `header_timeout`.
+    /// Unlike `is_timed_out`, further reads do not extend the deadline.
+    pub fn is_header_timed_out(&self) -> bool {
+        !self.http_parser.headers_complete()
+            && self.request_started_at.is_some_and(|started| started.elapsed() > self.header_timeout)
+    }
+
+    /// Check if the connection waits on a CGI script that has not started its
+    /// response; the script's own timeout applies instead of the idle one
+    pub fn is_awaiting_cgi(&self) -> bool {
+        self.cgi_job.is_some()
+            || self.cgi.as_ref().is_some_and(|cgi| cgi.output_mode != CgiOutputMode::Streaming && cgi.process.has_timeout())
+    }
+
     /// Reset connection for keep-alive
     pub fn reset_for_keep_alive(&mut self) {
         self.read_buffer.clear();
         self.write_buffer.clear();
+        self.pending_body = None;
         self.state = ConnectionState::Reading;
-        self.request_count += 1;
-        self.http_parser.reset();
+        // request_count is already advanced by ConnectionManager::record_request
+        // Bytes of a pipelined request stay with the parser
+        self.http_parser.reset_for_next_request();
+        // A pipelined request has already started arriving
+        self.request_started_at = self.http_parser.has_buffered_data().then(Instant::now);
         self.touch();
     }
 
     /// Check if connection should be closed
     pub fn should_close(&self) -> bool {
         matches!(self.state, ConnectionState::Closed) ||
-        (!self.keep_alive && self.write_buffer.is_empty())
+        (!self.keep_alive && self.is_response_sent())
     }
 }
 
@@ -77,6 +323,11 @@ pub struct ConnectionManager {
     timeout: Duration,
     timeout_manager: TimeoutManager,
     resource_monitor: ResourceMonitor,
+    max_body_size: usize,
+    max_header_line_bytes: usize,
+    merge_folded_headers: bool,
+    /// Parsers and buffers of closed connections, handed to new ones
+    pool: Vec<ConnectionParts>,
 }
 
 impl ConnectionManager {
@@ -86,17 +337,57 @@ impl ConnectionManager {
             timeout: Duration::from_secs(timeout_seconds),
             timeout_manager: TimeoutManager::with_defaults(),
             resource_monitor: ResourceMonitor::new(),
+            max_body_size: crate::defaults::DEFAULT_MAX_BODY_SIZE,
+            max_header_line_bytes: crate::http::request::DEFAULT_MAX_HEADER_LINE_BYTES,
+            merge_folded_headers: false,
+            pool: Vec::new(),
         }
     }
 
+    /// Set the body size limit applied to new connections' parsers
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    /// Set the header line length limit applied to new connections' parsers
+    pub fn set_max_header_line_bytes(&mut self, max_header_line_bytes: usize) {
+        self.max_header_line_bytes = max_header_line_bytes;
+    }
+
+    /// Set whether new connections' parsers join folded header lines instead of rejecting them
+    pub fn set_merge_folded_headers(&mut self, merge_folded_headers: bool) {
+        self.merge_folded_headers = merge_folded_headers;
+    }
+
+    /// Number of further requests a connection may carry when its server
+    /// allows `max_requests` per connection
+    pub fn requests_remaining(&self, fd: RawFd, max_requests: usize) -> usize {
+        self.connections.get(&fd)
+            .map(|conn| max_requests.saturating_sub(conn.request_count))
+            .unwrap_or(0)
+    }
+
+    /// How long a connection may sit idle between requests before it is
+    /// closed; route timeouts only last for the request they apply to
+    pub fn idle_timeout(&self) -> Duration {
+        self.timeout_manager.request_timeout()
+    }
+
     /// Add a new connection
-    pub fn add_connection(&mut self, fd: RawFd) -> Result<(), String> {
+    pub fn add_connection(&mut self, fd: RawFd, peer_addr: Option<SocketAddr>) -> Result<(), String> {
         // Check if we can add more connections
-        if let Err(e) = self.timeout_manager.add_connection(fd) {
-            return Err(e);
-        }
-
-        let connection = Connection::new(fd);
+        self.timeout_manager.add_connection(fd)?;
+
+        let parts = self.pool.pop().unwrap_or_else(ConnectionParts::new);
+        let mut connection = Connection::with_parts(fd, parts);
+        connection.peer_addr = peer_addr;
+        connection.http_parser.set_max_body_size(self.max_body_size);
+        connection.http_parser.set_max_header_line_bytes(self.max_header_line_bytes);
+        connection.http_parser.set_merge_folded_headers(self.merge_folded_headers);
+        // Room for one maximal request; anything more is refused rather than buffered
+        connection.read_buffer.set_max_capacity(Some(
+            self.max_body_size.saturating_add(crate::http::request::DEFAULT_MAX_HEADER_BYTES)
+        ));
         self.connections.insert(fd, connection);
 
         // Update resource monitor
@@ -115,13 +406,27 @@ impl ConnectionManager {
         self.connections.get_mut(&fd)
     }
 
-    /// Remove a connection
-    pub fn remove_connection(&mut self, fd: RawFd) -> Option<Connection> {
+    /// Remove a connection, keeping its parser and buffers for a later one.
+    /// Returns whether the connection existed.
+    pub fn remove_connection(&mut self, fd: RawFd) -> bool {
         // Remove from timeout manager
         self.timeout_manager.remove_connection(fd);
 
         // Remove from connections
-        self.connections.remove(&fd)
+        match self.connections.remove(&fd) {
+            Some(connection) => {
+                if self.pool.len() < self.timeout_manager.max_connections() {
+                    self.pool.push(connection.into_parts());
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of parser and buffer sets waiting to be reused
+    pub fn pooled_count(&self) -> usize {
+        self.pool.len()
     }
 
     /// Get all connection file descriptors
@@ -129,6 +434,14 @@ impl ConnectionManager {
         self.connections.keys().copied().collect()
     }
 
+    /// Get file descriptors of connections with a CGI script in flight
+    pub fn get_cgi_fds(&self) -> Vec<RawFd> {
+        self.connections.iter()
+            .filter(|(_, conn)| conn.cgi.is_some())
+            .map(|(&fd, _)| fd)
+            .collect()
+    }
+
     /// Remove timed out connections
     pub fn cleanup_timed_out(&mut self) -> Vec<RawFd> {
         let mut timed_out = Vec::new();
@@ -164,11 +477,46 @@ impl ConnectionManager {
         }
     }
 
+    /// Count bytes sent to a client; a client still receiving is not idle
+    pub fn record_bytes_written(&mut self, fd: RawFd, bytes_written: usize) {
+        self.timeout_manager.record_bytes_written(fd, bytes_written);
+    }
+
     /// Update connection state
     pub fn update_connection_state(&mut self, fd: RawFd, state: TimeoutConnectionState) {
         self.timeout_manager.update_state(fd, state);
     }
 
+    /// Set how many connections may be open at once
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.timeout_manager.set_max_connections(max_connections);
+    }
+
+    /// Check if no further connection can be added
+    pub fn is_full(&self) -> bool {
+        self.timeout_manager.is_at_limit()
+    }
+
+    /// Set how long a connection's client may take to send a request's headers
+    pub fn set_header_timeout(&mut self, fd: RawFd, timeout: Duration) {
+        if let Some(connection) = self.connections.get_mut(&fd) {
+            connection.header_timeout = timeout;
+        }
+    }
+
+    /// Apply a route's request timeout to a connection
+    pub fn set_request_timeout(&mut self, fd: RawFd, timeout: Duration) {
+        self.timeout_manager.set_request_timeout(fd, timeout);
+    }
+
+    /// Reset a connection for its next request, which finds its own route's timeout
+    pub fn reset_for_keep_alive(&mut self, fd: RawFd) {
+        if let Some(connection) = self.connections.get_mut(&fd) {
+            connection.reset_for_keep_alive();
+        }
+        self.timeout_manager.clear_request_timeout(fd);
+    }
+
     /// Record a completed request
     pub fn record_request(&mut self, fd: RawFd, bytes_transferred: usize) {
         self.timeout_manager.increment_requests(fd);
@@ -185,9 +533,16 @@ impl ConnectionManager {
         self.resource_monitor.record_error();
     }
 
-    /// Get timed out connections
+    /// Get timed out connections, including those sending headers too slowly
     pub fn get_timed_out_connections(&self) -> Vec<RawFd> {
-        self.timeout_manager.get_timed_out_connections()
+        let mut timed_out = self.timeout_manager.get_timed_out_connections();
+        timed_out.retain(|fd| !self.connections.get(fd).is_some_and(|connection| connection.is_awaiting_cgi()));
+        for (&fd, connection) in &self.connections {
+            if connection.is_header_timed_out() && !timed_out.contains(&fd) {
+                timed_out.push(fd);
+            }
+        }
+        timed_out
     }
 
     /// Check if at connection limit
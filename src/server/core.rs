@@ -2,69 +2,188 @@
  * Core server implementation
  */
 
-use crate::config::Config;
-use crate::error::{ServerError, ServerResult, HttpStatus};
+use crate::config::{AccessLog, Config, EpollMode, RateLimit, ServerConfig, ServerKey};
+use crate::error::{CgiErrorKind, ServerError, ServerResult, HttpStatus};
 use crate::error::pages::ErrorPageManager;
-use crate::http::HttpResponse;
-use crate::http::methods::MethodHandler;
+use crate::cgi::{CgiProcess, CgiWorkerPool};
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpVersion};
+use crate::http::methods::{Dispatch, MethodHandler};
+use crate::http::request::UPLOAD_SPOOL_THRESHOLD;
 use crate::session::SessionManager;
-use crate::server::connection::{ConnectionManager, ConnectionState};
+use crate::utils::buffer::Buffer;
+use crate::utils::log::{AccessLogEntry, AccessLogger};
+use crate::utils::{resolve_client_ip, RateLimiter};
+use crate::ws::{EchoHandler, WebSocket, WebSocketHandlerFactory, ECHO_HANDLER};
+use crate::server::connection::{CgiJobState, CgiOutputMode, CgiState, ConnectionManager, ConnectionState};
+use crate::server::signal::HangupPipe;
+use crate::server::tls::{self, TlsConfig, TlsSession};
 use crate::server::epoll::{Epoll, EPOLLIN, EPOLLOUT, EPOLLERR, EPOLLHUP, create_epoll_event, get_fd_from_event};
 use crate::server::socket::{
-    accept_connection, bind_socket, close_socket, create_tcp_socket, listen_socket,
+    accept_connection, bind_socket, close_socket, create_tcp_socket, create_unix_socket, format_host,
+    listen_socket, local_address, ListenOptions,
 };
 use std::collections::HashMap;
 use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Seconds clients turned away at the connection limit are asked to wait
+const SHED_RETRY_AFTER_SECS: u64 = 5;
+
+/// A listening socket
+#[derive(Debug, Clone)]
+enum Listener {
+    Tcp { host: String, port: u16, secure: bool },
+    Unix { path: String },
+}
+
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Listener::Tcp { host, port, secure } => {
+                let scheme = if *secure { "https" } else { "http" };
+                write!(f, "{}://{}:{}", scheme, format_host(host), port)
+            }
+            Listener::Unix { path } => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+/// Request handling built from one version of the configuration
+struct Handlers {
+    method_handler: MethodHandler,
+    /// Error pages for failures before a request is known to belong to a server
+    error_manager: ErrorPageManager,
+}
+
+impl Handlers {
+    fn new(config: &Config) -> Self {
+        // Create error manager from first server's configuration
+        let error_manager = if let Some(server) = config.servers.first() {
+            ErrorPageManager::from_config(server)
+        } else {
+            ErrorPageManager::new()
+        };
+
+        Self {
+            method_handler: MethodHandler::new(config.clone()),
+            error_manager,
+        }
+    }
+
+    /// Error page for a request the method handler failed on, from the
+    /// server the request is addressed to
+    fn error_response(&self, request: &HttpRequest, error: &ServerError) -> HttpResponse {
+        let error_manager = self.method_handler.error_manager_for_request(request);
+        match error.http_status() {
+            Some(status) => error_manager.generate_error_response(status, None),
+            None => error_manager.generate_error_response(HttpStatus::InternalServerError, Some("Internal server error")),
+        }
+    }
+}
 
 /// Main HTTP server structure
 pub struct Server {
     config: Config,
+    /// File the configuration is read from again on SIGHUP
+    config_path: Option<String>,
+    /// Reports SIGHUP to the event loop while a config path is set
+    hangup: Option<HangupPipe>,
     epoll: Epoll,
-    server_sockets: HashMap<RawFd, (String, u16)>, // fd -> (host, port)
+    server_sockets: HashMap<RawFd, Listener>,
+    /// Certificates of the listeners with `ssl on`
+    tls_listeners: HashMap<RawFd, TlsConfig>,
     connection_manager: ConnectionManager,
-    method_handler: MethodHandler,
-    error_manager: ErrorPageManager,
-    #[allow(dead_code)] // TODO: Implement session management
+    cgi_pipes: HashMap<RawFd, RawFd>, // CGI pipe fd -> client fd
+    /// Handlers for connections accepted from now on
+    handlers: Arc<Handlers>,
+    /// Handlers each connection was accepted with, kept until it closes
+    /// so a reload never changes the rules mid-connection
+    connection_handlers: HashMap<RawFd, Arc<Handlers>>,
     session_manager: SessionManager,
+    /// One logger per distinct access_log target, shared by servers naming the same one
+    access_loggers: HashMap<AccessLog, AccessLogger>,
+    /// Client buckets of each server block with a rate_limit, and the limit they were made for
+    rate_limiters: HashMap<ServerKey, (RateLimit, RateLimiter)>,
+    /// Threads running CGI scripts when `cgi_workers` is set
+    cgi_workers: Option<CgiWorkerPool>,
+    /// Handlers named by `websocket` route directives
+    websocket_handlers: HashMap<String, WebSocketHandlerFactory>,
     running: bool,
 }
 
 impl Server {
     /// Create a new server with the given configuration
-    pub fn new(config: Config) -> ServerResult<Self> {
-        let epoll = Epoll::new()?;
-        let connection_manager = ConnectionManager::new(30); // 30 second timeout
-        let method_handler = MethodHandler::new(config.clone());
+    pub fn new(mut config: Config) -> ServerResult<Self> {
+        config.resolve_roots()?;
+        let mut epoll = Epoll::new()?;
+        // Edge triggering is all-or-nothing for the shared event loop
+        epoll.set_edge_triggered(config.servers.iter().any(|server| server.epoll_mode == EpollMode::Edge));
+        let mut connection_manager = ConnectionManager::new(30); // 30 second timeout
+        configure_connections(&mut connection_manager, &config);
 
-        // Create error manager from first server's configuration
-        let error_manager = if let Some(server) = config.servers.first() {
-            ErrorPageManager::from_config(server)
+        let mut access_loggers = HashMap::new();
+        open_access_logs(&mut access_loggers, &config)?;
+
+        let mut rate_limiters = HashMap::new();
+        configure_rate_limiters(&mut rate_limiters, &config);
+
+        let worker_count = config.servers.iter().map(|server| server.cgi_workers).max().unwrap_or(0);
+        let cgi_workers = if worker_count > 0 {
+            let pool = CgiWorkerPool::new(worker_count)?;
+            epoll.add(pool.event_fd(), EPOLLIN)?;
+            Some(pool)
         } else {
-            ErrorPageManager::new()
+            None
         };
 
         Ok(Server {
+            handlers: Arc::new(Handlers::new(&config)),
+            connection_handlers: HashMap::new(),
             config,
+            config_path: None,
+            hangup: None,
             epoll,
             server_sockets: HashMap::new(),
+            tls_listeners: HashMap::new(),
             connection_manager,
-            method_handler,
-            error_manager,
+            cgi_pipes: HashMap::new(),
             session_manager: SessionManager::with_defaults(),
+            access_loggers,
+            rate_limiters,
+            cgi_workers,
+            websocket_handlers: HashMap::from([(ECHO_HANDLER.to_string(), EchoHandler::boxed as WebSocketHandlerFactory)]),
             running: false,
         })
     }
 
+    /// Make a handler available to routes naming it in their `websocket` directive
+    pub fn set_websocket_handler(&mut self, name: &str, factory: WebSocketHandlerFactory) {
+        self.websocket_handlers.insert(name.to_string(), factory);
+    }
+
+    /// Name the file the configuration came from, so SIGHUP reloads it
+    pub fn set_config_path(&mut self, path: &str) {
+        self.config_path = Some(path.to_string());
+    }
+
     /// Run the server (main event loop)
     pub fn run(&mut self) -> ServerResult<()> {
         println!("Starting localhost HTTP server...");
 
+        // Listen for SIGHUP before the first client can connect
+        if self.config_path.is_some() {
+            let hangup = HangupPipe::install()?;
+            self.epoll.add(hangup.read_fd(), EPOLLIN)?;
+            self.hangup = Some(hangup);
+        }
+
         // Create and bind server sockets
         self.setup_server_sockets()?;
 
         println!("Server listening on {} socket(s)", self.server_sockets.len());
-        for (_, (host, port)) in &self.server_sockets {
-            println!("  http://{}:{}", host, port);
+        for listener in self.server_sockets.values() {
+            println!("  {}", listener);
         }
 
         self.running = true;
@@ -75,23 +194,64 @@ impl Server {
 
     /// Setup server sockets for all configured servers
     fn setup_server_sockets(&mut self) -> ServerResult<()> {
+        // Server blocks on the same address share one socket; the Host header,
+        // and for TLS the SNI name, tells them apart
+        let mut addresses: Vec<((String, u16), Vec<&ServerConfig>)> = Vec::new();
         for server_config in &self.config.servers {
             for &port in &server_config.ports {
-                let socket_fd = create_tcp_socket()?;
+                let address = (server_config.host.clone(), port);
+                match addresses.iter_mut().find(|(bound, _)| *bound == address) {
+                    Some((_, servers)) => servers.push(server_config),
+                    None => addresses.push((address, vec![server_config])),
+                }
+            }
+        }
 
-                // Bind to address
-                bind_socket(socket_fd, &server_config.host, port)?;
+        for ((host, port), servers) in addresses {
+            // Socket options come from the first, default, server on the address
+            let options = ListenOptions::from_config(servers[0]);
+            let socket_fd = create_tcp_socket(&host, &options)?;
 
-                // Start listening
-                listen_socket(socket_fd, 128)?;
+            // Bind to address
+            if let Err(e) = bind_socket(socket_fd, &host, port) {
+                close_socket(socket_fd);
+                return Err(e);
+            }
 
-                // Add to epoll for accepting connections
-                self.epoll.add(socket_fd, EPOLLIN)?;
+            // Start listening
+            listen_socket(socket_fd, options.backlog)?;
 
-                // Store socket info
-                self.server_sockets.insert(socket_fd, (server_config.host.clone(), port));
+            // Add to epoll for accepting connections
+            self.epoll.add(socket_fd, EPOLLIN)?;
 
-                println!("Bound to {}:{}", server_config.host, port);
+            // Store socket info
+            let tls_config = tls::listener_config(&servers)?;
+            let listener = Listener::Tcp { host, port, secure: tls_config.is_some() };
+            println!("Bound to {}", listener);
+            self.server_sockets.insert(socket_fd, listener);
+            if let Some(tls_config) = tls_config {
+                self.tls_listeners.insert(socket_fd, tls_config);
+            }
+        }
+
+        for server_config in &self.config.servers {
+            if server_config.unix_sockets.is_empty() {
+                continue;
+            }
+            let options = ListenOptions::from_config(server_config);
+            let tls_config = tls::listener_config(&[server_config])?;
+
+            for path in &server_config.unix_sockets {
+                let socket_fd = create_unix_socket(path)?;
+                listen_socket(socket_fd, options.backlog)?;
+                self.epoll.add(socket_fd, EPOLLIN)?;
+
+                let listener = Listener::Unix { path: path.clone() };
+                println!("Bound to {}", listener);
+                self.server_sockets.insert(socket_fd, listener);
+                if let Some(tls_config) = &tls_config {
+                    self.tls_listeners.insert(socket_fd, tls_config.clone());
+                }
             }
         }
 
@@ -107,15 +267,19 @@ impl Server {
         let mut events = vec![create_epoll_event(0, 0); crate::defaults::MAX_EVENTS];
 
         while self.running {
-            // Wait for events with 1 second timeout
-            let event_count = self.epoll.wait(&mut events, 1000)?;
+            // Wait for events with 1 second timeout, polling faster while a
+            // CGI script has closed its output but not yet exited
+            let timeout_ms = if self.cgi_awaiting_exit() { 10 } else { 1000 };
+            let event_count = self.epoll.wait(&mut events, timeout_ms)?;
 
             // Check for timed out connections
             let _ = self.cleanup_timed_out_connections();
 
+            // Drop expired sessions once per cleanup interval
+            let _ = self.session_manager.maybe_cleanup();
+
             // Process events
-            for i in 0..event_count {
-                let event = &events[i];
+            for event in events.iter().take(event_count) {
                 let fd = get_fd_from_event(event);
                 let event_flags = event.events; // Copy to avoid packed field access
 
@@ -125,6 +289,9 @@ impl Server {
                 }
             }
 
+            // Finish exited CGI scripts and kill those that ran too long
+            self.check_cgi_processes();
+
             // Cleanup timed out connections
             let _ = self.cleanup_timed_out_connections();
         }
@@ -134,6 +301,24 @@ impl Server {
 
     /// Handle a single epoll event
     fn handle_event(&mut self, fd: RawFd, events: u32) -> ServerResult<()> {
+        // CGI pipes report EPOLLHUP on normal end of output, so route them first
+        if let Some(&client_fd) = self.cgi_pipes.get(&fd) {
+            self.handle_cgi_event(client_fd, fd);
+            return Ok(());
+        }
+
+        if self.cgi_workers.as_ref().is_some_and(|pool| pool.event_fd() == fd) {
+            self.finish_cgi_jobs();
+            return Ok(());
+        }
+
+        if self.hangup.as_ref().is_some_and(|hangup| hangup.read_fd() == fd) {
+            if self.hangup.as_ref().is_some_and(|hangup| hangup.drain()) {
+                self.reload_config();
+            }
+            return Ok(());
+        }
+
         // Check for errors first
         if events & (EPOLLERR | EPOLLHUP) != 0 {
             self.cleanup_connection(fd);
@@ -162,70 +347,172 @@ impl Server {
 
     /// Accept new connections on a server socket
     fn accept_new_connections(&mut self, server_fd: RawFd) -> ServerResult<()> {
-        loop {
-            match accept_connection(server_fd)? {
-                Some(client_fd) => {
-                    // Add client to epoll for reading
-                    self.epoll.add(client_fd, EPOLLIN)?;
-
-                    // Add to connection manager
-                    match self.connection_manager.add_connection(client_fd) {
-                        Ok(()) => {
-                            println!("New connection accepted: fd {}", client_fd);
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to add connection {}: {}", client_fd, e);
-                            self.connection_manager.record_error();
-                            close_socket(client_fd);
-                            self.epoll.remove(client_fd)?;
+        while let Some((client_fd, peer_addr)) = accept_connection(server_fd)? {
+            if self.connection_manager.is_full() {
+                self.shed_connection(server_fd, client_fd);
+                continue;
+            }
+
+            // Add client to epoll for reading
+            self.epoll.add(client_fd, EPOLLIN)?;
+
+            // Add to connection manager
+            match self.connection_manager.add_connection(client_fd, peer_addr) {
+                Ok(()) => {
+                    println!("New connection accepted: fd {}", client_fd);
+                    self.connection_handlers.insert(client_fd, Arc::clone(&self.handlers));
+                    if let Some(connection) = self.connection_manager.get_connection_mut(client_fd) {
+                        connection.local_addr = local_address(client_fd);
+                    }
+                    // The Host header is not known yet, so the listener's default server decides
+                    if let Some(server) = self.listener_owner(server_fd) {
+                        self.connection_manager.set_header_timeout(client_fd, Duration::from_secs(server.header_timeout));
+                    }
+                    if let Some(tls_config) = self.tls_listeners.get(&server_fd) {
+                        let session = TlsSession::new(tls_config.clone())?;
+                        if let Some(connection) = self.connection_manager.get_connection_mut(client_fd) {
+                            connection.tls = Some(session);
                         }
                     }
                 }
-                None => break, // No more connections to accept
+                Err(e) => {
+                    eprintln!("Failed to add connection {}: {}", client_fd, e);
+                    self.connection_manager.record_error();
+                    close_socket(client_fd);
+                    self.epoll.remove(client_fd)?;
+                }
             }
         }
         Ok(())
     }
 
+    /// The first, default, server of the current configuration bound to a listener
+    fn listener_owner(&self, server_fd: RawFd) -> Option<&ServerConfig> {
+        let listener = self.server_sockets.get(&server_fd)?;
+        self.config.servers.iter().find(|server| match listener {
+            Listener::Tcp { host, port, .. } => server.host == *host && server.ports.contains(port),
+            Listener::Unix { path } => server.unix_sockets.contains(path),
+        })
+    }
+
+    /// Turn away a connection accepted at the connection limit with a 503,
+    /// so the client learns why instead of seeing a reset
+    fn shed_connection(&mut self, server_fd: RawFd, client_fd: RawFd) {
+        eprintln!("Connection limit reached, refusing fd {}", client_fd);
+
+        // TLS clients cannot be answered before a handshake, so they are just closed
+        if !self.tls_listeners.contains_key(&server_fd) {
+            let mut response = self.handlers.error_manager.generate_error_response(
+                HttpStatus::ServiceUnavailable,
+                Some("Too many connections")
+            );
+            response.add_header("Retry-After", &SHED_RETRY_AFTER_SECS.to_string());
+            response.finalize();
+            response.set_keep_alive(false);
+
+            // A fresh socket's send buffer takes a short response whole; anything else is dropped
+            let mut buffer = Buffer::new(0);
+            if buffer.append(&response.to_bytes()).is_ok() {
+                let _ = buffer.write_to_fd(client_fd);
+            }
+        }
+        close_socket(client_fd);
+    }
+
+    /// Read the configuration file again and use it for connections accepted
+    /// from now on. Open connections finish under the configuration they
+    /// started with, and a file that fails to load leaves everything as it was.
+    /// Listening sockets and other process-wide settings are kept.
+    fn reload_config(&mut self) {
+        let path = match &self.config_path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let loaded = Config::from_file(&path).and_then(|mut config| {
+            config.validate()?;
+            config.resolve_roots()?;
+            Ok(config)
+        });
+        let config = match loaded {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Reloading configuration from '{}' failed, keeping the current one: {}", path, e);
+                return;
+            }
+        };
+        if let Err(e) = open_access_logs(&mut self.access_loggers, &config) {
+            eprintln!("Reloading configuration from '{}' failed, keeping the current one: {}", path, e);
+            return;
+        }
+
+        configure_connections(&mut self.connection_manager, &config);
+        configure_rate_limiters(&mut self.rate_limiters, &config);
+        let mut handlers = Handlers::new(&config);
+        handlers.method_handler.keep_cgi_limiters(&self.handlers.method_handler);
+        self.handlers = Arc::new(handlers);
+        self.config = config;
+        println!("Configuration reloaded from: {}", path);
+    }
+
+    /// Handlers for a connection, or the current ones if it has none yet
+    fn handlers(&self, fd: RawFd) -> Arc<Handlers> {
+        Arc::clone(self.connection_handlers.get(&fd).unwrap_or(&self.handlers))
+    }
+
     /// Handle read event on client connection
     fn handle_read(&mut self, fd: RawFd) -> ServerResult<()> {
+        let edge_triggered = self.epoll.is_edge_triggered();
+
         if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
             connection.touch();
 
-            match connection.read_buffer.read_from_fd(fd) {
-                Ok(0) => {
+            // Edge-triggered fds are not re-notified, so read until EAGAIN
+            let read_result = connection.read_from_socket(edge_triggered);
+
+            // Handshake messages go out as soon as rustls produces them
+            if connection.has_pending_tls() {
+                let _ = connection.flush_tls();
+                if connection.has_pending_tls() {
+                    self.epoll.modify(fd, EPOLLIN | EPOLLOUT)?;
+                }
+            }
+
+            match read_result {
+                Ok((0, true)) => {
                     // Client closed connection
                     self.cleanup_connection(fd);
                 }
-                Ok(_bytes_read) => {
+                Ok((0, false)) => {
+                    // Nothing to read yet
+                }
+                Ok((bytes_read, eof)) if matches!(connection.state, ConnectionState::WebSocket) => {
+                    let data = connection.read_buffer.readable_data().to_vec();
+                    connection.read_buffer.consume(data.len());
+                    self.connection_manager.update_activity(fd, bytes_read, true);
+                    self.receive_websocket(fd, &data)?;
+                    if eof {
+                        self.cleanup_connection(fd);
+                    }
+                }
+                Ok((bytes_read, eof)) => {
+                    connection.start_request();
+
                     // Try to parse HTTP request
+                    // The parser keeps partial requests in its own buffer, so
+                    // everything handed to it is consumed from the read buffer
                     let data = connection.read_buffer.readable_data();
-                    match connection.http_parser.parse(data) {
-                        Ok((Some(request), consumed)) => {
-                            // Consume only the parsed data from buffer
-                            connection.read_buffer.consume(consumed);
+                    let data_len = data.len();
+                    let result = connection.http_parser.parse(data);
+                    connection.read_buffer.consume(data_len);
 
-                            // Release the connection reference before calling update_activity
-
-                            // Update connection activity
-                            self.connection_manager.update_activity(fd, consumed, true);
-
-                            // Process the request and generate response
-                            self.process_http_request(fd, request)?;
-                        }
-                        Ok((None, consumed)) => {
-                            // Need more data to complete parsing
-                            // Consume any processed data
-                            if consumed > 0 {
-                                connection.read_buffer.consume(consumed);
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("HTTP parsing error on fd {}: {}", fd, e);
-                            self.connection_manager.record_error();
-                            self.send_error_response(fd, HttpStatus::BadRequest, Some("Invalid HTTP request"))?;
-                        }
-                    }
+                    self.connection_manager.update_activity(fd, bytes_read, true);
+                    self.handle_parse_result(fd, result, eof)?;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::OutOfMemory => {
+                    // The client sent more than any request may hold
+                    eprintln!("Read buffer limit reached on fd {}: {}", fd, e);
+                    self.connection_manager.record_error();
+                    self.send_error_response(fd, HttpStatus::RequestEntityTooLarge, Some("Request too large"))?;
                 }
                 Err(e) => {
                     eprintln!("Read error on fd {}: {}", fd, e);
@@ -236,24 +523,170 @@ impl Server {
         Ok(())
     }
 
+    /// Act on the outcome of feeding data to a connection's parser
+    fn handle_parse_result(&mut self, fd: RawFd, result: ServerResult<(Option<HttpRequest>, usize)>, eof: bool) -> ServerResult<()> {
+        match result {
+            Ok((Some(request), _consumed)) => {
+                // Process the request and generate response
+                self.process_http_request(fd, request)?;
+            }
+            Ok((None, _consumed)) => {
+                // Need more data to complete parsing, unless the client is gone
+                let (wants_continue, body_start) = self.connection_manager.get_connection_mut(fd)
+                    .map(|connection| (connection.http_parser.take_continue(), connection.http_parser.take_body_start()))
+                    .unwrap_or_default();
+                if eof {
+                    self.cleanup_connection(fd);
+                } else if body_start && self.declares_oversized_body(fd) {
+                    // Refused on its declared length alone, so the body is neither invited nor read
+                    self.connection_manager.record_error();
+                    self.send_error_response(fd, HttpStatus::RequestEntityTooLarge, Some("Request body too large"))?;
+                } else {
+                    if wants_continue {
+                        // The client is waiting for permission to send its body
+                        self.send_continue(fd);
+                    }
+                    if body_start {
+                        // The route's timeout covers reading the body, not just handling it
+                        self.apply_route_timeout(fd);
+                        self.spool_upload_body(fd)?;
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("HTTP parsing error on fd {}: {}", fd, e);
+                self.connection_manager.record_error();
+                match e {
+                    ServerError::Http(ref kind) => {
+                        self.send_error_response(fd, kind.status(), Some(kind.description()))?;
+                    }
+                    ServerError::Io(_) => {
+                        // Only a spooled body touches the disk while parsing
+                        self.send_error_response(fd, HttpStatus::InternalServerError, Some("Failed to store request body"))?;
+                    }
+                    _ => {
+                        self.send_error_response(fd, HttpStatus::BadRequest, Some("Invalid HTTP request"))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the headers just parsed on a connection declare a body larger
+    /// than the server they are addressed to accepts. The parser only knows the
+    /// largest limit of any server, so smaller ones are enforced here.
+    fn declares_oversized_body(&self, fd: RawFd) -> bool {
+        let request = match self.connection_manager.get_connection(fd) {
+            Some(connection) => connection.http_parser.pending_request(),
+            None => return false,
+        };
+        let length = match request.content_length() {
+            Some(length) => length,
+            None => return false,
+        };
+        self.handlers(fd).method_handler.server_for(request)
+            .is_some_and(|server| length > server.max_body_size)
+    }
+
+    /// Give a connection the request timeout of the route its pending request
+    /// is for, as soon as the request's headers are in
+    fn apply_route_timeout(&mut self, fd: RawFd) {
+        let handlers = self.handlers(fd);
+        let timeout = self.connection_manager.get_connection(fd)
+            .and_then(|connection| handlers.method_handler.request_timeout(connection.http_parser.pending_request()));
+        if let Some(timeout) = timeout {
+            self.connection_manager.set_request_timeout(fd, timeout);
+        }
+    }
+
+    /// Once the headers of a large upload bound for a route with
+    /// `upload_to_tempfile on` are in, write its body to disk as it arrives
+    fn spool_upload_body(&mut self, fd: RawFd) -> ServerResult<()> {
+        let handlers = self.handlers(fd);
+        let connection = match self.connection_manager.get_connection_mut(fd) {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+
+        // Chunked bodies have no declared size, so any of them may be large
+        let request = connection.http_parser.pending_request();
+        let large = request.content_length().is_none_or(|length| length > UPLOAD_SPOOL_THRESHOLD);
+        if !large || !handlers.method_handler.spools_upload(request) {
+            return Ok(());
+        }
+
+        let result = connection.http_parser.spool_body()
+            .map_err(ServerError::from)
+            .and_then(|_| connection.http_parser.parse(&[]));
+        self.handle_parse_result(fd, result, false)
+    }
+
     /// Handle write event on client connection
     fn handle_write(&mut self, fd: RawFd) -> ServerResult<()> {
-        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
-            connection.touch();
+        let (write_result, bytes_written) = match self.connection_manager.get_connection_mut(fd) {
+            Some(connection) => {
+                connection.touch();
+
+                // Write until the response is sent or the socket would block,
+                // reading streamed bodies from disk as the buffer drains;
+                // write_to_fd reports EAGAIN as zero bytes written
+                let mut write_result = Ok(0);
+                let mut bytes_written = 0;
+                loop {
+                    if let Err(e) = connection.fill_write_buffer() {
+                        write_result = Err(e);
+                        break;
+                    }
+                    if connection.write_buffer.is_empty() && !connection.has_pending_tls() {
+                        break;
+                    }
+                    write_result = connection.write_to_socket();
+                    match write_result {
+                        Ok(n) if n > 0 => bytes_written += n,
+                        _ => break,
+                    }
+                }
+                (write_result, bytes_written)
+            }
+            None => return Ok(()),
+        };
+        self.connection_manager.record_bytes_written(fd, bytes_written);
 
-            match connection.write_buffer.write_to_fd(fd) {
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            match write_result {
+                Ok(_) if matches!(connection.state, ConnectionState::Reading) => {
+                    // Only handshake bytes were waiting; go back to reading once they are out
+                    if !connection.has_pending_tls() {
+                        self.epoll.modify(fd, EPOLLIN)?;
+                    }
+                }
                 Ok(_bytes_written) => {
                     // Check if we've finished writing the response
-                    if connection.write_buffer.is_empty() {
-                        if connection.keep_alive {
+                    if connection.is_response_sent() {
+                        if connection.websocket.is_some() {
+                            self.resume_websocket(fd)?;
+                        } else if connection.keep_alive {
                             // Reset for next request
-                            connection.reset_for_keep_alive();
+                            self.connection_manager.reset_for_keep_alive(fd);
                             // Switch back to reading mode
                             self.epoll.modify(fd, EPOLLIN)?;
+
+                            // A pipelined request may already be buffered; no
+                            // further EPOLLIN will arrive for bytes already read
+                            if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+                                if connection.http_parser.has_buffered_data() {
+                                    let result = connection.http_parser.parse(&[]);
+                                    self.handle_parse_result(fd, result, false)?;
+                                }
+                            }
                         } else {
                             // Close connection
                             self.cleanup_connection(fd);
                         }
+                    } else if connection.write_buffer.is_empty() && !connection.has_pending_tls() && connection.is_streaming() {
+                        // Caught up with the CGI script; wait for more of its output
+                        self.epoll.modify(fd, 0)?;
                     }
                 }
                 Err(e) => {
@@ -266,34 +699,634 @@ impl Server {
     }
 
     /// Process HTTP request and generate response
-    fn process_http_request(&mut self, fd: RawFd, request: crate::http::HttpRequest) -> ServerResult<()> {
+    fn process_http_request(&mut self, fd: RawFd, mut request: HttpRequest) -> ServerResult<()> {
+        let handlers = self.handlers(fd);
+        let connection = self.connection_manager.get_connection(fd);
+        request.remote_addr = connection.and_then(|conn| conn.peer_addr);
+        request.local_addr = connection.and_then(|conn| conn.local_addr);
+        if let Some(peer) = request.remote_addr {
+            let trusted = handlers.method_handler.server_for(&request)
+                .map(|server| server.trusted_proxies.as_slice())
+                .unwrap_or_default();
+            request.client_ip = Some(resolve_client_ip(peer.ip(), &request.headers, trusted));
+        }
+
+        // Clients over their request budget are turned away before any other work
+        if let Some(retry_after) = self.check_rate_limit(&handlers, &request) {
+            let mut response = handlers.method_handler.error_manager_for_request(&request)
+                .generate_error_response(HttpStatus::TooManyRequests, None);
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.add_header("Retry-After", &seconds.max(1).to_string());
+            return self.complete_request(fd, &request, response);
+        }
+
+        self.attach_session(&mut request);
+
+        // The status endpoint is answered before routing
+        if is_status_request(&handlers, &request) {
+            let response = self.status_response(&request);
+            return self.complete_request(fd, &request, response);
+        }
+
+        // The matched route decides how long this connection may sit idle
+        if let Some(timeout) = handlers.method_handler.request_timeout(&request) {
+            self.connection_manager.set_request_timeout(fd, timeout);
+        }
+
         // Use the method handler to process the request
-        let response = self.method_handler.handle_request(&request)
-            .unwrap_or_else(|e| {
+        let response = match handlers.method_handler.dispatch(&mut request) {
+            Ok(Dispatch::Response(response)) => response,
+            Ok(Dispatch::Cgi(process)) => return self.start_cgi(fd, request, process),
+            Ok(Dispatch::WebSocket { response, handler }) => return self.start_websocket(fd, request, response, &handler),
+            Err(e) => {
                 eprintln!("Error processing request: {}", e);
-                self.error_manager.generate_error_response(HttpStatus::InternalServerError, Some("Internal server error"))
+                handlers.error_response(&request, &e)
+            }
+        };
+
+        self.complete_request(fd, &request, response)
+    }
+
+    /// Handle a parsed request in memory, as `process_http_request` would for a
+    /// client: sessions, routing, method handling and error pages apply, while
+    /// sockets, rate limits and the status endpoint play no part. CGI scripts
+    /// run to completion and WebSocket upgrades give just their handshake.
+    pub fn handle_request_for_test(&self, mut request: HttpRequest) -> HttpResponse {
+        self.attach_session(&mut request);
+        let mut response = self.handlers.method_handler.handle_request(&mut request)
+            .unwrap_or_else(|e| self.handlers.error_response(&request, &e));
+        self.prepare_response(&request, &mut response);
+        response
+    }
+
+    /// Attach the client's session (touching it) or start a new one
+    fn attach_session(&self, request: &mut HttpRequest) {
+        match self.session_manager.get_or_create_session(&request.cookies) {
+            Ok((session_id, _created)) => request.session_id = Some(session_id),
+            Err(e) => eprintln!("Session error: {}", e),
+        }
+    }
+
+    /// Take a token from the client's bucket, returning how long to wait when none is left
+    fn check_rate_limit(&mut self, handlers: &Handlers, request: &HttpRequest) -> Option<Duration> {
+        let server = handlers.method_handler.server_for(request)?;
+        let ip = request.client_ip?;
+        let (_, limiter) = self.rate_limiters.get_mut(&server.key())?;
+        limiter.check(ip).err()
+    }
+
+    /// Live server statistics, as JSON unless the client asks for Prometheus text
+    fn status_response(&self, request: &HttpRequest) -> HttpResponse {
+        let prometheus = request.query_params.get("format").is_some_and(|format| format == "prometheus")
+            || request.get_header("accept").is_some_and(|accept| accept.to_ascii_lowercase().contains("text/plain"));
+        let mut response = if prometheus {
+            self.prometheus_status()
+        } else {
+            self.json_status()
+        };
+        response.add_header("Cache-Control", "no-store");
+        response
+    }
+
+    /// JSON document of live server statistics
+    fn json_status(&self) -> HttpResponse {
+        let stats = self.connection_manager.get_resource_stats();
+        let timeouts = self.connection_manager.get_timeout_stats();
+        let json = format!(
+            "{{\"uptime_seconds\":{},\"current_connections\":{},\"peak_connections\":{},\"total_requests\":{},\"bytes_transferred\":{},\"connection_bytes_read\":{},\"connection_bytes_written\":{},\"error_count\":{},\"error_rate\":{:.4}}}",
+            stats.uptime.as_secs(),
+            self.connection_manager.connection_count(),
+            stats.peak_connections,
+            stats.total_requests_served,
+            stats.total_bytes_transferred,
+            timeouts.total_bytes_read,
+            timeouts.total_bytes_written,
+            stats.error_count,
+            stats.error_rate(),
+        );
+
+        HttpResponse::json(HttpStatus::Ok, &json)
+    }
+
+    /// Live server statistics in the Prometheus text exposition format
+    fn prometheus_status(&self) -> HttpResponse {
+        let stats = self.connection_manager.get_resource_stats();
+        let timeouts = self.connection_manager.get_timeout_stats();
+
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+            for (labels, value) in samples {
+                text.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        };
+
+        metric("http_requests_total", "counter", "Requests answered since startup.",
+            &[("", stats.total_requests_served.to_string())]);
+        metric("http_bytes_total", "counter", "Response bytes sent since startup.",
+            &[("", stats.total_bytes_transferred.to_string())]);
+        metric("http_errors_total", "counter", "Connection and request errors since startup.",
+            &[("", stats.error_count.to_string())]);
+        metric("http_connections_current", "gauge", "Open client connections.",
+            &[("", self.connection_manager.connection_count().to_string())]);
+        metric("http_connections_peak", "gauge", "Most client connections open at once.",
+            &[("", stats.peak_connections.to_string())]);
+        metric("http_connections_max", "gauge", "Client connections allowed at once.",
+            &[("", timeouts.max_connections.to_string())]);
+        metric("http_connections_state", "gauge", "Open client connections by state.", &[
+            ("{state=\"reading\"}", timeouts.reading_connections.to_string()),
+            ("{state=\"processing\"}", timeouts.processing_connections.to_string()),
+            ("{state=\"writing\"}", timeouts.writing_connections.to_string()),
+            ("{state=\"keepalive\"}", timeouts.keepalive_connections.to_string()),
+            ("{state=\"closing\"}", timeouts.closing_connections.to_string()),
+        ]);
+        metric("server_uptime_seconds", "gauge", "Seconds since the server started.",
+            &[("", stats.uptime.as_secs().to_string())]);
+
+        let mut response = HttpResponse::new(HttpStatus::Ok);
+        response.set_content_type("text/plain; version=0.0.4; charset=utf-8");
+        response.set_body_string(text);
+        response
+    }
+
+    /// Record a completed request and queue its response
+    fn complete_request(&mut self, fd: RawFd, request: &HttpRequest, mut response: HttpResponse) -> ServerResult<()> {
+        self.prepare_response(request, &mut response);
+        self.connection_manager.record_request(fd, response.wire_len());
+        let handlers = self.handlers(fd);
+        self.log_access(&handlers, request, &response);
+
+        // The server may refuse persistent connections whatever the client
+        // asks, and caps how many requests one connection carries
+        let server = handlers.method_handler.server_for(request);
+        let requests_remaining = if request.keep_alive() && server.is_none_or(|server| server.keep_alive_enabled) {
+            let max_requests = server
+                .map(|server| server.max_requests_per_connection)
+                .unwrap_or(crate::defaults::DEFAULT_MAX_REQUESTS_PER_CONNECTION);
+            self.connection_manager.requests_remaining(fd, max_requests)
+        } else {
+            0
+        };
+        self.send_response(fd, response, requests_remaining)
+    }
+
+    /// Fit a response to its request before it is sent
+    fn prepare_response(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        // Refresh the session cookie so its expiry slides with activity
+        if let Some(ref session_id) = request.session_id {
+            response.add_cookie(self.session_manager.create_session_cookie(session_id));
+        }
+
+        crate::http::compression::compress_response(response, request.get_header("accept-encoding").map(|s| s.as_str()));
+
+        // HEAD responses, error pages included, lose their body only once it
+        // is encoded, so they report the length a GET would be sent
+        if request.method == HttpMethod::HEAD {
+            response.strip_body();
+        }
+
+        // Answer in the client's own protocol version
+        response.version = request.version.clone();
+        response.finalize();
+    }
+
+    /// Write the access log line for a completed request
+    fn log_access(&mut self, handlers: &Handlers, request: &HttpRequest, response: &HttpResponse) {
+        let target = match handlers.method_handler.server_for(request) {
+            Some(server) => &server.access_log,
+            None => return,
+        };
+
+        if let Some(logger) = self.access_loggers.get_mut(target) {
+            logger.log(&AccessLogEntry {
+                remote_addr: request.client_ip.map(|ip| ip.to_string()),
+                time: std::time::SystemTime::now(),
+                method: request.method.as_str().to_string(),
+                uri: request.uri.clone(),
+                version: request.version.as_str().to_string(),
+                status: response.status.as_u16(),
+                bytes: response.body_len() as usize,
             });
+        }
+    }
+
+    /// Send the handshake response of an upgrade; the connection switches to
+    /// WebSocket once it is written
+    fn start_websocket(&mut self, fd: RawFd, request: HttpRequest, response: HttpResponse, handler: &str) -> ServerResult<()> {
+        let factory = match self.websocket_handlers.get(handler) {
+            Some(factory) => *factory,
+            None => {
+                eprintln!("No WebSocket handler named {}", handler);
+                let response = self.handlers(fd).method_handler.error_manager_for_request(&request)
+                    .generate_error_response(HttpStatus::InternalServerError, Some("Internal server error"));
+                return self.complete_request(fd, &request, response);
+            }
+        };
+        let max_message_size = self.handlers(fd).method_handler.server_for(&request)
+            .map_or(crate::defaults::DEFAULT_MAX_BODY_SIZE, |server| server.max_body_size);
+
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            connection.websocket = Some(WebSocket::new(factory(), max_message_size));
+        }
+        self.complete_request(fd, &request, response)
+    }
+
+    /// Start exchanging frames once the handshake response has been sent,
+    /// or end the connection once a Close frame has
+    fn resume_websocket(&mut self, fd: RawFd) -> ServerResult<()> {
+        let connection = match self.connection_manager.get_connection_mut(fd) {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+        if connection.websocket.as_ref().is_some_and(|websocket| websocket.is_closed()) {
+            self.cleanup_connection(fd);
+            return Ok(());
+        }
+
+        self.epoll.modify(fd, EPOLLIN)?;
+        if matches!(connection.state, ConnectionState::WebSocket) {
+            return Ok(());
+        }
+
+        // Frames sent right behind the handshake were read along with the request
+        connection.state = ConnectionState::WebSocket;
+        let early = connection.http_parser.take_buffered();
+        if early.is_empty() {
+            return Ok(());
+        }
+        self.receive_websocket(fd, &early)
+    }
+
+    /// Pass bytes from an upgraded client to its handler and queue the frames sent back
+    fn receive_websocket(&mut self, fd: RawFd, data: &[u8]) -> ServerResult<()> {
+        let connection = match self.connection_manager.get_connection_mut(fd) {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+        let websocket = match connection.websocket.as_mut() {
+            Some(websocket) => websocket,
+            None => return Ok(()),
+        };
+
+        let output = websocket.receive(data);
+        if !output.is_empty() {
+            connection.write_buffer.append(&output)?;
+            self.epoll.modify(fd, EPOLLOUT)?;
+        }
+        Ok(())
+    }
+
+    /// Register a spawned CGI script's pipes with epoll and park the connection
+    fn start_cgi(&mut self, fd: RawFd, request: HttpRequest, process: CgiProcess) -> ServerResult<()> {
+        // Workers hand back whole responses, so event streams stay on the event loop
+        if self.cgi_workers.is_some() && !self.handlers(fd).method_handler.streams_events(&request) {
+            return self.queue_cgi_job(fd, request, process);
+        }
+
+        if let Some(stdin_fd) = process.stdin_fd() {
+            self.epoll.add(stdin_fd, EPOLLOUT)?;
+            self.cgi_pipes.insert(stdin_fd, fd);
+        }
+        if let Some(stdout_fd) = process.stdout_fd() {
+            self.epoll.add(stdout_fd, EPOLLIN)?;
+            self.cgi_pipes.insert(stdout_fd, fd);
+        }
+
+        // Output is streamed only to clients that understand chunked framing,
+        // and never when it is to be cached
+        let output_mode = if request.version == HttpVersion::Http11 && request.method != HttpMethod::HEAD
+            && !self.handlers(fd).method_handler.caches_cgi(&request)
+        {
+            CgiOutputMode::Pending
+        } else {
+            CgiOutputMode::Buffered
+        };
+
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            connection.cgi = Some(CgiState { process, request, output_mode });
+            connection.state = ConnectionState::WaitingForCgi;
+        }
+
+        // Stop reading from the client until the response is ready
+        self.epoll.modify(fd, 0)?;
+
+        self.poll_cgi(fd)
+    }
+
+    /// Hand a spawned CGI script to the worker pool and park the connection
+    /// until its response comes back
+    fn queue_cgi_job(&mut self, fd: RawFd, request: HttpRequest, process: CgiProcess) -> ServerResult<()> {
+        let id = match self.cgi_workers.as_mut() {
+            Some(pool) => pool.submit(fd, process),
+            None => return Ok(()),
+        };
+
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            connection.cgi_job = Some(CgiJobState { id, request });
+            connection.state = ConnectionState::WaitingForCgi;
+        }
+
+        // Stop reading from the client until the response is ready
+        self.epoll.modify(fd, 0)
+    }
+
+    /// Send the responses of CGI scripts the workers have finished
+    fn finish_cgi_jobs(&mut self) {
+        let outcomes = match self.cgi_workers.as_mut() {
+            Some(pool) => pool.take_outcomes(),
+            None => return,
+        };
+
+        for outcome in outcomes {
+            let fd = outcome.client_fd;
+            // The client may have gone, and its descriptor been reused, while the script ran
+            let job = match self.connection_manager.get_connection_mut(fd) {
+                Some(connection) if connection.cgi_job.as_ref().is_some_and(|job| job.id == outcome.id) => {
+                    connection.cgi_job.take()
+                }
+                _ => None,
+            };
+
+            if let Some(job) = job {
+                let response = self.handlers(fd).method_handler.finish_cgi(&job.request, outcome.result);
+                if let Err(e) = self.complete_request(fd, &job.request, response) {
+                    eprintln!("Error sending CGI response on fd {}: {}", fd, e);
+                    self.cleanup_connection(fd);
+                }
+            }
+        }
+    }
+
+    /// Handle readiness of one of a CGI script's pipes
+    fn handle_cgi_event(&mut self, client_fd: RawFd, pipe_fd: RawFd) {
+        let process = match self.connection_manager.get_connection_mut(client_fd)
+            .and_then(|connection| connection.cgi.as_mut())
+        {
+            Some(state) => &mut state.process,
+            None => {
+                self.unregister_cgi_pipe(pipe_fd);
+                return;
+            }
+        };
+
+        if process.stdin_fd() == Some(pipe_fd) {
+            // A failed write means the script stopped reading its input
+            if process.write_input().unwrap_or(true) {
+                self.unregister_cgi_pipe(pipe_fd);
+                self.close_cgi_pipe(client_fd, pipe_fd);
+            }
+            return;
+        }
+
+        match process.read_output() {
+            Ok(false) => {
+                if let Err(e) = self.relay_cgi_output(client_fd, false) {
+                    eprintln!("Error relaying CGI output on fd {}: {}", client_fd, e);
+                    self.cleanup_connection(client_fd);
+                }
+            }
+            Ok(true) => {
+                if let Err(e) = self.relay_cgi_output(client_fd, true) {
+                    eprintln!("Error relaying CGI output on fd {}: {}", client_fd, e);
+                    self.cleanup_connection(client_fd);
+                    return;
+                }
+                self.unregister_cgi_pipe(pipe_fd);
+                self.close_cgi_pipe(client_fd, pipe_fd);
+                if let Err(e) = self.poll_cgi(client_fd) {
+                    eprintln!("Error completing CGI request on fd {}: {}", client_fd, e);
+                    self.cleanup_connection(client_fd);
+                }
+            }
+            Err(e) => self.fail_cgi(client_fd, e),
+        }
+    }
+
+    /// Send the CGI response once the script has closed its output and exited
+    fn poll_cgi(&mut self, fd: RawFd) -> ServerResult<()> {
+        let finished = match self.connection_manager.get_connection_mut(fd)
+            .and_then(|connection| connection.cgi.as_mut())
+        {
+            Some(state) => state.process.stdout_fd().is_none() && state.process.try_wait()?,
+            None => false,
+        };
+
+        if finished {
+            if let Some(state) = self.detach_cgi(fd) {
+                if state.output_mode == CgiOutputMode::Streaming {
+                    let succeeded = state.process.succeeded();
+                    self.end_cgi_stream(fd, state, succeeded)?;
+                } else {
+                    let response = self.handlers(fd).method_handler.finish_cgi(&state.request, state.process.into_response());
+                    self.complete_request(fd, &state.request, response)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pass newly read CGI output on to the client. Once the script's headers
+    /// are in, a chunked response is started unless the script gave its own
+    /// Content-Length or has already finished, in which case it is buffered.
+    fn relay_cgi_output(&mut self, fd: RawFd, eof: bool) -> ServerResult<()> {
+        let state = match self.connection_manager.get_connection_mut(fd)
+            .and_then(|connection| connection.cgi.as_mut())
+        {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        if state.output_mode == CgiOutputMode::Pending {
+            if eof {
+                // All of the output is in; send it with its length once the script exits
+                state.output_mode = CgiOutputMode::Buffered;
+                return Ok(());
+            }
+
+            let (mut head, head_len) = match state.process.parse_head() {
+                Ok(Some(head)) => head,
+                Ok(None) => return Ok(()),
+                Err(_) => {
+                    // Malformed headers are reported once the script exits
+                    state.output_mode = CgiOutputMode::Buffered;
+                    return Ok(());
+                }
+            };
 
-        // Record the completed request
-        let response_size = response.to_bytes().len();
-        self.connection_manager.record_request(fd, response_size);
+            if head.get_header("Content-Length").is_some() || head.is_bodyless() {
+                state.output_mode = CgiOutputMode::Buffered;
+                return Ok(());
+            }
+
+            state.output_mode = CgiOutputMode::Streaming;
+            let mut output = state.process.take_output();
+            output.drain(..head_len);
+            let request = std::mem::take(&mut state.request);
+
+            head.set_body_stream();
+            let response = self.handlers(fd).method_handler.finish_cgi(&request, Ok(head));
+            self.complete_request(fd, &request, response)?;
+
+            if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+                connection.queue_chunk(&output)?;
+            }
+            return Ok(());
+        }
+
+        if state.output_mode == CgiOutputMode::Streaming {
+            let output = state.process.take_output();
+            if !output.is_empty() {
+                if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+                    connection.queue_chunk(&output)?;
+                }
+                // A long-running stream is active as long as output flows; the
+                // bytes are counted once they are written
+                self.connection_manager.update_activity(fd, 0, false);
+                self.epoll.modify(fd, EPOLLOUT)?;
+            }
+        }
 
-        self.send_response(fd, response, request.keep_alive())
+        Ok(())
+    }
+
+    /// Send the rest of a streamed CGI response. A script that failed after its
+    /// headers went out gets no last chunk, so the client sees the body cut short.
+    fn end_cgi_stream(&mut self, fd: RawFd, mut state: CgiState, succeeded: bool) -> ServerResult<()> {
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            connection.queue_chunk(&state.process.take_output())?;
+            if succeeded {
+                connection.finish_stream()?;
+            } else {
+                connection.pending_body = None;
+                connection.keep_alive = false;
+            }
+            self.epoll.modify(fd, EPOLLOUT)?;
+        }
+        Ok(())
+    }
+
+    /// Abort a connection's CGI script and answer with an error page
+    fn fail_cgi(&mut self, fd: RawFd, error: ServerError) {
+        if let Some(state) = self.detach_cgi(fd) {
+            if state.output_mode == CgiOutputMode::Streaming {
+                eprintln!("CGI execution error after response started on fd {}: {}", fd, error);
+                if let Err(e) = self.end_cgi_stream(fd, state, false) {
+                    eprintln!("Error ending CGI response on fd {}: {}", fd, e);
+                    self.cleanup_connection(fd);
+                }
+                return;
+            }
+            let response = self.handlers(fd).method_handler.finish_cgi(&state.request, Err(error));
+            if let Err(e) = self.complete_request(fd, &state.request, response) {
+                eprintln!("Error sending CGI error response on fd {}: {}", fd, e);
+                self.cleanup_connection(fd);
+            }
+        }
+    }
+
+    /// Finish exited CGI scripts and kill those past their timeout
+    fn check_cgi_processes(&mut self) {
+        for fd in self.connection_manager.get_cgi_fds() {
+            let timed_out = self.connection_manager.get_connection(fd)
+                .and_then(|connection| connection.cgi.as_ref())
+                .map(|state| state.process.is_timed_out())
+                .unwrap_or(false);
+
+            if timed_out {
+                self.fail_cgi(fd, CgiErrorKind::Timeout.into());
+            } else if let Err(e) = self.poll_cgi(fd) {
+                self.fail_cgi(fd, e);
+            }
+        }
+    }
+
+    /// Check if any CGI script has closed its output but not yet exited
+    fn cgi_awaiting_exit(&self) -> bool {
+        self.connection_manager.get_cgi_fds().into_iter().any(|fd| {
+            self.connection_manager.get_connection(fd)
+                .and_then(|connection| connection.cgi.as_ref())
+                .map(|state| state.process.is_awaiting_exit())
+                .unwrap_or(false)
+        })
+    }
+
+    /// Take a connection's CGI state, unregistering any pipes still open
+    fn detach_cgi(&mut self, fd: RawFd) -> Option<CgiState> {
+        let state = self.connection_manager.get_connection_mut(fd)?.cgi.take()?;
+        for pipe_fd in [state.process.stdin_fd(), state.process.stdout_fd()].into_iter().flatten() {
+            self.unregister_cgi_pipe(pipe_fd);
+        }
+        Some(state)
+    }
+
+    /// Remove a CGI pipe from epoll and the pipe map
+    fn unregister_cgi_pipe(&mut self, pipe_fd: RawFd) {
+        let _ = self.epoll.remove(pipe_fd);
+        self.cgi_pipes.remove(&pipe_fd);
+    }
+
+    /// Close one of a connection's CGI pipes
+    fn close_cgi_pipe(&mut self, client_fd: RawFd, pipe_fd: RawFd) {
+        if let Some(state) = self.connection_manager.get_connection_mut(client_fd)
+            .and_then(|connection| connection.cgi.as_mut())
+        {
+            if state.process.stdin_fd() == Some(pipe_fd) {
+                state.process.close_stdin();
+            } else if state.process.stdout_fd() == Some(pipe_fd) {
+                state.process.close_stdout();
+            }
+        }
     }
 
     /// Send an error response
     fn send_error_response(&mut self, fd: RawFd, status: HttpStatus, message: Option<&str>) -> ServerResult<()> {
-        let response = self.error_manager.generate_error_response(status, message);
-        self.send_response(fd, response, false)
+        let response = self.handlers(fd).error_manager.generate_error_response(status, message);
+        self.send_response(fd, response, 0)
     }
 
-    /// Send HTTP response to client
-    fn send_response(&mut self, fd: RawFd, mut response: HttpResponse, keep_alive: bool) -> ServerResult<()> {
-        response.set_keep_alive(keep_alive);
-        let response_bytes = response.to_bytes();
+    /// Send an interim `100 Continue` ahead of the request body
+    fn send_continue(&mut self, fd: RawFd) {
+        let mut bytes_written = 0;
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            let status = HttpStatus::Continue;
+            let interim = format!("HTTP/1.1 {} {}\r\n\r\n", status.as_u16(), status.reason_phrase());
+
+            // Anything the socket does not take now goes out ahead of the final response
+            if connection.write_buffer.append(interim.as_bytes()).is_ok() {
+                while let Ok(n @ 1..) = connection.write_to_socket() {
+                    bytes_written += n;
+                }
+            }
+        }
+        self.connection_manager.record_bytes_written(fd, bytes_written);
+    }
+
+    /// Send HTTP response to client; `requests_remaining` further requests may
+    /// follow on the connection, and with none left it closes after this one
+    fn send_response(&mut self, fd: RawFd, mut response: HttpResponse, requests_remaining: usize) -> ServerResult<()> {
+        response.finalize();
+        // An upgraded connection stays open for the new protocol, keeping its
+        // `Connection: Upgrade`
+        let upgrade = response.status == HttpStatus::SwitchingProtocols;
+        // A response the client cannot delimit must end with the connection,
+        // as must the last one a connection is allowed to carry
+        let keep_alive = upgrade || (requests_remaining > 0 && response.is_framed());
+        if !upgrade {
+            response.set_keep_alive(keep_alive);
+        }
+        if keep_alive && !upgrade {
+            response.add_header("Keep-Alive", &format!(
+                "timeout={}, max={}",
+                self.connection_manager.idle_timeout().as_secs(),
+                requests_remaining
+            ));
+        }
+        let (head, body) = response.into_parts();
 
         if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
-            connection.write_buffer.append(&response_bytes);
+            connection.write_buffer.append(&head)?;
+            connection.queue_body(body)?;
             connection.keep_alive = keep_alive;
 
             // Switch to writing mode and modify epoll to watch for write events
@@ -306,7 +1339,15 @@ impl Server {
 
     /// Cleanup a connection
     fn cleanup_connection(&mut self, fd: RawFd) {
-        if let Some(_connection) = self.connection_manager.remove_connection(fd) {
+        // Dropping the CGI state kills the script
+        self.detach_cgi(fd);
+
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            connection.close_tls();
+        }
+
+        if self.connection_manager.remove_connection(fd) {
+            self.connection_handlers.remove(&fd);
             let _ = self.epoll.remove(fd);
             close_socket(fd);
         }
@@ -314,15 +1355,43 @@ impl Server {
 
     /// Cleanup timed out connections
     fn cleanup_timed_out_connections(&mut self) -> ServerResult<()> {
-        let timed_out = self.connection_manager.cleanup_expired();
+        let timed_out = self.connection_manager.get_timed_out_connections();
         for fd in timed_out {
             println!("Connection {} timed out, cleaning up", fd);
-            let _ = self.epoll.remove(fd);
-            close_socket(fd);
+            // A client cut off mid-request is told why; idle keep-alive connections just close
+            let mid_request = self.connection_manager.get_connection(fd)
+                .is_some_and(|connection| connection.is_mid_request());
+            if mid_request {
+                self.send_timeout_response(fd);
+            }
+            self.cleanup_connection(fd);
+        }
+
+        // Forget clients whose buckets have refilled
+        for (_, limiter) in self.rate_limiters.values_mut() {
+            limiter.prune();
         }
         Ok(())
     }
 
+    /// Best-effort `408 Request Timeout` written straight to the socket before closing
+    fn send_timeout_response(&mut self, fd: RawFd) {
+        let mut response = self.handlers(fd).error_manager.generate_error_response(HttpStatus::RequestTimeout, Some("Request timed out"));
+        response.finalize();
+        response.set_keep_alive(false);
+
+        let mut bytes_written = 0;
+        if let Some(connection) = self.connection_manager.get_connection_mut(fd) {
+            connection.write_buffer.clear();
+            if connection.write_buffer.append(&response.to_bytes()).is_ok() {
+                while let Ok(n @ 1..) = connection.write_to_socket() {
+                    bytes_written += n;
+                }
+            }
+        }
+        self.connection_manager.record_bytes_written(fd, bytes_written);
+    }
+
     /// Shutdown the server
     fn shutdown(&mut self) -> ServerResult<()> {
         println!("Shutting down server...");
@@ -332,10 +1401,13 @@ impl Server {
             self.cleanup_connection(fd);
         }
 
-        // Close server sockets
-        for (&fd, _) in &self.server_sockets {
+        // Close server sockets, removing Unix socket files
+        for (&fd, listener) in &self.server_sockets {
             let _ = self.epoll.remove(fd);
             close_socket(fd);
+            if let Listener::Unix { path } = listener {
+                let _ = std::fs::remove_file(path);
+            }
         }
 
         self.server_sockets.clear();
@@ -360,3 +1432,57 @@ impl Drop for Server {
         }
     }
 }
+
+/// Check if a request is a GET of its server's status endpoint
+fn is_status_request(handlers: &Handlers, request: &HttpRequest) -> bool {
+    request.method == HttpMethod::GET
+        && handlers.method_handler.server_for(request)
+            .and_then(|server| server.status_endpoint.as_deref())
+            == Some(request.path.as_str())
+}
+
+/// Apply the connection limits of a configuration; connections already open keep theirs
+fn configure_connections(connection_manager: &mut ConnectionManager, config: &Config) {
+    let max_body_size = config.servers.iter()
+        .map(|server| server.max_body_size)
+        .max()
+        .unwrap_or(crate::defaults::DEFAULT_MAX_BODY_SIZE);
+    connection_manager.set_max_body_size(max_body_size);
+    let max_header_line = config.servers.iter()
+        .map(|server| server.client_max_header_line)
+        .max()
+        .unwrap_or(crate::http::request::DEFAULT_MAX_HEADER_LINE_BYTES);
+    connection_manager.set_max_header_line_bytes(max_header_line);
+    // Parsers join folded lines if any server allows it; dispatch refuses them for the others
+    connection_manager.set_merge_folded_headers(config.servers.iter().any(|server| server.merge_folded_headers));
+    // Only the first server may set the process-wide connection limit
+    let max_connections = config.servers.first()
+        .map(|server| server.max_connections)
+        .unwrap_or(crate::defaults::MAX_CONNECTIONS);
+    connection_manager.set_max_connections(max_connections);
+}
+
+/// Open a logger for each access_log target not open already
+fn open_access_logs(access_loggers: &mut HashMap<AccessLog, AccessLogger>, config: &Config) -> ServerResult<()> {
+    for server in &config.servers {
+        if !access_loggers.contains_key(&server.access_log) {
+            let logger = AccessLogger::open(&server.access_log)
+                .map_err(|e| ServerError::Config(format!("Failed to open access log: {}", e)))?;
+            access_loggers.insert(server.access_log.clone(), logger);
+        }
+    }
+    Ok(())
+}
+
+/// Give each server block with a rate_limit its own limiter. Servers whose
+/// limit is unchanged keep theirs, so clients keep the buckets they have.
+fn configure_rate_limiters(rate_limiters: &mut HashMap<ServerKey, (RateLimit, RateLimiter)>, config: &Config) {
+    let limits: HashMap<ServerKey, RateLimit> = config.servers.iter()
+        .filter_map(|server| server.rate_limit.map(|limit| (server.key(), limit)))
+        .collect();
+    rate_limiters.retain(|key, (limit, _)| limits.get(key) == Some(limit));
+    for (key, limit) in limits {
+        rate_limiters.entry(key)
+            .or_insert_with(|| (limit, RateLimiter::new(limit.requests, Duration::from_secs(limit.period))));
+    }
+}
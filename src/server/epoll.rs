@@ -36,6 +36,7 @@ pub fn get_fd_from_event(event: &EpollEvent) -> RawFd {
 /// Epoll wrapper
 pub struct Epoll {
     epfd: RawFd,
+    edge_triggered: bool,
 }
 
 impl Epoll {
@@ -46,13 +47,32 @@ impl Epoll {
             return Err(ServerError::Io(std::io::Error::last_os_error()));
         }
 
-        Ok(Self { epfd })
+        Ok(Self { epfd, edge_triggered: false })
+    }
+
+    /// Register all fds with EPOLLET from now on
+    pub fn set_edge_triggered(&mut self, edge_triggered: bool) {
+        self.edge_triggered = edge_triggered;
+    }
+
+    /// Check if fds are registered edge-triggered
+    pub fn is_edge_triggered(&self) -> bool {
+        self.edge_triggered
+    }
+
+    /// Apply the trigger mode to an interest set
+    fn with_mode(&self, events: u32) -> u32 {
+        if self.edge_triggered {
+            events | EPOLLET
+        } else {
+            events
+        }
     }
 
     /// Add a file descriptor to epoll
     pub fn add(&self, fd: RawFd, events: u32) -> ServerResult<()> {
         let mut event = libc::epoll_event {
-            events: events,
+            events: self.with_mode(events),
             u64: fd as u64,
         };
 
@@ -70,7 +90,7 @@ impl Epoll {
     /// Modify a file descriptor in epoll
     pub fn modify(&self, fd: RawFd, events: u32) -> ServerResult<()> {
         let mut event = libc::epoll_event {
-            events: events,
+            events: self.with_mode(events),
             u64: fd as u64,
         };
 
@@ -110,7 +130,12 @@ impl Epoll {
         };
 
         if result == -1 {
-            return Err(ServerError::Io(std::io::Error::last_os_error()));
+            let error = std::io::Error::last_os_error();
+            // A signal arrived first; its handler has left word for the loop
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(0);
+            }
+            return Err(ServerError::Io(error));
         }
 
         Ok(result as usize)
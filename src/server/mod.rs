@@ -8,6 +8,8 @@ pub mod core;
 pub mod epoll;
 pub mod socket;
 pub mod connection;
+pub mod tls;
+pub mod signal;
 
 pub use core::Server;
 pub use connection::Connection;
@@ -0,0 +1,107 @@
+/*!
+ * Signals handled on the event loop
+ *
+ * A signal handler may do almost nothing safely, so the one installed here
+ * only writes a byte to a pipe. The event loop watches the read end and acts
+ * on the signal between events.
+ */
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Write end of the pipe SIGHUP is reported on, or -1 when none is installed
+static HANGUP_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_hangup(_signal: libc::c_int) {
+    let fd = HANGUP_PIPE.load(Ordering::Acquire);
+    if fd < 0 {
+        return;
+    }
+
+    // The interrupted code may be about to look at errno
+    unsafe {
+        let errno = *libc::__errno_location();
+        libc::write(fd, b"h".as_ptr() as *const libc::c_void, 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+/// Pipe made readable by SIGHUP. Only one is active per process; creating
+/// another takes the signal over.
+pub struct HangupPipe {
+    read_fd: RawFd,
+    write_fd: RawFd,
+}
+
+impl HangupPipe {
+    /// Create the pipe and install the SIGHUP handler
+    pub fn install() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let pipe = Self { read_fd: fds[0], write_fd: fds[1] };
+        HANGUP_PIPE.store(pipe.write_fd, Ordering::Release);
+
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_hangup as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGHUP, &action, std::ptr::null_mut())
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(pipe)
+    }
+
+    /// Descriptor to watch for readability
+    pub fn read_fd(&self) -> RawFd {
+        self.read_fd
+    }
+
+    /// Empty the pipe, returning whether any signal had arrived
+    pub fn drain(&self) -> bool {
+        let mut received = false;
+        let mut buffer = [0u8; 64];
+        while unsafe { libc::read(self.read_fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) } > 0 {
+            received = true;
+        }
+        received
+    }
+}
+
+impl Drop for HangupPipe {
+    fn drop(&mut self) {
+        // Give SIGHUP back its default action unless another pipe has taken it over
+        if HANGUP_PIPE.compare_exchange(self.write_fd, -1, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            unsafe {
+                libc::signal(libc::SIGHUP, libc::SIG_DFL);
+            }
+        }
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hangup_makes_pipe_readable() {
+        let pipe = HangupPipe::install().unwrap();
+        assert!(!pipe.drain());
+
+        unsafe {
+            libc::raise(libc::SIGHUP);
+            libc::raise(libc::SIGHUP);
+        }
+        assert!(pipe.drain());
+        assert!(!pipe.drain());
+    }
+}
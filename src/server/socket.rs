@@ -2,15 +2,54 @@
  * Socket management
  */
 
+use crate::config::ServerConfig;
 use crate::error::{ServerError, ServerResult};
-use libc::{self, c_int, sockaddr, sockaddr_in, socklen_t};
+use libc::{self, c_int, sockaddr, sockaddr_in, sockaddr_in6, sockaddr_storage, socklen_t};
 use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::os::unix::io::RawFd;
 
-/// Create a non-blocking TCP socket
-pub fn create_tcp_socket() -> ServerResult<RawFd> {
+/// Settings applied to a listening socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenOptions {
+    pub backlog: c_int,
+    pub reuse_port: bool,
+    /// Disable Nagle's algorithm on accepted connections
+    pub nodelay: bool,
+}
+
+impl ListenOptions {
+    /// Take the listen settings from a server block
+    pub fn from_config(server: &ServerConfig) -> Self {
+        Self {
+            backlog: server.listen_backlog.min(c_int::MAX as u32) as c_int,
+            reuse_port: server.reuse_port,
+            nodelay: server.tcp_nodelay,
+        }
+    }
+}
+
+impl Default for ListenOptions {
+    fn default() -> Self {
+        Self {
+            backlog: crate::defaults::DEFAULT_LISTEN_BACKLOG as c_int,
+            reuse_port: false,
+            nodelay: true,
+        }
+    }
+}
+
+/// Create a non-blocking TCP socket of the address family matching `host`
+///
+/// `*` listens on all IPv4 and IPv6 addresses through a dual-stack socket;
+/// any other IPv6 host, including `::`, gets an IPv6-only socket so it can
+/// coexist with a separate `0.0.0.0` listener on the same port.
+pub fn create_tcp_socket(host: &str, options: &ListenOptions) -> ServerResult<RawFd> {
+    let ipv6 = is_ipv6_host(host);
+    let family = if ipv6 { libc::AF_INET6 } else { libc::AF_INET };
+
     let socket_fd = unsafe {
-        libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0)
+        libc::socket(family, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0)
     };
 
     if socket_fd == -1 {
@@ -23,6 +62,74 @@ pub fn create_tcp_socket() -> ServerResult<RawFd> {
     // Set SO_REUSEADDR to allow quick restart
     set_reuseaddr(socket_fd)?;
 
+    // Let several processes accept on the same port
+    if options.reuse_port {
+        set_reuseport(socket_fd)?;
+    }
+
+    if ipv6 {
+        set_ipv6_only(socket_fd, host != "*")?;
+    }
+
+    // Accepted connections inherit the option from the listener
+    if options.nodelay {
+        set_nodelay(socket_fd)?;
+    }
+
+    Ok(socket_fd)
+}
+
+/// Create a non-blocking Unix domain stream socket bound to `path`
+///
+/// A stale socket file left by a previous run is unlinked first; any other
+/// kind of file at `path` is left alone and binding fails.
+pub fn create_unix_socket(path: &str) -> ServerResult<RawFd> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    // Leave room for the terminating NUL
+    let path_bytes = path.as_bytes();
+    if path_bytes.is_empty() || path_bytes.len() >= addr.sun_path.len() || path_bytes.contains(&0) {
+        return Err(ServerError::Config(format!("Invalid unix socket path: {}", path)));
+    }
+    for (dst, &src) in addr.sun_path.iter_mut().zip(path_bytes) {
+        *dst = src as libc::c_char;
+    }
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        use std::os::unix::fs::FileTypeExt;
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path).map_err(ServerError::Io)?;
+        }
+    }
+
+    let socket_fd = unsafe {
+        libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0)
+    };
+
+    if socket_fd == -1 {
+        return Err(ServerError::Io(std::io::Error::last_os_error()));
+    }
+
+    if let Err(e) = set_nonblocking(socket_fd) {
+        close_socket(socket_fd);
+        return Err(e);
+    }
+
+    let result = unsafe {
+        libc::bind(
+            socket_fd,
+            &addr as *const libc::sockaddr_un as *const sockaddr,
+            mem::size_of::<libc::sockaddr_un>() as socklen_t,
+        )
+    };
+
+    if result == -1 {
+        let error = std::io::Error::last_os_error();
+        close_socket(socket_fd);
+        return Err(ServerError::Io(error));
+    }
+
     Ok(socket_fd)
 }
 
@@ -61,15 +168,56 @@ pub fn set_reuseaddr(fd: RawFd) -> ServerResult<()> {
     Ok(())
 }
 
-/// Bind socket to address and port
-pub fn bind_socket(fd: RawFd, host: &str, port: u16) -> ServerResult<()> {
-    let addr = create_sockaddr_in(host, port)?;
+/// Set SO_REUSEPORT
+pub fn set_reuseport(fd: RawFd) -> ServerResult<()> {
+    let optval: c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &optval as *const c_int as *const libc::c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    };
+
+    if result == -1 {
+        return Err(ServerError::Io(std::io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+/// Set TCP_NODELAY, sending small writes at once instead of waiting to coalesce them
+pub fn set_nodelay(fd: RawFd) -> ServerResult<()> {
+    let optval: c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_NODELAY,
+            &optval as *const c_int as *const libc::c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    };
 
+    if result == -1 {
+        return Err(ServerError::Io(std::io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+/// Set or clear IPV6_V6ONLY
+fn set_ipv6_only(fd: RawFd, only: bool) -> ServerResult<()> {
+    let optval: c_int = only as c_int;
     let result = unsafe {
-        libc::bind(
+        libc::setsockopt(
             fd,
-            &addr as *const sockaddr_in as *const sockaddr,
-            mem::size_of::<sockaddr_in>() as socklen_t,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            &optval as *const c_int as *const libc::c_void,
+            mem::size_of::<c_int>() as socklen_t,
         )
     };
 
@@ -80,6 +228,60 @@ pub fn bind_socket(fd: RawFd, host: &str, port: u16) -> ServerResult<()> {
     Ok(())
 }
 
+/// Check if a host string names an IPv6 address (or the `*` wildcard)
+pub fn is_ipv6_host(host: &str) -> bool {
+    host == "*" || host.contains(':')
+}
+
+/// Format a host for use in a URL, bracketing IPv6 addresses
+pub fn format_host(host: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
+/// Bind socket to address and port
+pub fn bind_socket(fd: RawFd, host: &str, port: u16) -> ServerResult<()> {
+    let result = if is_ipv6_host(host) {
+        let addr = create_sockaddr_in6(host, port)?;
+        unsafe {
+            libc::bind(
+                fd,
+                &addr as *const sockaddr_in6 as *const sockaddr,
+                mem::size_of::<sockaddr_in6>() as socklen_t,
+            )
+        }
+    } else {
+        let addr = create_sockaddr_in(host, port)?;
+        unsafe {
+            libc::bind(
+                fd,
+                &addr as *const sockaddr_in as *const sockaddr,
+                mem::size_of::<sockaddr_in>() as socklen_t,
+            )
+        }
+    };
+
+    if result == -1 {
+        return Err(bind_error(host, port, std::io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+/// Describe a failed bind with the address and, for common causes, what to do about it
+fn bind_error(host: &str, port: u16, error: std::io::Error) -> ServerError {
+    let reason = match error.raw_os_error() {
+        Some(libc::EADDRINUSE) => "address already in use; is another server running on this port?".to_string(),
+        Some(libc::EACCES) => "permission denied; ports below 1024 require root or CAP_NET_BIND_SERVICE".to_string(),
+        Some(libc::EADDRNOTAVAIL) => "address not available; is the host an address of this machine?".to_string(),
+        _ => error.to_string(),
+    };
+    ServerError::Config(format!("Cannot bind {}:{}: {}", format_host(host), port, reason))
+}
+
 /// Listen on socket
 pub fn listen_socket(fd: RawFd, backlog: c_int) -> ServerResult<()> {
     let result = unsafe { libc::listen(fd, backlog) };
@@ -91,9 +293,13 @@ pub fn listen_socket(fd: RawFd, backlog: c_int) -> ServerResult<()> {
     Ok(())
 }
 
-/// Accept a connection
-pub fn accept_connection(fd: RawFd) -> ServerResult<Option<RawFd>> {
-    let client_fd = unsafe { libc::accept(fd, std::ptr::null_mut(), std::ptr::null_mut()) };
+/// Accept a connection, with the peer's address for TCP clients
+pub fn accept_connection(fd: RawFd) -> ServerResult<Option<(RawFd, Option<SocketAddr>)>> {
+    let mut storage: sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+    let client_fd = unsafe {
+        libc::accept(fd, &mut storage as *mut sockaddr_storage as *mut sockaddr, &mut len)
+    };
 
     if client_fd == -1 {
         let error = std::io::Error::last_os_error();
@@ -104,7 +310,39 @@ pub fn accept_connection(fd: RawFd) -> ServerResult<Option<RawFd>> {
     } else {
         // Set client socket to non-blocking
         set_nonblocking(client_fd)?;
-        Ok(Some(client_fd))
+        Ok(Some((client_fd, socket_addr_from_storage(&storage))))
+    }
+}
+
+/// Local address a connected socket was accepted on, if it is IPv4 or IPv6
+pub fn local_address(fd: RawFd) -> Option<SocketAddr> {
+    let mut storage: sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+    let result = unsafe {
+        libc::getsockname(fd, &mut storage as *mut sockaddr_storage as *mut sockaddr, &mut len)
+    };
+    if result == -1 {
+        return None;
+    }
+    socket_addr_from_storage(&storage)
+}
+
+/// Convert an address filled in by the kernel, if it is IPv4 or IPv6
+fn socket_addr_from_storage(storage: &sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(addr.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in6) };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                ip, u16::from_be(addr.sin6_port), addr.sin6_flowinfo, addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
     }
 }
 
@@ -125,6 +363,55 @@ fn create_sockaddr_in(host: &str, port: u16) -> ServerResult<sockaddr_in> {
     Ok(addr)
 }
 
+/// Create sockaddr_in6 structure
+fn create_sockaddr_in6(host: &str, port: u16) -> ServerResult<sockaddr_in6> {
+    let mut addr: sockaddr_in6 = unsafe { mem::zeroed() };
+    addr.sin6_family = libc::AF_INET6 as u16;
+    addr.sin6_port = port.to_be();
+
+    // "*", "::" and "[::]" all leave the address as in6addr_any
+    if host != "*" {
+        let (ip, scope_id) = parse_ipv6_address(host)?;
+        addr.sin6_addr.s6_addr = ip.octets();
+        addr.sin6_scope_id = scope_id;
+    }
+
+    Ok(addr)
+}
+
+/// Parse an IPv6 address, optionally bracketed and with a `%zone` suffix
+fn parse_ipv6_address(host: &str) -> ServerResult<(Ipv6Addr, u32)> {
+    let invalid = || ServerError::Config(format!("Invalid IPv6 address: {}", host));
+
+    let unbracketed = host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let (ip, zone) = match unbracketed.split_once('%') {
+        Some((ip, zone)) => (ip, Some(zone)),
+        None => (unbracketed, None),
+    };
+
+    let ip: Ipv6Addr = ip.parse().map_err(|_| invalid())?;
+
+    // Link-local addresses need a zone: either an index or an interface name
+    let scope_id = match zone {
+        None => 0,
+        Some(zone) => match zone.parse::<u32>() {
+            Ok(index) => index,
+            Err(_) => {
+                let name = std::ffi::CString::new(zone).map_err(|_| invalid())?;
+                let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+                if index == 0 {
+                    return Err(invalid());
+                }
+                index
+            }
+        },
+    };
+
+    Ok((ip, scope_id))
+}
+
 /// Parse IP address string to u32
 fn parse_ip_address(ip: &str) -> ServerResult<u32> {
     let parts: Vec<&str> = ip.split('.').collect();
@@ -148,3 +435,176 @@ pub fn close_socket(fd: RawFd) {
         libc::close(fd);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound_family(fd: RawFd) -> c_int {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::sockaddr_storage>() as socklen_t;
+        let result = unsafe {
+            libc::getsockname(fd, &mut storage as *mut _ as *mut sockaddr, &mut len)
+        };
+        assert_eq!(result, 0);
+        storage.ss_family as c_int
+    }
+
+    #[test]
+    fn test_bind_ipv6_loopback() {
+        let fd = create_tcp_socket("::1", &ListenOptions::default()).unwrap();
+        bind_socket(fd, "::1", 0).expect("bind to ::1 should succeed");
+        listen_socket(fd, 16).unwrap();
+        assert_eq!(bound_family(fd), libc::AF_INET6);
+        close_socket(fd);
+    }
+
+    #[test]
+    fn test_accept_reports_peer_address() {
+        let fd = create_tcp_socket("127.0.0.1", &ListenOptions::default()).unwrap();
+        bind_socket(fd, "127.0.0.1", 0).unwrap();
+        listen_socket(fd, 16).unwrap();
+
+        let mut storage: sockaddr_storage = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+        assert_eq!(unsafe { libc::getsockname(fd, &mut storage as *mut _ as *mut sockaddr, &mut len) }, 0);
+        let listen_addr = socket_addr_from_storage(&storage).unwrap();
+
+        let client = std::net::TcpStream::connect(listen_addr).unwrap();
+        let mut accepted = None;
+        for _ in 0..100 {
+            accepted = accept_connection(fd).unwrap();
+            if accepted.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let (client_fd, peer_addr) = accepted.expect("connection should be accepted");
+        assert_eq!(peer_addr, Some(client.local_addr().unwrap()));
+        close_socket(client_fd);
+        close_socket(fd);
+    }
+
+    #[test]
+    fn test_reuse_port_option() {
+        let options = ListenOptions { reuse_port: true, ..ListenOptions::default() };
+        let fd = create_tcp_socket("127.0.0.1", &options).unwrap();
+        let mut value: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as socklen_t;
+        let result = unsafe {
+            libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, &mut value as *mut c_int as *mut libc::c_void, &mut len)
+        };
+        assert_eq!(result, 0);
+        assert_eq!(value, 1);
+        close_socket(fd);
+    }
+
+    fn int_option(fd: RawFd, level: c_int, name: c_int) -> c_int {
+        let mut value: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as socklen_t;
+        let result = unsafe {
+            libc::getsockopt(fd, level, name, &mut value as *mut c_int as *mut libc::c_void, &mut len)
+        };
+        assert_eq!(result, 0);
+        value
+    }
+
+    #[test]
+    fn test_nodelay_reaches_accepted_connections() {
+        let fd = create_tcp_socket("127.0.0.1", &ListenOptions::default()).unwrap();
+        bind_socket(fd, "127.0.0.1", 0).unwrap();
+        listen_socket(fd, 16).unwrap();
+        assert_eq!(int_option(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY), 1);
+
+        let mut storage: sockaddr_storage = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+        assert_eq!(unsafe { libc::getsockname(fd, &mut storage as *mut _ as *mut sockaddr, &mut len) }, 0);
+        let _client = std::net::TcpStream::connect(socket_addr_from_storage(&storage).unwrap()).unwrap();
+        let mut accepted = None;
+        for _ in 0..100 {
+            accepted = accept_connection(fd).unwrap();
+            if accepted.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let (client_fd, _) = accepted.expect("connection should be accepted");
+        assert_eq!(int_option(client_fd, libc::IPPROTO_TCP, libc::TCP_NODELAY), 1);
+        close_socket(client_fd);
+        close_socket(fd);
+
+        let options = ListenOptions { nodelay: false, ..ListenOptions::default() };
+        let fd = create_tcp_socket("127.0.0.1", &options).unwrap();
+        assert_eq!(int_option(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY), 0);
+        close_socket(fd);
+    }
+
+    #[test]
+    fn test_bind_error_names_port_in_use() {
+        let first = create_tcp_socket("127.0.0.1", &ListenOptions::default()).unwrap();
+        bind_socket(first, "127.0.0.1", 0).unwrap();
+        listen_socket(first, 16).unwrap();
+
+        let mut storage: sockaddr_storage = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+        assert_eq!(unsafe { libc::getsockname(first, &mut storage as *mut _ as *mut sockaddr, &mut len) }, 0);
+        let port = socket_addr_from_storage(&storage).unwrap().port();
+
+        let second = create_tcp_socket("127.0.0.1", &ListenOptions::default()).unwrap();
+        let error = bind_socket(second, "127.0.0.1", port).unwrap_err();
+        assert!(matches!(error, ServerError::Config(_)), "unexpected error: {}", error);
+        let message = error.to_string();
+        assert!(message.contains(&format!("127.0.0.1:{}", port)), "unexpected error: {}", message);
+        assert!(message.contains("in use"), "unexpected error: {}", message);
+
+        close_socket(second);
+        close_socket(first);
+    }
+
+    #[test]
+    fn test_parse_ipv6_address() {
+        assert_eq!(parse_ipv6_address("::1").unwrap().0, Ipv6Addr::LOCALHOST);
+        assert_eq!(parse_ipv6_address("[::]").unwrap().0, Ipv6Addr::UNSPECIFIED);
+        assert_eq!(parse_ipv6_address("fe80::1%7").unwrap(), ("fe80::1".parse().unwrap(), 7));
+        assert!(parse_ipv6_address("fe80::zz").is_err());
+    }
+
+    #[test]
+    fn test_unix_socket_replaces_stale_file() {
+        let path = std::env::temp_dir().join(format!("localhost_sock_test_{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        // A socket file left over from an earlier run
+        let stale = std::os::unix::net::UnixListener::bind(path).unwrap();
+        drop(stale);
+
+        let fd = create_unix_socket(path).expect("stale socket should be replaced");
+        listen_socket(fd, 16).unwrap();
+        assert_eq!(bound_family(fd), libc::AF_UNIX);
+        std::os::unix::net::UnixStream::connect(path).expect("connect should succeed");
+
+        close_socket(fd);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_does_not_remove_regular_file() {
+        let path = std::env::temp_dir().join(format!("localhost_sock_file_{}", std::process::id()));
+        std::fs::write(&path, "keep me").unwrap();
+
+        assert!(create_unix_socket(path.to_str().unwrap()).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_host_family_detection() {
+        assert!(is_ipv6_host("::1"));
+        assert!(is_ipv6_host("[::]"));
+        assert!(is_ipv6_host("*"));
+        assert!(!is_ipv6_host("127.0.0.1"));
+        assert_eq!(format_host("::1"), "[::1]");
+        assert_eq!(format_host("127.0.0.1"), "127.0.0.1");
+    }
+}
@@ -0,0 +1,284 @@
+/*!
+ * TLS termination
+ *
+ * A connection accepted on an `ssl on` listener carries a rustls session.
+ * Encrypted bytes read from the socket are decrypted into the connection's
+ * read buffer, and bytes taken from its write buffer are encrypted on the way
+ * out, so the parser and response code never see TLS. Server blocks sharing
+ * a listener each present their own certificate, chosen by SNI.
+ */
+
+use crate::config::ServerConfig;
+use crate::error::{ServerError, ServerResult};
+use crate::routing::router::host_matches;
+use crate::utils::buffer::Buffer;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConnection;
+use std::io::{self, Read, Write};
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+
+/// Certificates and settings shared by every session on a listener
+pub type TlsConfig = Arc<rustls::ServerConfig>;
+
+/// Load a PEM certificate chain and its private key
+pub fn load_certified_key(cert_path: &str, key_path: &str) -> ServerResult<Arc<CertifiedKey>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| ServerError::Config(format!("Failed to read ssl_cert {}: {}", cert_path, e)))?;
+    if certs.is_empty() {
+        return Err(ServerError::Config(format!("No certificate found in {}", cert_path)));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| ServerError::Config(format!("Failed to read ssl_key {}: {}", key_path, e)))?;
+
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| ServerError::Config(format!("Unsupported ssl_key {}: {}", key_path, e)))?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    certified.keys_match()
+        .map_err(|e| ServerError::Config(format!("ssl_key {} does not match ssl_cert {}: {}", key_path, cert_path, e)))?;
+    Ok(Arc::new(certified))
+}
+
+/// TLS settings for a listener shared by `servers`, the first being the default.
+/// None when the servers do not use TLS.
+pub fn listener_config(servers: &[&ServerConfig]) -> ServerResult<Option<TlsConfig>> {
+    let mut resolver: Option<SniResolver> = None;
+    for server in servers {
+        let (cert, key) = match (server.ssl, &server.ssl_cert, &server.ssl_key) {
+            (true, Some(cert), Some(key)) => (cert, key),
+            _ => continue,
+        };
+        let certified = load_certified_key(cert, key)?;
+        let resolver = resolver.get_or_insert_with(|| SniResolver::new(certified.clone()));
+        for name in &server.server_names {
+            resolver.add_name(name, certified.clone());
+        }
+    }
+    resolver.map(SniResolver::into_config).transpose()
+}
+
+/// Picks the certificate for a connection from the name the client asked for
+/// in its ClientHello (SNI), for server blocks sharing a listener
+#[derive(Debug)]
+pub struct SniResolver {
+    /// Served when the client names no configured server, or none at all
+    default: Arc<CertifiedKey>,
+    /// Server names in configuration order; `*.` prefixes match any subdomain
+    names: Vec<(String, Arc<CertifiedKey>)>,
+}
+
+impl SniResolver {
+    pub fn new(default: Arc<CertifiedKey>) -> Self {
+        Self { default, names: Vec::new() }
+    }
+
+    /// Serve `key` to clients asking for `server_name`; earlier names win
+    pub fn add_name(&mut self, server_name: &str, key: Arc<CertifiedKey>) {
+        self.names.push((server_name.to_string(), key));
+    }
+
+    /// Certificate for a requested name
+    fn lookup(&self, requested: Option<&str>) -> Arc<CertifiedKey> {
+        requested
+            .and_then(|requested| self.names.iter().find(|(name, _)| host_matches(name, requested)))
+            .map(|(_, key)| key.clone())
+            .unwrap_or_else(|| self.default.clone())
+    }
+
+    /// Settings for a listener whose certificates this resolver picks
+    pub fn into_config(self) -> ServerResult<TlsConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| ServerError::Config(format!("Invalid TLS settings: {}", e)))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(self));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.lookup(client_hello.server_name()))
+    }
+}
+
+/// Socket reads and writes for rustls, which treats EAGAIN as `WouldBlock`
+struct SocketIo(RawFd);
+
+impl Read for SocketIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+impl Write for SocketIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = unsafe { libc::write(self.0, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Server side of one TLS connection
+pub struct TlsSession {
+    connection: ServerConnection,
+}
+
+impl TlsSession {
+    pub fn new(config: TlsConfig) -> io::Result<Self> {
+        let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+        Ok(Self { connection })
+    }
+
+    /// Read and decrypt until the socket would block, appending the plaintext to `buffer`.
+    /// Returns the plaintext bytes added and whether the client has closed.
+    pub fn read_from_fd(&mut self, fd: RawFd, buffer: &mut Buffer) -> io::Result<(usize, bool)> {
+        let mut total = 0;
+        let mut eof = false;
+        loop {
+            match self.connection.read_tls(&mut SocketIo(fd)) {
+                Ok(0) => eof = true,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+
+            if let Err(e) = self.connection.process_new_packets() {
+                // Let the client know why before the connection is dropped
+                let _ = self.flush(fd);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+
+            // Decrypted data must be taken before rustls accepts more
+            let (read, closed) = self.take_plaintext(buffer)?;
+            total += read;
+            if eof || closed {
+                return Ok((total, true));
+            }
+        }
+        Ok((total, eof))
+    }
+
+    /// Move decrypted bytes into `buffer`, reporting whether the client sent close_notify
+    fn take_plaintext(&mut self, buffer: &mut Buffer) -> io::Result<(usize, bool)> {
+        let mut total = 0;
+        loop {
+            buffer.ensure_writable_space(1024)?;
+            match self.connection.reader().read(buffer.writable_space()) {
+                Ok(0) => return Ok((total, true)),
+                Ok(n) => {
+                    buffer.advance_write(n);
+                    total += n;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok((total, false)),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok((total, true)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Encrypt as much of `buffer` as the session will queue and send it.
+    /// Returns the plaintext bytes taken from the buffer.
+    pub fn write_to_fd(&mut self, fd: RawFd, buffer: &mut Buffer) -> io::Result<usize> {
+        let taken = if buffer.is_empty() {
+            0
+        } else {
+            self.connection.writer().write(buffer.readable_data())?
+        };
+        buffer.consume(taken);
+        self.flush(fd)?;
+        Ok(taken)
+    }
+
+    /// Send encrypted bytes until none are left or the socket would block
+    pub fn flush(&mut self, fd: RawFd) -> io::Result<()> {
+        while self.connection.wants_write() {
+            match self.connection.write_tls(&mut SocketIo(fd)) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if encrypted bytes are waiting for the socket
+    pub fn wants_write(&self) -> bool {
+        self.connection.wants_write()
+    }
+
+    /// Tell the client the connection is closing, best effort
+    pub fn close(&mut self, fd: RawFd) {
+        self.connection.send_close_notify();
+        let _ = self.flush(fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed certificate for `name`, written to PEM files under the temp directory
+    fn certified_key(name: &str) -> Arc<CertifiedKey> {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("localhost_tls_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        std::fs::write(&cert, generated.cert.pem()).unwrap();
+        std::fs::write(&key, generated.signing_key.serialize_pem()).unwrap();
+        load_certified_key(cert.to_str().unwrap(), key.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_sni_picks_certificate_by_name() {
+        let default = certified_key("default.test");
+        let alpha = certified_key("alpha.test");
+        let wildcard = certified_key("beta.test");
+
+        let mut resolver = SniResolver::new(default.clone());
+        resolver.add_name("alpha.test", alpha.clone());
+        resolver.add_name("*.beta.test", wildcard.clone());
+
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("alpha.test")), &alpha));
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("ALPHA.test")), &alpha));
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("www.beta.test")), &wildcard));
+
+        // Unknown names and clients without SNI get the default server's certificate
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("beta.test")), &default));
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("other.test")), &default));
+        assert!(Arc::ptr_eq(&resolver.lookup(None), &default));
+    }
+
+    #[test]
+    fn test_mismatched_key_rejected() {
+        let first = rcgen::generate_simple_self_signed(vec!["a.test".to_string()]).unwrap();
+        let second = rcgen::generate_simple_self_signed(vec!["b.test".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("localhost_tls_mismatch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        std::fs::write(&cert, first.cert.pem()).unwrap();
+        std::fs::write(&key, second.signing_key.serialize_pem()).unwrap();
+
+        assert!(load_certified_key(cert.to_str().unwrap(), key.to_str().unwrap()).is_err());
+    }
+}
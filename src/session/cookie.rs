@@ -2,9 +2,10 @@
  * HTTP Cookie handling and management
  */
 
+use crate::utils::http_date;
 use std::collections::HashMap;
 use std::fmt;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime};
 
 /// HTTP Cookie representation
 #[derive(Debug, Clone)]
@@ -121,11 +122,7 @@ impl Cookie {
         }
 
         if let Some(expires) = self.expires {
-            if let Ok(duration) = expires.duration_since(UNIX_EPOCH) {
-                // Format as HTTP date (RFC 7231)
-                let timestamp = duration.as_secs();
-                parts.push(format!("Expires={}", format_http_date(timestamp)));
-            }
+            parts.push(format!("Expires={}", http_date::format(expires)));
         }
 
         if let Some(max_age) = self.max_age {
@@ -266,37 +263,6 @@ impl CookieJar {
     }
 }
 
-/// Format timestamp as HTTP date (simplified)
-fn format_http_date(timestamp: u64) -> String {
-    // This is a simplified implementation
-    // In production, you'd want to use a proper date formatting library
-    let days = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
-    let months = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
-                  "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
-
-    let total_days = timestamp / 86400;
-    let day_of_week = (total_days + 4) % 7; // Unix epoch was Thursday
-
-    // Simplified date calculation (not accounting for leap years properly)
-    let year = 1970 + total_days / 365;
-    let day_of_year = total_days % 365;
-    let month = day_of_year / 30; // Simplified
-    let day = (day_of_year % 30) + 1;
-
-    let hour = (timestamp % 86400) / 3600;
-    let minute = (timestamp % 3600) / 60;
-    let second = timestamp % 60;
-
-    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
-            days[day_of_week as usize],
-            day,
-            months[month.min(11) as usize],
-            year,
-            hour,
-            minute,
-            second)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,6 +290,14 @@ mod tests {
         assert!(header.contains("Secure"));
     }
 
+    #[test]
+    fn test_cookie_expires_header() {
+        let expires = std::time::UNIX_EPOCH + Duration::from_secs(1609459200);
+        let cookie = Cookie::new("test".to_string(), "value".to_string()).expires(expires);
+
+        assert!(cookie.to_header_value().contains("Expires=Fri, 01 Jan 2021 00:00:00 GMT"));
+    }
+
     #[test]
     fn test_cookie_jar() {
         let mut jar = CookieJar::new();
@@ -232,6 +232,15 @@ impl SessionManager {
         }
     }
 
+    /// Resolve the session for a request's cookies, creating one if absent or expired.
+    /// Returns the session ID and whether it was newly created.
+    pub fn get_or_create_session(&self, cookie_jar: &CookieJar) -> Result<(String, bool), String> {
+        match self.get_session_from_cookies(cookie_jar)? {
+            Some(session) => Ok((session.id, false)),
+            None => Ok((self.create_session()?, true)),
+        }
+    }
+
     /// Create session cookie
     pub fn create_session_cookie(&self, session_id: &str) -> Cookie {
         let mut cookie = Cookie::new(self.config.cookie_name.clone(), session_id.to_string())
@@ -382,4 +391,18 @@ mod tests {
         let session = manager.get_session(&session_id).unwrap();
         assert!(session.is_none());
     }
+
+    #[test]
+    fn test_get_or_create_session() {
+        let manager = SessionManager::with_defaults();
+
+        let (session_id, created) = manager.get_or_create_session(&CookieJar::new()).unwrap();
+        assert!(created);
+
+        let mut jar = CookieJar::new();
+        jar.add(manager.create_session_cookie(&session_id));
+        let (resolved_id, created) = manager.get_or_create_session(&jar).unwrap();
+        assert!(!created);
+        assert_eq!(resolved_id, session_id);
+    }
 }
@@ -10,6 +10,7 @@ pub struct Buffer {
     data: Vec<u8>,
     read_pos: usize,
     write_pos: usize,
+    max_capacity: Option<usize>,
 }
 
 impl Buffer {
@@ -19,9 +20,22 @@ impl Buffer {
             data: vec![0; capacity],
             read_pos: 0,
             write_pos: 0,
+            max_capacity: None,
         }
     }
 
+    /// Create a buffer that refuses to grow beyond `max_capacity` bytes
+    pub fn with_max_capacity(capacity: usize, max_capacity: usize) -> Self {
+        let mut buffer = Self::new(capacity.min(max_capacity));
+        buffer.max_capacity = Some(max_capacity);
+        buffer
+    }
+
+    /// Limit how large the buffer may grow
+    pub fn set_max_capacity(&mut self, max_capacity: Option<usize>) {
+        self.max_capacity = max_capacity;
+    }
+
     /// Get the available data for reading
     pub fn readable_data(&self) -> &[u8] {
         &self.data[self.read_pos..self.write_pos]
@@ -69,8 +83,25 @@ impl Buffer {
         self.write_pos = 0;
     }
 
-    /// Ensure buffer has enough space for writing
-    pub fn ensure_writable_space(&mut self, needed: usize) {
+    /// Bytes currently allocated
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Empty the buffer for reuse, dropping its limit and giving back any
+    /// allocation beyond `keep` bytes
+    pub fn recycle(&mut self, keep: usize) {
+        self.clear();
+        self.max_capacity = None;
+        if self.data.len() > keep {
+            self.data.truncate(keep);
+            self.data.shrink_to_fit();
+        }
+    }
+
+    /// Ensure buffer has enough space for writing.
+    /// Fails with `ErrorKind::OutOfMemory` if that would exceed the maximum capacity.
+    pub fn ensure_writable_space(&mut self, needed: usize) -> io::Result<()> {
         if self.writable_bytes() < needed {
             // Try to compact first
             if self.read_pos > 0 {
@@ -81,15 +112,26 @@ impl Buffer {
 
             // If still not enough space, grow the buffer
             if self.writable_bytes() < needed {
-                let new_size = std::cmp::max(self.data.len() * 2, self.data.len() + needed);
+                let mut new_size = std::cmp::max(self.data.len() * 2, self.data.len() + needed);
+                if let Some(max_capacity) = self.max_capacity {
+                    if self.write_pos + needed > max_capacity {
+                        return Err(io::Error::new(
+                            io::ErrorKind::OutOfMemory,
+                            format!("buffer would exceed its maximum capacity of {} bytes", max_capacity),
+                        ));
+                    }
+                    new_size = new_size.min(max_capacity);
+                }
                 self.data.resize(new_size, 0);
             }
         }
+        Ok(())
     }
 
-    /// Read from a file descriptor into the buffer
-    pub fn read_from_fd(&mut self, fd: RawFd) -> io::Result<usize> {
-        self.ensure_writable_space(1024);
+    /// Read from a file descriptor into the buffer.
+    /// Returns None if the fd would block, and `Some(0)` only at end of file.
+    pub fn read_from_fd(&mut self, fd: RawFd) -> io::Result<Option<usize>> {
+        self.ensure_writable_space(1024)?;
 
         let bytes_read = unsafe {
             libc::read(
@@ -102,12 +144,45 @@ impl Buffer {
         if bytes_read == -1 {
             let error = io::Error::last_os_error();
             match error.raw_os_error() {
-                Some(libc::EAGAIN) => Ok(0),
+                Some(libc::EAGAIN) | Some(libc::EINTR) => Ok(None),
                 _ => Err(error),
             }
         } else {
             self.advance_write(bytes_read as usize);
-            Ok(bytes_read as usize)
+            Ok(Some(bytes_read as usize))
+        }
+    }
+
+    /// Read until the fd would block or reaches end of file.
+    /// Returns the bytes read and whether end of file was seen.
+    pub fn drain_from_fd(&mut self, fd: RawFd) -> io::Result<(usize, bool)> {
+        let mut total = 0;
+        loop {
+            self.ensure_writable_space(1024)?;
+
+            let bytes_read = unsafe {
+                libc::read(
+                    fd,
+                    self.data[self.write_pos..].as_mut_ptr() as *mut libc::c_void,
+                    self.writable_bytes(),
+                )
+            };
+
+            match bytes_read {
+                -1 => {
+                    let error = io::Error::last_os_error();
+                    match error.raw_os_error() {
+                        Some(libc::EAGAIN) => return Ok((total, false)),
+                        Some(libc::EINTR) => continue,
+                        _ => return Err(error),
+                    }
+                }
+                0 => return Ok((total, true)),
+                n => {
+                    self.advance_write(n as usize);
+                    total += n as usize;
+                }
+            }
         }
     }
 
@@ -138,10 +213,11 @@ impl Buffer {
     }
 
     /// Append data to the buffer
-    pub fn append(&mut self, data: &[u8]) {
-        self.ensure_writable_space(data.len());
+    pub fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.ensure_writable_space(data.len())?;
         self.data[self.write_pos..self.write_pos + data.len()].copy_from_slice(data);
         self.advance_write(data.len());
+        Ok(())
     }
 
     /// Get all data as a string (for debugging)
@@ -149,3 +225,88 @@ impl Buffer {
         String::from_utf8_lossy(self.readable_data()).to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nonblocking_pipe() -> (RawFd, RawFd) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) }, 0);
+        (fds[0], fds[1])
+    }
+
+    #[test]
+    fn test_drain_stops_at_eagain() {
+        let (read_fd, write_fd) = nonblocking_pipe();
+        let data = vec![7u8; 100_000];
+        let mut written = 0;
+        while written < 60_000 {
+            let n = unsafe { libc::write(write_fd, data[written..].as_ptr() as *const libc::c_void, 60_000 - written) };
+            assert!(n > 0);
+            written += n as usize;
+        }
+
+        let mut buffer = Buffer::new(16);
+        assert_eq!(buffer.drain_from_fd(read_fd).unwrap(), (60_000, false));
+        assert_eq!(buffer.readable_bytes(), 60_000);
+
+        // Nothing left: would block, which is not end of file
+        assert_eq!(buffer.drain_from_fd(read_fd).unwrap(), (0, false));
+
+        unsafe {
+            libc::close(write_fd);
+        }
+        assert_eq!(buffer.drain_from_fd(read_fd).unwrap(), (0, true));
+        unsafe {
+            libc::close(read_fd);
+        }
+    }
+
+    #[test]
+    fn test_read_tells_eagain_from_end_of_file() {
+        let (read_fd, write_fd) = nonblocking_pipe();
+        let mut buffer = Buffer::new(16);
+
+        // An empty pipe would block, which is not end of file
+        assert_eq!(buffer.read_from_fd(read_fd).unwrap(), None);
+
+        assert_eq!(unsafe { libc::write(write_fd, b"hello".as_ptr() as *const libc::c_void, 5) }, 5);
+        assert_eq!(buffer.read_from_fd(read_fd).unwrap(), Some(5));
+        assert_eq!(buffer.readable_data(), b"hello");
+
+        unsafe {
+            libc::close(write_fd);
+        }
+        assert_eq!(buffer.read_from_fd(read_fd).unwrap(), Some(0));
+        unsafe {
+            libc::close(read_fd);
+        }
+    }
+
+    #[test]
+    fn test_max_capacity_refuses_growth() {
+        let mut buffer = Buffer::with_max_capacity(16, 64);
+        buffer.append(&[1u8; 64]).unwrap();
+        let error = buffer.append(&[1u8]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::OutOfMemory);
+        assert_eq!(buffer.readable_bytes(), 64);
+
+        // Consumed space is reclaimed before the limit applies
+        buffer.consume(32);
+        buffer.append(&[2u8; 32]).unwrap();
+        assert_eq!(buffer.readable_bytes(), 64);
+
+        // Reads hit the same limit instead of draining without bound
+        let (read_fd, write_fd) = nonblocking_pipe();
+        let data = vec![7u8; 8192];
+        assert_eq!(unsafe { libc::write(write_fd, data.as_ptr() as *const libc::c_void, data.len()) }, 8192);
+        let mut buffer = Buffer::with_max_capacity(1024, 4096);
+        assert_eq!(buffer.drain_from_fd(read_fd).unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+        assert!(buffer.readable_bytes() <= 4096);
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+    }
+}
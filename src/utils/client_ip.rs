@@ -0,0 +1,145 @@
+/*!
+ * Client address resolution behind reverse proxies
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Address range written as `a.b.c.d/n`, `x::y/n` or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Parse a CIDR range or a single address
+    pub fn parse(s: &str) -> Option<Self> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address.parse::<IpAddr>().ok()?, Some(prefix_len.parse::<u8>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            return None;
+        }
+
+        Some(Self { address, prefix_len })
+    }
+
+    /// Check whether an address falls inside the range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Find the address of the client a request came from.
+/// A peer outside `trusted` is the client. Otherwise `X-Forwarded-For` is
+/// walked from the right, skipping trusted proxies, and the first untrusted
+/// entry is taken; entries further left could have been written by anyone.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HashMap<String, Vec<String>>, trusted: &[IpNetwork]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|network| network.contains(ip));
+
+    let forwarded = match headers.get("x-forwarded-for") {
+        Some(values) => values,
+        None => return peer,
+    };
+
+    let mut client = peer;
+    for entry in forwarded.iter().flat_map(|value| value.split(',')).rev() {
+        if !is_trusted(client) {
+            break;
+        }
+        match parse_forwarded_address(entry.trim()) {
+            Some(ip) => client = ip,
+            None => break,
+        }
+    }
+    client
+}
+
+/// Parse one `X-Forwarded-For` entry, allowing a port after the address
+fn parse_forwarded_address(entry: &str) -> Option<IpAddr> {
+    entry.parse::<IpAddr>().ok()
+        .or_else(|| entry.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(forwarded_for: &[&str]) -> HashMap<String, Vec<String>> {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            forwarded_for.iter().map(|value| value.to_string()).collect(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_ip_network() {
+        let network = IpNetwork::parse("10.0.0.0/8").unwrap();
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+
+        let single = IpNetwork::parse("127.0.0.1").unwrap();
+        assert!(single.contains("127.0.0.1".parse().unwrap()));
+        assert!(!single.contains("127.0.0.2".parse().unwrap()));
+
+        let v6 = IpNetwork::parse("fd00::/8").unwrap();
+        assert!(v6.contains("fd12::1".parse().unwrap()));
+        assert!(!v6.contains("10.0.0.1".parse().unwrap()));
+
+        assert!(IpNetwork::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!(IpNetwork::parse("10.0.0.0/33").is_none());
+        assert!(IpNetwork::parse("localhost").is_none());
+    }
+
+    #[test]
+    fn test_trusted_proxy_peer_uses_forwarded_for() {
+        let trusted = vec![IpNetwork::parse("127.0.0.1").unwrap(), IpNetwork::parse("10.0.0.0/8").unwrap()];
+        let peer = "127.0.0.1".parse().unwrap();
+
+        // The rightmost untrusted entry wins, whatever the client put before it
+        let client = resolve_client_ip(peer, &headers(&["1.1.1.1, 203.0.113.9, 10.0.0.5"]), &trusted);
+        assert_eq!(client, "203.0.113.9".parse::<IpAddr>().unwrap());
+
+        // Repeated headers are read as one list
+        let client = resolve_client_ip(peer, &headers(&["203.0.113.9", "10.0.0.5:4431"]), &trusted);
+        assert_eq!(client, "203.0.113.9".parse::<IpAddr>().unwrap());
+
+        // Garbage stops the walk at the last good hop
+        let client = resolve_client_ip(peer, &headers(&["203.0.113.9, unknown"]), &trusted);
+        assert_eq!(client, peer);
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_forwarded_for() {
+        let trusted = vec![IpNetwork::parse("127.0.0.1").unwrap()];
+        let peer = "198.51.100.4".parse().unwrap();
+
+        let client = resolve_client_ip(peer, &headers(&["127.0.0.1, 203.0.113.9"]), &trusted);
+        assert_eq!(client, peer);
+        assert_eq!(resolve_client_ip(peer, &headers(&["203.0.113.9"]), &[]), peer);
+    }
+}
@@ -0,0 +1,49 @@
+/*!
+ * Escaping for text placed in HTML and URLs
+ */
+
+/// Escape the characters that are special in HTML text and attribute values
+pub fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encode a URL path, leaving `/` and unreserved characters as they are.
+/// The result decodes back to `s` through the request parser.
+pub fn url_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(html_escape("<a href=\"x\">Tom & Jerry's</a>"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+        assert_eq!(html_escape("plain.txt"), "plain.txt");
+    }
+
+    #[test]
+    fn test_url_encode() {
+        assert_eq!(url_encode("/files/a b+c&d.txt"), "/files/a%20b%2Bc%26d.txt");
+        assert_eq!(url_encode("/caf\u{e9}/"), "/caf%C3%A9/");
+    }
+}
@@ -0,0 +1,168 @@
+/*!
+ * HTTP date formatting and parsing (RFC 7231 IMF-fixdate)
+ */
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Broken-down UTC time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub weekday: u32, // 0 = Thursday (the Unix epoch), see DAY_NAMES
+}
+
+impl DateTime {
+    /// Break a system time down into UTC calendar fields
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        let days = secs.div_euclid(86400);
+        let secs_of_day = secs.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (secs_of_day / 3600) as u32,
+            minute: ((secs_of_day % 3600) / 60) as u32,
+            second: (secs_of_day % 60) as u32,
+            weekday: days.rem_euclid(7) as u32,
+        }
+    }
+}
+
+/// Format a system time as an HTTP date, e.g. "Fri, 01 Jan 2021 00:00:00 GMT"
+pub fn format(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAY_NAMES[dt.weekday as usize],
+        dt.day,
+        MONTH_NAMES[(dt.month - 1) as usize],
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+/// Format a system time for Common Log Format, e.g. "01/Jan/2021:00:00:00 +0000"
+pub fn format_common_log(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        dt.day,
+        MONTH_NAMES[(dt.month - 1) as usize],
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+/// Parse an HTTP date in IMF-fixdate format
+pub fn parse(s: &str) -> Option<SystemTime> {
+    // "Fri, 01 Jan 2021 00:00:00 GMT"
+    let (_, rest) = s.trim().split_once(", ")?;
+    let parts: Vec<&str> = rest.split_whitespace().collect();
+    if parts.len() != 5 || parts[4] != "GMT" {
+        return None;
+    }
+
+    let day: u32 = parts[0].parse().ok()?;
+    let month = MONTH_NAMES.iter().position(|m| *m == parts[1])? as u32 + 1;
+    let year: i64 = parts[2].parse().ok()?;
+
+    let time: Vec<&str> = parts[3].split(':').collect();
+    if time.len() != 3 {
+        return None;
+    }
+    let hour: u64 = time[0].parse().ok()?;
+    let minute: u64 = time[1].parse().ok()?;
+    let second: u64 = time[2].parse().ok()?;
+    if day == 0 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+
+    let secs = days as u64 * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) civil date to days since the Unix epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_format_known_timestamps() {
+        assert_eq!(format(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format(at(1609459200)), "Fri, 01 Jan 2021 00:00:00 GMT");
+        assert_eq!(format(at(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        // Leap day
+        assert_eq!(format(at(1709164800)), "Thu, 29 Feb 2024 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        for secs in [0, 784111777, 951782400, 1609459200, 1709164800, 4102444799] {
+            let formatted = format(at(secs));
+            assert_eq!(parse(&formatted), Some(at(secs)), "round trip of {}", formatted);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("Fri, 01 Foo 2021 00:00:00 GMT"), None);
+        assert_eq!(parse("Fri, 01 Jan 2021 00:00 GMT"), None);
+        assert_eq!(parse("Fri, 01 Jan 2021 00:00:00 PST"), None);
+    }
+}
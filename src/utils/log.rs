@@ -0,0 +1,101 @@
+/*!
+ * Access logging in Common Log Format
+ */
+
+use crate::config::AccessLog;
+use crate::utils::http_date;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+/// One completed request
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub remote_addr: Option<String>,
+    pub time: SystemTime,
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+    pub status: u16,
+    pub bytes: usize,
+}
+
+impl AccessLogEntry {
+    /// Render the entry as a Common Log Format line (without the newline)
+    pub fn to_common_log(&self) -> String {
+        let bytes = if self.bytes == 0 { "-".to_string() } else { self.bytes.to_string() };
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.remote_addr.as_deref().unwrap_or("-"),
+            http_date::format_common_log(self.time),
+            self.method,
+            self.uri,
+            self.version,
+            self.status,
+            bytes
+        )
+    }
+}
+
+/// Writes access log lines to a file or standard output
+pub struct AccessLogger {
+    writer: Option<Box<dyn Write + Send>>,
+}
+
+impl AccessLogger {
+    /// Open the log target described by the configuration
+    pub fn open(target: &AccessLog) -> io::Result<Self> {
+        let writer: Option<Box<dyn Write + Send>> = match target {
+            AccessLog::Off => None,
+            AccessLog::Stdout => Some(Box::new(io::stdout())),
+            AccessLog::File(path) => Some(Box::new(
+                OpenOptions::new().create(true).append(true).open(path)?
+            )),
+        };
+        Ok(Self { writer })
+    }
+
+    /// Write one line for a completed request
+    pub fn log(&mut self, entry: &AccessLogEntry) {
+        if let Some(writer) = self.writer.as_mut() {
+            // Logging must never take the server down
+            if writeln!(writer, "{}", entry.to_common_log()).and_then(|_| writer.flush()).is_err() {
+                eprintln!("Failed to write access log entry");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(bytes: usize) -> AccessLogEntry {
+        AccessLogEntry {
+            remote_addr: Some("127.0.0.1".to_string()),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136), // 10 Oct 2000 13:55:36 UTC
+            method: "GET".to_string(),
+            uri: "/apache_pb.gif".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_common_log_line() {
+        assert_eq!(
+            entry(2326).to_common_log(),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.1\" 200 2326"
+        );
+    }
+
+    #[test]
+    fn test_empty_body_and_unknown_host() {
+        let mut entry = entry(0);
+        entry.remote_addr = None;
+        assert!(entry.to_common_log().starts_with("- - - ["));
+        assert!(entry.to_common_log().ends_with(" 200 -"));
+    }
+}
@@ -0,0 +1,207 @@
+/*!
+ * Minimal Markdown to HTML conversion
+ *
+ * Covers what a README needs: headings, paragraphs, bulleted and numbered
+ * lists, fenced code blocks, and inline code, bold, italic and links. All
+ * text is HTML-escaped, and links to schemes other than http, https and
+ * mailto are left as plain text.
+ */
+
+use crate::utils::html_escape;
+
+/// Render Markdown source as an HTML fragment
+pub fn markdown_to_html(source: &str) -> String {
+    let mut renderer = Renderer::default();
+    let mut lines = source.lines();
+
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+
+        // Fenced code is copied as it is, up to the closing fence
+        if line.starts_with("```") {
+            renderer.end_blocks();
+            renderer.html.push_str("<pre><code>");
+            for code in lines.by_ref() {
+                if code.trim_start().starts_with("```") {
+                    break;
+                }
+                renderer.html.push_str(&html_escape(code));
+                renderer.html.push('\n');
+            }
+            renderer.html.push_str("</code></pre>\n");
+        } else if line.is_empty() {
+            renderer.end_blocks();
+        } else if let Some((level, text)) = heading(line) {
+            renderer.end_blocks();
+            renderer.html.push_str(&format!("<h{}>{}</h{}>\n", level, render_inline(text), level));
+        } else if let Some((tag, text)) = list_item(line) {
+            renderer.end_paragraph();
+            if renderer.list != Some(tag) {
+                renderer.end_list();
+                renderer.html.push_str(&format!("<{}>\n", tag));
+                renderer.list = Some(tag);
+            }
+            renderer.html.push_str(&format!("<li>{}</li>\n", render_inline(text)));
+        } else {
+            renderer.end_list();
+            renderer.paragraph.push(line);
+        }
+    }
+
+    renderer.end_blocks();
+    renderer.html
+}
+
+/// Output so far and the blocks still open
+#[derive(Default)]
+struct Renderer<'a> {
+    html: String,
+    /// Lines of the paragraph being collected
+    paragraph: Vec<&'a str>,
+    /// Tag of the list being written
+    list: Option<&'static str>,
+}
+
+impl Renderer<'_> {
+    fn end_paragraph(&mut self) {
+        if !self.paragraph.is_empty() {
+            let text = self.paragraph.join(" ");
+            self.html.push_str(&format!("<p>{}</p>\n", render_inline(&text)));
+            self.paragraph.clear();
+        }
+    }
+
+    fn end_list(&mut self) {
+        if let Some(tag) = self.list.take() {
+            self.html.push_str(&format!("</{}>\n", tag));
+        }
+    }
+
+    fn end_blocks(&mut self) {
+        self.end_paragraph();
+        self.end_list();
+    }
+}
+
+/// Level and text of an ATX heading such as `## Usage`
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let text = &line[level..];
+    if !(1..=6).contains(&level) || !(text.is_empty() || text.starts_with(' ')) {
+        return None;
+    }
+    Some((level, text.trim().trim_end_matches('#').trim_end()))
+}
+
+/// List tag and text of a list item such as `- one` or `2. two`
+fn list_item(line: &str) -> Option<(&'static str, &str)> {
+    if let Some(text) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")).or_else(|| line.strip_prefix("+ ")) {
+        return Some(("ul", text.trim_start()));
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let text = line[digits..].strip_prefix(". ")?;
+    (digits > 0).then_some(("ol", text.trim_start()))
+}
+
+/// Render the inline markup of a line of text
+fn render_inline(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let before = text[..i].chars().next_back();
+        if let Some((span, used)) = code_span(rest).or_else(|| link(rest)).or_else(|| emphasis(rest, before)) {
+            html.push_str(&span);
+            i += used;
+        } else {
+            html.push_str(&html_escape(c.encode_utf8(&mut [0; 4])));
+            i += c.len_utf8();
+        }
+    }
+    html
+}
+
+/// `` `code` ``, with the bytes it used
+fn code_span(text: &str) -> Option<(String, usize)> {
+    let inner = text.strip_prefix('`')?;
+    let end = inner.find('`')?;
+    Some((format!("<code>{}</code>", html_escape(&inner[..end])), end + 2))
+}
+
+/// `[text](url)`, with the bytes it used
+fn link(text: &str) -> Option<(String, usize)> {
+    let inner = text.strip_prefix('[')?;
+    let label_end = inner.find("](")?;
+    let target = &inner[label_end + 2..];
+    let target_end = target.find(')')?;
+    let url = target[..target_end].trim();
+    if !is_safe_url(url) {
+        return None;
+    }
+
+    let html = format!("<a href=\"{}\">{}</a>", html_escape(url), render_inline(&inner[..label_end]));
+    Some((html, label_end + target_end + 4))
+}
+
+/// `**bold**` or `*italic*`, or the same with underscores, with the bytes it used
+fn emphasis(text: &str, before: Option<char>) -> Option<(String, usize)> {
+    for (marker, tag) in [("**", "strong"), ("__", "strong"), ("*", "em"), ("_", "em")] {
+        let inner = match text.strip_prefix(marker) {
+            Some(inner) => inner,
+            None => continue,
+        };
+        // Underscores inside words, as in snake_case, are literal
+        if marker.starts_with('_') && before.is_some_and(char::is_alphanumeric) {
+            continue;
+        }
+        if inner.starts_with(char::is_whitespace) {
+            continue;
+        }
+        if let Some(end) = inner.find(marker).filter(|&end| end > 0) {
+            let html = format!("<{}>{}</{}>", tag, render_inline(&inner[..end]), tag);
+            return Some((html, end + marker.len() * 2));
+        }
+    }
+    None
+}
+
+/// Check if a link target is relative or uses a scheme safe to follow
+fn is_safe_url(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+            ["http", "https", "mailto"].iter().any(|safe| scheme.eq_ignore_ascii_case(safe))
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        let html = markdown_to_html("# Title\n\nSome text\ncontinued.\n\n- one\n- two\n\n1. first\n2. second\n\n```\nlet x = 1 < 2;\n```\n## Next ##\n");
+        assert_eq!(html, "<h1>Title</h1>\n<p>Some text continued.</p>\n<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+            <ol>\n<li>first</li>\n<li>second</li>\n</ol>\n<pre><code>let x = 1 &lt; 2;\n</code></pre>\n<h2>Next</h2>\n");
+        assert_eq!(markdown_to_html("#hashtag"), "<p>#hashtag</p>\n");
+    }
+
+    #[test]
+    fn test_inline_markup() {
+        assert_eq!(
+            render_inline("**bold**, *em*, __b__, _e_, `a<b>` and [the *docs*](docs/API.md)"),
+            "<strong>bold</strong>, <em>em</em>, <strong>b</strong>, <em>e</em>, <code>a&lt;b&gt;</code> and <a href=\"docs/API.md\">the <em>docs</em></a>"
+        );
+        assert_eq!(render_inline("max_body_size and 2 * 3 * 4"), "max_body_size and 2 * 3 * 4");
+        assert_eq!(render_inline("<script>"), "&lt;script&gt;");
+    }
+
+    #[test]
+    fn test_unsafe_links_left_as_text() {
+        assert_eq!(render_inline("[x](javascript:alert(1))"), "[x](javascript:alert(1))");
+        assert_eq!(render_inline("[x](https://example.com/a?b=\"c\")"), "<a href=\"https://example.com/a?b=&quot;c&quot;\">x</a>");
+    }
+}
@@ -1,30 +1,44 @@
 /*!
  * MIME type detection
+ *
+ * Built-in text types get a charset parameter added when they are looked up,
+ * so the table holds bare types. Configured mappings are used exactly as given.
  */
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
+/// Charset given to text types unless configured otherwise
+pub const DEFAULT_CHARSET: &str = "utf-8";
+
 /// MIME type detector
 pub struct MimeDetector {
     extensions: HashMap<String, String>,
+    /// Extensions mapped through `add_mapping`, which never get a charset added
+    verbatim: HashSet<String>,
+    charset: String,
 }
 
 impl MimeDetector {
     /// Create a new MIME detector with default mappings
     pub fn new() -> Self {
+        Self::with_charset(DEFAULT_CHARSET)
+    }
+
+    /// Create a MIME detector that labels text types with `charset`
+    pub fn with_charset(charset: &str) -> Self {
         let mut extensions = HashMap::new();
 
         // Text files
-        extensions.insert("html".to_string(), "text/html; charset=utf-8".to_string());
-        extensions.insert("htm".to_string(), "text/html; charset=utf-8".to_string());
-        extensions.insert("css".to_string(), "text/css; charset=utf-8".to_string());
-        extensions.insert("js".to_string(), "application/javascript; charset=utf-8".to_string());
-        extensions.insert("json".to_string(), "application/json; charset=utf-8".to_string());
-        extensions.insert("xml".to_string(), "application/xml; charset=utf-8".to_string());
-        extensions.insert("txt".to_string(), "text/plain; charset=utf-8".to_string());
-        extensions.insert("md".to_string(), "text/markdown; charset=utf-8".to_string());
-        extensions.insert("csv".to_string(), "text/csv; charset=utf-8".to_string());
+        extensions.insert("html".to_string(), "text/html".to_string());
+        extensions.insert("htm".to_string(), "text/html".to_string());
+        extensions.insert("css".to_string(), "text/css".to_string());
+        extensions.insert("js".to_string(), "application/javascript".to_string());
+        extensions.insert("json".to_string(), "application/json".to_string());
+        extensions.insert("xml".to_string(), "application/xml".to_string());
+        extensions.insert("txt".to_string(), "text/plain".to_string());
+        extensions.insert("md".to_string(), "text/markdown".to_string());
+        extensions.insert("csv".to_string(), "text/csv".to_string());
 
         // Images
         extensions.insert("png".to_string(), "image/png".to_string());
@@ -85,39 +99,85 @@ impl MimeDetector {
         extensions.insert("rb".to_string(), "text/x-ruby".to_string());
         extensions.insert("go".to_string(), "text/x-go".to_string());
 
-        Self { extensions }
+        Self {
+            extensions,
+            verbatim: HashSet::new(),
+            charset: charset.to_string(),
+        }
+    }
+
+    /// Charset added to text types
+    pub fn charset(&self) -> &str {
+        &self.charset
+    }
+
+    /// Change the charset added to text types
+    pub fn set_charset(&mut self, charset: &str) {
+        self.charset = charset.to_string();
     }
 
     /// Detect MIME type from file path
     pub fn detect_from_path(&self, path: &Path) -> String {
-        if let Some(extension) = path.extension() {
-            if let Some(ext_str) = extension.to_str() {
-                let ext_lower = ext_str.to_lowercase();
-                if let Some(mime_type) = self.extensions.get(&ext_lower) {
-                    return mime_type.clone();
-                }
-            }
+        // Default to binary if no match
+        self.lookup_extension(path, &self.charset)
+            .unwrap_or_else(|| "application/octet-stream".to_string())
+    }
+
+    /// Detect MIME type from the extension, sniffing the content when the extension is unknown
+    pub fn detect(&self, path: &Path, content: &[u8]) -> String {
+        self.detect_with_charset(path, content, &self.charset)
+    }
+
+    /// Like `detect`, labelling a text type with `charset` instead of the configured one
+    pub fn detect_with_charset(&self, path: &Path, content: &[u8], charset: &str) -> String {
+        self.lookup_extension(path, charset)
+            .or_else(|| sniff(content).map(|mime_type| self.add_charset(mime_type.to_string(), charset)))
+            .unwrap_or_else(|| "application/octet-stream".to_string())
+    }
+
+    /// Detect MIME type from the magic bytes at the start of a file
+    pub fn detect_from_content(&self, bytes: &[u8]) -> Option<String> {
+        sniff(bytes).map(|mime_type| self.add_charset(mime_type.to_string(), &self.charset))
+    }
+
+    /// Append a charset parameter to a text type
+    fn add_charset(&self, mime_type: String, charset: &str) -> String {
+        if self.is_text_type(&mime_type) {
+            format!("{}; charset={}", mime_type, charset)
+        } else {
+            mime_type
         }
+    }
 
-        // Default to binary if no match
-        "application/octet-stream".to_string()
+    /// Look up the MIME type registered for a path's extension
+    fn lookup_extension(&self, path: &Path, charset: &str) -> Option<String> {
+        let extension = path.extension()?.to_str()?;
+        self.lookup(extension, charset)
     }
 
-    /// Detect MIME type from filename
-    pub fn detect_from_filename(&self, filename: &str) -> String {
-        if let Some(dot_pos) = filename.rfind('.') {
-            let extension = &filename[dot_pos + 1..].to_lowercase();
-            if let Some(mime_type) = self.extensions.get(extension) {
-                return mime_type.clone();
-            }
+    /// Look up the MIME type registered for an extension, adding `charset` to a built-in text type
+    fn lookup(&self, extension: &str, charset: &str) -> Option<String> {
+        let extension = extension.to_lowercase();
+        let mime_type = self.extensions.get(&extension)?.clone();
+        if self.verbatim.contains(&extension) {
+            Some(mime_type)
+        } else {
+            Some(self.add_charset(mime_type, charset))
         }
+    }
 
-        "application/octet-stream".to_string()
+    /// Detect MIME type from filename
+    pub fn detect_from_filename(&self, filename: &str) -> String {
+        filename.rfind('.')
+            .and_then(|dot_pos| self.lookup(&filename[dot_pos + 1..], &self.charset))
+            .unwrap_or_else(|| "application/octet-stream".to_string())
     }
 
-    /// Add or update a MIME type mapping
+    /// Add or update a MIME type mapping, used exactly as given
     pub fn add_mapping(&mut self, extension: &str, mime_type: &str) {
-        self.extensions.insert(extension.to_lowercase(), mime_type.to_string());
+        let extension = extension.to_lowercase();
+        self.verbatim.insert(extension.clone());
+        self.extensions.insert(extension, mime_type.to_string());
     }
 
     /// Check if a MIME type is text-based
@@ -153,6 +213,35 @@ impl Default for MimeDetector {
     }
 }
 
+/// Recognize a type from the magic bytes at the start of a file
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+
+    for (magic, mime_type) in SIGNATURES {
+        if bytes.starts_with(magic) {
+            return Some(*mime_type);
+        }
+    }
+
+    // HTML may be preceded by whitespace and is matched without regard to case
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    let text = &bytes[start..];
+    let is_html = [&b"<!doctype html"[..], b"<html"].iter()
+        .any(|tag| text.len() >= tag.len() && text[..tag.len()].eq_ignore_ascii_case(tag));
+    if is_html {
+        return Some("text/html");
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,6 +277,37 @@ mod tests {
         assert_eq!(detector.detect_from_path(&path), "text/html; charset=utf-8");
     }
 
+    #[test]
+    fn test_content_detection() {
+        let detector = MimeDetector::new();
+
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR";
+        let pdf = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n";
+        assert_eq!(detector.detect_from_content(png).as_deref(), Some("image/png"));
+        assert_eq!(detector.detect_from_content(pdf).as_deref(), Some("application/pdf"));
+        assert_eq!(detector.detect_from_content(b"\n  <!DOCTYPE html><html>").as_deref(), Some("text/html; charset=utf-8"));
+        assert_eq!(detector.detect_from_content(b"plain words"), None);
+
+        // Content only decides when the extension is unknown
+        assert_eq!(detector.detect(Path::new("upload"), png), "image/png");
+        assert_eq!(detector.detect(Path::new("notes.txt"), pdf), "text/plain; charset=utf-8");
+        assert_eq!(detector.detect(Path::new("blob"), b"\x00\x01"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_charset_only_on_text_types() {
+        let mut detector = MimeDetector::with_charset("iso-8859-1");
+        detector.add_mapping("log", "text/plain");
+
+        assert_eq!(detector.detect_from_filename("page.html"), "text/html; charset=iso-8859-1");
+        assert_eq!(detector.detect_from_filename("data.json"), "application/json; charset=iso-8859-1");
+        assert_eq!(detector.detect_from_filename("server.log"), "text/plain");
+        assert_eq!(detector.detect_from_filename("image.png"), "image/png");
+        assert_eq!(detector.detect(Path::new("blob"), b"\x00\x01"), "application/octet-stream");
+        assert_eq!(detector.detect_with_charset(Path::new("page.htm"), b"", "koi8-r"), "text/html; charset=koi8-r");
+        assert_eq!(detector.detect_with_charset(Path::new("song.mp3"), b"", "koi8-r"), "audio/mpeg");
+    }
+
     #[test]
     fn test_type_checking() {
         let detector = MimeDetector::new();
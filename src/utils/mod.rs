@@ -7,7 +7,21 @@
 pub mod timeout;
 pub mod buffer;
 pub mod mime;
+pub mod http_date;
+pub mod log;
+pub mod client_ip;
+pub mod ratelimit;
+pub mod escape;
+pub mod base64;
+pub mod sha1;
+pub mod markdown;
 
 pub use timeout::{TimeoutManager, ConnectionInfo, ConnectionState, TimeoutStats, ResourceMonitor, ResourceStats};
 pub use buffer::*;
 pub use mime::*;
+pub use client_ip::{IpNetwork, resolve_client_ip};
+pub use ratelimit::RateLimiter;
+pub use escape::{html_escape, url_encode};
+pub use base64::{base64_decode, base64_encode};
+pub use sha1::sha1;
+pub use markdown::markdown_to_html;
@@ -0,0 +1,131 @@
+/*!
+ * Per-client request rate limiting
+ *
+ * Token buckets keyed on client address: each holds up to the configured
+ * number of requests and refills evenly over the configured period.
+ */
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Tokens left for one client
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token-bucket rate limiter keyed on client address
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    /// Tokens added per second
+    refill_rate: f64,
+    period: Duration,
+    buckets: HashMap<IpAddr, Bucket>,
+    last_prune: Instant,
+}
+
+impl RateLimiter {
+    /// Allow `requests` requests per client every `period`
+    pub fn new(requests: u32, period: Duration) -> Self {
+        let capacity = requests as f64;
+        Self {
+            capacity,
+            refill_rate: capacity / period.as_secs_f64().max(f64::MIN_POSITIVE),
+            period,
+            buckets: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    /// Take a token for a request from `ip`.
+    /// When none is left, returns how long until one will be.
+    pub fn check(&mut self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&mut self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let bucket = self.buckets.entry(ip).or_insert(Bucket { tokens: self.capacity, last_refill: now });
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_rate).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.refill_rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_rate))
+        } else {
+            Err(self.period)
+        }
+    }
+
+    /// Drop buckets that have refilled completely, at most once per period
+    pub fn prune(&mut self) {
+        self.prune_at(Instant::now());
+    }
+
+    fn prune_at(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.last_prune) < self.period {
+            return;
+        }
+        self.last_prune = now;
+
+        // A full bucket behaves exactly like a missing one
+        let period = self.period;
+        self.buckets.retain(|_, bucket| now.saturating_duration_since(bucket.last_refill) < period);
+    }
+
+    /// Number of clients currently tracked
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Check if no client is tracked
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_bucket_empties_and_refills() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(limiter.check_at(ip("10.0.0.1"), start).is_ok());
+        assert!(limiter.check_at(ip("10.0.0.1"), start).is_ok());
+        let retry_after = limiter.check_at(ip("10.0.0.1"), start).unwrap_err();
+        assert_eq!(retry_after.as_secs(), 5);
+
+        // Other clients have their own budget
+        assert!(limiter.check_at(ip("10.0.0.2"), start).is_ok());
+
+        // One token comes back every five seconds
+        assert!(limiter.check_at(ip("10.0.0.1"), start + Duration::from_secs(5)).is_ok());
+        assert!(limiter.check_at(ip("10.0.0.1"), start + Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn test_prune_drops_full_buckets() {
+        let mut limiter = RateLimiter::new(5, Duration::from_secs(60));
+        let start = Instant::now();
+        limiter.check_at(ip("10.0.0.1"), start).unwrap();
+        limiter.check_at(ip("10.0.0.2"), start + Duration::from_secs(30)).unwrap();
+
+        limiter.prune_at(start + Duration::from_secs(70));
+        assert_eq!(limiter.len(), 1);
+        limiter.prune_at(start + Duration::from_secs(200));
+        assert!(limiter.is_empty());
+    }
+}
@@ -25,6 +25,8 @@ pub struct ConnectionInfo {
     pub bytes_read: usize,
     pub bytes_written: usize,
     pub state: ConnectionState,
+    /// Route-specific request timeout overriding the manager default
+    pub request_timeout: Option<Duration>,
 }
 
 /// Connection state
@@ -72,6 +74,7 @@ impl TimeoutManager {
             bytes_read: 0,
             bytes_written: 0,
             state: ConnectionState::Reading,
+            request_timeout: None,
         };
 
         self.connections.insert(fd, info);
@@ -95,6 +98,17 @@ impl TimeoutManager {
         }
     }
 
+    /// Count bytes written to a connection; a client still taking the
+    /// response is active, so any progress refreshes its idle clock
+    pub fn record_bytes_written(&mut self, fd: RawFd, bytes_written: usize) {
+        if let Some(info) = self.connections.get_mut(&fd) {
+            info.bytes_written += bytes_written;
+            if bytes_written > 0 {
+                info.last_activity = Instant::now();
+            }
+        }
+    }
+
     /// Update connection state
     pub fn update_state(&mut self, fd: RawFd, state: ConnectionState) {
         if let Some(info) = self.connections.get_mut(&fd) {
@@ -111,6 +125,37 @@ impl TimeoutManager {
         }
     }
 
+    /// Override the request timeout for a single connection
+    pub fn set_request_timeout(&mut self, fd: RawFd, timeout: Duration) {
+        if let Some(info) = self.connections.get_mut(&fd) {
+            info.request_timeout = Some(timeout);
+        }
+    }
+
+    /// Drop a connection's request timeout override, going back to the default
+    pub fn clear_request_timeout(&mut self, fd: RawFd) {
+        if let Some(info) = self.connections.get_mut(&fd) {
+            info.request_timeout = None;
+        }
+    }
+
+    /// Get the request timeout of connections without an override
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    /// Get the request timeout that applies to a connection
+    pub fn request_timeout_for(&self, fd: RawFd) -> Duration {
+        self.connections.get(&fd)
+            .and_then(|info| info.request_timeout)
+            .unwrap_or(self.request_timeout)
+    }
+
+    /// Set how many connections may be tracked at once
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+
     /// Get connections that have timed out
     pub fn get_timed_out_connections(&self) -> Vec<RawFd> {
         let now = Instant::now();
@@ -119,7 +164,7 @@ impl TimeoutManager {
         for (fd, info) in &self.connections {
             let timeout = match info.state {
                 ConnectionState::KeepAlive => self.keep_alive_timeout,
-                _ => self.request_timeout,
+                _ => info.request_timeout.unwrap_or(self.request_timeout),
             };
 
             if now.duration_since(info.last_activity) > timeout {
@@ -145,6 +190,11 @@ impl TimeoutManager {
         self.connections.len()
     }
 
+    /// Most connections tracked at once
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
     /// Check if at connection limit
     pub fn is_at_limit(&self) -> bool {
         self.connections.len() >= self.max_connections
@@ -325,6 +375,15 @@ impl ResourceStats {
         }
     }
 
+    /// Get errors per request served
+    pub fn error_rate(&self) -> f64 {
+        if self.total_requests_served > 0 {
+            self.error_count as f64 / self.total_requests_served as f64
+        } else {
+            0.0
+        }
+    }
+
     /// Get bytes per second
     pub fn bytes_per_second(&self) -> f64 {
         let uptime_secs = self.uptime.as_secs_f64();
@@ -349,6 +408,24 @@ impl ResourceStats {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_per_connection_request_timeout() {
+        let mut manager = TimeoutManager::new(Duration::from_secs(30), Duration::from_secs(60), 10);
+        manager.add_connection(1).unwrap();
+        manager.add_connection(2).unwrap();
+
+        manager.set_request_timeout(1, Duration::ZERO);
+        assert_eq!(manager.request_timeout_for(1), Duration::ZERO);
+        assert_eq!(manager.request_timeout_for(2), Duration::from_secs(30));
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(manager.get_timed_out_connections(), vec![1]);
+
+        manager.clear_request_timeout(1);
+        assert_eq!(manager.request_timeout_for(1), Duration::from_secs(30));
+        assert!(manager.get_timed_out_connections().is_empty());
+    }
+
     #[test]
     fn test_timeout_manager() {
         let mut manager = TimeoutManager::with_defaults();
@@ -362,6 +439,17 @@ mod tests {
         let info = manager.get_connection(1).unwrap();
         assert_eq!(info.bytes_read, 100);
 
+        // Writes that made progress refresh the idle clock; empty ones do not
+        let last_activity = info.last_activity;
+        manager.record_bytes_written(1, 0);
+        assert_eq!(manager.get_connection(1).unwrap().last_activity, last_activity);
+        std::thread::sleep(Duration::from_millis(2));
+        manager.record_bytes_written(1, 250);
+        let info = manager.get_connection(1).unwrap();
+        assert_eq!(info.bytes_written, 250);
+        assert!(info.last_activity > last_activity);
+        assert_eq!(manager.get_stats().total_bytes_written, 250);
+
         // Remove connection
         assert!(manager.remove_connection(1).is_some());
         assert_eq!(manager.connection_count(), 0);
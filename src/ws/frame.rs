@@ -0,0 +1,218 @@
+/*!
+ * WebSocket frames (RFC 6455 section 5)
+ */
+
+/// Status codes sent in a Close frame
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Largest payload a control frame may carry
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// What a frame carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Check if the frame manages the connection rather than carrying a message
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// A single frame, with its payload unmasked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Whether this is the last frame of its message
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Self { fin: true, opcode, payload }
+    }
+
+    pub fn text(text: &str) -> Self {
+        Self::new(Opcode::Text, text.as_bytes().to_vec())
+    }
+
+    pub fn binary(data: Vec<u8>) -> Self {
+        Self::new(Opcode::Binary, data)
+    }
+
+    pub fn pong(payload: Vec<u8>) -> Self {
+        Self::new(Opcode::Pong, payload)
+    }
+
+    /// Close frame carrying a status code
+    pub fn close(code: u16) -> Self {
+        Self::new(Opcode::Close, code.to_be_bytes().to_vec())
+    }
+
+    /// Parse a frame sent by a client, which must be masked. Returns None until
+    /// the whole frame has arrived, with the bytes it took up, or the status
+    /// code to close the connection with when the frame breaks the protocol.
+    pub fn parse(data: &[u8], max_payload: usize) -> Result<Option<(Frame, usize)>, u16> {
+        if data.len() < 2 {
+            return Ok(None);
+        }
+
+        let fin = data[0] & 0x80 != 0;
+        // No extensions are negotiated, so the reserved bits must be clear
+        if data[0] & 0x70 != 0 {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        let opcode = Opcode::from_u8(data[0] & 0x0F).ok_or(CLOSE_PROTOCOL_ERROR)?;
+        if data[1] & 0x80 == 0 {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+
+        let (len, mut pos) = match data[1] & 0x7F {
+            126 => match data.get(2..4) {
+                Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+                None => return Ok(None),
+            },
+            127 => match data.get(2..10) {
+                Some(bytes) => {
+                    let mut len = [0u8; 8];
+                    len.copy_from_slice(bytes);
+                    (u64::from_be_bytes(len), 10)
+                }
+                None => return Ok(None),
+            },
+            len => (u64::from(len), 2),
+        };
+
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(CLOSE_PROTOCOL_ERROR);
+        }
+        if len > max_payload as u64 {
+            return Err(CLOSE_MESSAGE_TOO_BIG);
+        }
+        let len = len as usize;
+
+        let mask = match data.get(pos..pos + 4) {
+            Some(mask) => [mask[0], mask[1], mask[2], mask[3]],
+            None => return Ok(None),
+        };
+        pos += 4;
+
+        let payload = match data.get(pos..pos + len) {
+            Some(payload) => payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect(),
+            None => return Ok(None),
+        };
+
+        Ok(Some((Frame { fin, opcode, payload }, pos + len)))
+    }
+
+    /// Serialize for sending from the server, which never masks
+    pub fn encode(&self) -> Vec<u8> {
+        let len = self.payload.len();
+        let mut out = Vec::with_capacity(len + 10);
+        out.push(if self.fin { 0x80 } else { 0 } | self.opcode.as_u8());
+        if len < 126 {
+            out.push(len as u8);
+        } else if len <= u16::MAX as usize {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        out.extend_from_slice(&self.payload);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame as a client would send it
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut out = vec![first];
+        if payload.len() < 126 {
+            out.push(0x80 | payload.len() as u8);
+        } else {
+            out.push(0x80 | 126);
+            out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    #[test]
+    fn test_masked_text_frame() {
+        // Example from RFC 6455 section 5.7
+        let data = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, used) = Frame::parse(&data, 1024).unwrap().unwrap();
+        assert_eq!(frame, Frame::text("Hello"));
+        assert_eq!(used, data.len());
+
+        // Every prefix is incomplete rather than an error
+        for end in 0..data.len() {
+            assert_eq!(Frame::parse(&data[..end], 1024), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_extended_length() {
+        let payload = vec![b'x'; 300];
+        let data = masked(0x82, &payload);
+        let (frame, used) = Frame::parse(&data, 1024).unwrap().unwrap();
+        assert_eq!(frame.payload, payload);
+        assert_eq!(used, data.len());
+
+        assert_eq!(Frame::parse(&data, 299), Err(CLOSE_MESSAGE_TOO_BIG));
+        assert_eq!(Frame::binary(payload.clone()).encode()[..4], [0x82, 126, 0x01, 0x2c]);
+    }
+
+    #[test]
+    fn test_protocol_violations() {
+        // Unmasked client frame
+        assert_eq!(Frame::parse(&[0x81, 0x00], 1024), Err(CLOSE_PROTOCOL_ERROR));
+        // Reserved bit set
+        assert_eq!(Frame::parse(&masked(0xC1, b"a"), 1024), Err(CLOSE_PROTOCOL_ERROR));
+        // Unknown opcode
+        assert_eq!(Frame::parse(&masked(0x83, b"a"), 1024), Err(CLOSE_PROTOCOL_ERROR));
+        // Fragmented or oversized control frames
+        assert_eq!(Frame::parse(&masked(0x09, b"a"), 1024), Err(CLOSE_PROTOCOL_ERROR));
+        assert_eq!(Frame::parse(&masked(0x89, &[0; 126]), 1024), Err(CLOSE_PROTOCOL_ERROR));
+    }
+}
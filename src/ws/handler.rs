@@ -0,0 +1,38 @@
+/*!
+ * Application side of a WebSocket connection
+ */
+
+/// A complete message, reassembled from its frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Answers the messages of one WebSocket connection
+pub trait WebSocketHandler {
+    /// Handle a message from the client, returning the messages to send back
+    fn on_message(&mut self, message: Message) -> Vec<Message>;
+}
+
+/// Creates the handler for each connection upgraded on a route
+pub type WebSocketHandlerFactory = fn() -> Box<dyn WebSocketHandler>;
+
+/// Name of the handler used by routes with `websocket on`
+pub const ECHO_HANDLER: &str = "echo";
+
+/// Sends every message back to the client unchanged
+#[derive(Debug, Default)]
+pub struct EchoHandler;
+
+impl EchoHandler {
+    pub fn boxed() -> Box<dyn WebSocketHandler> {
+        Box::new(EchoHandler)
+    }
+}
+
+impl WebSocketHandler for EchoHandler {
+    fn on_message(&mut self, message: Message) -> Vec<Message> {
+        vec![message]
+    }
+}
@@ -0,0 +1,93 @@
+/*!
+ * WebSocket opening handshake (RFC 6455 section 4.2)
+ */
+
+use crate::error::HttpStatus;
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpVersion};
+use crate::utils::{base64_decode, base64_encode, sha1};
+
+/// Appended to the client's key before hashing, fixed by the protocol
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only protocol version this server speaks, sent back with `426 Upgrade Required`
+pub const WEBSOCKET_VERSION: &str = "13";
+
+/// Check if a header's comma-separated value lists a token, ignoring case
+fn header_has_token(request: &HttpRequest, name: &str, token: &str) -> bool {
+    request.get_header_all(name).iter()
+        .flat_map(|value| value.split(','))
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// Check if a request asks to switch the connection to WebSocket
+pub fn is_upgrade_request(request: &HttpRequest) -> bool {
+    header_has_token(request, "upgrade", "websocket")
+}
+
+/// `Sec-WebSocket-Accept` value proving the server read the client's key
+pub fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// `101 Switching Protocols` answering a valid upgrade request, or the status to
+/// refuse it with: `426 Upgrade Required` for another protocol version, else 400
+pub fn handshake_response(request: &HttpRequest) -> Result<HttpResponse, HttpStatus> {
+    if request.method != HttpMethod::GET || request.version != HttpVersion::Http11
+        || !header_has_token(request, "connection", "upgrade")
+    {
+        return Err(HttpStatus::BadRequest);
+    }
+
+    if request.get_header("sec-websocket-version").map(|version| version.trim()) != Some(WEBSOCKET_VERSION) {
+        return Err(HttpStatus::UpgradeRequired);
+    }
+
+    // The key is 16 random bytes, base64 encoded
+    let key = match request.get_header("sec-websocket-key").map(|key| key.trim()) {
+        Some(key) if base64_decode(key).is_some_and(|bytes| bytes.len() == 16) => key,
+        _ => return Err(HttpStatus::BadRequest),
+    };
+
+    let mut response = HttpResponse::new(HttpStatus::SwitchingProtocols);
+    response.add_header("Upgrade", "websocket");
+    response.add_header("Connection", "Upgrade");
+    response.add_header("Sec-WebSocket-Accept", &accept_key(key));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade_request(key: &str, version: &str) -> HttpRequest {
+        let mut request = HttpRequest::new();
+        request.add_header("Host", "localhost");
+        request.add_header("Upgrade", "websocket");
+        request.add_header("Connection", "keep-alive, Upgrade");
+        request.add_header("Sec-WebSocket-Key", key);
+        request.add_header("Sec-WebSocket-Version", version);
+        request
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_handshake_response() {
+        let request = upgrade_request("dGhlIHNhbXBsZSBub25jZQ==", "13");
+        assert!(is_upgrade_request(&request));
+        let response = handshake_response(&request).unwrap();
+        assert_eq!(response.status, HttpStatus::SwitchingProtocols);
+        assert_eq!(response.get_header("Sec-WebSocket-Accept").map(String::as_str), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        let refused = handshake_response(&upgrade_request("dGhlIHNhbXBsZSBub25jZQ==", "8"));
+        assert_eq!(refused.err(), Some(HttpStatus::UpgradeRequired));
+
+        // Keys must decode to 16 bytes
+        let refused = handshake_response(&upgrade_request("c2hvcnQ=", "13"));
+        assert_eq!(refused.err(), Some(HttpStatus::BadRequest));
+    }
+}
@@ -0,0 +1,15 @@
+/*!
+ * WebSocket module
+ *
+ * Upgrading HTTP/1.1 connections to WebSocket (RFC 6455) on routes with
+ * `websocket` set, and exchanging frames with the route's handler
+ */
+
+pub mod frame;
+pub mod handshake;
+pub mod handler;
+pub mod socket;
+
+pub use frame::{Frame, Opcode};
+pub use handler::{EchoHandler, Message, WebSocketHandler, WebSocketHandlerFactory, ECHO_HANDLER};
+pub use socket::WebSocket;
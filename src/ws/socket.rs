@@ -0,0 +1,200 @@
+/*!
+ * WebSocket connection state
+ *
+ * Reassembles fragmented messages for the handler, answers pings, and
+ * closes the connection with the matching status code when the client
+ * breaks the protocol.
+ */
+
+use crate::ws::frame::{Frame, Opcode, CLOSE_INVALID_DATA, CLOSE_MESSAGE_TOO_BIG, CLOSE_NORMAL, CLOSE_PROTOCOL_ERROR};
+use crate::ws::handler::{Message, WebSocketHandler};
+
+/// An upgraded connection
+pub struct WebSocket {
+    handler: Box<dyn WebSocketHandler>,
+    /// Bytes of a frame that has not fully arrived
+    pending: Vec<u8>,
+    /// Opcode and payload so far of a fragmented message
+    fragments: Option<(Opcode, Vec<u8>)>,
+    /// Largest message accepted, whole or reassembled
+    max_message_size: usize,
+    /// Whether a Close frame has been sent; nothing more is read after it
+    closed: bool,
+}
+
+impl WebSocket {
+    pub fn new(handler: Box<dyn WebSocketHandler>, max_message_size: usize) -> Self {
+        Self {
+            handler,
+            pending: Vec::new(),
+            fragments: None,
+            max_message_size,
+            closed: false,
+        }
+    }
+
+    /// Take bytes read from the client, returning the bytes to send back
+    pub fn receive(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        if self.closed {
+            return output;
+        }
+        self.pending.extend_from_slice(data);
+
+        let mut offset = 0;
+        while !self.closed {
+            match Frame::parse(&self.pending[offset..], self.max_message_size) {
+                Ok(Some((frame, used))) => {
+                    offset += used;
+                    for reply in self.handle_frame(frame) {
+                        output.extend_from_slice(&reply.encode());
+                    }
+                }
+                Ok(None) => break,
+                Err(code) => output.extend_from_slice(&self.close(code).encode()),
+            }
+        }
+        // Closing has already dropped whatever was pending
+        if !self.closed {
+            self.pending.drain(..offset);
+        }
+        output
+    }
+
+    /// Check if the connection has been closed and should end once its output is sent
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn close(&mut self, code: u16) -> Frame {
+        self.closed = true;
+        self.pending.clear();
+        self.fragments = None;
+        Frame::close(code)
+    }
+
+    /// Frames to send in answer to one frame from the client
+    fn handle_frame(&mut self, frame: Frame) -> Vec<Frame> {
+        match frame.opcode {
+            Opcode::Ping => vec![Frame::pong(frame.payload)],
+            Opcode::Pong => Vec::new(),
+            Opcode::Close => vec![self.answer_close(&frame.payload)],
+            Opcode::Text | Opcode::Binary if self.fragments.is_some() => {
+                // A new message may not start inside a fragmented one
+                vec![self.close(CLOSE_PROTOCOL_ERROR)]
+            }
+            Opcode::Text | Opcode::Binary if !frame.fin => {
+                self.fragments = Some((frame.opcode, frame.payload));
+                Vec::new()
+            }
+            Opcode::Text | Opcode::Binary => self.deliver(frame.opcode, frame.payload),
+            Opcode::Continuation => {
+                let (opcode, mut payload) = match self.fragments.take() {
+                    Some(fragments) => fragments,
+                    None => return vec![self.close(CLOSE_PROTOCOL_ERROR)],
+                };
+                if payload.len() + frame.payload.len() > self.max_message_size {
+                    return vec![self.close(CLOSE_MESSAGE_TOO_BIG)];
+                }
+                payload.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    self.deliver(opcode, payload)
+                } else {
+                    self.fragments = Some((opcode, payload));
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    /// Hand a complete message to the handler
+    fn deliver(&mut self, opcode: Opcode, payload: Vec<u8>) -> Vec<Frame> {
+        let message = if opcode == Opcode::Text {
+            match String::from_utf8(payload) {
+                Ok(text) => Message::Text(text),
+                Err(_) => return vec![self.close(CLOSE_INVALID_DATA)],
+            }
+        } else {
+            Message::Binary(payload)
+        };
+
+        self.handler.on_message(message).into_iter()
+            .map(|reply| match reply {
+                Message::Text(text) => Frame::text(&text),
+                Message::Binary(data) => Frame::binary(data),
+            })
+            .collect()
+    }
+
+    /// Echo the client's Close frame, checking the status code and reason it carries
+    fn answer_close(&mut self, payload: &[u8]) -> Frame {
+        let code = match payload {
+            [] => CLOSE_NORMAL,
+            [_] => CLOSE_PROTOCOL_ERROR,
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                if std::str::from_utf8(reason).is_err() {
+                    CLOSE_INVALID_DATA
+                } else if code < 1000 || matches!(code, 1004..=1006 | 1015) || (1016..3000).contains(&code) {
+                    // Reserved codes that may not be sent on the wire
+                    CLOSE_PROTOCOL_ERROR
+                } else {
+                    code
+                }
+            }
+        };
+        self.close(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::handler::EchoHandler;
+
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut out = vec![first, 0x80 | payload.len() as u8];
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    #[test]
+    fn test_fragmented_message_is_reassembled() {
+        let mut socket = WebSocket::new(EchoHandler::boxed(), 1024);
+        let mut data = masked(0x01, b"Hel");
+        // A ping may arrive between fragments
+        data.extend(masked(0x89, b"p"));
+        data.extend(masked(0x80, b"lo"));
+
+        // Split mid-frame to check partial frames wait for the rest
+        let output = [socket.receive(&data[..5]), socket.receive(&data[5..])].concat();
+        let mut expected = Frame::pong(b"p".to_vec()).encode();
+        expected.extend(Frame::text("Hello").encode());
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_close_is_echoed() {
+        let mut socket = WebSocket::new(EchoHandler::boxed(), 1024);
+        let output = socket.receive(&masked(0x88, &1001u16.to_be_bytes()));
+        assert_eq!(output, Frame::close(1001).encode());
+        assert!(socket.is_closed());
+        assert!(socket.receive(&masked(0x81, b"late")).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_messages_close_the_connection() {
+        let mut socket = WebSocket::new(EchoHandler::boxed(), 1024);
+        assert_eq!(socket.receive(&masked(0x81, &[0xff, 0xfe])), Frame::close(CLOSE_INVALID_DATA).encode());
+
+        let mut socket = WebSocket::new(EchoHandler::boxed(), 1024);
+        assert_eq!(socket.receive(&masked(0x80, b"orphan")), Frame::close(CLOSE_PROTOCOL_ERROR).encode());
+
+        let mut socket = WebSocket::new(EchoHandler::boxed(), 4);
+        let mut data = masked(0x02, b"abc");
+        data.extend(masked(0x80, b"de"));
+        assert_eq!(socket.receive(&data), Frame::close(CLOSE_MESSAGE_TOO_BIG).encode());
+    }
+}
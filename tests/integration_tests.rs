@@ -2,77 +2,154 @@
  * Integration tests for localhost HTTP server
  */
 
+use localhost_http_server::config::parser::parse_config;
+use localhost_http_server::http::auth::ssha_hash;
+use localhost_http_server::utils::base64_encode;
+use localhost_http_server::Server;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::fs;
-use std::path::Path;
 
 /// Test configuration
 const TEST_HOST: &str = "127.0.0.1";
 const TEST_PORT: u16 = 8889;
 const TEST_CONFIG: &str = "config/test-listing.conf";
 
-/// Helper function to start the server
-fn start_test_server() -> std::process::Child {
-    Command::new("./target/release/localhost-server")
-        .arg(TEST_CONFIG)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-        .expect("Failed to start test server")
+/// CGI script that echoes its method, query string and body
+const ECHO_CGI: &str = "printf 'Content-Type: text/html\\r\\n\\r\\n<h1>CGI Test Script</h1>\\n'\n\
+printf 'REQUEST_METHOD=%s\\n' \"$REQUEST_METHOD\"\n\
+printf 'QUERY_STRING=%s\\n' \"$QUERY_STRING\"\n\
+if [ -n \"$CONTENT_LENGTH\" ]; then printf 'BODY='; head -c \"$CONTENT_LENGTH\"; fi\n";
+
+/// CGI script that starts a session unless the client already has one
+const SESSION_CGI: &str = "case \"$HTTP_COOKIE\" in\n\
+    *SESSIONID=*) ;;\n\
+    *) printf 'Set-Cookie: SESSIONID=%s; Path=/; HttpOnly; Max-Age=3600\\r\\n' \"$$\" ;;\n\
+esac\n\
+printf 'Content-Type: text/plain\\r\\n\\r\\nsession page'\n";
+
+/// Start the test config in process on a free port, serving the repository's www directory
+fn start_test_server() -> u16 {
+    let config = fs::read_to_string(TEST_CONFIG).expect("Failed to read test config");
+    start_in_process_server(&config.replace(&format!("port {}", TEST_PORT), "port {port}"))
+}
+
+/// Start a server with the test config's body limit and a /cgi-bin route of shell scripts
+fn start_cgi_server(name: &str) -> u16 {
+    let site = temp_site(name);
+    fs::write(site.join("echo.sh"), ECHO_CGI).unwrap();
+    fs::write(site.join("session.sh"), SESSION_CGI).unwrap();
+
+    start_in_process_server(&format!(
+        "server {{\n    host {}\n    port {{port}}\n    max_body_size 1048576\n\n    route /cgi-bin {{\n        methods GET POST\n        root {}\n        cgi sh\n    }}\n}}\n",
+        TEST_HOST, site.display()
+    ))
+}
+
+/// Send a GET for `path` and return the response with any chunked body decoded
+fn get(port: u16, path: &str) -> String {
+    dechunk(raw_request(port, &format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path)))
+}
+
+/// Create an empty scratch directory for an in-process server
+fn temp_site(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localhost_it_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Failed to create test site");
+    dir
 }
 
-/// Helper function to wait for server to be ready
-fn wait_for_server() {
-    thread::sleep(Duration::from_millis(500));
+/// Start a server in a background thread; `{port}` in the config is replaced with a free port
+fn start_in_process_server(config: &str) -> u16 {
+    let port = TcpListener::bind((TEST_HOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free port")
+        .port();
+    let config = parse_config(&config.replace("{port}", &port.to_string()))
+        .expect("Invalid test config");
+
+    thread::spawn(move || {
+        let mut server = Server::new(config).expect("Failed to create server");
+        let _ = server.run();
+    });
+
+    // Wait until the listener accepts connections
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while TcpStream::connect((TEST_HOST, port)).is_err() {
+        assert!(Instant::now() < deadline, "server did not start");
+        thread::sleep(Duration::from_millis(10));
+    }
+    port
 }
 
-/// Helper function to make HTTP request
-fn make_request(path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let url = format!("http://{}:{}{}", TEST_HOST, TEST_PORT, path);
-    let output = Command::new("curl")
-        .args(&["-s", &url])
-        .output()?;
-    
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+/// Send a raw request and read the response until the server closes the connection
+fn raw_request(port: u16, request: &str) -> String {
+    let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(request.as_bytes()).expect("Failed to send request");
+
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    String::from_utf8_lossy(&response).to_string()
 }
 
-/// Helper function to make HTTP request with headers
-fn make_request_with_headers(path: &str, headers: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
-    let url = format!("http://{}:{}{}", TEST_HOST, TEST_PORT, path);
-    let mut cmd = Command::new("curl");
-    cmd.arg("-s");
-    
-    for header in headers {
-        cmd.args(&["-H", header]);
-    }
-    
-    cmd.arg(&url);
-    let output = cmd.output()?;
-    
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+/// Read a single Content-Length framed response from a persistent connection
+fn read_response(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => response.push(byte[0]),
+            _ => return String::from_utf8_lossy(&response).to_string(),
+        }
+    }
+
+    let head = String::from_utf8_lossy(&response).to_string();
+    let content_length = head.lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; content_length];
+    stream.read_exact(&mut body).expect("Failed to read response body");
+    response.extend_from_slice(&body);
+    String::from_utf8_lossy(&response).to_string()
 }
 
-/// Helper function to make POST request
-fn make_post_request(path: &str, data: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let url = format!("http://{}:{}{}", TEST_HOST, TEST_PORT, path);
-    let output = Command::new("curl")
-        .args(&["-s", "-X", "POST", "-d", data, &url])
-        .output()?;
-    
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+/// Read until the server closes the connection, taking `chunk` bytes at a time
+/// with a pause after each, like a client on a slow link
+fn read_slowly(stream: &mut TcpStream, chunk: usize, pause: Duration) -> Vec<u8> {
+    let mut received = Vec::new();
+    let mut buf = vec![0u8; chunk];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return received,
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+        }
+        thread::sleep(pause);
+    }
 }
 
-/// Helper function to get HTTP status code
-fn get_status_code(path: &str) -> Result<u16, Box<dyn std::error::Error>> {
-    let url = format!("http://{}:{}{}", TEST_HOST, TEST_PORT, path);
-    let output = Command::new("curl")
-        .args(&["-s", "-o", "/dev/null", "-w", "%{http_code}", &url])
-        .output()?;
-    
-    let status_str = String::from_utf8_lossy(&output.stdout);
-    Ok(status_str.parse()?)
+/// Replace a chunked body with the bytes it carries, leaving other responses as they are
+fn dechunk(response: String) -> String {
+    let (head, mut rest) = match response.split_once("\r\n\r\n") {
+        Some(parts) if parts.0.contains("Transfer-Encoding: chunked") => parts,
+        _ => return response,
+    };
+
+    let mut body = String::new();
+    while let Some((size, after)) = rest.split_once("\r\n") {
+        let size = usize::from_str_radix(size.trim(), 16).expect("bad chunk size");
+        if size == 0 {
+            break;
+        }
+        body.push_str(&after[..size]);
+        rest = &after[size + 2..];
+    }
+    format!("{}\r\n\r\n{}", head, body)
 }
 
 #[cfg(test)]
@@ -81,222 +158,2244 @@ mod tests {
 
     #[test]
     fn test_static_file_serving() {
-        let mut server = start_test_server();
-        wait_for_server();
+        let port = start_test_server();
 
-        // Test serving index.html
-        let response = make_request("/static/").expect("Failed to make request");
+        // Test serving the directory listing
+        let response = get(port, "/static/");
         assert!(response.contains("Directory Listing"));
         assert!(response.contains("test.txt"));
 
         // Test serving specific file
-        let response = make_request("/static/test.txt").expect("Failed to make request");
-        assert_eq!(response.trim(), "Hello, World!");
-
-        server.kill().expect("Failed to kill server");
+        let response = get(port, "/static/test.txt");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(body.trim(), "Hello from static file!");
     }
 
     #[test]
     fn test_directory_listing() {
-        let mut server = start_test_server();
-        wait_for_server();
+        let port = start_test_server();
+
+        let response = get(port, "/static/");
 
-        let response = make_request("/static/").expect("Failed to make request");
-        
         // Check for directory listing elements
         assert!(response.contains("Directory Listing"));
         assert!(response.contains("test.txt"));
         assert!(response.contains("24 B")); // File size
         assert!(response.contains("📄")); // File icon
-
-        server.kill().expect("Failed to kill server");
     }
 
     #[test]
     fn test_error_pages() {
-        let mut server = start_test_server();
-        wait_for_server();
+        let port = start_test_server();
 
         // Test 404 error
-        let status = get_status_code("/nonexistent").expect("Failed to get status");
-        assert_eq!(status, 404);
-
-        let response = make_request("/nonexistent").expect("Failed to make request");
-        assert!(response.contains("404"));
+        let response = get(port, "/nonexistent");
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
         assert!(response.contains("Not Found"));
 
         // Test 405 error (method not allowed)
-        let url = format!("http://{}:{}/static/", TEST_HOST, TEST_PORT);
-        let output = Command::new("curl")
-            .args(&["-s", "-X", "PATCH", &url])
-            .output()
-            .expect("Failed to make PATCH request");
-        
-        let response = String::from_utf8_lossy(&output.stdout);
-        assert!(response.contains("405") || response.contains("Method Not Allowed"));
-
-        server.kill().expect("Failed to kill server");
+        let response = raw_request(port, "PATCH /static/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"), "unexpected response: {}", response);
+        assert!(response.contains("Method Not Allowed"));
     }
 
     #[test]
     fn test_cgi_functionality() {
-        let mut server = start_test_server();
-        wait_for_server();
+        let port = start_cgi_server("cgi_functionality");
 
         // Test CGI script execution
-        let response = make_request("/cgi-bin/hello.py").expect("Failed to make request");
+        let response = get(port, "/cgi-bin/echo.sh");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
         assert!(response.contains("CGI Test Script"));
-        assert!(response.contains("REQUEST_METHOD"));
-        assert!(response.contains("GET"));
+        assert!(response.contains("REQUEST_METHOD=GET"));
 
         // Test CGI with query parameters
-        let response = make_request("/cgi-bin/hello.py?name=test&value=123").expect("Failed to make request");
-        assert!(response.contains("name=test"));
-        assert!(response.contains("value=123"));
-
-        server.kill().expect("Failed to kill server");
+        let response = get(port, "/cgi-bin/echo.sh?name=test&value=123");
+        assert!(response.contains("QUERY_STRING=name=test&value=123"), "unexpected response: {}", response);
     }
 
     #[test]
     fn test_cgi_post_request() {
-        let mut server = start_test_server();
-        wait_for_server();
+        let port = start_cgi_server("cgi_post");
 
         // Test CGI with POST data
-        let response = make_post_request("/cgi-bin/hello.py", "name=John&email=john@example.com")
-            .expect("Failed to make POST request");
-        
-        assert!(response.contains("POST"));
-        assert!(response.contains("name"));
-        assert!(response.contains("John"));
-        assert!(response.contains("email"));
+        let body = "name=John&email=john@example.com";
+        let response = dechunk(raw_request(port, &format!(
+            "POST /cgi-bin/echo.sh HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body
+        )));
 
-        server.kill().expect("Failed to kill server");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("REQUEST_METHOD=POST"));
+        assert!(response.contains("BODY=name=John&email=john@example.com"), "unexpected response: {}", response);
     }
 
     #[test]
     fn test_session_cookies() {
-        let mut server = start_test_server();
-        wait_for_server();
+        let port = start_cgi_server("session_cookies");
 
         // Test session creation
-        let url = format!("http://{}:{}/cgi-bin/session_test.py", TEST_HOST, TEST_PORT);
-        let output = Command::new("curl")
-            .args(&["-v", &url])
-            .output()
-            .expect("Failed to make request");
-        
-        let response = String::from_utf8_lossy(&output.stderr);
+        let response = get(port, "/cgi-bin/session.sh");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
         assert!(response.contains("Set-Cookie: SESSIONID="));
         assert!(response.contains("HttpOnly"));
-        assert!(response.contains("Max-Age=3600"));
-
-        server.kill().expect("Failed to kill server");
+        assert!(response.contains("Max-Age=3600"), "unexpected response: {}", response);
     }
 
     #[test]
     fn test_mime_types() {
-        let mut server = start_test_server();
-        wait_for_server();
-
-        // Create test files with different extensions
-        fs::write("www/static/test.json", r#"{"test": "data"}"#).expect("Failed to create test.json");
-        fs::write("www/static/test.css", "body { color: red; }").expect("Failed to create test.css");
+        let port = start_test_server();
 
         // Test JSON MIME type
-        let url = format!("http://{}:{}/static/test.json", TEST_HOST, TEST_PORT);
-        let output = Command::new("curl")
-            .args(&["-v", &url])
-            .output()
-            .expect("Failed to make request");
-        
-        let response = String::from_utf8_lossy(&output.stderr);
-        assert!(response.contains("Content-Type: application/json"));
+        let response = get(port, "/static/data.json");
+        assert!(response.contains("Content-Type: application/json"), "unexpected response: {}", response);
 
         // Test CSS MIME type
-        let url = format!("http://{}:{}/static/test.css", TEST_HOST, TEST_PORT);
-        let output = Command::new("curl")
-            .args(&["-v", &url])
-            .output()
-            .expect("Failed to make request");
-        
-        let response = String::from_utf8_lossy(&output.stderr);
-        assert!(response.contains("Content-Type: text/css"));
-
-        // Cleanup
-        let _ = fs::remove_file("www/static/test.json");
-        let _ = fs::remove_file("www/static/test.css");
-
-        server.kill().expect("Failed to kill server");
+        let response = get(port, "/static/style.css");
+        assert!(response.contains("Content-Type: text/css"), "unexpected response: {}", response);
     }
 
     #[test]
     fn test_concurrent_requests() {
-        let mut server = start_test_server();
-        wait_for_server();
+        let port = start_cgi_server("concurrent_requests");
 
         let handles: Vec<_> = (0..10).map(|i| {
-            thread::spawn(move || {
-                let path = format!("/cgi-bin/hello.py?request={}", i);
-                make_request(&path)
-            })
+            thread::spawn(move || get(port, &format!("/cgi-bin/echo.sh?request={}", i)))
         }).collect();
 
-        let mut success_count = 0;
-        for handle in handles {
-            if let Ok(Ok(response)) = handle.join() {
-                if response.contains("CGI Test Script") {
-                    success_count += 1;
-                }
-            }
+        for (i, handle) in handles.into_iter().enumerate() {
+            let response = handle.join().unwrap();
+            assert!(response.contains("CGI Test Script"), "unexpected response: {}", response);
+            assert!(response.contains(&format!("QUERY_STRING=request={}", i)));
         }
-
-        assert!(success_count >= 8, "At least 8 out of 10 concurrent requests should succeed");
-
-        server.kill().expect("Failed to kill server");
     }
 
     #[test]
     fn test_large_request_body() {
-        let mut server = start_test_server();
-        wait_for_server();
-
-        // Test with large POST data (should be rejected due to size limit)
-        let large_data = "a".repeat(2 * 1024 * 1024); // 2MB
-        let url = format!("http://{}:{}/cgi-bin/hello.py", TEST_HOST, TEST_PORT);
-        let output = Command::new("curl")
-            .args(&["-s", "-o", "/dev/null", "-w", "%{http_code}", "-X", "POST", "-d", &large_data, &url])
-            .output()
-            .expect("Failed to make request");
-        
-        let status_str = String::from_utf8_lossy(&output.stdout);
-        let status: u16 = status_str.parse().expect("Failed to parse status code");
-        
-        // Should return 413 (Request Entity Too Large)
-        assert_eq!(status, 413);
+        let port = start_cgi_server("large_request_body");
+
+        // A 2MB POST is over the 1MB limit; like curl, ask before sending it
+        let response = raw_request(port, &format!(
+            "POST /cgi-bin/echo.sh HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n",
+            2 * 1024 * 1024
+        ));
 
-        server.kill().expect("Failed to kill server");
+        // Should return 413 (Request Entity Too Large)
+        assert!(response.starts_with("HTTP/1.1 413"), "unexpected response: {}", response);
     }
 
     #[test]
     fn test_http_headers() {
-        let mut server = start_test_server();
-        wait_for_server();
-
-        let url = format!("http://{}:{}/static/test.txt", TEST_HOST, TEST_PORT);
-        let output = Command::new("curl")
-            .args(&["-v", &url])
-            .output()
-            .expect("Failed to make request");
-        
-        let response = String::from_utf8_lossy(&output.stderr);
-        
+        let port = start_test_server();
+
+        let response = get(port, "/static/test.txt");
+
         // Check for required headers
         assert!(response.contains("Server: localhost-http-server"));
         assert!(response.contains("Content-Type: text/plain"));
         assert!(response.contains("Content-Length:"));
         assert!(response.contains("Date:"));
+    }
+
+    #[test]
+    fn test_slow_cgi_does_not_block_other_clients() {
+        let site = temp_site("slow_cgi");
+        fs::write(site.join("slow.sh"), "sleep 2\nprintf 'Content-Type: text/plain\\r\\n\\r\\nslow done'\n").unwrap();
+        fs::write(site.join("fast.txt"), "fast").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET POST\n        root {}\n        cgi sh\n    }}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        let slow = thread::spawn(move || {
+            dechunk(raw_request(port, "GET /cgi/slow.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"))
+        });
+        thread::sleep(Duration::from_millis(200));
+
+        let started = Instant::now();
+        let fast = raw_request(port, "GET /fast.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(fast.starts_with("HTTP/1.1 200"), "unexpected response: {}", fast);
+        assert!(fast.ends_with("fast"));
+        assert!(started.elapsed() < Duration::from_secs(1), "static request waited for CGI");
+
+        let slow = slow.join().unwrap();
+        assert!(slow.starts_with("HTTP/1.1 200"), "unexpected response: {}", slow);
+        assert!(slow.ends_with("slow done"));
+    }
+
+    #[test]
+    fn test_cgi_workers_run_scripts_in_parallel() {
+        let site = temp_site("cgi_workers");
+        // Output comes in two parts, which the event loop would stream as chunks
+        fs::write(site.join("slow.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\nslow '\nsleep 1\nprintf 'done'\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    cgi_workers 4\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let started = Instant::now();
+        let clients: Vec<_> = (0..4).map(|_| thread::spawn(move || {
+            raw_request(port, "GET /cgi/slow.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        })).collect();
+
+        for client in clients {
+            let response = client.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+            // Only a worker collects the output whole, so this shows the pool ran the script
+            assert!(response.contains("Content-Length: 9\r\n"), "unexpected response: {}", response);
+            assert!(!response.contains("Transfer-Encoding"), "unexpected response: {}", response);
+            assert!(response.ends_with("slow done"));
+        }
+        assert!(started.elapsed() < Duration::from_secs(3), "scripts ran one after another");
+    }
+
+    #[test]
+    fn test_cgi_cache_reuses_responses() {
+        let site = temp_site("cgi_cache");
+        let counter = site.join("runs.log");
+        fs::write(site.join("count.sh"), format!(
+            "echo run >> {}\nprintf 'Content-Type: text/plain\\r\\n\\r\\ncached output'\n",
+            counter.display()
+        )).unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET POST\n        root {}\n        cgi sh\n        cgi_cache on\n        cgi_cache_ttl 60\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+        let runs = || fs::read_to_string(&counter).unwrap_or_default().lines().count();
+        let post = |body: &str| raw_request(port, &format!(
+            "POST /cgi/count.sh HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body
+        ));
+
+        let first = post("a=1");
+        assert!(first.starts_with("HTTP/1.1 200"), "unexpected response: {}", first);
+        assert!(first.ends_with("cached output"));
+        let etag = first.lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .expect("ETag header missing")
+            .to_string();
+
+        let second = post("a=1");
+        assert!(second.ends_with("cached output"), "unexpected response: {}", second);
+        assert!(second.contains(&format!("ETag: {}", etag)));
+        assert_eq!(runs(), 1, "identical request ran the script again");
+
+        // The client already holds the cached response
+        let revalidated = raw_request(port, &format!(
+            "POST /cgi/count.sh HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nIf-None-Match: {}\r\nConnection: close\r\n\r\na=1",
+            etag
+        ));
+        assert!(revalidated.starts_with("HTTP/1.1 304"), "unexpected response: {}", revalidated);
+        assert_eq!(runs(), 1);
+
+        // A different body is a different request
+        let other = post("a=2");
+        assert!(other.ends_with("cached output"));
+        assert_eq!(runs(), 2, "request with another body was served from cache");
+    }
+
+    #[test]
+    fn test_cgi_post_body_larger_than_pipe_buffer() {
+        let site = temp_site("cgi_echo");
+        fs::write(site.join("echo.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\ncat\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_body_size 1048576\n\n    route /cgi {{\n        methods GET POST\n        root {}\n        cgi sh\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // A non-repeating pattern so duplicated or dropped bytes show up
+        let body: String = (0..200 * 1024).map(|i| (b'a' + (i % 23) as u8) as char).collect();
+        let response = dechunk(raw_request(port, &format!(
+            "POST /cgi/echo.sh HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body
+        )));
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", &response[..response.len().min(200)]);
+        assert!(response.ends_with(&body));
+    }
+
+    #[test]
+    fn test_custom_error_pages() {
+        let site = temp_site("error_pages");
+        fs::write(site.join("custom404.html"), "<h1>Custom missing page</h1>").unwrap();
+        fs::write(site.join("custom405.html"), "<h1>Custom method page</h1>").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    error_page 404 {}\n    error_page 405 {}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST,
+            site.join("custom404.html").display(),
+            site.join("custom405.html").display(),
+            site.display()
+        ));
+
+        let response = raw_request(port, "GET /missing.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>Custom missing page</h1>"));
+
+        let response = raw_request(port, "DELETE /custom404.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>Custom method page</h1>"));
+    }
+
+    #[test]
+    fn test_unlisted_directory_gets_error_page_or_redirect() {
+        let site = temp_site("unlisted_directory");
+        fs::create_dir_all(site.join("private")).unwrap();
+        fs::write(site.join("private/notes.txt"), "notes").unwrap();
+        fs::write(site.join("custom403.html"), "<h1>Nothing to see here</h1>").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    error_page 403 {}\n\n    route /members {{\n        methods GET\n        root {}\n        directory_redirect /login.html\n    }}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.join("custom403.html").display(), site.display(), site.display()
+        ));
+
+        let response = raw_request(port, "GET /private/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>Nothing to see here</h1>"), "unexpected response: {}", response);
+        assert!(!response.contains("notes.txt"));
+
+        let response = raw_request(port, "GET /members/private/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 302"), "unexpected response: {}", response);
+        assert!(response.contains("Location: /login.html\r\n"), "unexpected response: {}", response);
+
+        // Files inside are still served
+        let response = raw_request(port, "GET /members/private/notes.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_error_pages_root_of_each_server() {
+        let site = temp_site("error_pages_root_servers");
+        let pages = site.join("pages");
+        fs::create_dir_all(&pages).unwrap();
+        fs::write(pages.join("404.html"), "<h1>b.test has no such page</h1>").unwrap();
+        fs::write(pages.join("405.html"), "<h1>b.test does not do that</h1>").unwrap();
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name a.test\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name b.test\n    error_pages_root {}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, pages.display(), site.display()
+        ));
+
+        let response = raw_request(port, "GET /missing.txt HTTP/1.1\r\nHost: b.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>b.test has no such page</h1>"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "DELETE /index.html HTTP/1.1\r\nHost: b.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>b.test does not do that</h1>"), "unexpected response: {}", response);
+
+        // The first server keeps the built-in pages
+        let response = raw_request(port, "GET /missing.txt HTTP/1.1\r\nHost: a.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
+        assert!(!response.contains("b.test"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_static_and_cgi_errors_use_their_server_pages() {
+        let site = temp_site("error_pages_servers");
+        fs::create_dir_all(site.join("private")).unwrap();
+        fs::write(site.join("fail.sh"), "exit 1\n").unwrap();
+        fs::write(site.join("b403.html"), "<h1>b.test keeps this closed</h1>").unwrap();
+        fs::write(site.join("b404.html"), "<h1>b.test has no such file</h1>").unwrap();
+        fs::write(site.join("b502.html"), "<h1>b.test script failed</h1>").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name a.test\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name b.test\n    error_page 403 {}\n    error_page 404 {}\n    error_page 502 {}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n    }}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST,
+            site.join("b403.html").display(), site.join("b404.html").display(), site.join("b502.html").display(),
+            site.display(), site.display()
+        ));
+
+        // Refused by the file server, which renders the second server's page
+        let response = raw_request(port, "GET /private/ HTTP/1.1\r\nHost: b.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>b.test keeps this closed</h1>"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /missing.txt HTTP/1.1\r\nHost: b.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>b.test has no such file</h1>"), "unexpected response: {}", response);
+
+        // As is a script that fails
+        let response = raw_request(port, "GET /cgi/fail.sh HTTP/1.1\r\nHost: b.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 502"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>b.test script failed</h1>"), "unexpected response: {}", response);
+
+        // The first server's requests are not given the second server's pages
+        let response = raw_request(port, "GET /private/ HTTP/1.1\r\nHost: a.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403"), "unexpected response: {}", response);
+        assert!(!response.contains("b.test"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_markdown_index_served_for_directory() {
+        let site = temp_site("markdown_index");
+        fs::create_dir_all(site.join("guide")).unwrap();
+        fs::write(site.join("guide/README.md"), "# Guide\n\n- [Install](install.html)\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET\n        root {}\n        markdown_index on\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /guide/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("Content-Type: text/html"), "unexpected response: {}", response);
+        assert!(response.contains("<h1>Guide</h1>"), "unexpected response: {}", response);
+        assert!(response.contains("<li><a href=\"install.html\">Install</a></li>"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_session_cookie_reused_across_requests() {
+        let site = temp_site("sessions");
+        fs::write(site.join("session.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n%s' \"$SESSION_ID\"\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let first = dechunk(raw_request(port, "GET /cgi/session.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"));
+        let cookie = first.lines()
+            .find_map(|line| line.strip_prefix("Set-Cookie: SESSIONID="))
+            .and_then(|value| value.split(';').next())
+            .expect("first response should set a session cookie")
+            .to_string();
+        assert!(first.ends_with(&cookie), "CGI should see the session ID: {}", first);
+
+        let second = dechunk(raw_request(port, &format!(
+            "GET /cgi/session.sh HTTP/1.1\r\nHost: localhost\r\nCookie: SESSIONID={}\r\nConnection: close\r\n\r\n",
+            cookie
+        )));
+        assert!(second.contains(&format!("Set-Cookie: SESSIONID={};", cookie)));
+        assert!(second.ends_with(&cookie), "second request should resolve to the same session: {}", second);
+    }
+
+    #[test]
+    fn test_unix_socket_listener() {
+        use std::os::unix::net::UnixStream;
+
+        let site = temp_site("unix_socket");
+        fs::write(site.join("hello.txt"), "hello over unix").unwrap();
+        let socket_path = site.join("server.sock");
+
+        start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    listen unix:{}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, socket_path.display(), site.display()
+        ));
+
+        let mut stream = UnixStream::connect(&socket_path).expect("Failed to connect to unix socket");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"GET /hello.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.ends_with("hello over unix"));
+    }
+
+    #[test]
+    fn test_tls_handshake_and_static_file() {
+        use std::sync::Arc;
+
+        let site = temp_site("tls");
+        fs::write(site.join("index.html"), "<h1>over tls</h1>").unwrap();
+        let large: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(site.join("large.bin"), &large).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(site.join("cert.pem"), certified.cert.pem()).unwrap();
+        fs::write(site.join("key.pem"), certified.signing_key.serialize_pem()).unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    ssl on\n    ssl_cert {}\n    ssl_key {}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.join("cert.pem").display(), site.join("key.pem").display(), site.display()
+        ));
+
+        // Trust only the self-signed certificate
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let client_config = Arc::new(client_config);
+        let tls_request = |request: &str| {
+            let session = rustls::ClientConnection::new(client_config.clone(), "localhost".try_into().unwrap()).unwrap();
+            let stream = TcpStream::connect((TEST_HOST, port)).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let mut tls = rustls::StreamOwned::new(session, stream);
+            tls.write_all(request.as_bytes()).unwrap();
+
+            let mut response = Vec::new();
+            tls.read_to_end(&mut response).expect("TLS connection was not closed cleanly");
+            response
+        };
+
+        let response = String::from_utf8(tls_request("GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.ends_with("<h1>over tls</h1>"), "unexpected response: {}", response);
+
+        // Bodies streamed from disk are encrypted as the socket drains
+        let response = tls_request("GET /large.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        let body_start = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert_eq!(&response[body_start..], &large[..]);
+
+        // Plaintext HTTP on a TLS port gets no HTTP response
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(!response.starts_with("HTTP/1.1"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_sni_selects_certificate_of_named_server() {
+        use std::sync::Arc;
+
+        let site = temp_site("sni");
+        let mut roots = rustls::RootCertStore::empty();
+        let mut certs = Vec::new();
+        for name in ["alpha.test", "beta.test"] {
+            let mut params = rcgen::CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.distinguished_name.push(rcgen::DnType::CommonName, name);
+            let key = rcgen::KeyPair::generate().unwrap();
+            let cert = params.self_signed(&key).unwrap();
+
+            fs::create_dir_all(site.join(name)).unwrap();
+            fs::write(site.join(name).join("index.html"), name).unwrap();
+            fs::write(site.join(format!("{}.pem", name)), cert.pem()).unwrap();
+            fs::write(site.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+            roots.add(cert.der().clone()).unwrap();
+            certs.push(cert);
+        }
+
+        // Both servers share one port; alpha.test is the default
+        let server_block = |name: &str| format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name {}\n    ssl on\n    ssl_cert {}\n    ssl_key {}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, name,
+            site.join(format!("{}.pem", name)).display(),
+            site.join(format!("{}.key", name)).display(),
+            site.join(name).display()
+        );
+        let port = start_in_process_server(&format!("{}\n{}", server_block("alpha.test"), server_block("beta.test")));
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client_config = Arc::new(rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth());
+
+        for (name, cert) in ["alpha.test", "beta.test"].iter().zip(&certs) {
+            let session = rustls::ClientConnection::new(client_config.clone(), name.to_string().try_into().unwrap()).unwrap();
+            let stream = TcpStream::connect((TEST_HOST, port)).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let mut tls = rustls::StreamOwned::new(session, stream);
+            tls.write_all(format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", name).as_bytes()).unwrap();
+
+            let mut response = String::new();
+            tls.read_to_string(&mut response).expect("TLS connection was not closed cleanly");
+            assert!(response.ends_with(name), "unexpected response: {}", response);
+
+            // The certificate with this name as its CN was served
+            let served = &tls.conn.peer_certificates().unwrap()[0];
+            assert_eq!(served, cert.der(), "wrong certificate for {}", name);
+        }
+    }
+
+    #[test]
+    fn test_edge_triggered_mode_drains_large_transfers() {
+        let site = temp_site("edge_mode");
+        let large: String = (0..1024 * 1024).map(|i| (b'a' + (i % 19) as u8) as char).collect();
+        fs::write(site.join("large.txt"), &large).unwrap();
+        fs::write(site.join("echo.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\ncat\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    epoll_mode edge\n    max_body_size 1048576\n\n    route /cgi {{\n        methods GET POST\n        root {}\n        cgi sh\n    }}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        // Response larger than the socket send buffer
+        let response = raw_request(port, "GET /large.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", &response[..response.len().min(200)]);
+        assert!(response.ends_with(&large));
+
+        // Request body larger than a single read
+        let body = &large[..300 * 1024];
+        let response = dechunk(raw_request(port, &format!(
+            "POST /cgi/echo.sh HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body
+        )));
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", &response[..response.len().min(200)]);
+        assert!(response.ends_with(body));
+    }
+
+    #[test]
+    fn test_large_file_streamed_over_keep_alive() {
+        let site = temp_site("streamed");
+        let large: String = (0..3 * 1024 * 1024).map(|i| (b'a' + (i % 23) as u8) as char).collect();
+        fs::write(site.join("large.txt"), &large).unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+        // Far larger than the write buffer, so it must be refilled from disk
+        stream.write_all(b"GET /large.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", &response[..response.len().min(200)]);
+        assert!(response.contains(&format!("Content-Length: {}\r\n", large.len())));
+        assert!(response.ends_with(&large));
+
+        // The connection is reusable afterwards, and ranges stream too
+        stream.write_all(b"GET /large.txt HTTP/1.1\r\nHost: localhost\r\nRange: bytes=100000-\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 206"), "unexpected response: {}", &response[..response.len().min(200)]);
+        assert!(response.ends_with(&large[100000..]));
+    }
+
+    #[test]
+    fn test_slow_reader_keeps_streamed_file_alive() {
+        let site = temp_site("file_slow_reader");
+        let large: Vec<u8> = (0..12 * 1024 * 1024).map(|i| b'a' + (i % 23) as u8).collect();
+        fs::write(site.join("large.bin"), &large).unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET\n        root {}\n        request_timeout 1\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let started = Instant::now();
+        stream.write_all(b"GET /large.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+        // Refilling the write buffer from disk as the client drains it is progress
+        let received = read_slowly(&mut stream, 64 * 1024, Duration::from_millis(20));
+        let head_end = received.windows(4).position(|window| window == b"\r\n\r\n").expect("no response head") + 4;
+        let head = String::from_utf8_lossy(&received[..head_end]);
+        assert!(head.starts_with("HTTP/1.1 200"), "unexpected response: {}", head);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", large.len())));
+        assert_eq!(received.len() - head_end, large.len());
+        assert!(received[head_end..] == large[..]);
+        assert!(started.elapsed() > Duration::from_secs(2), "download was not slow enough to test the timeout");
+    }
+
+    #[test]
+    fn test_route_headers_on_static_and_cgi_responses() {
+        let site = temp_site("route_headers");
+        fs::write(site.join("index.html"), "hello").unwrap();
+        fs::write(site.join("hello.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\nfrom cgi'\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n        header X-Frame-Options SAMEORIGIN\n    }}\n\n    route / {{\n        methods GET\n        root {}\n        header X-Frame-Options SAMEORIGIN\n        header Access-Control-Allow-Origin *\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("X-Frame-Options: SAMEORIGIN\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Content-Length: 5\r\n"), "unexpected response: {}", response);
+
+        let response = dechunk(raw_request(port, "GET /cgi/hello.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"));
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("X-Frame-Options: SAMEORIGIN\r\n"), "unexpected response: {}", response);
+        assert!(!response.contains("Access-Control-Allow-Origin"), "unexpected response: {}", response);
+        assert!(response.ends_with("from cgi"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_cors_preflight() {
+        let site = temp_site("cors");
+        fs::write(site.join("data.json"), "{}").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /api {{\n        methods GET POST\n        root {}\n        cors_allow_origin https://app.example.com\n        cors_allow_methods GET POST\n        cors_allow_headers Content-Type\n    }}\n\n    route / {{\n        methods GET\n        root {}\n        cors_allow_origin *\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        let response = raw_request(port, "OPTIONS /api/data.json HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: POST\r\nAccess-Control-Request-Headers: content-type\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(response.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Access-Control-Allow-Methods: GET, POST\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Access-Control-Allow-Headers: content-type\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Vary: Origin\r\n"), "unexpected response: {}", response);
+
+        // The actual request echoes the allowed origin
+        let response = raw_request(port, "GET /api/data.json HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example.com\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"), "unexpected response: {}", response);
+
+        // Any origin on the wildcard route, without credentials
+        let response = raw_request(port, "GET /data.json HTTP/1.1\r\nHost: localhost\r\nOrigin: https://other.example\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"), "unexpected response: {}", response);
+        assert!(!response.contains("Access-Control-Allow-Credentials"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_cors_preflight_refused() {
+        let site = temp_site("cors_refused");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET POST\n        root {}\n        cors_allow_origin https://app.example.com\n        cors_allow_methods GET\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // Method outside cors_allow_methods
+        let response = raw_request(port, "OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: DELETE\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(!response.contains("Access-Control-Allow-Origin"), "unexpected response: {}", response);
+
+        // Origin not in the list
+        let response = raw_request(port, "OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://evil.example\r\nAccess-Control-Request-Method: GET\r\nConnection: close\r\n\r\n");
+        assert!(!response.contains("Access-Control-Allow-Origin"), "unexpected response: {}", response);
+
+        // Header that was not allowed
+        let response = raw_request(port, "OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: GET\r\nAccess-Control-Request-Headers: X-Token\r\nConnection: close\r\n\r\n");
+        assert!(!response.contains("Access-Control-Allow-Origin"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_put_creates_then_overwrites_file() {
+        let site = temp_site("put");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET PUT PATCH OPTIONS\n        root {}\n        upload_enabled on\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "PUT /notes.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nConnection: close\r\n\r\nfirst");
+        assert!(response.starts_with("HTTP/1.1 201"), "unexpected response: {}", response);
+        assert!(response.contains("Location: /notes.txt"));
+        assert_eq!(fs::read_to_string(site.join("notes.txt")).unwrap(), "first");
+
+        let response = raw_request(port, "PUT /notes.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nConnection: close\r\n\r\nnew");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert_eq!(fs::read_to_string(site.join("notes.txt")).unwrap(), "new");
+
+        let response = raw_request(port, "PATCH /notes.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nConnection: close\r\n\r\n+more");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert_eq!(fs::read_to_string(site.join("notes.txt")).unwrap(), "new+more");
+
+        let response = raw_request(port, "OPTIONS /notes.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(response.contains("Allow: GET, PUT, PATCH, OPTIONS"));
+    }
+
+    #[test]
+    fn test_upload_to_tempfile_saves_large_body() {
+        const BODY_SIZE: usize = 10 * 1024 * 1024;
+        let site = temp_site("upload_tempfile");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_body_size {}\n\n    route / {{\n        methods GET POST PUT\n        root {}\n        upload_enabled on\n        upload_to_tempfile on\n    }}\n}}\n",
+            TEST_HOST, BODY_SIZE, site.display()
+        ));
+
+        let body: Vec<u8> = (0..BODY_SIZE).map(|i| (i % 251) as u8).collect();
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+        let head = format!("PUT /big.bin HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", BODY_SIZE);
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(&body).unwrap();
+
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 201"), "unexpected response: {}", response);
+        assert!(fs::read(site.join("big.bin")).unwrap() == body, "saved file differs from upload");
+
+        // Raw POST bodies are moved into the upload directory the same way
+        let response = raw_request(port, &format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100000\r\nConnection: close\r\n\r\n{}",
+            "p".repeat(100_000)
+        ));
+        assert!(response.starts_with("HTTP/1.1 201"), "unexpected response: {}", response);
+        let uploaded = fs::read_dir(&site).unwrap()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().to_string_lossy().starts_with("upload_"))
+            .expect("POST body was not saved");
+        assert_eq!(fs::metadata(uploaded.path()).unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn test_method_override_from_header_and_form_field() {
+        let site = temp_site("method_override");
+        let uploads = site.join("uploads");
+        fs::create_dir_all(&uploads).unwrap();
+        fs::write(uploads.join("by_header.txt"), "x").unwrap();
+        fs::write(uploads.join("by_form.txt"), "x").unwrap();
+        fs::write(site.join("kept.txt"), "x").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    method_override on\n\n    route /uploads {{\n        methods POST DELETE\n        root {}\n        upload_enabled on\n    }}\n\n    route / {{\n        methods GET POST\n        root {}\n        upload_enabled on\n    }}\n}}\n",
+            TEST_HOST, uploads.display(), site.display()
+        ));
+
+        let response = raw_request(port, "POST /uploads/by_header.txt HTTP/1.1\r\nHost: localhost\r\nX-HTTP-Method-Override: DELETE\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(!uploads.join("by_header.txt").exists());
+
+        let response = raw_request(port, "POST /uploads/by_form.txt HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 14\r\nConnection: close\r\n\r\n_method=delete");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(!uploads.join("by_form.txt").exists());
+
+        // The route must allow the method asked for
+        let response = raw_request(port, "POST /kept.txt HTTP/1.1\r\nHost: localhost\r\nX-HTTP-Method-Override: DELETE\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"), "unexpected response: {}", response);
+        assert!(response.contains("Allow: GET, POST\r\n"), "unexpected response: {}", response);
+        assert!(site.join("kept.txt").exists());
+
+        let response = raw_request(port, "POST /kept.txt HTTP/1.1\r\nHost: localhost\r\nX-HTTP-Method-Override: FROB\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_method_override_patch_appends_spooled_body() {
+        let site = temp_site("override_spooled");
+        fs::write(site.join("log.txt"), "start\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    method_override on\n\n    route / {{\n        methods GET POST PATCH\n        root {}\n        upload_enabled on\n        upload_to_tempfile on\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // Large enough to be written to disk while it arrives, before the override is read
+        let body = "p".repeat(200_000);
+        let response = raw_request(port, &format!(
+            "POST /log.txt HTTP/1.1\r\nHost: localhost\r\nX-HTTP-Method-Override: PATCH\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body
+        ));
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert_eq!(fs::read_to_string(site.join("log.txt")).unwrap(), format!("start\n{}", body));
+    }
+
+    #[test]
+    fn test_method_override_ignored_unless_enabled() {
+        let site = temp_site("method_override_off");
+        fs::write(site.join("kept.txt"), "x").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods POST DELETE\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "POST /kept.txt HTTP/1.1\r\nHost: localhost\r\nX-HTTP-Method-Override: DELETE\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(site.join("kept.txt").exists());
+    }
+
+    #[test]
+    fn test_strict_host_requires_one_host_header() {
+        let site = temp_site("strict_host");
+        fs::write(site.join("index.html"), "home").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    strict_host on\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"), "unexpected response: {}", response);
+
+        // HTTP/1.0 clients need not send Host
+        let response = raw_request(port, "GET /index.html HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_folded_headers_merged_only_for_servers_that_allow_it() {
+        let site = temp_site("folded_headers");
+        fs::write(site.join("index.html"), "home").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name lenient.test\n    merge_folded_headers on\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name strict.test\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: lenient.test\r\nX-Long: a\r\n b\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: strict.test\r\nX-Long: a\r\n b\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: strict.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_strict_host_applies_to_shared_listener() {
+        let site = temp_site("strict_host_shared");
+        fs::write(site.join("index.html"), "home").unwrap();
+
+        // The strict server is not the default one its port falls back to
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name lenient.test\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name strict.test\n    strict_host on\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: lenient.test\r\nHost: strict.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: lenient.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+
+        // A strict server on another port leaves this one alone
+        let strict_port = TcpListener::bind((TEST_HOST, 0)).unwrap().local_addr().unwrap().port();
+        let other_port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {}\n    server_name strict.test\n    strict_host on\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, strict_port, site.display()
+        ));
+        let response = raw_request(other_port, "GET /index.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_host_header_optional_unless_strict() {
+        let site = temp_site("lenient_host");
+        fs::write(site.join("index.html"), "home").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_redirect_uses_configured_status() {
+        let site = temp_site("redirect_status");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /old {{\n        methods GET\n        redirect 301 /new\n    }}\n\n    route /api {{\n        methods GET\n        redirect 308 /api/v2\n    }}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /old HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently"), "unexpected response: {}", response);
+        assert!(response.contains("Location: /new\r\n"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /api HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect"), "unexpected response: {}", response);
+        assert!(response.contains("Location: /api/v2\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_redirect_applies_to_post() {
+        let site = temp_site("redirect_post");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /form {{\n        methods GET POST\n        redirect /submit\n    }}\n\n    route /moved {{\n        methods POST\n        redirect 301 /new\n    }}\n\n    route / {{\n        methods GET POST\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // A 302 would let the client retry as GET, so POST gets 307
+        let response = raw_request(port, "POST /form HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nConnection: close\r\n\r\na=1");
+        assert!(response.starts_with("HTTP/1.1 307 Temporary Redirect"), "unexpected response: {}", response);
+        assert!(response.contains("Location: /submit\r\n"), "unexpected response: {}", response);
+        assert!(!response.contains("POST request received"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "POST /moved HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /form HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 302 Found"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_try_files_falls_back_to_index() {
+        let site = temp_site("try_files");
+        fs::write(site.join("index.html"), "spa shell").unwrap();
+        fs::write(site.join("app.js"), "console.log(1)").unwrap();
+        fs::create_dir_all(site.join("docs")).unwrap();
+        fs::write(site.join("docs").join("index.html"), "docs index").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /strict {{\n        methods GET\n        root {}\n        try_files $uri\n    }}\n\n    route / {{\n        methods GET\n        root {}\n        try_files $uri $uri/ /index.html\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        let response = raw_request(port, "GET /app/some/deep/route HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.ends_with("spa shell"), "unexpected response: {}", response);
+
+        // Existing files and directories are served as they are
+        let response = raw_request(port, "GET /app.js HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("console.log(1)"), "unexpected response: {}", response);
+        let response = raw_request(port, "GET /docs HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("docs index"), "unexpected response: {}", response);
+
+        // Without a fallback, a missing file is still 404
+        let response = raw_request(port, "GET /strict/missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_connections_over_limit_get_503() {
+        let site = temp_site("max_connections");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_connections 1\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // The first client holds the only slot open with keep-alive, once the
+        // startup probe's connection has been cleaned up
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let first = loop {
+            let mut stream = TcpStream::connect((TEST_HOST, port)).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let response = read_response(&mut stream);
+            if response.starts_with("HTTP/1.1 200") {
+                break stream;
+            }
+            assert!(Instant::now() < deadline, "no slot became free: {}", response);
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        let response = raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "unexpected response: {}", response);
+        assert!(response.contains("Retry-After: 5\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Connection: close\r\n"), "unexpected response: {}", response);
+
+        // Once the slot is free, clients are served again
+        drop(first);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let response = raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+            if response.starts_with("HTTP/1.1 200") {
+                break;
+            }
+            assert!(Instant::now() < deadline, "slot was not freed: {}", response);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_websocket_handshake_and_echo() {
+        let site = temp_site("websocket");
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /ws {{\n        methods GET\n        root {}\n        websocket on\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Connection: Upgrade\r\n"), "unexpected response: {}", response);
+
+        // A masked text frame comes back unmasked
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![0x81, 0x85];
+        frame.extend_from_slice(&mask);
+        frame.extend(b"hello".iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        stream.write_all(&frame).unwrap();
+        let mut echoed = [0u8; 7];
+        stream.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"\x81\x05hello");
+
+        // Closing is answered in kind, then the connection ends
+        stream.write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8]).unwrap();
+        let mut rest = Vec::new();
+        let _ = stream.read_to_end(&mut rest);
+        assert_eq!(rest, [0x88, 0x02, 0x03, 0xe8]);
+
+        // Other protocol versions are refused, naming the one spoken
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 426"), "unexpected response: {}", response);
+        assert!(response.contains("Sec-WebSocket-Version: 13\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_event_stream_pushes_events_as_they_are_sent() {
+        let site = temp_site("event_stream");
+        fs::write(site.join("clock.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\nfor i in 1 2; do\n    printf 'data: %s\\n\\n' $i\n    sleep 0.1\ndone\nsleep 2\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /events {{\n        methods GET\n        root {}\n        cgi sh\n        event_stream on\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let started = Instant::now();
+        stream.write_all(b"GET /events/clock.sh HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        // Both events arrive while the script is still running
+        let mut received = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !String::from_utf8_lossy(&received).contains("data: 2\n\n") {
+            let n = stream.read(&mut buf).expect("Failed to read events");
+            assert!(n > 0, "connection closed early: {}", String::from_utf8_lossy(&received));
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert!(started.elapsed() < Duration::from_millis(1500), "events waited for the script to finish");
+
+        let response = String::from_utf8_lossy(&received).to_string();
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("Content-Type: text/event-stream\r\n"), "unexpected response: {}", response);
+        assert!(response.contains("Cache-Control: no-cache\r\n"));
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!response.contains("Content-Length:"));
+        assert!(!response.contains("Connection: close"));
+        assert!(response.contains("data: 1\n\n"));
+    }
+
+    #[test]
+    fn test_route_access_rules_by_client_ip() {
+        let site = temp_site("access_rules");
+        fs::write(site.join("index.html"), "secret").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /local {{\n        methods GET\n        root {}\n        allow 127.0.0.0/8\n        deny all\n    }}\n\n    route /remote {{\n        methods GET\n        root {}\n        allow 10.0.0.0/8\n        deny all\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        let response = raw_request(port, "GET /local/index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.ends_with("secret"));
+
+        let response = raw_request(port, "GET /remote/index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403"), "unexpected response: {}", response);
+        assert!(!response.contains("secret"));
+    }
+
+    #[test]
+    fn test_basic_auth_on_route() {
+        let site = temp_site("basic_auth");
+        fs::write(site.join("whoami.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\nprintf 'user=%s' \"$REMOTE_USER\"\n").unwrap();
+        let users = site.join("users");
+        fs::write(&users, format!("alice:{}\n", ssha_hash("wonderland", b"n4Cl"))).unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /private {{\n        methods GET\n        root {}\n        cgi sh\n        auth_basic \"Staff Only\"\n        auth_user_file {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), users.display()
+        ));
+        let request = |authorization: Option<&str>| {
+            let header = authorization.map(|value| format!("Authorization: {}\r\n", value)).unwrap_or_default();
+            raw_request(port, &format!("GET /private/whoami.sh HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n", header))
+        };
+
+        let response = request(None);
+        assert!(response.starts_with("HTTP/1.1 401"), "unexpected response: {}", response);
+        assert!(response.contains("WWW-Authenticate: Basic realm=\"Staff Only\""), "unexpected response: {}", response);
+
+        let wrong = format!("Basic {}", base64_encode(b"alice:looking-glass"));
+        let response = request(Some(&wrong));
+        assert!(response.starts_with("HTTP/1.1 401"), "unexpected response: {}", response);
+        assert!(!response.contains("user="));
+
+        let correct = format!("Basic {}", base64_encode(b"alice:wonderland"));
+        let response = request(Some(&correct));
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("user=alice"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_cgi_process_limit_turns_away_excess_requests() {
+        let site = temp_site("cgi_limit");
+        fs::write(site.join("slow.sh"), "sleep 0.5\nprintf 'Content-Type: text/plain\\r\\n\\r\\ndone'\n").unwrap();
+        fs::write(site.join("stuck.sh"), "exec sleep 10\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_cgi_processes 2\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n        cgi_timeout 2\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let clients: Vec<_> = (0..6).map(|_| thread::spawn(move || {
+            raw_request(port, "GET /cgi/slow.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        })).collect();
+        let responses: Vec<String> = clients.into_iter().map(|client| client.join().unwrap()).collect();
+
+        let ok = responses.iter().filter(|response| response.starts_with("HTTP/1.1 200")).count();
+        let unavailable: Vec<_> = responses.iter().filter(|response| response.starts_with("HTTP/1.1 503")).collect();
+        assert!((1..=2).contains(&ok), "expected at most 2 scripts to run: {:?}", responses);
+        assert_eq!(ok + unavailable.len(), 6, "unexpected responses: {:?}", responses);
+        assert!(unavailable.iter().all(|response| response.contains("Retry-After: 1\r\n")));
+
+        // Slots held by scripts that time out are given back too
+        for _ in 0..2 {
+            let response = raw_request(port, "GET /cgi/stuck.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 503") && response.contains("CGI script timed out"), "unexpected response: {}", response);
+        }
+        let response = raw_request(port, "GET /cgi/slow.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "slots were not released: {}", response);
+    }
+
+    #[test]
+    fn test_cgi_process_limit_applies_per_server() {
+        let site = temp_site("cgi_limit_per_server");
+        fs::write(site.join("slow.sh"), "sleep 0.5\nprintf 'Content-Type: text/plain\\r\\n\\r\\ndone'\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name roomy.test\n    max_cgi_processes 8\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name tight.test\n    max_cgi_processes 1\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, site.display()
+        ));
+
+        let run = |host: &'static str| -> Vec<String> {
+            let clients: Vec<_> = (0..4).map(|_| thread::spawn(move || {
+                raw_request(port, &format!("GET /cgi/slow.sh HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", host))
+            })).collect();
+            clients.into_iter().map(|client| client.join().unwrap()).collect()
+        };
+
+        // The smaller limit holds even though another server allows more
+        let responses = run("tight.test");
+        let ok = responses.iter().filter(|response| response.starts_with("HTTP/1.1 200")).count();
+        assert_eq!(ok, 1, "expected one script to run: {:?}", responses);
+
+        let responses = run("roomy.test");
+        assert!(responses.iter().all(|response| response.starts_with("HTTP/1.1 200")), "unexpected responses: {:?}", responses);
+    }
+
+    #[test]
+    fn test_cgi_failures_answered_by_kind() {
+        let site = temp_site("cgi_failures");
+        fs::write(site.join("sleepy.sh"), "exec sleep 10\n").unwrap();
+        fs::write(site.join("crash.sh"), "echo 'about to fail' >&2\nexit 1\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n        cgi_timeout 1\n    }}\n\n    route /broken {{\n        methods GET\n        root {}\n        cgi /nonexistent/interpreter\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        // A script out of time is taken as passing overload
+        let response = raw_request(port, "GET /cgi/sleepy.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "unexpected response: {}", response);
+        assert!(response.contains("Retry-After: 1\r\n"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /cgi/crash.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway"), "unexpected response: {}", response);
+        assert!(!response.contains("Retry-After"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /broken/crash.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_cgi_interpreter_chosen_by_extension() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let site = temp_site("cgi_extensions");
+        let interpreter = site.join("fake-php");
+        fs::write(&interpreter, "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\n\\r\\nphp ran %s' \"${1##*/}\"\n").unwrap();
+        fs::set_permissions(&interpreter, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::create_dir_all(site.join("app")).unwrap();
+        fs::write(site.join("app/hello.py"), "printf 'Content-Type: text/plain\\r\\n\\r\\nsh ran hello.py'\n").unwrap();
+        fs::write(site.join("app/index.php"), "<?php echo 'never run by sh';\n").unwrap();
+        fs::write(site.join("app/style.css"), "body {}").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /app {{\n        methods GET\n        root {}/app\n        cgi .py sh\n        cgi .php {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), interpreter.display()
+        ));
+
+        let response = raw_request(port, "GET /app/hello.py HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("sh ran hello.py"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /app/index.php HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("php ran index.php"), "unexpected response: {}", response);
+
+        // A file with no interpreter is served as it is
+        let response = raw_request(port, "GET /app/style.css HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("Content-Type: text/css"), "unexpected response: {}", response);
+        assert!(response.ends_with("body {}"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_cgi_exec_runs_script_through_shebang() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let site = temp_site("cgi_exec");
+        fs::write(site.join("hello.cgi"), "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\n\\r\\nran as %s' \"${0##*/}\"\n").unwrap();
+        fs::set_permissions(site.join("hello.cgi"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::copy(site.join("hello.cgi"), site.join("plain.cgi")).unwrap();
+        fs::set_permissions(site.join("plain.cgi"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi .cgi exec\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /cgi/hello.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("ran as hello.cgi"), "unexpected response: {}", response);
+
+        // Without the execute bit the script is refused rather than read by some interpreter
+        let response = raw_request(port, "GET /cgi/plain.cgi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403"), "unexpected response: {}", response);
+        assert!(response.contains("CGI script is not executable"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_sighup_reloads_config_for_new_connections() {
+        let old_root = temp_site("reload_old");
+        let new_root = temp_site("reload_new");
+        fs::write(old_root.join("index.html"), "old site").unwrap();
+        fs::write(new_root.join("index.html"), "new site").unwrap();
+
+        let port = TcpListener::bind((TEST_HOST, 0)).and_then(|listener| listener.local_addr()).unwrap().port();
+        let config_for = |root: &PathBuf| format!(
+            "server {{\n    host {}\n    port {}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, port, root.display()
+        );
+        let config_path = old_root.with_extension("conf");
+        fs::write(&config_path, config_for(&old_root)).unwrap();
+
+        let mut server = Command::new(env!("CARGO_BIN_EXE_localhost-server"))
+            .arg(&config_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start server");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while TcpStream::connect((TEST_HOST, port)).is_err() {
+            assert!(Instant::now() < deadline, "server did not start");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let hangup = |server: &std::process::Child| unsafe {
+            libc::kill(server.id() as libc::pid_t, libc::SIGHUP);
+        };
+        let get = || raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+
+        // A connection opened before the reload keeps the old root
+        let mut kept = TcpStream::connect((TEST_HOST, port)).unwrap();
+        kept.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        kept.write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(read_response(&mut kept).ends_with("old site"));
+
+        fs::write(&config_path, config_for(&new_root)).unwrap();
+        hangup(&server);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !get().ends_with("new site") {
+            assert!(Instant::now() < deadline, "configuration was not reloaded");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        kept.write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(read_response(&mut kept).ends_with("old site"));
+
+        // A broken file leaves the running configuration in place
+        fs::write(&config_path, "server {\n    port not-a-port\n").unwrap();
+        hangup(&server);
+        thread::sleep(Duration::from_millis(200));
+        assert!(get().ends_with("new site"));
+
+        assert!(server.try_wait().unwrap().is_none(), "server exited");
+        server.kill().unwrap();
+        server.wait().unwrap();
+        let _ = fs::remove_file(&config_path);
+    }
+
+    #[test]
+    fn test_automatic_options_lists_route_methods() {
+        let site = temp_site("options");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /api {{\n        methods GET POST\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "OPTIONS /api HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(response.contains("Allow: GET, POST\r\n"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "DELETE /api HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"), "unexpected response: {}", response);
+        assert!(response.contains("Allow: GET, POST\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_options_asterisk_lists_server_methods() {
+        let site = temp_site("options_asterisk");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /api {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // The answer covers the whole server, not the methods of any one route
+        let response = raw_request(port, "OPTIONS * HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(response.contains("Allow: GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_unimplemented_method_gets_501() {
+        let site = temp_site("not_implemented");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET TRACE\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // Listed for the route, but the server has no handler for it
+        let response = raw_request(port, "TRACE / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 501"), "unexpected response: {}", response);
+
+        // Not listed for the route
+        let response = raw_request(port, "CONNECT / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"), "unexpected response: {}", response);
+        assert!(response.contains("Allow: GET, TRACE\r\n"), "unexpected response: {}", response);
+
+        // Not a method the server recognises at all
+        let response = raw_request(port, "BREW / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 501"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_connection_closed_after_max_requests() {
+        let site = temp_site("max_requests");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_requests_per_connection 2\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let request = "GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        stream.write_all(request.as_bytes()).unwrap();
+        let first = read_response(&mut stream);
+        assert!(first.contains("Connection: keep-alive"), "unexpected response: {}", first);
+
+        stream.write_all(request.as_bytes()).unwrap();
+        let second = read_response(&mut stream);
+        assert!(second.contains("Connection: close"), "unexpected response: {}", second);
+        assert!(second.ends_with("hello"));
+
+        // The server hangs up after the final response
+        let mut rest = Vec::new();
+        let _ = stream.read_to_end(&mut rest);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_max_requests_taken_from_serving_server() {
+        let site = temp_site("max_requests_servers");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name a.test\n    max_requests_per_connection 2\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name b.test\n    max_requests_per_connection 5\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, site.display()
+        ));
+
+        // Each connection gets the budget of the server it talks to, not the largest one
+        for (host, closes_after) in [("a.test", 2), ("b.test", 5)] {
+            let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let request = format!("GET /index.html HTTP/1.1\r\nHost: {}\r\n\r\n", host);
+            for count in 1..=closes_after {
+                stream.write_all(request.as_bytes()).unwrap();
+                let response = read_response(&mut stream);
+                let expected = if count == closes_after { "Connection: close" } else { "Connection: keep-alive" };
+                assert!(response.contains(expected), "request {} to {}: {}", count, host, response);
+            }
+
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_keep_alive_header_reports_timeout_and_budget() {
+        let site = temp_site("keep_alive_hint");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_requests_per_connection 3\n\n    route / {{\n        methods GET\n        root {}\n        request_timeout 45\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let request = "GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        // The route's timeout lasts for its request; between requests the default applies
+        stream.write_all(request.as_bytes()).unwrap();
+        let first = read_response(&mut stream);
+        assert!(first.contains("Keep-Alive: timeout=30, max=2\r\n"), "unexpected response: {}", first);
+
+        stream.write_all(request.as_bytes()).unwrap();
+        let second = read_response(&mut stream);
+        assert!(second.contains("Keep-Alive: timeout=30, max=1\r\n"), "unexpected response: {}", second);
+
+        // The last response closes the connection and carries no hint
+        stream.write_all(request.as_bytes()).unwrap();
+        let third = read_response(&mut stream);
+        assert!(third.contains("Connection: close\r\n"), "unexpected response: {}", third);
+        assert!(!third.contains("Keep-Alive:"), "unexpected response: {}", third);
+    }
+
+    #[test]
+    fn test_keep_alive_header_budget_from_serving_server() {
+        let site = temp_site("keep_alive_hint_servers");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name a.test\n    max_requests_per_connection 4\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name b.test\n    max_requests_per_connection 250\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, site.display()
+        ));
+
+        for (host, max) in [("a.test", 4), ("b.test", 250)] {
+            let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let request = format!("GET /index.html HTTP/1.1\r\nHost: {}\r\n\r\n", host);
+
+            stream.write_all(request.as_bytes()).unwrap();
+            let first = read_response(&mut stream);
+            let expected = format!("Keep-Alive: timeout=30, max={}\r\n", max - 1);
+            assert!(first.contains(&expected), "unexpected response from {}: {}", host, first);
+        }
+    }
+
+    #[test]
+    fn test_keep_alive_off_closes_every_connection() {
+        let site = temp_site("keep_alive_off");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    keep_alive off\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // raw_request reads until the server closes, so this returning at all proves the close
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("Connection: close\r\n"), "unexpected response: {}", response);
+        assert!(!response.contains("Keep-Alive:"), "unexpected response: {}", response);
+        assert!(response.ends_with("hello"));
+    }
+
+    #[test]
+    fn test_slow_headers_cut_off_after_header_timeout() {
+        let site = temp_site("header_timeout");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    header_timeout 1\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // Dribble one header byte at a time; activity must not extend the deadline
+        let started = Instant::now();
+        for byte in "GET / HTTP/1.1\r\nX-Slow: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".bytes() {
+            if stream.write_all(&[byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        let mut rest = Vec::new();
+        let closed = match stream.read_to_end(&mut rest) {
+            Ok(_) => true,
+            Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+        };
+        assert!(closed, "connection was not closed");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_header_timeout_of_listening_server() {
+        let site = temp_site("header_timeout_servers");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let patient_port = TcpListener::bind((TEST_HOST, 0)).unwrap().local_addr().unwrap().port();
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    header_timeout 1\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {}\n    header_timeout 30\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, patient_port, site.display()
+        ));
+
+        let mut hasty = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        hasty.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut patient = TcpStream::connect((TEST_HOST, patient_port)).expect("Failed to connect");
+        patient.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        hasty.write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+        patient.write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+
+        // The short deadline is not stretched by the other server's long one
+        let mut response = Vec::new();
+        let _ = hasty.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 408"), "unexpected response: {}", response);
+
+        // Nor does it cut short the server that allows more time
+        thread::sleep(Duration::from_millis(1500));
+        patient.write_all(b"Connection: close\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = patient.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_unfinished_request_gets_408() {
+        let site = temp_site("request_timeout");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    header_timeout 1\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // Headers are never terminated
+        let response = raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n");
+        assert!(response.starts_with("HTTP/1.1 408"), "unexpected response: {}", response);
+        assert!(response.contains("Connection: close\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_route_timeout_applies_while_body_is_read() {
+        let site = temp_site("route_timeout_body");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /upload {{\n        methods POST\n        root {}\n        request_timeout 1\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // The body stalls halfway; the route's deadline, not the default one, runs out
+        let started = Instant::now();
+        let response = raw_request(port, "POST /upload/file.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\nConnection: close\r\n\r\nhalf");
+        assert!(response.starts_with("HTTP/1.1 408"), "unexpected response: {}", response);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_route_timeout_ends_with_its_request() {
+        let site = temp_site("route_timeout_reset");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /fast {{\n        methods GET\n        root {}\n        request_timeout 1\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"GET /fast/index.html HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let first = read_response(&mut stream);
+        assert!(first.starts_with("HTTP/1.1 200"), "unexpected response: {}", first);
+
+        // Idling past the route's timeout does not close the connection
+        thread::sleep(Duration::from_millis(2500));
+        stream.write_all(b"GET /elsewhere HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let second = read_response(&mut stream);
+        assert!(second.starts_with("HTTP/1.1 404"), "unexpected response: {}", second);
+    }
+
+    #[test]
+    fn test_cgi_timeout_longer_than_request_timeout() {
+        let site = temp_site("cgi_timeout_long");
+        fs::write(site.join("slow.sh"), "sleep 2\nprintf 'Content-Type: text/plain\\r\\n\\r\\ndone'\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n        request_timeout 1\n        cgi_timeout 5\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // The client waits on the script, which has until its own timeout
+        let response = dechunk(raw_request(port, "GET /cgi/slow.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"));
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.ends_with("done"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_status_endpoint_reports_stats() {
+        let site = temp_site("status");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    status_endpoint /server-status\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        let response = raw_request(port, "GET /server-status HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("Content-Type: application/json"));
+
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        let field = |name: &str| -> f64 {
+            let start = body.find(&format!("\"{}\":", name)).unwrap_or_else(|| panic!("missing {}", name)) + name.len() + 3;
+            let end = body[start..].find([',', '}']).unwrap() + start;
+            body[start..end].parse().unwrap()
+        };
+        assert!(field("total_requests") >= 1.0);
+        assert!(field("bytes_transferred") > 0.0);
+        assert!(field("peak_connections") >= 1.0);
+        assert!(field("current_connections") >= 1.0);
+        assert_eq!(field("error_rate"), 0.0);
+        field("uptime_seconds");
+        field("error_count");
+    }
+
+    #[test]
+    fn test_status_endpoint_counts_connection_bytes() {
+        let site = temp_site("status_bytes");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    status_endpoint /server-status\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let first = "GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        stream.write_all(first.as_bytes()).unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+
+        // Statistics are taken before the status response itself is written
+        let second = "GET /server-status HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        stream.write_all(second.as_bytes()).unwrap();
+        let status = read_response(&mut stream);
+        let body = &status[status.find("\r\n\r\n").unwrap() + 4..];
+        let field = |name: &str| -> usize {
+            let start = body.find(&format!("\"{}\":", name)).unwrap_or_else(|| panic!("missing {}", name)) + name.len() + 3;
+            let end = body[start..].find([',', '}']).unwrap() + start;
+            body[start..end].parse().unwrap()
+        };
+        assert_eq!(field("connection_bytes_written"), response.len());
+        assert_eq!(field("connection_bytes_read"), first.len() + second.len());
+    }
+
+    #[test]
+    fn test_status_endpoint_prometheus_format() {
+        let site = temp_site("status_prometheus");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    status_endpoint /server-status\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+        raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+
+        for request in [
+            "GET /server-status?format=prometheus HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            "GET /server-status HTTP/1.1\r\nHost: localhost\r\nAccept: text/plain;version=0.0.4\r\nConnection: close\r\n\r\n",
+        ] {
+            let response = raw_request(port, request);
+            assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+            assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+
+            let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+            let mut samples = std::collections::HashMap::new();
+            for line in body.lines() {
+                if let Some(comment) = line.strip_prefix("# ") {
+                    assert!(comment.starts_with("HELP ") || comment.starts_with("TYPE "), "bad comment: {}", line);
+                    continue;
+                }
+                let (name, value) = line.rsplit_once(' ').unwrap_or_else(|| panic!("bad sample: {}", line));
+                let metric = name.split('{').next().unwrap();
+                assert!(metric.chars().all(|c| c.is_ascii_lowercase() || c == '_'), "bad metric name: {}", name);
+                let value: f64 = value.parse().unwrap_or_else(|_| panic!("bad value: {}", line));
+                samples.insert(name.to_string(), value);
+            }
+
+            assert!(samples["http_requests_total"] >= 1.0);
+            assert!(samples["http_bytes_total"] > 0.0);
+            assert_eq!(samples["http_errors_total"], 0.0);
+            assert!(samples["http_connections_current"] >= 1.0);
+            assert!(samples.contains_key("server_uptime_seconds"));
+            assert!(samples.contains_key("http_connections_state{state=\"reading\"}"));
+        }
+    }
+
+    #[test]
+    fn test_oversized_body_rejected_before_buffering() {
+        let site = temp_site("body_limit");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_body_size 1048576\n\n    route / {{\n        methods GET POST\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // The answer arrives after the headers alone, without the 5MB body
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let head = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", 5 * 1024 * 1024);
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(&vec![b'x'; 64 * 1024]).unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 413"), "unexpected response: {}", response);
+        assert!(response.contains("Connection: close\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_http10_response_version_and_connection() {
+        let site = temp_site("http10");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // HTTP/1.0 closes by default, so reading to the end terminates
+        let response = raw_request(port, "GET /index.html HTTP/1.0\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200"), "unexpected response: {}", response);
+        assert!(response.contains("Connection: close\r\n"), "unexpected response: {}", response);
+        assert!(response.ends_with("hello"));
+
+        // An explicit keep-alive keeps the connection open for another request
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let request = "GET /index.html HTTP/1.0\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n";
+        stream.write_all(request.as_bytes()).unwrap();
+        let first = read_response(&mut stream);
+        assert!(first.starts_with("HTTP/1.0 200"), "unexpected response: {}", first);
+        assert!(first.contains("Connection: keep-alive\r\n"), "unexpected response: {}", first);
+
+        stream.write_all(b"GET /index.html HTTP/1.0\r\nHost: localhost\r\n\r\n").unwrap();
+        let second = read_response(&mut stream);
+        assert!(second.starts_with("HTTP/1.0 200"), "unexpected response: {}", second);
+        assert!(second.contains("Connection: close\r\n"), "unexpected response: {}", second);
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let site = temp_site("expect_continue");
+        fs::write(site.join("echo.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\ncat\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    max_body_size 1024\n\n    route /cgi {{\n        methods POST\n        root {}\n        cgi sh\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // The server invites the body before the client sends it
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"POST /cgi/echo.sh HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n").unwrap();
+        let interim = read_response(&mut stream);
+        assert_eq!(interim, "HTTP/1.1 100 Continue\r\n\r\n");
+
+        stream.write_all(b"hello").unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        let response = dechunk(response);
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.ends_with("hello"));
+
+        // An oversized body is refused without being read
+        let response = raw_request(port, "POST /cgi/echo.sh HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4096\r\nExpect: 100-continue\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 413"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_expect_continue_refused_on_server_body_limit() {
+        let site = temp_site("expect_continue_limit");
+        fs::write(site.join("echo.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\ncat\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {host}\n    port {{port}}\n    server_name big.test\n\n    route /cgi {{\n        methods POST\n        root {root}\n        cgi sh\n    }}\n}}\n\n\
+             server {{\n    host {host}\n    port {{port}}\n    server_name small.test\n    max_body_size 1024\n\n    route /cgi {{\n        methods POST\n        root {root}\n        cgi sh\n    }}\n}}\n",
+            host = TEST_HOST, root = site.display()
+        ));
+
+        // The smaller limit of the addressed server decides, before the body is invited
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"POST /cgi/echo.sh HTTP/1.1\r\nHost: small.test\r\nContent-Length: 4096\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(response.starts_with("HTTP/1.1 413"), "unexpected response: {}", response);
+        assert!(!response.contains("100 Continue"), "unexpected response: {}", response);
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"POST /cgi/echo.sh HTTP/1.1\r\nHost: big.test\r\nContent-Length: 4096\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut stream), "HTTP/1.1 100 Continue\r\n\r\n");
+    }
+
+    #[test]
+    fn test_delete_answers_204_without_body() {
+        let site = temp_site("delete");
+        fs::write(site.join("old.txt"), "remove me").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /uploads {{\n        methods DELETE\n        root {}\n        upload_enabled on\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "DELETE /uploads/old.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"), "unexpected response: {}", response);
+        assert!(!response.contains("Content-Type"), "unexpected response: {}", response);
+        assert!(response.ends_with("\r\n\r\n"), "unexpected response: {}", response);
+        assert!(!site.join("old.txt").exists());
+    }
+
+    #[test]
+    fn test_traversal_outside_upload_root_is_forbidden() {
+        let site = temp_site("traversal");
+        let uploads = site.join("public").join("uploads");
+        fs::create_dir_all(&uploads).unwrap();
+        fs::write(site.join("secret"), "keep me").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /uploads {{\n        methods DELETE PUT\n        root {}\n        upload_enabled on\n    }}\n}}\n",
+            TEST_HOST, uploads.display()
+        ));
+
+        let response = raw_request(port, "DELETE /uploads/../../secret HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403"), "unexpected response: {}", response);
+        assert_eq!(fs::read_to_string(site.join("secret")).unwrap(), "keep me");
+
+        // The check also holds for targets that do not exist yet
+        let response = raw_request(port, "PUT /uploads/../../planted HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nConnection: close\r\n\r\nevil");
+        assert!(response.starts_with("HTTP/1.1 403"), "unexpected response: {}", response);
+        assert!(!site.join("planted").exists());
+    }
+
+    #[test]
+    fn test_access_log_common_log_format() {
+        let site = temp_site("access_log");
+        fs::write(site.join("index.html"), "hello").unwrap();
+        let log_path = site.join("access.log");
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    access_log {}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, log_path.display(), site.display()
+        ));
+
+        raw_request(port, "GET /index.html?v=1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        raw_request(port, "GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2, "unexpected log: {}", log);
+
+        // host ident user [dd/Mon/yyyy:hh:mm:ss +0000] "request line" status bytes
+        let (prefix, rest) = lines[0].split_once(" [").unwrap();
+        assert_eq!(prefix, format!("{} - -", TEST_HOST));
+        let (date, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(date.len(), "10/Oct/2000:13:55:36 +0000".len());
+        assert!(date.ends_with(" +0000"));
+        assert_eq!(rest, "\"GET /index.html?v=1 HTTP/1.1\" 200 5");
+
+        assert!(lines[1].contains("\"GET /missing HTTP/1.1\" 404 "), "unexpected log line: {}", lines[1]);
+    }
+
+    #[test]
+    fn test_pipelined_requests_in_one_write() {
+        let site = temp_site("pipelining");
+        fs::write(site.join("a.txt"), "first").unwrap();
+        fs::write(site.join("b.txt"), "second").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+        let first = read_response(&mut stream);
+        assert!(first.starts_with("HTTP/1.1 200"), "unexpected response: {}", first);
+        assert!(first.ends_with("first"));
+
+        let second = read_response(&mut stream);
+        assert!(second.starts_with("HTTP/1.1 200"), "unexpected response: {}", second);
+        assert!(second.ends_with("second"));
+    }
+
+    #[test]
+    fn test_cgi_output_streamed_as_it_is_produced() {
+        let site = temp_site("cgi_stream");
+        fs::write(site.join("ticker.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\necho one\nsleep 1\necho two\n").unwrap();
+        fs::write(site.join("sized.sh"), "printf 'Content-Type: text/plain\\r\\nContent-Length: 5\\r\\n\\r\\n'\nsleep 1\nprintf sized\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let started = Instant::now();
+        stream.write_all(b"GET /cgi/ticker.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+        // The first line arrives as its own chunk while the script is still sleeping
+        let mut received = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !String::from_utf8_lossy(&received).contains("4\r\none\n\r\n") {
+            let n = stream.read(&mut buf).expect("Failed to read first chunk");
+            assert!(n > 0, "connection closed early: {}", String::from_utf8_lossy(&received));
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert!(started.elapsed() < Duration::from_millis(900), "first chunk waited for the script to finish");
+
+        let _ = stream.read_to_end(&mut received);
+        let response = String::from_utf8_lossy(&received).to_string();
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!response.contains("Content-Length:"));
+        assert!(response.ends_with("4\r\ntwo\n\r\n0\r\n\r\n"), "unexpected response: {}", response);
+
+        // A script that gives its own length is sent unchunked
+        let response = raw_request(port, "GET /cgi/sized.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Length: 5\r\n"), "unexpected response: {}", response);
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("\r\n\r\nsized"));
+    }
+
+    #[test]
+    fn test_slow_reader_keeps_streamed_cgi_response_alive() {
+        let site = temp_site("cgi_slow_reader");
+        // Written in bursts under the CGI output limit, far faster than the client reads
+        let size = 48 * 256 * 1024;
+        fs::write(site.join("big.sh"), "printf 'Content-Type: text/plain\\r\\n\\r\\n'\n\
+            i=0\nwhile [ $i -lt 48 ]; do head -c 262144 /dev/zero | tr '\\0' x; sleep 0.02; i=$((i + 1)); done\n").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route /cgi {{\n        methods GET\n        root {}\n        cgi sh\n        request_timeout 1\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let started = Instant::now();
+        stream.write_all(b"GET /cgi/big.sh HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+        // The download outlasts request_timeout, but every write is progress
+        let received = read_slowly(&mut stream, 64 * 1024, Duration::from_millis(20));
+        let response = dechunk(String::from_utf8_lossy(&received).to_string());
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", &response[..response.len().min(200)]);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(body.len(), size);
+        assert!(started.elapsed() > Duration::from_secs(2), "download was not slow enough to test the timeout");
+    }
+
+    #[test]
+    fn test_rate_limit_returns_429() {
+        let site = temp_site("rate_limit");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    rate_limit 3 per 60\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        for _ in 0..3 {
+            let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        }
+
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests"), "unexpected response: {}", response);
+        assert!(response.contains("Retry-After: 20\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_rate_limit_buckets_kept_per_server() {
+        let site = temp_site("rate_limit_servers");
+        fs::write(site.join("index.html"), "hello").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name a.test\n    rate_limit 2 per 60\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name b.test\n    rate_limit 2 per 60\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, site.display()
+        ));
+
+        for _ in 0..2 {
+            let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: a.test\r\nConnection: close\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+        }
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: a.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 429"), "unexpected response: {}", response);
+
+        // The same limit on another server is a separate budget
+        let response = raw_request(port, "GET /index.html HTTP/1.1\r\nHost: b.test\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_head_reports_get_content_length() {
+        let site = temp_site("head");
+        fs::create_dir_all(site.join("static")).unwrap();
+        fs::write(site.join("static/test.txt"), "head and get agree").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET HEAD\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // Both on one connection: a HEAD response with a body would corrupt the next one
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"HEAD /static/test.txt HTTP/1.1\r\nHost: localhost\r\n\r\nGET /static/test.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+
+        let second = response.rfind("HTTP/1.1 200").filter(|&second| second > 0)
+            .expect("expected two responses");
+        let (head, get) = response.split_at(second);
+        assert!(head.contains("Content-Length: 18\r\n"), "unexpected HEAD response: {}", head);
+        assert!(head.contains("Content-Type: text/plain"));
+        assert!(head.ends_with("\r\n\r\n"));
+        assert!(get.contains("Content-Length: 18\r\n"), "unexpected GET response: {}", get);
+        assert!(get.ends_with("head and get agree"));
+    }
+
+    #[test]
+    fn test_configured_mime_types() {
+        let site = temp_site("mime_types");
+        fs::write(site.join("module.wasm"), "\0asm").unwrap();
+        fs::write(site.join("notes.txt"), "notes").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    mime_type wasm application/wasm\n    mime_type txt text/x-notes\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /module.wasm HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Type: application/wasm\r\n"), "unexpected response: {}", response);
+
+        // Built-in mappings can be replaced
+        let response = raw_request(port, "GET /notes.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Type: text/x-notes\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_mime_types_apply_to_their_own_server() {
+        let site = temp_site("mime_types_servers");
+        fs::write(site.join("notes.txt"), "notes").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    server_name notes.test\n    mime_type txt text/x-notes\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n\n\
+             server {{\n    host {}\n    port {{port}}\n    server_name plain.test\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), TEST_HOST, site.display()
+        ));
+
+        let response = raw_request(port, "GET /notes.txt HTTP/1.1\r\nHost: notes.test\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Type: text/x-notes\r\n"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /notes.txt HTTP/1.1\r\nHost: plain.test\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_default_charset_labels_text_files() {
+        let site = temp_site("default_charset");
+        fs::create_dir_all(site.join("ru")).unwrap();
+        fs::write(site.join("page.html"), b"<p>caf\xe9</p>").unwrap();
+        fs::write(site.join("ru/page.html"), "<p>page</p>").unwrap();
+        fs::write(site.join("image.png"), b"\x89PNG").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n    default_charset iso-8859-1\n\n    route /ru {{\n        methods GET\n        root {}/ru\n        charset koi8-r\n    }}\n\n    route / {{\n        methods GET\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display(), site.display()
+        ));
+
+        let response = raw_request(port, "GET /page.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Type: text/html; charset=iso-8859-1\r\n"), "unexpected response: {}", response);
+
+        // Binary types never carry a charset
+        let response = raw_request(port, "GET /image.png HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Type: image/png\r\n"), "unexpected response: {}", response);
+
+        let response = raw_request(port, "GET /ru/page.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("Content-Type: text/html; charset=koi8-r\r\n"), "unexpected response: {}", response);
+    }
+
+    #[test]
+    fn test_gzip_compression_negotiated() {
+        let site = temp_site("gzip");
+        let page = "<p>all work and no play makes jack a dull boy</p>\n".repeat(100);
+        fs::write(site.join("page.html"), &page).unwrap();
+        fs::write(site.join("tiny.txt"), "tiny").unwrap();
+
+        let port = start_in_process_server(&format!(
+            "server {{\n    host {}\n    port {{port}}\n\n    route / {{\n        methods GET HEAD\n        root {}\n    }}\n}}\n",
+            TEST_HOST, site.display()
+        ));
+
+        // The body is binary, so it is read as bytes
+        let mut stream = TcpStream::connect((TEST_HOST, port)).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"GET /page.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip, deflate\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        let split = response.windows(4).position(|window| window == b"\r\n\r\n").expect("no header end") + 4;
+        let head = String::from_utf8_lossy(&response[..split]).to_string();
+        let body = &response[split..];
+        assert!(head.starts_with("HTTP/1.1 200"), "unexpected response: {}", head);
+        assert!(head.contains("Content-Encoding: gzip\r\n"), "unexpected response: {}", head);
+        assert!(head.contains("Vary: Accept-Encoding\r\n"), "unexpected response: {}", head);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())), "unexpected response: {}", head);
+        assert!(body.len() < page.len());
+
+        let mut gunzip = Command::new("gzip")
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to run gzip");
+        gunzip.stdin.take().unwrap().write_all(body).unwrap();
+        let output = gunzip.wait_with_output().unwrap();
+        assert!(output.status.success(), "gzip could not decode the body");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), page);
+
+        // Refused or not asked for, the body is sent as it is
+        let response = raw_request(port, "GET /page.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip;q=0, identity\r\nConnection: close\r\n\r\n");
+        assert!(!response.contains("Content-Encoding"), "unexpected response: {}", response);
+        assert!(response.contains("Vary: Accept-Encoding\r\n"), "unexpected response: {}", response);
+        assert!(response.ends_with(&page));
+
+        // HEAD reports the length GET would have sent
+        let response = raw_request(port, "HEAD /page.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n");
+        assert!(response.contains(&format!("Content-Length: {}\r\n", body.len())), "unexpected response: {}", response);
 
-        server.kill().expect("Failed to kill server");
+        // Small bodies are not worth compressing
+        let response = raw_request(port, "GET /tiny.txt HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n");
+        assert!(!response.contains("Content-Encoding"), "unexpected response: {}", response);
+        assert!(response.ends_with("tiny"));
     }
 }
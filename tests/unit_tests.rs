@@ -6,7 +6,6 @@
 mod config_tests {
     use localhost_http_server::config::*;
     use std::fs;
-    use std::path::Path;
 
     #[test]
     fn test_config_parsing() {
@@ -42,7 +41,7 @@ server {
         assert_eq!(config.servers.len(), 1);
         
         let server = &config.servers[0];
-        assert_eq!(server.server_name, Some("localhost".to_string()));
+        assert_eq!(server.server_names, vec!["localhost".to_string()]);
         assert_eq!(server.ports, vec![8080]);
         assert_eq!(server.max_body_size, 1048576);
         assert_eq!(server.routes.len(), 2);
@@ -52,15 +51,15 @@ server {
         assert_eq!(route1.path, "/");
         assert_eq!(route1.methods, vec!["GET", "POST"]);
         assert_eq!(route1.root, Some("www".to_string()));
-        assert_eq!(route1.index, Some("index.html".to_string()));
-        assert_eq!(route1.directory_listing, true);
+        assert_eq!(route1.index, vec!["index.html".to_string()]);
+        assert!(route1.directory_listing);
         
         // Verify second route
         let route2 = &server.routes[1];
         assert_eq!(route2.path, "/api");
         assert_eq!(route2.methods, vec!["GET", "POST", "PUT", "DELETE"]);
         assert_eq!(route2.root, Some("api".to_string()));
-        assert_eq!(route2.directory_listing, false);
+        assert!(!route2.directory_listing);
         
         // Cleanup
         fs::remove_file(test_config_path).ok();
@@ -83,18 +82,435 @@ server {
         // Cleanup
         fs::remove_file(test_config_path).ok();
     }
+
+    #[test]
+    fn test_route_timeouts() {
+        let config_content = r#"
+server {
+    listen 8080
+
+    route /upload {
+        methods POST
+        root uploads
+        request_timeout 60
+        cgi_timeout 10
+    }
+
+    route / {
+        methods GET
+        root www
+    }
+}
+"#;
+
+        let test_config_path = "route_timeouts.conf";
+        fs::write(test_config_path, config_content).expect("Failed to write test config");
+        let config = Config::from_file(test_config_path).expect("Failed to parse config");
+        fs::remove_file(test_config_path).ok();
+
+        let routes = &config.servers[0].routes;
+        assert_eq!(routes[0].request_timeout, 60);
+        assert_eq!(routes[0].cgi_timeout, 10);
+
+        // Unset timeouts keep the defaults
+        assert_eq!(routes[1].request_timeout, localhost_http_server::defaults::DEFAULT_TIMEOUT);
+        assert_eq!(routes[1].cgi_timeout, localhost_http_server::defaults::DEFAULT_TIMEOUT);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_misplaced_route_wildcard_rejected() {
+        let config_content = "server {\n    listen 8080\n    route /files/*/raw {\n        methods GET\n    }\n}\n";
+
+        let test_config_path = "misplaced_wildcard.conf";
+        fs::write(test_config_path, config_content).expect("Failed to write test config");
+        let config = Config::from_file(test_config_path).expect("Failed to parse config");
+        fs::remove_file(test_config_path).ok();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_route_path_without_slash_rejected() {
+        let config = parse_config("server {\n    listen 8080\n    route api {\n        methods GET\n    }\n}\n")
+            .expect("Failed to parse config");
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("api") && error.contains("127.0.0.1:8080"), "unexpected error: {}", error);
+    }
+
+    #[test]
+    fn test_duplicate_route_path_rejected() {
+        // Paths are compared after runs of slashes are collapsed
+        let config = parse_config("server {\n    listen 8080\n    server_name example.com\n    route /api//v1 {\n        methods GET\n    }\n    route /api/v1 {\n        methods POST\n    }\n}\n")
+            .expect("Failed to parse config");
+        assert_eq!(config.servers[0].routes[0].path, "/api/v1");
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("Duplicate route /api/v1 in server example.com"), "unexpected error: {}", error);
+
+        // The same path in different servers is fine
+        let config = parse_config("server {\n    listen 8080\n    route /api {\n        methods GET\n    }\n}\nserver {\n    listen 8081\n    route /api {\n        methods GET\n    }\n}\n")
+            .expect("Failed to parse config");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_zero_route_timeout_rejected() {
+        let config_content = "server {\n    listen 8080\n    route / {\n        cgi_timeout 0\n    }\n}\n";
+
+        let test_config_path = "zero_timeout.conf";
+        fs::write(test_config_path, config_content).expect("Failed to write test config");
+        let config = Config::from_file(test_config_path).expect("Failed to parse config");
+        fs::remove_file(test_config_path).ok();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_listen_backlog_and_reuse_port() {
+        use localhost_http_server::server::socket::ListenOptions;
+
+        let config_content = "server {\n    listen 8080\n    listen_backlog 512\n    reuse_port on\n    route / {\n        methods GET\n    }\n}\n";
+
+        let test_config_path = "listen_backlog.conf";
+        fs::write(test_config_path, config_content).expect("Failed to write test config");
+        let config = Config::from_file(test_config_path).expect("Failed to parse config");
+        fs::remove_file(test_config_path).ok();
+
+        assert!(config.validate().is_ok());
+        let options = ListenOptions::from_config(&config.servers[0]);
+        assert_eq!(options.backlog, 512);
+        assert!(options.reuse_port);
+        assert!(options.nodelay);
+
+        let config = parse_config("server {\n    listen 8080\n    tcp_nodelay off\n}\n").expect("Failed to parse config");
+        assert!(!ListenOptions::from_config(&config.servers[0]).nodelay);
+
+        let mut config = config;
+        config.servers[0].listen_backlog = 70000;
+        assert!(config.validate().is_err());
+        config.servers[0].listen_backlog = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_redirect_status_codes() {
+        let config = parse_config("server {\n    listen 8080\n    route /old {\n        redirect 301 /new\n    }\n    route /api {\n        redirect 308 /api/v2\n    }\n    route /tmp {\n        redirect /elsewhere\n    }\n}\n")
+            .expect("Failed to parse config");
+        let redirects: Vec<_> = config.servers[0].routes.iter().map(|route| route.redirect.clone()).collect();
+        assert_eq!(redirects, vec![
+            Some((301, "/new".to_string())),
+            Some((308, "/api/v2".to_string())),
+            Some((302, "/elsewhere".to_string())),
+        ]);
+
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        redirect 404 /new\n    }\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        redirect soon /new\n    }\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_max_connections_directive() {
+        let config = parse_config("server {\n    listen 8080\n    max_connections 2048\n}\n").expect("Failed to parse config");
+        assert_eq!(config.servers[0].max_connections, 2048);
+        assert!(config.validate().is_ok());
+
+        let config = parse_config("server {\n    listen 8080\n}\n").expect("Failed to parse config");
+        assert_eq!(config.servers[0].max_connections, 1024);
+
+        let config = parse_config("server {\n    listen 8080\n    max_connections 0\n}\n").expect("Failed to parse config");
+        assert!(config.validate().is_err());
+        assert!(parse_config("server {\n    listen 8080\n    max_connections many\n}\n").is_err());
+
+        // The limit is process-wide, so later server blocks may not set their own
+        let config = parse_config("server {\n    listen 8080\n    max_connections 2048\n}\n\nserver {\n    listen 8081\n}\n").expect("Failed to parse config");
+        assert_eq!(config.servers[0].max_connections, 2048);
+        assert!(parse_config("server {\n    listen 8080\n}\n\nserver {\n    listen 8081\n    max_connections 2048\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_trusted_proxy_directive() {
+        let config = parse_config("server {\n    listen 8080\n    trusted_proxy 127.0.0.1\n    trusted_proxy 10.0.0.0/8 fd00::/8\n    route / {\n        methods GET\n    }\n}\n")
+            .expect("Failed to parse config");
+        let trusted: Vec<String> = config.servers[0].trusted_proxies.iter().map(|network| network.to_string()).collect();
+        assert_eq!(trusted, vec!["127.0.0.1/32", "10.0.0.0/8", "fd00::/8"]);
+
+        assert!(parse_config("server {\n    listen 8080\n    trusted_proxy 10.0.0.0/40\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    trusted_proxy proxy.local\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_rate_limit_directive() {
+        let config = parse_config("server {\n    listen 8080\n    rate_limit 100 per 60\n}\n")
+            .expect("Failed to parse config");
+        assert_eq!(config.servers[0].rate_limit, Some(RateLimit { requests: 100, period: 60 }));
+        assert_eq!(parse_config("server {\n    listen 8080\n}\n").unwrap().servers[0].rate_limit, None);
+
+        assert!(parse_config("server {\n    listen 8080\n    rate_limit 100\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    rate_limit 100 every 60\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    rate_limit 0 per 60\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_mime_type_directive() {
+        use localhost_http_server::utils::MimeDetector;
+
+        let config = parse_config("server {\n    listen 8080\n    mime_type wasm application/wasm\n    mime_type .TXT text/plain; charset=latin1\n}\n")
+            .expect("Failed to parse config");
+        let mime_types = &config.servers[0].mime_types;
+        assert_eq!(mime_types.get("wasm"), Some(&"application/wasm".to_string()));
+        assert_eq!(mime_types.get("txt"), Some(&"text/plain; charset=latin1".to_string()));
+
+        let mut detector = MimeDetector::new();
+        for (extension, mime_type) in mime_types {
+            detector.add_mapping(extension, mime_type);
+        }
+        assert_eq!(detector.detect_from_filename("module.wasm"), "application/wasm");
+        assert_eq!(detector.detect_from_filename("notes.txt"), "text/plain; charset=latin1");
+
+        assert!(parse_config("server {\n    listen 8080\n    mime_type wasm\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    mime_type wasm wasm\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_ssl_directives() {
+        let config = parse_config("server {\n    listen 8443\n    ssl on\n    ssl_cert /etc/cert.pem\n    ssl_key /etc/key.pem\n}\n")
+            .expect("Failed to parse config");
+        assert!(config.servers[0].ssl);
+        assert_eq!(config.servers[0].ssl_cert.as_deref(), Some("/etc/cert.pem"));
+        assert_eq!(config.servers[0].ssl_key.as_deref(), Some("/etc/key.pem"));
+        assert!(config.validate().is_ok());
+
+        // A certificate alone is not enough
+        let config = parse_config("server {\n    listen 8443\n    ssl on\n    ssl_cert /etc/cert.pem\n}\n")
+            .expect("Failed to parse config");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_servers_sharing_a_port() {
+        // A second server on the same port is reached by name
+        let named = "server {\n    listen 8443\n    server_name a.test\n}\nserver {\n    listen 8443\n    server_name b.test\n}\n";
+        assert!(parse_config(named).expect("Failed to parse config").validate().is_ok());
+
+        let unnamed = "server {\n    listen 8443\n    server_name a.test\n}\nserver {\n    listen 8443\n}\n";
+        let error = parse_config(unnamed).expect("Failed to parse config").validate().unwrap_err().to_string();
+        assert!(error.contains("Duplicate port 8443"), "unexpected error: {}", error);
+
+        let mixed = "server {\n    listen 8443\n    ssl on\n    ssl_cert a.pem\n    ssl_key a.key\n}\nserver {\n    listen 8443\n    server_name b.test\n}\n";
+        assert!(parse_config(mixed).expect("Failed to parse config").validate().is_err());
+    }
+
+    #[test]
+    fn test_charset_directives() {
+        let config = parse_config("server {\n    listen 8080\n    default_charset ISO-8859-1\n    route / {\n        charset koi8-r\n    }\n}\n")
+            .expect("Failed to parse config");
+        assert_eq!(config.servers[0].default_charset, "iso-8859-1");
+        assert_eq!(config.servers[0].routes[0].charset.as_deref(), Some("koi8-r"));
+
+        let config = parse_config("server {\n    listen 8080\n}\n").expect("Failed to parse config");
+        assert_eq!(config.servers[0].default_charset, "utf-8");
+        assert_eq!(config.servers[0].routes[0].charset, None);
+
+        assert!(parse_config("server {\n    listen 8080\n    default_charset utf-8;x=1\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_error_pages_root() {
+        use localhost_http_server::error::pages::ErrorPageManager;
+        use localhost_http_server::error::HttpStatus;
+
+        let dir = std::env::temp_dir().join(format!("localhost_error_pages_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("500.html"), "<h1>Our fault</h1>").unwrap();
+        fs::write(dir.join("404.html"), "<h1>From the directory</h1>").unwrap();
+        fs::write(dir.join("missing.html"), "<h1>Explicit page</h1>").unwrap();
+
+        let config = parse_config(&format!(
+            "server {{\n    listen 8080\n    error_pages_root {}\n    error_page 404 {}\n}}\n",
+            dir.display(), dir.join("missing.html").display()
+        )).expect("Failed to parse config");
+        let manager = ErrorPageManager::from_config(&config.servers[0]);
+
+        let response = manager.generate_error_response(HttpStatus::InternalServerError, None);
+        assert_eq!(response.status, HttpStatus::InternalServerError);
+        assert_eq!(response.body, b"<h1>Our fault</h1>");
+
+        // An explicit error_page wins over the directory
+        let response = manager.generate_error_response(HttpStatus::NotFound, None);
+        assert_eq!(response.body, b"<h1>Explicit page</h1>");
+
+        // Statuses without a page in the directory use the built-in template
+        let response = manager.generate_error_response(HttpStatus::Forbidden, None);
+        assert!(String::from_utf8_lossy(&response.body).contains("403"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_route_header_directive() {
+        let config = parse_config("server {\n    listen 8080\n    route / {\n        header X-Frame-Options SAMEORIGIN\n        header Content-Security-Policy default-src 'self'\n    }\n}\n")
+            .expect("Failed to parse config");
+        assert_eq!(config.servers[0].routes[0].headers, vec![
+            ("X-Frame-Options".to_string(), "SAMEORIGIN".to_string()),
+            ("Content-Security-Policy".to_string(), "default-src 'self'".to_string()),
+        ]);
+
+        // Framing headers stay under the server's control
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        header content-length 0\n    }\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        header X-Empty\n    }\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        header Bad:Name x\n    }\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_cors_directives() {
+        let config = parse_config("server {\n    listen 8080\n    route / {\n        cors_allow_origin *\n        cors_allow_methods get post\n        cors_allow_headers Content-Type X-Token\n        cors_allow_credentials on\n    }\n}\n")
+            .expect("Failed to parse config");
+        let cors = &config.servers[0].routes[0].cors;
+        assert!(cors.is_enabled());
+        assert!(cors.allows_origin("https://anything.example"));
+        assert_eq!(cors.allow_methods, vec!["GET", "POST"]);
+        assert!(cors.allows_header("x-token"));
+        assert!(!cors.allows_header("Authorization"));
+        assert!(cors.allow_credentials);
+
+        assert!(!RouteConfig::default().cors.is_enabled());
+    }
+
+    #[test]
+    fn test_allow_deny_directives() {
+        let config = parse_config("server {\n    listen 8080\n    route /admin {\n        allow 127.0.0.1 10.0.0.0/8\n        deny 10.1.0.0/16\n        deny all\n    }\n}\n")
+            .expect("Failed to parse config");
+        let rules = &config.servers[0].routes[0].access_rules;
+        assert_eq!(rules.len(), 4);
+
+        // The first matching rule decides, so 10.1.2.3 is allowed by the /8
+        assert!(is_access_allowed(rules, Some("127.0.0.1".parse().unwrap())));
+        assert!(is_access_allowed(rules, Some("10.1.2.3".parse().unwrap())));
+        assert!(!is_access_allowed(rules, Some("192.168.1.1".parse().unwrap())));
+        assert!(!is_access_allowed(rules, Some("::1".parse().unwrap())));
+        assert!(!is_access_allowed(rules, None));
+        assert!(is_access_allowed(&[], Some("192.168.1.1".parse().unwrap())));
+
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        allow localhost\n    }\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        deny\n    }\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_auth_basic_directives() {
+        let config = parse_config("server {\n    listen 8080\n    route /admin {\n        auth_basic \"Staff Only\"\n        auth_user_file /etc/site.users\n    }\n}\n")
+            .expect("Failed to parse config");
+        let route = &config.servers[0].routes[0];
+        assert_eq!(route.auth_basic.as_deref(), Some("Staff Only"));
+        assert_eq!(route.auth_user_file.as_deref(), Some("/etc/site.users"));
+
+        // A realm needs a file to check credentials against
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        auth_basic Admin\n    }\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        auth_basic \"Bad\"Realm\"\n        auth_user_file u\n    }\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_max_cgi_processes_directive() {
+        let config = parse_config("server {\n    listen 8080\n    max_cgi_processes 4\n    route / {\n    }\n}\n")
+            .expect("Failed to parse config");
+        assert_eq!(config.servers[0].max_cgi_processes, 4);
+        assert_eq!(ServerConfig::default().max_cgi_processes, 16);
+
+        assert!(parse_config("server {\n    listen 8080\n    max_cgi_processes 0\n}\n").is_err());
+        assert!(parse_config("server {\n    listen 8080\n    max_cgi_processes many\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_cgi_interpreters_by_extension() {
+        let config = parse_config("server {\n    listen 8080\n    route /cgi {\n        cgi .py /usr/bin/python3\n        cgi .php /usr/bin/php-cgi\n    }\n    route /legacy {\n        cgi ./bin/python3\n        cgi .sh sh\n    }\n}\n")
+            .expect("Failed to parse config");
+        let mapped = &config.servers[0].routes[0];
+        assert_eq!(mapped.cgi, None);
+        assert_eq!(mapped.cgi_interpreter("/cgi/app.py"), Some("/usr/bin/python3"));
+        assert_eq!(mapped.cgi_interpreter("/cgi/index.php"), Some("/usr/bin/php-cgi"));
+        assert_eq!(mapped.cgi_interpreter("/cgi/style.css"), None);
+        assert_eq!(mapped.cgi_interpreter("/cgi/README"), None);
+
+        // Extensions without a mapping fall back to the route-wide interpreter
+        let legacy = &config.servers[0].routes[1];
+        assert_eq!(legacy.cgi_interpreter("/legacy/run.sh"), Some("sh"));
+        assert_eq!(legacy.cgi_interpreter("/legacy/app.py"), Some("./bin/python3"));
+
+        assert!(parse_config("server {\n    listen 8080\n    route / {\n        cgi .py\n    }\n}\n").is_err());
+    }
+
+    #[test]
+    fn test_include_directives() {
+        let dir = std::env::temp_dir().join(format!("localhost_include_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sites")).unwrap();
+
+        fs::write(dir.join("main.conf"), "include sites/*.conf\n").unwrap();
+        fs::write(dir.join("sites/app.conf"), "server {\n    listen 8080\n    route / {\n        methods GET\n    }\n    include ../extra_routes.inc\n}\n").unwrap();
+        fs::write(dir.join("sites/notes.txt"), "not a config").unwrap();
+        fs::write(dir.join("extra_routes.inc"), "route /api {\n    methods GET POST\n}\n").unwrap();
+
+        let config = Config::from_file(dir.join("main.conf")).expect("Failed to parse config");
+        assert_eq!(config.servers.len(), 1);
+        let paths: Vec<&str> = config.servers[0].routes.iter().map(|route| route.path.as_str()).collect();
+        assert_eq!(paths, vec!["/", "/api"]);
+
+        // A file that includes itself is rejected rather than recursing forever
+        fs::write(dir.join("loop.conf"), "include loop.conf\n").unwrap();
+        assert!(Config::from_file(dir.join("loop.conf")).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_route_roots_resolved_once() {
+        let dir = std::env::temp_dir().join(format!("localhost_roots_{}", std::process::id()));
+        fs::create_dir_all(dir.join("site")).unwrap();
+
+        let mut config = parse_config(&format!(
+            "server {{\n    listen 8080\n    route / {{\n        root {}/./site\n    }}\n    route /files {{\n        root {}/site\n    }}\n}}\n",
+            dir.display(), dir.display()
+        )).expect("Failed to parse config");
+        config.resolve_roots().expect("Roots should resolve");
+        let canonical = dir.join("site").canonicalize().unwrap();
+        assert!(config.servers[0].routes.iter().all(|route| route.canonical_root.as_ref() == Some(&canonical)));
+
+        // Redirect routes serve no files, so their root need not exist
+        let mut config = parse_config(&format!(
+            "server {{\n    listen 8080\n    route /old {{\n        redirect /\n        root {}/nonexistent\n    }}\n}}\n", dir.display()
+        )).expect("Failed to parse config");
+        config.resolve_roots().expect("Redirect route roots should be skipped");
+        assert!(config.servers[0].routes[0].canonical_root.is_none());
+
+        // A missing root is reported at startup rather than answering 404 to everything
+        let mut config = parse_config(&format!(
+            "server {{\n    listen 8080\n    route / {{\n        root {}/nonexistent\n    }}\n}}\n", dir.display()
+        )).expect("Failed to parse config");
+        let error = config.resolve_roots().unwrap_err().to_string();
+        assert!(error.contains("nonexistent"), "unexpected error: {}", error);
+
+        // So is a root that is a file
+        fs::write(dir.join("file"), "").unwrap();
+        let mut config = parse_config(&format!(
+            "server {{\n    listen 8080\n    route / {{\n        root {}/file\n    }}\n}}\n", dir.display()
+        )).expect("Failed to parse config");
+        assert!(config.resolve_roots().is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
 }
 
 #[cfg(test)]
 mod http_tests {
     use localhost_http_server::http::*;
+    use localhost_http_server::http::request::DEFAULT_MAX_HEADER_COUNT;
+    use localhost_http_server::error::{HttpErrorKind, HttpStatus, ServerError};
 
     #[test]
     fn test_http_request_parsing() {
         let request_data = b"GET /test?param=value HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test\r\n\r\n";
         
         let mut parser = HttpRequestParser::new();
-        let request = parser.parse(request_data).expect("Failed to parse request").expect("Request not complete");
+        let request = parser.parse(request_data).expect("Failed to parse request").0.expect("Request not complete");
         
         assert_eq!(request.method, HttpMethod::GET);
         assert_eq!(request.path, "/test");
@@ -105,6 +521,28 @@ mod http_tests {
         assert_eq!(request.query_params.get("param"), Some(&"value".to_string()));
     }
 
+    #[test]
+    fn test_connection_header_tokens() {
+        let parse = |raw: &str| {
+            let mut parser = HttpRequestParser::new();
+            parser.parse(raw.as_bytes()).unwrap().0.expect("Incomplete request")
+        };
+
+        let request = parse("GET / HTTP/1.0\r\nConnection: keep-alive, Foo\r\n\r\n");
+        assert_eq!(request.connection_tokens(), vec!["keep-alive", "foo"]);
+        assert!(request.keep_alive());
+
+        let request = parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: close, X-Custom\r\n\r\n");
+        assert!(!request.keep_alive());
+        let request = parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade\r\nConnection: CLOSE\r\n\r\n");
+        assert!(!request.keep_alive());
+
+        // Unrelated tokens keep each version's default
+        assert!(parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive, Upgrade\r\n\r\n").keep_alive());
+        assert!(!parse("GET / HTTP/1.0\r\nConnection: Foo\r\n\r\n").keep_alive());
+        assert!(parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: ,\r\n\r\n").connection_tokens().is_empty());
+    }
+
     #[test]
     fn test_http_response_generation() {
         let mut response = HttpResponse::new(HttpStatus::Ok);
@@ -120,6 +558,147 @@ mod http_tests {
         assert!(response_str.contains("Hello, World!"));
     }
 
+    #[test]
+    fn test_finalize_restores_cleared_content_length() {
+        let mut response = HttpResponse::text(HttpStatus::Ok, "Hello");
+        response.remove_header("Content-Length");
+        assert!(!response.is_framed());
+
+        response.finalize();
+        assert!(response.is_framed());
+
+        let response_str = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(response_str.contains("Content-Length: 5\r\n"));
+        assert!(response_str.ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    fn test_finalize_chunked_framing() {
+        let mut response = HttpResponse::text(HttpStatus::Ok, "Hello");
+        response.add_header("Transfer-Encoding", "chunked");
+        response.finalize();
+
+        assert!(response.get_header("content-length").is_none());
+        let response_str = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(response_str.ends_with("\r\n\r\n5\r\nHello\r\n0\r\n\r\n"));
+
+        // HTTP/1.0 falls back to Content-Length
+        let mut response = HttpResponse::text(HttpStatus::Ok, "Hello");
+        response.version = HttpVersion::Http10;
+        response.add_header("Transfer-Encoding", "chunked");
+        response.finalize();
+        assert!(!response.is_chunked());
+        assert_eq!(response.get_header("Content-Length"), Some(&"5".to_string()));
+    }
+
+    #[test]
+    fn test_finalize_no_content_has_no_body() {
+        let mut response = HttpResponse::text(HttpStatus::NoContent, "ignored");
+        response.finalize();
+
+        assert!(response.body.is_empty());
+        assert!(response.get_header("Content-Length").is_none());
+        assert!(response.is_framed());
+    }
+
+    #[test]
+    fn test_no_content_response() {
+        let mut response = HttpResponse::no_content();
+        response.finalize();
+
+        assert_eq!(response.status, HttpStatus::NoContent);
+        assert!(response.get_header("Content-Type").is_none());
+        assert!(response.body.is_empty());
+        let response_str = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(response_str.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response_str.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_to_bytes_omits_body_for_bodyless_statuses() {
+        for status in [HttpStatus::Continue, HttpStatus::NoContent, HttpStatus::NotModified] {
+            // Even without finalize, a body set by mistake is never sent
+            let response = HttpResponse::text(status, "stray body");
+            let response_str = String::from_utf8(response.to_bytes()).unwrap();
+            assert!(response_str.ends_with("\r\n\r\n"), "body sent for {:?}", status);
+        }
+    }
+
+    #[test]
+    fn test_strip_body_keeps_content_length() {
+        let mut response = HttpResponse::text(HttpStatus::Ok, "Hello");
+        response.strip_body();
+        response.strip_body();
+        response.finalize();
+
+        assert!(response.is_framed());
+        let response_str = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(response_str.contains("Content-Length: 5\r\n"));
+        assert!(response_str.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_response_headers_keep_order() {
+        let mut response = HttpResponse::text(HttpStatus::Ok, "Hello");
+        response.add_header("X-First", "1");
+        response.add_header("X-Second", "2");
+        response.add_header("content-type", "text/html");
+        response.add_header("Set-Cookie", "a=1");
+        response.add_header("Set-Cookie", "b=2");
+        response.finalize();
+
+        let bytes = response.to_bytes();
+        assert_eq!(bytes, response.to_bytes());
+
+        let response_str = String::from_utf8(bytes).unwrap();
+        let names: Vec<&str> = response_str.lines()
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split(':').next())
+            .collect();
+        assert_eq!(names, vec!["Server", "Date", "content-type", "X-First", "X-Second", "Set-Cookie", "Set-Cookie", "Content-Length"]);
+        assert!(response_str.contains("content-type: text/html\r\n"));
+        assert!(response_str.contains("Set-Cookie: a=1\r\n"));
+        assert!(response_str.contains("Set-Cookie: b=2\r\n"));
+    }
+
+    #[test]
+    fn test_wire_len_matches_serialized_response() {
+        let mut response = HttpResponse::text(HttpStatus::Ok, "Hello, World!");
+        response.finalize();
+        assert_eq!(response.wire_len(), response.to_bytes().len());
+
+        let mut chunked = HttpResponse::text(HttpStatus::Ok, "streamed body");
+        chunked.add_header("Transfer-Encoding", "chunked");
+        chunked.finalize();
+        assert_eq!(chunked.wire_len(), chunked.to_bytes().len());
+
+        let mut head = HttpResponse::text(HttpStatus::Ok, "Hello, World!");
+        head.strip_body();
+        head.finalize();
+        assert_eq!(head.wire_len(), head.to_bytes().len());
+    }
+
+    #[test]
+    fn test_multiple_set_cookie_lines() {
+        use localhost_http_server::session::{Cookie, CookieJar};
+
+        let mut response = HttpResponse::text(HttpStatus::Ok, "Hello");
+        response.add_cookie(Cookie::new("session".to_string(), "abc123".to_string()));
+        response.add_cookie(Cookie::new("flash".to_string(), "saved".to_string()));
+
+        let mut jar = CookieJar::new();
+        jar.add(Cookie::new("theme".to_string(), "dark".to_string()));
+        response.add_cookie_jar(&jar);
+        assert_eq!(response.cookies().len(), 3);
+
+        let response_str = String::from_utf8(response.to_bytes()).unwrap();
+        assert_eq!(response_str.matches("Set-Cookie: ").count(), 3);
+        assert!(response_str.contains("Set-Cookie: session=abc123"));
+        assert!(response_str.contains("Set-Cookie: flash=saved"));
+        assert!(response_str.contains("Set-Cookie: theme=dark"));
+    }
+
     #[test]
     fn test_http_status_codes() {
         assert_eq!(HttpStatus::Ok.as_u16(), 200);
@@ -137,9 +716,46 @@ mod http_tests {
         assert_eq!(HttpMethod::POST.as_str(), "POST");
         assert_eq!(HttpMethod::DELETE.as_str(), "DELETE");
         
-        assert_eq!(HttpMethod::from_str("GET"), Ok(HttpMethod::GET));
-        assert_eq!(HttpMethod::from_str("POST"), Ok(HttpMethod::POST));
-        assert!(HttpMethod::from_str("INVALID").is_err());
+        assert_eq!(HttpMethod::from_str("GET"), Some(HttpMethod::GET));
+        assert_eq!(HttpMethod::from_str("POST"), Some(HttpMethod::POST));
+        assert!(HttpMethod::from_str("INVALID").is_none());
+    }
+
+    #[test]
+    fn test_absolute_form_target() {
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(b"GET http://example.com:8080/docs/page?x=1 HTTP/1.1\r\nHost: other.example\r\n\r\n")
+            .expect("Failed to parse request").0.expect("Request not complete");
+
+        assert_eq!(request.path, "/docs/page");
+        assert_eq!(request.uri, "/docs/page?x=1");
+        assert_eq!(request.query_params.get("x"), Some(&"1".to_string()));
+        assert_eq!(request.authority.as_deref(), Some("example.com:8080"));
+        // The target's authority wins over the Host header
+        assert_eq!(request.get_header_all("host"), vec!["example.com:8080"]);
+
+        // An empty path is the root, and a Host header may be left out
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(b"GET HTTP://example.com HTTP/1.1\r\nAccept: */*\r\n\r\n")
+            .expect("Failed to parse request").0.expect("Request not complete");
+        assert_eq!(request.path, "/");
+        assert_eq!(request.get_header("host"), Some(&"example.com".to_string()));
+
+        let mut parser = HttpRequestParser::new();
+        assert!(parser.parse(b"GET http://user@example.com/ HTTP/1.1\r\nAccept: */*\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_asterisk_form_target() {
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .expect("Failed to parse request").0.expect("Request not complete");
+        assert_eq!(request.method, HttpMethod::OPTIONS);
+        assert_eq!(request.path, "*");
+        assert!(request.authority.is_none());
+
+        let mut parser = HttpRequestParser::new();
+        assert!(parser.parse(b"GET * HTTP/1.1\r\nHost: localhost\r\n\r\n").is_err());
     }
 
     #[test]
@@ -150,17 +766,464 @@ mod http_tests {
         assert!(parser.parse(malformed_data).is_err());
     }
 
+    #[test]
+    fn test_decoded_path_checks() {
+        // A NUL could cut the name short at the filesystem
+        let mut parser = HttpRequestParser::new();
+        let error = parser.parse(b"GET /file%00.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap_err();
+        assert!(matches!(error, ServerError::Http(HttpErrorKind::InvalidEncoding(_))));
+        assert_eq!(error.http_status(), Some(HttpStatus::BadRequest));
+
+        let mut parser = HttpRequestParser::new();
+        assert!(parser.parse(b"GET /a%0Ab HTTP/1.1\r\nHost: localhost\r\n\r\n").is_err());
+
+        let mut parser = HttpRequestParser::new();
+        let request = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", "a".repeat(5000));
+        let error = parser.parse(request.as_bytes()).unwrap_err();
+        assert!(matches!(error, ServerError::Http(HttpErrorKind::UriTooLong(_))));
+
+        // Encoded characters count once decoded, and ordinary ones pass
+        let mut parser = HttpRequestParser::new();
+        let request = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", "%41".repeat(2000));
+        let (request, _) = parser.parse(request.as_bytes()).unwrap();
+        assert_eq!(request.unwrap().path.len(), 2001);
+    }
+
+    #[test]
+    fn test_parse_error_kinds_map_to_status() {
+        let mut parser = HttpRequestParser::new();
+        let error = parser.parse(b"GET / HTTP/2.0\r\n\r\n").unwrap_err();
+        assert!(matches!(error, ServerError::Http(HttpErrorKind::UnsupportedVersion(ref v)) if v == "HTTP/2.0"));
+        assert_eq!(error.http_status(), Some(HttpStatus::HttpVersionNotSupported));
+
+        let mut parser = HttpRequestParser::new();
+        let error = parser.parse(b"GET /%zz HTTP/1.1\r\n\r\n").unwrap_err();
+        assert!(matches!(error, ServerError::Http(HttpErrorKind::InvalidEncoding(_))));
+        assert_eq!(error.http_status(), Some(HttpStatus::BadRequest));
+
+        assert_eq!(ServerError::Http(HttpErrorKind::PathTraversal).http_status(), Some(HttpStatus::Forbidden));
+        assert_eq!(ServerError::Config("bad".to_string()).http_status(), None);
+        assert_eq!(error.to_string(), "HTTP error: Invalid encoding: invalid percent-encoding");
+    }
+
+    #[test]
+    fn test_folded_header_rejected_or_merged() {
+        let data = b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Long: first part\r\n \t second part\r\nAccept: */*\r\n\r\n";
+
+        let mut parser = HttpRequestParser::new();
+        let error = parser.parse(data).unwrap_err();
+        assert!(matches!(error, ServerError::Http(HttpErrorKind::MalformedHeader(ref m)) if m.contains("folding in header x-long")));
+        assert_eq!(error.http_status(), Some(HttpStatus::BadRequest));
+
+        let mut parser = HttpRequestParser::new();
+        parser.set_merge_folded_headers(true);
+        let request = parser.parse(data).expect("Failed to parse request").0.expect("Request not complete");
+        assert_eq!(request.get_header("x-long"), Some(&"first part second part".to_string()));
+        assert_eq!(request.get_header("accept"), Some(&"*/*".to_string()));
+        assert!(request.folded_headers);
+
+        // A bare CR in a continuation must not smuggle in another header
+        let mut parser = HttpRequestParser::new();
+        parser.set_merge_folded_headers(true);
+        let error = parser.parse(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-A: a\r\n b\rSet-Cookie: evil=1\r\n\r\n").unwrap_err();
+        assert!(matches!(error, ServerError::Http(HttpErrorKind::MalformedHeader(ref m)) if m.contains("invalid value for header x-a")));
+
+        // There is nothing to continue before the first header
+        let mut parser = HttpRequestParser::new();
+        parser.set_merge_folded_headers(true);
+        assert!(parser.parse(b"GET / HTTP/1.1\r\n folded\r\nHost: localhost\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_header_line_limit() {
+        let mut parser = HttpRequestParser::new();
+        parser.set_max_header_line_bytes(32);
+        let request = parser.parse(b"GET / HTTP/1.1\r\nX-Short: 0123456789\r\n\r\n").unwrap().0;
+        assert!(request.is_some());
+
+        // Refused before the header section is complete
+        let mut parser = HttpRequestParser::new();
+        parser.set_max_header_line_bytes(32);
+        let error = parser.parse(b"GET / HTTP/1.1\r\nX-Long: 0123456789012345678901234567890").unwrap_err();
+        assert_eq!(error.http_status(), Some(HttpStatus::RequestHeaderFieldsTooLarge));
+    }
+
     #[test]
     fn test_post_request_with_body() {
         let request_data = b"POST /submit HTTP/1.1\r\nHost: localhost\r\nContent-Length: 13\r\n\r\nHello, World!";
         
         let mut parser = HttpRequestParser::new();
-        let request = parser.parse(request_data).expect("Failed to parse request").expect("Request not complete");
+        let request = parser.parse(request_data).expect("Failed to parse request").0.expect("Request not complete");
         
         assert_eq!(request.method, HttpMethod::POST);
         assert_eq!(request.path, "/submit");
         assert_eq!(request.body, b"Hello, World!");
     }
+
+    #[test]
+    fn test_chunked_request_split_across_reads() {
+        let first = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHel";
+        let second = b"lo\r\n8;ext=1\r\n, World!\r\n0\r\nX-Trailer: done\r\n\r\n";
+
+        let mut parser = HttpRequestParser::new();
+        let (request, _) = parser.parse(first).expect("Failed to parse first read");
+        assert!(request.is_none());
+
+        let request = parser.parse(second).expect("Failed to parse second read").0.expect("Request not complete");
+        assert_eq!(request.method, HttpMethod::POST);
+        assert_eq!(request.body, b"Hello, World!");
+    }
+
+    #[test]
+    fn test_chunked_request_invalid_chunk_size() {
+        let request_data = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nHello\r\n0\r\n\r\n";
+
+        let mut parser = HttpRequestParser::new();
+        assert!(parser.parse(request_data).is_err());
+    }
+
+    #[test]
+    fn test_chunked_request_exceeds_max_body_size() {
+        let request_data = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\na\r\n0123456789\r\n0\r\n\r\n";
+
+        let mut parser = HttpRequestParser::new();
+        parser.set_max_body_size(8);
+        assert!(parser.parse(request_data).is_err());
+    }
+
+    #[test]
+    fn test_pipelined_request_survives_reset() {
+        let mut parser = HttpRequestParser::new();
+        let data = b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let first = parser.parse(data).unwrap().0.expect("first request should parse");
+        assert_eq!(first.path, "/a");
+        assert!(parser.has_buffered_data());
+
+        parser.reset_for_next_request();
+        let second = parser.parse(&[]).unwrap().0.expect("second request should parse");
+        assert_eq!(second.path, "/b");
+
+        parser.reset_for_next_request();
+        assert!(!parser.has_buffered_data());
+    }
+
+    #[test]
+    fn test_percent_decoding_utf8() {
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(b"GET /caf%C3%A9?q=a%20b HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap().0.expect("request should parse");
+        assert_eq!(request.path, "/caf\u{e9}");
+        assert_eq!(request.query_params.get("q"), Some(&"a b".to_string()));
+
+        // 0xC3 followed by '(' is not valid UTF-8
+        let mut parser = HttpRequestParser::new();
+        assert!(parser.parse(b"GET /%C3%28 HTTP/1.1\r\nHost: localhost\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_repeated_headers_are_retained() {
+        let request_data = b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: 10.0.0.1\r\nX-Forwarded-For: 10.0.0.2, 10.0.0.3\r\n\r\n";
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(request_data).unwrap().0.expect("request should parse");
+
+        assert_eq!(request.get_header_all("x-forwarded-for"), vec!["10.0.0.1", "10.0.0.2, 10.0.0.3"]);
+        assert_eq!(request.get_header("X-Forwarded-For"), Some(&"10.0.0.1".to_string()));
+        assert_eq!(
+            request.get_header_joined("X-Forwarded-For"),
+            Some("10.0.0.1, 10.0.0.2, 10.0.0.3".to_string())
+        );
+        assert!(request.get_header_all("x-missing").is_empty());
+    }
+
+    #[test]
+    fn test_expect_continue_signalled_once() {
+        let mut parser = HttpRequestParser::new();
+        let headers = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n";
+        assert!(parser.parse(headers).unwrap().0.is_none());
+        assert!(parser.take_continue());
+        assert!(!parser.take_continue());
+
+        let request = parser.parse(b"hello").unwrap().0.expect("request should complete");
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn test_expect_continue_rejects_oversized_body() {
+        let mut parser = HttpRequestParser::new();
+        parser.set_max_body_size(4);
+        let headers = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n";
+        assert!(matches!(
+            parser.parse(headers),
+            Err(ServerError::Http(HttpErrorKind::BodyTooLarge(_)))
+        ));
+    }
+
+    #[test]
+    fn test_too_many_headers_rejected() {
+        let mut request_data = String::from("GET / HTTP/1.1\r\nHost: localhost\r\n");
+        for i in 0..200 {
+            request_data.push_str(&format!("X-Header-{}: value\r\n", i));
+        }
+        request_data.push_str("\r\n");
+
+        let mut parser = HttpRequestParser::new();
+        assert!(matches!(
+            parser.parse(request_data.as_bytes()),
+            Err(ServerError::Http(HttpErrorKind::HeaderTooLarge(_)))
+        ));
+    }
+
+    #[test]
+    fn test_oversized_header_line_rejected_before_terminator() {
+        // The header never ends, so the parser must bail out on size alone
+        let request_data = format!("GET / HTTP/1.1\r\nX-Large: {}", "a".repeat(64 * 1024));
+
+        let mut parser = HttpRequestParser::new();
+        assert!(matches!(
+            parser.parse(request_data.as_bytes()),
+            Err(ServerError::Http(HttpErrorKind::HeaderTooLarge(_)))
+        ));
+    }
+
+    #[test]
+    fn test_long_request_line_rejected_across_reads() {
+        // The line never ends; each read adds 1KB until the 8KB limit is passed
+        let request_line = format!("GET /{} HTTP/1.1", "a".repeat(16 * 1024));
+        let mut parser = HttpRequestParser::new();
+        let mut result = Ok((None, 0));
+        for (i, chunk) in request_line.as_bytes().chunks(1024).enumerate() {
+            result = parser.parse(chunk);
+            if result.is_err() {
+                assert!(i < 9, "limit enforced only after {} chunks", i + 1);
+                break;
+            }
+        }
+
+        match result {
+            Err(ServerError::Http(kind @ HttpErrorKind::UriTooLong(_))) => {
+                assert_eq!(kind.status(), HttpStatus::UriTooLong);
+                assert_eq!(kind.status().as_u16(), 414);
+            }
+            other => panic!("expected UriTooLong, got {:?}", other.map(|(request, _)| request.is_some())),
+        }
+
+        // A line within the limit still parses; the path itself has a shorter limit
+        let mut parser = HttpRequestParser::new();
+        let request = format!("GET /?q={} HTTP/1.1\r\nHost: localhost\r\n\r\n", "a".repeat(8000));
+        assert!(parser.parse(request.as_bytes()).unwrap().0.is_some());
+    }
+
+    #[test]
+    fn test_header_limits_allow_exact_count() {
+        let mut request_data = String::from("GET / HTTP/1.1\r\n");
+        for i in 0..DEFAULT_MAX_HEADER_COUNT {
+            request_data.push_str(&format!("X-Header-{}: value\r\n", i));
+        }
+        request_data.push_str("\r\n");
+
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(request_data.as_bytes()).unwrap().0.expect("request should parse");
+        assert_eq!(request.headers.len(), DEFAULT_MAX_HEADER_COUNT);
+    }
+
+    #[test]
+    fn test_parse_bare_lf_request() {
+        let data = b"POST /submit HTTP/1.1\nHost: localhost\nContent-Length: 4\n\nbody";
+        let mut parser = HttpRequestParser::new();
+        let (request, consumed) = parser.parse(data).unwrap();
+        let request = request.expect("request should complete");
+
+        assert_eq!(consumed, data.len());
+        assert_eq!(request.path, "/submit");
+        assert_eq!(request.get_header("host"), Some(&"localhost".to_string()));
+        assert_eq!(request.body, b"body");
+    }
+
+    #[test]
+    fn test_parse_mixed_line_endings() {
+        let mut parser = HttpRequestParser::new();
+        let request = parser
+            .parse(b"GET /mixed HTTP/1.1\r\nHost: localhost\nX-Odd: ab\r\n\n")
+            .unwrap().0
+            .expect("request should complete");
+
+        assert_eq!(request.path, "/mixed");
+        assert_eq!(request.get_header("host"), Some(&"localhost".to_string()));
+        assert_eq!(request.get_header("x-odd"), Some(&"ab".to_string()));
+
+        // A lone CR does not end a line, and is not allowed inside one
+        let mut parser = HttpRequestParser::new();
+        assert!(parser.parse(b"GET /mixed HTTP/1.1\r\nHost: localhost\nX-Odd: a\rb\r\n\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_request_without_headers() {
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(b"GET / HTTP/1.0\r\n\r\n").unwrap().0.expect("request should complete");
+        assert!(request.headers.is_empty());
+    }
+
+    fn assert_bad_framing(request_data: &[u8]) {
+        let mut parser = HttpRequestParser::new();
+        let error = parser.parse(request_data).unwrap_err();
+        assert!(matches!(error, ServerError::Http(HttpErrorKind::MalformedHeader(_))), "unexpected error: {}", error);
+        assert_eq!(error.http_status(), Some(HttpStatus::BadRequest));
+    }
+
+    #[test]
+    fn test_invalid_header_characters_rejected() {
+        // Names are tokens: no spaces, inside or before the colon
+        assert_bad_framing(b"GET / HTTP/1.1\r\nHost: localhost\r\nX Bad: 1\r\n\r\n");
+        assert_bad_framing(b"GET / HTTP/1.1\r\nHost : localhost\r\n\r\n");
+        assert_bad_framing(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-\x01: 1\r\n\r\n");
+        assert_bad_framing(b"GET / HTTP/1.1\r\nHost: localhost\r\n: empty\r\n\r\n");
+
+        // Values cannot carry a CR that would start a new response line, or NUL
+        assert_bad_framing(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Note: a\r\rSet-Cookie: x=1\r\n\r\n");
+        assert_bad_framing(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Note: a\x00b\r\n\r\n");
+
+        // Tabs and punctuation allowed in tokens still pass
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Odd_Name!: a\tb\r\n\r\n")
+            .unwrap().0.expect("Request not complete");
+        assert_eq!(request.get_header("x-odd_name!"), Some(&"a\tb".to_string()));
+    }
+
+    #[test]
+    fn test_form_urlencoded_body_parsed_into_map() {
+        let mut request = HttpRequest::new();
+        request.add_header("Content-Type", "application/x-www-form-urlencoded; charset=UTF-8");
+        request.body = b"a=1&b=hello%20world&c=&d&caf%C3%A9=cr%C3%A8me+br%C3%BBl%C3%A9e&a=2&bad=%zz".to_vec();
+
+        let params = request.form_params();
+        assert_eq!(params.get("a"), Some(&"2".to_string()));
+        assert_eq!(params.get("b"), Some(&"hello world".to_string()));
+        assert_eq!(params.get("c"), Some(&String::new()));
+        assert_eq!(params.get("d"), Some(&String::new()));
+        assert_eq!(params.get("caf\u{e9}"), Some(&"cr\u{e8}me br\u{fb}l\u{e9}e".to_string()));
+        assert!(!params.contains_key("bad"));
+        assert_eq!(params.len(), 5);
+
+        // Other content types carry no form fields
+        let mut request = HttpRequest::new();
+        request.add_header("Content-Type", "text/plain");
+        request.body = b"a=1".to_vec();
+        assert!(request.form_params().is_empty());
+    }
+
+    #[test]
+    fn test_json_body_parsed_into_tree() {
+        let mut request = HttpRequest::new();
+        request.add_header("Content-Type", "application/json; charset=utf-8");
+        request.body = br#"{"user": {"name": "Ada \"L\"", "tags": ["x", 2, -0.5e1, true, null]}, "ok": false}"#.to_vec();
+
+        let value = request.json_value().expect("JSON body rejected");
+        let user = value.get("user").expect("missing user");
+        assert_eq!(user.get("name").and_then(JsonValue::as_str), Some("Ada \"L\""));
+        let tags = user.get("tags").and_then(JsonValue::as_array).expect("missing tags");
+        assert_eq!(tags, &[
+            JsonValue::String("x".to_string()),
+            JsonValue::Number(2.0),
+            JsonValue::Number(-5.0),
+            JsonValue::Bool(true),
+            JsonValue::Null,
+        ]);
+        assert_eq!(value.get("ok").and_then(JsonValue::as_bool), Some(false));
+    }
+
+    #[test]
+    fn test_malformed_json_body_rejected() {
+        for body in [&b"{\"a\": 1,}"[..], b"[1 2]", b"{a: 1}", b"\"unterminated", b"{} extra", b"tru", b""] {
+            let mut request = HttpRequest::new();
+            request.add_header("Content-Type", "application/json");
+            request.body = body.to_vec();
+            match request.json_value() {
+                Err(ServerError::Http(HttpErrorKind::MalformedBody(_))) => {}
+                other => panic!("{:?} gave {:?}", String::from_utf8_lossy(body), other),
+            }
+        }
+
+        // A JSON body sent as another type is refused with 415
+        let mut request = HttpRequest::new();
+        request.add_header("Content-Type", "text/plain");
+        request.body = b"{}".to_vec();
+        let err = request.json_value().unwrap_err();
+        assert_eq!(err.http_status(), Some(HttpStatus::UnsupportedMediaType));
+    }
+
+    #[test]
+    fn test_content_length_with_transfer_encoding_rejected() {
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_conflicting_content_length_rejected() {
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nContent-Length: 6\r\n\r\nbodybo");
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4, 6\r\n\r\nbodybo");
+
+        // Repeated identical values describe the same body
+        let mut parser = HttpRequestParser::new();
+        let request = parser.parse(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nContent-Length: 4\r\n\r\nbody")
+            .unwrap().0.expect("Request not complete");
+        assert_eq!(request.body, b"body");
+    }
+
+    #[test]
+    fn test_non_numeric_content_length_rejected() {
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: abc\r\n\r\n");
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: +4\r\n\r\nbody");
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: -1\r\n\r\n");
+        assert_bad_framing(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: \r\n\r\n");
+    }
+
+    #[test]
+    fn test_spooled_body_stays_out_of_memory() {
+        const BODY_SIZE: usize = 10 * 1024 * 1024;
+        let mut parser = HttpRequestParser::new();
+        parser.set_max_body_size(BODY_SIZE);
+
+        let head = format!("PUT /big.bin HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", BODY_SIZE);
+        assert!(parser.parse(head.as_bytes()).unwrap().0.is_none());
+        assert!(parser.take_body_start());
+        parser.spool_body().unwrap();
+
+        let chunk = vec![b'x'; 64 * 1024];
+        let mut request = None;
+        for _ in 0..BODY_SIZE / chunk.len() {
+            assert!(parser.pending_request().body.is_empty());
+            request = parser.parse(&chunk).unwrap().0;
+        }
+
+        let request = request.expect("Request not complete");
+        assert!(request.body.is_empty());
+        let spooled = request.body_file.clone().expect("Body was not spooled");
+        assert_eq!(spooled.len(), BODY_SIZE as u64);
+        assert_eq!(request.body_len(), BODY_SIZE);
+        assert_eq!(std::fs::metadata(spooled.path()).unwrap().len(), BODY_SIZE as u64);
+
+        // The temporary file goes away with the last request holding it
+        let path = spooled.path().to_path_buf();
+        drop(spooled);
+        drop(request);
+        parser.reset();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_spooled_chunked_body_keeps_decoded_bytes() {
+        let mut parser = HttpRequestParser::new();
+        let head = b"POST /up HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n";
+        assert!(parser.parse(head).unwrap().0.is_none());
+        assert!(parser.take_body_start());
+        parser.spool_body().unwrap();
+
+        let request = parser.parse(b"6\r\n world\r\n0\r\n\r\n").unwrap().0.expect("Request not complete");
+        assert!(request.body.is_empty());
+        let spooled = request.body_file.expect("Body was not spooled");
+        assert_eq!(std::fs::read(spooled.path()).unwrap(), b"hello world");
+    }
 }
 
 #[cfg(test)]
@@ -229,6 +1292,30 @@ mod session_tests {
         assert!(session.is_none());
     }
 
+    #[test]
+    fn test_maybe_cleanup_purges_expired_sessions() {
+        let config = SessionConfig {
+            session_timeout: Duration::from_millis(50),
+            cleanup_interval: Duration::from_millis(100),
+            ..SessionConfig::default()
+        };
+        let manager = SessionManager::new(config);
+        manager.create_session().expect("Failed to create session");
+        manager.create_session().expect("Failed to create session");
+        assert_eq!(manager.get_stats().unwrap().total_sessions, 2);
+
+        // Expired, but the cleanup interval has not yet passed
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(manager.maybe_cleanup().unwrap(), 0);
+        assert_eq!(manager.get_stats().unwrap().expired_sessions, 2);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(manager.maybe_cleanup().unwrap(), 2);
+        let stats = manager.get_stats().unwrap();
+        assert_eq!(stats.total_sessions, 0);
+        assert_eq!(stats.expired_sessions, 0);
+    }
+
     #[test]
     fn test_session_data() {
         let mut session = Session::new("test_id".to_string());
@@ -268,8 +1355,29 @@ mod session_tests {
 mod cgi_tests {
     use localhost_http_server::cgi::*;
     use localhost_http_server::config::*;
+    use localhost_http_server::error::{CgiErrorKind, HttpStatus, ServerError};
     use localhost_http_server::http::*;
 
+    #[test]
+    fn test_cgi_error_kinds_map_to_statuses() {
+        let executor = CgiExecutor::new();
+
+        let response = executor.error_response(&CgiErrorKind::Timeout.into());
+        assert_eq!(response.status, HttpStatus::ServiceUnavailable);
+        assert_eq!(response.get_header("Retry-After"), Some(&"1".to_string()));
+
+        let response = executor.error_response(&CgiErrorKind::Failed("exit status: 1".to_string()).into());
+        assert_eq!(response.status, HttpStatus::BadGateway);
+        assert_eq!(response.get_header("Retry-After"), None);
+
+        let response = executor.error_response(&CgiErrorKind::Spawn("no such file".to_string()).into());
+        assert_eq!(response.status, HttpStatus::InternalServerError);
+
+        let response = executor.error_response(&ServerError::Internal("other".to_string()));
+        assert_eq!(response.status, HttpStatus::InternalServerError);
+        assert_eq!(HttpStatus::BadGateway.reason_phrase(), "Bad Gateway");
+    }
+
     #[test]
     fn test_cgi_environment() {
         let mut request = HttpRequest::new();
@@ -278,6 +1386,9 @@ mod cgi_tests {
         request.path = "/cgi-bin/test.py".to_string();
         request.add_header("user-agent", "test-agent");
         request.add_header("host", "localhost:8080");
+        request.add_header("x-forwarded-for", "10.0.0.1");
+        request.add_header("x-forwarded-for", "10.0.0.2");
+        request.path_params.insert("user-id".to_string(), "42".to_string());
         
         let server_config = ServerConfig::default();
         let env = CgiEnvironment::from_request(&request, &server_config, "/cgi-bin/test.py", "");
@@ -289,14 +1400,35 @@ mod cgi_tests {
         assert_eq!(env.get("SERVER_SOFTWARE"), Some(&"localhost-http-server/0.1.0".to_string()));
         assert_eq!(env.get("GATEWAY_INTERFACE"), Some(&"CGI/1.1".to_string()));
         
+        // Check route parameters
+        assert_eq!(env.get("PATH_PARAM_USER_ID"), Some(&"42".to_string()));
+
         // Check HTTP headers
         assert_eq!(env.get("HTTP_USER_AGENT"), Some(&"test-agent".to_string()));
         assert_eq!(env.get("HTTP_HOST"), Some(&"localhost:8080".to_string()));
+        assert_eq!(env.get("HTTP_X_FORWARDED_FOR"), Some(&"10.0.0.1, 10.0.0.2".to_string()));
         
         // Validate environment
         assert!(env.validate().is_ok());
     }
 
+    #[test]
+    fn test_cgi_environment_drops_connection_headers() {
+        let mut request = HttpRequest::new();
+        request.uri = "/cgi-bin/test.py".to_string();
+        request.path = "/cgi-bin/test.py".to_string();
+        request.add_header("connection", "close, X-Custom");
+        request.add_header("x-custom", "for this hop");
+        request.add_header("keep-alive", "timeout=5");
+        request.add_header("x-other", "passed on");
+
+        let env = CgiEnvironment::from_request(&request, &ServerConfig::default(), "/cgi-bin/test.py", "");
+        assert_eq!(env.get("HTTP_X_CUSTOM"), None);
+        assert_eq!(env.get("HTTP_X_OTHER"), Some(&"passed on".to_string()));
+        assert_eq!(env.get("HTTP_KEEP_ALIVE"), Some(&"timeout=5".to_string()));
+        assert_eq!(env.get("HTTP_CONNECTION"), Some(&"close, X-Custom".to_string()));
+    }
+
     #[test]
     fn test_cgi_environment_post() {
         let mut request = HttpRequest::new();
@@ -305,14 +1437,14 @@ mod cgi_tests {
         request.path = "/cgi-bin/form.py".to_string();
         request.body = b"name=John&email=john@example.com".to_vec();
         request.add_header("content-type", "application/x-www-form-urlencoded");
-        request.add_header("content-length", "30");
+        request.add_header("content-length", "32");
         
         let server_config = ServerConfig::default();
         let env = CgiEnvironment::from_request(&request, &server_config, "/cgi-bin/form.py", "");
         
         assert_eq!(env.get("REQUEST_METHOD"), Some(&"POST".to_string()));
         assert_eq!(env.get("CONTENT_TYPE"), Some(&"application/x-www-form-urlencoded".to_string()));
-        assert_eq!(env.get("CONTENT_LENGTH"), Some(&"30".to_string()));
+        assert_eq!(env.get("CONTENT_LENGTH"), Some(&"32".to_string()));
     }
 
     #[test]
@@ -337,7 +1469,6 @@ mod cgi_tests {
 #[cfg(test)]
 mod timeout_tests {
     use localhost_http_server::utils::*;
-    use std::time::Duration;
 
     #[test]
     fn test_timeout_manager() {
@@ -363,6 +1494,59 @@ mod timeout_tests {
         assert_eq!(manager.connection_count(), 0);
     }
 
+    #[test]
+    fn test_connection_parts_are_reused() {
+        use localhost_http_server::server::connection::ConnectionManager;
+
+        let mut manager = ConnectionManager::new(30);
+        manager.add_connection(10, None).unwrap();
+
+        // Grow the write buffer to a size a fresh connection would never start with
+        let connection = manager.get_connection_mut(10).unwrap();
+        connection.write_buffer.ensure_writable_space(20000).unwrap();
+        let sentinel = connection.write_buffer.capacity();
+        connection.write_buffer.append(b"stale").unwrap();
+
+        assert!(manager.remove_connection(10));
+        assert_eq!(manager.pooled_count(), 1);
+        assert!(!manager.remove_connection(10));
+
+        manager.add_connection(11, None).unwrap();
+        assert_eq!(manager.pooled_count(), 0);
+        let connection = manager.get_connection(11).unwrap();
+        assert_eq!(connection.write_buffer.capacity(), sentinel);
+        assert!(connection.write_buffer.is_empty());
+        assert!(!connection.http_parser.has_buffered_data());
+    }
+
+    #[test]
+    fn test_connection_survives_read_with_no_data_ready() {
+        use localhost_http_server::server::connection::ConnectionManager;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) }, 0);
+        let (read_fd, write_fd) = (fds[0], fds[1]);
+
+        let mut manager = ConnectionManager::new(30);
+        manager.add_connection(read_fd, None).unwrap();
+        let connection = manager.get_connection_mut(read_fd).unwrap();
+
+        // EAGAIN reads nothing but is not the peer closing, drained or not
+        assert_eq!(connection.read_from_socket(false).unwrap(), (0, false));
+        assert_eq!(connection.read_from_socket(true).unwrap(), (0, false));
+
+        assert_eq!(unsafe { libc::write(write_fd, b"GET".as_ptr() as *const libc::c_void, 3) }, 3);
+        assert_eq!(connection.read_from_socket(false).unwrap(), (3, false));
+
+        unsafe {
+            libc::close(write_fd);
+        }
+        assert_eq!(connection.read_from_socket(false).unwrap(), (0, true));
+        unsafe {
+            libc::close(read_fd);
+        }
+    }
+
     #[test]
     fn test_resource_monitor() {
         let mut monitor = ResourceMonitor::new();
@@ -396,3 +1580,82 @@ mod timeout_tests {
         assert_eq!(stats.avg_requests_per_connection(), 0.0);
     }
 }
+
+#[cfg(test)]
+mod handler_tests {
+    use localhost_http_server::config::parse_config;
+    use localhost_http_server::error::HttpStatus;
+    use localhost_http_server::http::{HttpRequest, HttpRequestParser};
+    use localhost_http_server::server::Server;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Server for a site with an index page, driven without sockets
+    fn test_server(name: &str) -> (Server, PathBuf) {
+        let site = std::env::temp_dir().join(format!("localhost_handler_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&site).unwrap();
+        fs::write(site.join("index.html"), "<h1>home</h1>").unwrap();
+
+        let config = parse_config(&format!(
+            "server {{\n    listen 8080\n\n    route /old {{\n        redirect 301 /\n    }}\n\n    route / {{\n        methods GET HEAD\n        root {}\n        index index.html\n    }}\n}}\n",
+            site.display()
+        )).expect("Failed to parse config");
+        (Server::new(config).expect("Failed to create server"), site)
+    }
+
+    fn request(raw: &str) -> HttpRequest {
+        HttpRequestParser::new().parse(raw.as_bytes()).unwrap().0.expect("Incomplete request")
+    }
+
+    #[test]
+    fn test_get_serves_file_with_session() {
+        let (server, site) = test_server("get");
+
+        let response = server.handle_request_for_test(request("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert_eq!(response.body, b"<h1>home</h1>");
+        let session = response.cookies.iter().find(|cookie| cookie.name == "SESSIONID").expect("No session cookie").value.clone();
+
+        // The session is kept when the client sends its cookie back
+        let response = server.handle_request_for_test(request(&format!("GET / HTTP/1.1\r\nHost: localhost\r\nCookie: SESSIONID={}\r\n\r\n", session)));
+        assert!(response.cookies.iter().any(|cookie| cookie.name == "SESSIONID" && cookie.value == session));
+
+        // HEAD keeps the headers but not the body
+        let response = server.handle_request_for_test(request("HEAD /index.html HTTP/1.0\r\n\r\n"));
+        assert_eq!(response.status, HttpStatus::Ok);
+        assert!(response.body.is_empty());
+        assert_eq!(response.get_header("Content-Length").map(String::as_str), Some("13"));
+
+        fs::remove_dir_all(&site).ok();
+    }
+
+    #[test]
+    fn test_missing_file_and_wrong_method() {
+        let (server, site) = test_server("errors");
+
+        let response = server.handle_request_for_test(request("GET /missing.html HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert_eq!(response.status, HttpStatus::NotFound);
+
+        let response = server.handle_request_for_test(request("DELETE /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert_eq!(response.status, HttpStatus::MethodNotAllowed);
+        assert_eq!(response.get_header("Allow").map(String::as_str), Some("GET, HEAD"));
+        assert!(site.join("index.html").exists());
+
+        // Paths escaping the root are refused
+        let response = server.handle_request_for_test(request("GET /../etc/passwd HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert_eq!(response.status, HttpStatus::Forbidden);
+
+        fs::remove_dir_all(&site).ok();
+    }
+
+    #[test]
+    fn test_redirect_route() {
+        let (server, site) = test_server("redirect");
+
+        let response = server.handle_request_for_test(request("GET /old/page HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert_eq!(response.status, HttpStatus::MovedPermanently);
+        assert_eq!(response.get_header("Location").map(String::as_str), Some("/"));
+
+        fs::remove_dir_all(&site).ok();
+    }
+}